@@ -1,28 +1,196 @@
 //! Global configuration helpers and defaults for the library.
 
 use anyhow::{anyhow, Result};
-use once_cell::sync::OnceCell;
-use tracing_subscriber::{fmt, EnvFilter};
+use once_cell::sync::{Lazy, OnceCell};
+use std::{cell::Cell, fmt::Debug, sync::Arc, sync::RwLock};
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_subscriber::{
+    fmt,
+    layer::{Context, Layer, SubscriberExt},
+    registry::Registry,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter,
+};
 
 /// Default list of bootstrap peers used to connect to the network.
 pub const DEFAULT_BOOTSTRAP_PEERS: &[&str] = &[];
 
+/// Gossipsub topic the node subscribes to and publishes on by default.
+pub const DEFAULT_GOSSIPSUB_TOPIC: &str = "echo";
+
 static TRACING_INITIALIZED: OnceCell<()> = OnceCell::new();
+static LOG_FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+static LOG_HOOK: Lazy<RwLock<HookState>> = Lazy::new(RwLock::default);
+
+thread_local! {
+    /// Set while a hook runs, so logs emitted by the hook do not re-enter it.
+    static IN_LOG_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Where the subscriber installed by [`init_tracing_with_output`] writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TracingOutput {
+    /// Formatted lines on stdout, plus the log hook when one is set.
+    #[default]
+    Formatted,
+    /// Only the log hook; for hosts with their own logging stack.
+    HookOnly,
+}
+
+/// Structured log event handed to the hook installed with [`set_log_hook`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord<'a> {
+    pub level: Level,
+    /// Event target (`peer`, `ffi`, `transport`, ...) behind the prefix set
+    /// with [`set_log_target_prefix`].
+    pub target: &'a str,
+    pub message: String,
+    /// Remaining event fields, formatted with their `Debug` representation.
+    pub fields: Vec<(&'static str, String)>,
+}
+
+/// Callback receiving every log event passing the log filter.
+pub type LogHook = Arc<dyn Fn(&LogRecord<'_>) + Send + Sync>;
+
+#[derive(Default)]
+struct HookState {
+    hook: Option<LogHook>,
+    target_prefix: Option<String>,
+}
 
 /// Initializes the global [`tracing`] subscriber once per process.
 ///
 /// Subsequent invocations become no-ops, making it safe to call from
 /// different entry points without worrying about initialization order.
 pub fn init_tracing() -> Result<()> {
+    init_tracing_with_output(TracingOutput::Formatted)
+}
+
+/// Initializes the global [`tracing`] subscriber once per process, writing
+/// to `output`. Like [`init_tracing`], only the first call has an effect.
+pub fn init_tracing_with_output(output: TracingOutput) -> Result<()> {
     TRACING_INITIALIZED
         .get_or_try_init(|| {
             let env_filter =
                 EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-            fmt::Subscriber::builder()
-                .with_env_filter(env_filter)
+            let (filter, handle) = reload::Layer::new(env_filter);
+            let formatted = (output == TracingOutput::Formatted).then(fmt::layer);
+            tracing_subscriber::registry()
+                .with(filter)
+                .with(formatted)
+                .with(HookLayer)
                 .try_init()
                 .map_err(|err| anyhow!(err))?;
+            let _ = LOG_FILTER.set(handle);
             Ok(())
         })
         .map(|_| ())
 }
+
+/// Installs (or with `None` removes) the hook receiving log events. Events
+/// only flow once tracing was initialized through [`init_tracing`] or
+/// [`init_tracing_with_output`], and are filtered like the formatted output.
+pub fn set_log_hook(hook: Option<LogHook>) {
+    match LOG_HOOK.write() {
+        Ok(mut state) => state.hook = hook,
+        Err(_) => eprintln!("fidonext: log hook lock poisoned"),
+    }
+}
+
+/// Prepends `prefix` to the targets handed to the log hook, e.g. `pheonx/`
+/// turns `peer` into `pheonx/peer` for a logcat tag. `None` removes it.
+pub fn set_log_target_prefix(prefix: Option<&str>) {
+    match LOG_HOOK.write() {
+        Ok(mut state) => state.target_prefix = prefix.map(str::to_string),
+        Err(_) => eprintln!("fidonext: log hook lock poisoned"),
+    }
+}
+
+/// Layer forwarding events to the hook installed with [`set_log_hook`].
+struct HookLayer;
+
+impl<S: Subscriber> Layer<S> for HookLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if IN_LOG_HOOK.with(Cell::get) {
+            return;
+        }
+        let Ok(state) = LOG_HOOK.read() else {
+            return;
+        };
+        let Some(hook) = state.hook.clone() else {
+            return;
+        };
+        let metadata = event.metadata();
+        let target = match &state.target_prefix {
+            Some(prefix) => format!("{prefix}{}", metadata.target()),
+            None => metadata.target().to_string(),
+        };
+        drop(state);
+
+        let mut visitor = RecordVisitor::default();
+        event.record(&mut visitor);
+        let record = LogRecord {
+            level: *metadata.level(),
+            target: &target,
+            message: visitor.message,
+            fields: visitor.fields,
+        };
+        let _guard = InLogHookGuard::enter();
+        hook(&record);
+    }
+}
+
+/// Marks the current thread as running the log hook until dropped, so the
+/// flag is cleared even when the hook panics.
+struct InLogHookGuard;
+
+impl InLogHookGuard {
+    fn enter() -> Self {
+        IN_LOG_HOOK.with(|in_hook| in_hook.set(true));
+        Self
+    }
+}
+
+impl Drop for InLogHookGuard {
+    fn drop(&mut self) {
+        IN_LOG_HOOK.with(|in_hook| in_hook.set(false));
+    }
+}
+
+#[derive(Default)]
+struct RecordVisitor {
+    message: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl tracing::field::Visit for RecordVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields.push((field.name(), value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            self.fields.push((field.name(), format!("{value:?}")));
+        }
+    }
+}
+
+/// Replaces the log filter of the subscriber installed by [`init_tracing`]
+/// with `directives` (e.g. `info,peer=debug`).
+pub fn set_log_filter(directives: &str) -> Result<()> {
+    let handle = LOG_FILTER
+        .get()
+        .ok_or_else(|| anyhow!("tracing was not initialized through init_tracing"))?;
+    let filter = EnvFilter::try_new(directives)
+        .map_err(|err| anyhow!("invalid log filter {directives:?}: {err}"))?;
+    handle
+        .reload(filter)
+        .map_err(|err| anyhow!("failed to reload log filter: {err}"))
+}
@@ -0,0 +1,108 @@
+//! Deterministic entry points for fuzz targets, enabled with the `fuzzing`
+//! feature.
+//!
+//! Each function feeds arbitrary bytes to one inbound decoding path without
+//! touching the network, the clock or a random source, and panics only when
+//! an invariant of that path breaks (e.g. a decoded value that does not
+//! encode back to the same bytes). A `cargo fuzz` target is one line:
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| cabi_rust_libp2p::fuzz::inbound_payload(data));
+//! ```
+
+use std::time::Duration;
+
+use crate::{
+    messaging::{Envelope, GroupFrame, ReplayCache, SignedPayload, TopicCipher},
+    peer::Key,
+};
+
+/// Topic the cipher of [`topic_ciphertext`] and [`inbound_payload`] is bound to.
+pub const FUZZ_TOPIC: &str = "fuzz";
+/// Shared secret of the cipher of [`topic_ciphertext`] and [`inbound_payload`].
+pub const FUZZ_TOPIC_KEY: [u8; 32] = [7; 32];
+/// Clock reading, in milliseconds since the Unix epoch, replay checks run at.
+pub const FUZZ_NOW_MS: u64 = 1_700_000_000_000;
+
+/// Decodes a replay envelope, checks it round-trips and runs it twice through
+/// a replay cache: a fresh envelope must be refused the second time.
+pub fn envelope(data: &[u8]) {
+    let Ok(envelope) = Envelope::decode(data) else {
+        return;
+    };
+    assert_eq!(envelope.encode(), data, "envelope does not round-trip");
+
+    let mut cache = ReplayCache::new(Duration::from_secs(60));
+    if cache.check_at(None, &envelope, FUZZ_NOW_MS).is_ok() {
+        assert!(
+            cache.check_at(None, &envelope, FUZZ_NOW_MS).is_err(),
+            "replayed envelope accepted"
+        );
+    }
+}
+
+/// Decodes a signed payload, checks it round-trips and verifies it. The
+/// re-encoded public key may differ from a non-canonical input encoding, so
+/// the round trip compares decoded values.
+pub fn signed_payload(data: &[u8]) {
+    let Ok(signed) = SignedPayload::decode(data) else {
+        return;
+    };
+    assert_eq!(
+        SignedPayload::decode(&signed.encode()).ok().as_ref(),
+        Some(&signed),
+        "signed payload does not round-trip"
+    );
+    let _ = signed.verify();
+}
+
+/// Decodes a group frame and checks it round-trips.
+pub fn group_frame(data: &[u8]) {
+    let Ok(frame) = GroupFrame::decode(data) else {
+        return;
+    };
+    assert_eq!(
+        GroupFrame::decode(&frame.encode()).ok(),
+        Some(frame),
+        "group frame does not round-trip"
+    );
+}
+
+/// Decrypts a topic ciphertext with [`FUZZ_TOPIC_KEY`].
+pub fn topic_ciphertext(data: &[u8]) {
+    let _ = TopicCipher::new(FUZZ_TOPIC, &FUZZ_TOPIC_KEY).decrypt(data);
+}
+
+/// Parses a namespaced DHT key and checks it round-trips.
+pub fn dht_key(data: &[u8]) {
+    let Ok(key) = Key::parse(data) else {
+        return;
+    };
+    assert_eq!(key.to_bytes(), data, "DHT key does not round-trip");
+}
+
+/// Unwraps the layers an inbound gossip payload may carry, outermost first
+/// as the peer manager does: topic encryption, replay envelope, signature,
+/// group frame. Each layer is optional and recognised by its magic bytes.
+pub fn inbound_payload(data: &[u8]) {
+    let mut payload = data.to_vec();
+    if payload.starts_with(b"pxc1") {
+        match TopicCipher::new(FUZZ_TOPIC, &FUZZ_TOPIC_KEY).decrypt(&payload) {
+            Ok(plaintext) => payload = plaintext,
+            Err(_) => return,
+        }
+    }
+    if payload.starts_with(b"pxe1") {
+        match Envelope::decode(&payload) {
+            Ok(envelope) => payload = envelope.payload,
+            Err(_) => return,
+        }
+    }
+    if let Ok(signed) = SignedPayload::decode(&payload) {
+        if !signed.verify() {
+            return;
+        }
+        payload = signed.payload;
+    }
+    group_frame(&payload);
+}
@@ -2,8 +2,12 @@
 //! surface that can be consumed by other runtimes.
 
 pub mod config;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
 pub mod messaging;
 pub mod peer;
+#[cfg(feature = "test_support")]
+pub mod test_support;
 pub mod transport;
 
 pub use messaging::*;
@@ -11,18 +15,28 @@ pub use peer::*;
 pub use transport::*;
 
 use std::{
-    ffi::CStr,
-    os::raw::{c_char, c_int},
+    collections::{HashMap, VecDeque},
+    ffi::{CStr, CString},
+    future::Future,
+    num::NonZeroUsize,
+    os::raw::{c_char, c_int, c_void},
+    path::PathBuf,
     ptr,
     slice,
     str::FromStr,
-    sync::atomic::{AtomicU64, Ordering},
-    sync::{Arc, RwLock},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{Context, Result};
-use ::libp2p::{autonat, Multiaddr, PeerId};
-use tokio::{runtime::Runtime, sync::watch, task::JoinHandle};
+use ::libp2p::{autonat, identity, kad, Multiaddr, PeerId};
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::Lazy;
+use tokio::{
+    runtime::{EnterGuard, Handle, Runtime},
+    sync::watch,
+    task::JoinHandle,
+};
 
 /// More suitable alias for results while using C-ABI libp2p rust lib
 type FfiResult<T> = std::result::Result<T, c_int>;
@@ -45,6 +59,14 @@ pub const CABI_STATUS_BUFFER_TOO_SMALL: c_int = -2;
 pub const CABI_STATUS_TIMEOUT: c_int = 6;
 /// The target peer could not be located in the DHT.
 pub const CABI_STATUS_NOT_FOUND: c_int = 7;
+/// The discovery query timed out after reporting some peers.
+pub const CABI_STATUS_PARTIAL_SUCCESS: c_int = 10;
+/// The node exists but has not been started (or was stopped).
+pub const CABI_STATUS_NOT_RUNNING: c_int = 8;
+/// The handle does not refer to a live node (never created or already destroyed).
+pub const CABI_STATUS_INVALID_HANDLE: c_int = 9;
+/// The dialed address belongs to the local node; no connection was attempted.
+pub const CABI_STATUS_SELF_DIAL: c_int = 11;
 
 
 /// AutoNAT status has not yet been determined.
@@ -55,141 +77,925 @@ pub const CABI_AUTONAT_PRIVATE: c_int = 1;
 pub const CABI_AUTONAT_PUBLIC: c_int = 2;
 
 
+/// Dial over direct TCP connections only.
+pub const CABI_TRANSPORT_TCP: c_int = 0;
+/// Dial over direct QUIC connections only.
+pub const CABI_TRANSPORT_QUIC: c_int = 1;
+/// Dial over relay circuits only.
+pub const CABI_TRANSPORT_RELAY: c_int = 2;
+
+/// The host switched networks, e.g. from Wi-Fi to cellular.
+pub const CABI_NETWORK_SWITCHED: c_int = 0;
+/// The host lost connectivity, e.g. in airplane mode.
+pub const CABI_NETWORK_LOST: c_int = 1;
+/// The host has connectivity again after losing it.
+pub const CABI_NETWORK_RESTORED: c_int = 2;
+
+/// Regular intervals, mesh size and roles.
+pub const CABI_POWER_PROFILE_NORMAL: c_int = 0;
+/// Battery and data saving profile, see [`PowerProfile::LowPower`].
+pub const CABI_POWER_PROFILE_LOW: c_int = 1;
+
+
 /// Discovery event carries an address for a peer.
 pub const CABI_DISCOVERY_EVENT_ADDRESS: c_int = 0;
 /// Discovery query has finished.
 pub const CABI_DISCOVERY_EVENT_FINISHED: c_int = 1;
+/// Discovery event carries a provider found by a get_providers query.
+pub const CABI_DISCOVERY_EVENT_PROVIDER: c_int = 2;
+/// Discovery event carries a record found by a get_record query: the
+/// publisher as peer id and the value length in bytes, in decimal, as
+/// address. Fetch the value with [`cabi_node_take_record_value`].
+pub const CABI_DISCOVERY_EVENT_RECORD: c_int = 3;
+/// A put_record query finished; its status tells whether the quorum stored it.
+pub const CABI_DISCOVERY_EVENT_RECORD_STORED: c_int = 4;
+/// A signed peer record of the find_peer target is known; the target is
+/// given as peer id. Fetch it with [`cabi_node_export_peer_record`].
+pub const CABI_DISCOVERY_EVENT_PEER_RECORD: c_int = 5;
+/// A start_providing query finished; its status tells whether the provider
+/// record reached the DHT.
+pub const CABI_DISCOVERY_EVENT_PROVIDING_STARTED: c_int = 6;
+
+/// Node event carries a received message payload.
+pub const CABI_NODE_EVENT_MESSAGE: c_int = 0;
+/// Node event carries a discovery result as `key=value` text.
+pub const CABI_NODE_EVENT_DISCOVERY: c_int = 1;
+/// Node event reports an established connection as `key=value` text.
+pub const CABI_NODE_EVENT_CONNECTION_ESTABLISHED: c_int = 2;
+/// Node event reports a closed connection as `key=value` text.
+pub const CABI_NODE_EVENT_CONNECTION_CLOSED: c_int = 3;
+/// Node event reports a new AutoNAT status as `key=value` text.
+pub const CABI_NODE_EVENT_NAT_STATUS: c_int = 4;
+/// Node event carries an error message.
+pub const CABI_NODE_EVENT_ERROR: c_int = 5;
+/// Node event carries another network event (hole punch, relay reservation,
+/// slow consumer) as debug text.
+pub const CABI_NODE_EVENT_NETWORK: c_int = 6;
+/// Node event reports the outcome of a dial started with a request id as
+/// `key=value` text.
+pub const CABI_NODE_EVENT_DIAL_FINISHED: c_int = 7;
+/// Node event reports a group membership change or message as `key=value`
+/// text, the message payload hex-encoded.
+pub const CABI_NODE_EVENT_GROUP: c_int = 8;
+/// Node event reports a peer disconnected for running an incompatible
+/// protocol or agent version as `key=value` text.
+pub const CABI_NODE_EVENT_INCOMPATIBLE_PEER: c_int = 9;
+/// Node event reports a peer the node disconnected or banned on its own as
+/// `key=value` text, the free-form `detail` last.
+pub const CABI_NODE_EVENT_PEER_PENALIZED: c_int = 10;
+/// Node event reports the topics subscribed and unsubscribed by one
+/// [`cabi_node_set_subscriptions`] call as `key=value` text, each list
+/// comma-separated.
+pub const CABI_NODE_EVENT_SUBSCRIPTIONS: c_int = 11;
+
+/// Log level passed to a [`CabiLogCallback`]: error.
+pub const CABI_LOG_LEVEL_ERROR: c_int = 1;
+/// Log level passed to a [`CabiLogCallback`]: warning.
+pub const CABI_LOG_LEVEL_WARN: c_int = 2;
+/// Log level passed to a [`CabiLogCallback`]: info.
+pub const CABI_LOG_LEVEL_INFO: c_int = 3;
+/// Log level passed to a [`CabiLogCallback`]: debug.
+pub const CABI_LOG_LEVEL_DEBUG: c_int = 4;
+/// Log level passed to a [`CabiLogCallback`]: trace.
+pub const CABI_LOG_LEVEL_TRACE: c_int = 5;
+
+/// Receives the log events routed by [`cabi_set_log_callback`], from any
+/// thread. `level` is one of the `CABI_LOG_LEVEL_*` values; `target` and
+/// `message` are null-terminated and only valid during the call. `message`
+/// holds the event message followed by its fields as `key=value` text.
+pub type CabiLogCallback = extern "C" fn(
+    level: c_int,
+    target: *const c_char,
+    message: *const c_char,
+    user_data: *mut c_void,
+);
 
-/// Opaque handle that callers treat as an identifier for a running node.
+/// Size of the peer id buffer in [`CabiDiscoveryEvent`], including the null terminator.
+pub const CABI_DISCOVERY_PEER_ID_LEN: usize = 128;
+/// Size of the multiaddr buffer in [`CabiDiscoveryEvent`], including the null terminator.
+pub const CABI_DISCOVERY_ADDRESS_LEN: usize = 512;
+/// Size of the trace id buffers of [`cabi_node_publish_traced`] and
+/// [`cabi_node_dequeue_traced_message`].
+pub const CABI_TRACE_ID_LEN: usize = 16;
+/// Values of dequeued record events kept for [`cabi_node_take_record_value`];
+/// the oldest are dropped beyond this.
+pub const CABI_MAX_PENDING_RECORD_VALUES: usize = 64;
+
+/// Discovery event as written by [`cabi_node_drain_discovery_events`].
+#[repr(C)]
+pub struct CabiDiscoveryEvent {
+    /// One of the `CABI_DISCOVERY_EVENT_*` kinds.
+    pub event_kind: c_int,
+    /// Request identifier returned when the query was started.
+    pub request_id: u64,
+    /// `CABI_STATUS_*` code of the query (meaningful for finished events).
+    pub status_code: c_int,
+    /// Number of peers reported by the query (finished events only).
+    pub peers_found: u64,
+    /// Null-terminated peer id (discovered peer, provider or record
+    /// publisher, or the target when finished or for peer record events;
+    /// empty when a get_providers, get_record or put_record query finished).
+    pub peer_id: [c_char; CABI_DISCOVERY_PEER_ID_LEN],
+    /// Null-terminated multiaddr (empty for finished events). Provider events
+    /// carry the first locally known address of the provider, if any, and
+    /// record events the length of the record value.
+    pub address: [c_char; CABI_DISCOVERY_ADDRESS_LEN],
+}
+
+/// Opaque handle that callers treat as an identifier for a node.
+///
+/// The pointer value is a registry key and is never dereferenced, so stale
+/// handles are reported as [`CABI_STATUS_INVALID_HANDLE`] instead of causing
+/// use-after-free.
+///
+/// Every `cabi_node_*` function may be called concurrently from any host
+/// thread with the same handle; no external locking is required. Commands are
+/// forwarded to the node's own runtime, and queue reads are serialized
+/// internally.
 #[repr(C)]
 pub struct CabiNodeHandle {
     _private: [u8; 0],
 }
 
-/// Wrapper struct around peer manager and tokio runtime
-struct ManagedNode {
-    runtime: Runtime,
+const _: () = assert!(CABI_TRACE_ID_LEN == messaging::TRACE_ID_LEN);
+
+// Node state is shared between host threads through the handle registry.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ManagedNode>();
+    assert_send_sync::<peer::PeerManagerHandle>();
+};
+
+/// Live state of a started node.
+struct RunningNode {
     handle: peer::PeerManagerHandle,
-    worker: Option<JoinHandle<()>>,
+    worker: JoinHandle<()>,
     autonat_status: watch::Receiver<autonat::NatStatus>,
-    message_queue: messaging::MessageQueue,
-    discovery_queue: peer::DiscoveryQueue,
+    events: peer::NodeEventStream,
+}
+
+/// Runtime a node runs on.
+///
+/// A multi-threaded runtime drives itself. A current-thread runtime starts no
+/// thread at all: it makes progress only on host threads inside a
+/// `cabi_node_*` call, which drive it while they wait, and inside
+/// [`cabi_node_drive`].
+struct NodeRuntime {
+    runtime: Runtime,
+    current_thread: bool,
+}
+
+impl NodeRuntime {
+    fn new(settings: &transport::ExecutorSettings) -> Result<Self> {
+        let runtime = settings
+            .build_runtime()
+            .context("failed to create tokio runtime")?;
+        Ok(Self {
+            runtime,
+            current_thread: settings.current_thread,
+        })
+    }
+
+    fn enter(&self) -> EnterGuard<'_> {
+        self.runtime.enter()
+    }
+
+    fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.runtime.spawn(future)
+    }
+
+    /// Waits for `future` on the calling thread, driving a current-thread
+    /// runtime meanwhile.
+    fn block_on<F: Future>(&self, future: F) -> Result<F::Output> {
+        if !self.current_thread {
+            return Ok(futures::executor::block_on(future));
+        }
+        if Handle::try_current().is_ok() {
+            return Err(anyhow!(
+                "a current-thread node cannot be driven from inside a tokio runtime"
+            ));
+        }
+        Ok(self.runtime.block_on(future))
+    }
+}
+
+/// Wrapper struct around peer manager and tokio runtime.
+///
+/// The queues outlive individual start/stop cycles so events produced before a
+/// stop can still be drained afterwards.
+struct ManagedNode {
+    runtime: NodeRuntime,
+    config: Mutex<transport::TransportConfig>,
+    bootstrap_peers: Vec<Multiaddr>,
+    running: Mutex<Option<RunningNode>>,
+    message_queue: Mutex<messaging::MessageQueue>,
+    message_sender: messaging::MessageQueueSender,
+    discovery_queue: Mutex<peer::DiscoveryQueue>,
+    discovery_sender: peer::DiscoveryEventSender,
     discovery_sequence: AtomicU64,
     addr_state: Arc<RwLock<AddrState>>,
+    /// Registrations of topic consumers, kept across restarts like the queues.
+    topic_router: messaging::TopicRouter,
+    topic_consumers: Mutex<HashMap<u64, messaging::TopicConsumer>>,
+    /// Event taken from the node event stream that did not fit the caller's
+    /// buffer; returned again by the next poll.
+    pending_event: Mutex<Option<peer::NodeEvent>>,
+    /// Policy applied to dials, publishes and DHT queries issued through the C-ABI.
+    retry: Mutex<RetryPolicy>,
+    /// Values of dequeued record events by request id, oldest first, until
+    /// taken with [`cabi_node_take_record_value`].
+    record_values: Mutex<VecDeque<(u64, Vec<u8>)>>,
 }
 
 impl ManagedNode {
-    /// Creates new peer manager for the single peer
+    /// Creates a stopped node; call [`ManagedNode::start`] to spawn the peer manager.
     fn new(config: transport::TransportConfig, bootstrap_peers: Vec<Multiaddr>) -> Result<Self> {
-        let runtime = Runtime::new().context("failed to create tokio runtime")?;
+        config.validate()?;
+        let runtime = NodeRuntime::new(&config.executor)?;
         let message_queue = messaging::MessageQueue::new(messaging::DEFAULT_MESSAGE_QUEUE_CAPACITY);
         let discovery_queue = peer::DiscoveryQueue::new(peer::DEFAULT_DISCOVERY_QUEUE_CAPACITY);
         let addr_state = Arc::new(RwLock::new(AddrState::default()));
 
-        let (manager, handle) = peer::PeerManager::new(
-            config,
-            message_queue.sender(),
-            discovery_queue.sender(),
-            addr_state.clone(),
+        Ok(Self {
+            runtime,
+            config: Mutex::new(config),
             bootstrap_peers,
+            running: Mutex::new(None),
+            message_sender: message_queue.sender(),
+            message_queue: Mutex::new(message_queue),
+            discovery_sender: discovery_queue.sender(),
+            discovery_queue: Mutex::new(discovery_queue),
+            discovery_sequence: AtomicU64::new(0),
+            addr_state,
+            topic_router: messaging::TopicRouter::default(),
+            topic_consumers: Mutex::new(HashMap::new()),
+            pending_event: Mutex::new(None),
+            retry: Mutex::new(RetryPolicy::none()),
+            record_values: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// Builds the swarm and spawns the peer manager. No-op when already running.
+    ///
+    /// The identity picked on the first start is kept for later restarts,
+    /// unless the node uses an ephemeral identity.
+    fn start(&self) -> Result<()> {
+        let mut running = lock(&self.running)?;
+        if running.is_some() {
+            return Ok(());
+        }
+
+        let mut config = lock(&self.config)?;
+        let _guard = self.runtime.enter();
+        let (mut manager, handle) = peer::PeerManager::new(
+            config.clone(),
+            self.message_sender.clone(),
+            self.discovery_sender.clone(),
+            self.addr_state.clone(),
+            self.bootstrap_peers.clone(),
         )?;
+        manager.set_topic_router(self.topic_router.clone());
+
+        if config.identity_seed.is_none()
+            && config.keypair_source == transport::KeypairSource::Generate
+            && !config.ephemeral_identity
+        {
+            config.identity_seed = ed25519_seed(manager.keypair());
+        }
 
         let autonat_status = handle.autonat_status();
-        let worker = runtime.spawn(async move {
+        let events = handle.node_events();
+        let worker = self.runtime.spawn(async move {
             if let Err(err) = manager.run().await {
                 tracing::error!(target: "ffi", %err, "peer manager exited with error");
             }
         });
 
-        Ok(Self {
-            runtime,
+        *running = Some(RunningNode {
             handle,
+            worker,
             autonat_status,
-            worker: Some(worker),
-            message_queue,
-            discovery_queue,
-            discovery_sequence: AtomicU64::new(0),
-            addr_state,
-        })
+            events,
+        });
+        Ok(())
     }
 
-    fn reserve_relay(&self, address: Multiaddr) -> Result<()> {
+    /// Requests to gracefully shutdown peer manager and joins the background task.
+    /// No-op when the node is not running.
+    fn stop(&self) {
+        let Some(running) = lock(&self.running)
+            .ok()
+            .and_then(|mut running| running.take())
+        else {
+            return;
+        };
+
+        let RunningNode { handle, worker, .. } = running;
+        let result = self.run(async move {
+            if let Err(err) = handle.shutdown().await {
+                tracing::warn!(target: "ffi", %err, "node shutdown request failed");
+            }
+            worker
+                .await
+                .map_err(|err| anyhow!("peer manager task join failed: {err}"))
+        });
+
+        if let Err(err) = result {
+            tracing::warn!(target: "ffi", %err, "node stop failed");
+        }
+    }
+
+    /// Runs `future` on the node's runtime and waits for its output.
+    ///
+    /// On a multi-threaded runtime the future is spawned rather than driven
+    /// with `Runtime::block_on`, so this is safe from any host thread,
+    /// including threads that are already inside a tokio runtime. A
+    /// current-thread runtime is driven by the calling thread instead, which
+    /// must not be inside a tokio runtime.
+    fn run<T, F>(&self, future: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let task = self.runtime.spawn(future);
         self.runtime
-            .block_on(self.handle.reserve_relay(address))
+            .block_on(task)?
+            .map_err(|err| anyhow!("node task failed: {err}"))?
+    }
+
+    /// Drives a current-thread runtime on the calling thread for `duration`;
+    /// returns at once for a multi-threaded runtime, which drives itself.
+    fn drive(&self, duration: Duration) -> Result<()> {
+        if !self.runtime.current_thread {
+            return Ok(());
+        }
+        self.runtime.block_on(tokio::time::sleep(duration))
+    }
+
+    /// Sets the swarm idle connection timeout (`None` for the libp2p default),
+    /// applied when the node is next started.
+    fn set_idle_connection_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        let mut config = lock(&self.config)?;
+        let mut updated = config.clone();
+        updated.idle_connection_timeout = timeout;
+        updated.validate()?;
+        *config = updated;
+        Ok(())
+    }
+
+    /// Loads the identity keypair from `path`, creating the file when missing,
+    /// when the node is next started.
+    fn set_keypair_file(&self, path: PathBuf) -> Result<()> {
+        let mut config = lock(&self.config)?;
+        let mut updated = config.clone();
+        updated.keypair_source = transport::KeypairSource::File(path);
+        updated.validate()?;
+        *config = updated;
+        Ok(())
+    }
+
+    /// Sets the keep-alive ping interval of relayed connections for the next
+    /// start; `None` turns the extra pings off.
+    fn set_relay_ping_interval(&self, interval: Option<Duration>) -> Result<()> {
+        let mut config = lock(&self.config)?;
+        let mut updated = config.clone();
+        updated.relay_ping_interval = interval;
+        updated.validate()?;
+        *config = updated;
+        Ok(())
+    }
+
+    /// Sets the gossip throttle for the next start; `None` turns it off.
+    fn set_gossip_throttle(&self, settings: Option<GossipThrottleSettings>) -> Result<()> {
+        let mut config = lock(&self.config)?;
+        let mut updated = config.clone();
+        updated.gossip_throttle = settings;
+        updated.validate()?;
+        *config = updated;
+        Ok(())
+    }
+
+    /// Turns the exchange of signed peer records on or off when the node is
+    /// next started.
+    fn set_signed_peer_records(&self, enabled: bool) -> Result<()> {
+        let mut config = lock(&self.config)?;
+        let mut updated = config.clone();
+        updated.signed_peer_records = enabled;
+        updated.validate()?;
+        *config = updated;
+        Ok(())
+    }
+
+    fn export_peer_record(&self, peer_id: Option<PeerId>) -> Result<Option<Vec<u8>>> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.export_peer_record(peer_id).await })
+            .context("failed to export peer record")
+    }
+
+    fn import_peer_record(&self, record: Vec<u8>) -> Result<PeerId> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.import_peer_record(record).await })
+            .context("failed to import peer record")
+    }
+
+    fn import_peer_records(&self, bytes: Vec<u8>) -> Result<Vec<PeerId>> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.import_peer_records(bytes).await })
+            .context("failed to import peer records")
+    }
+
+    /// Turns clock offset sampling and network-time replay checks on or off
+    /// when the node is next started.
+    fn set_time_sync(&self, enabled: bool) -> Result<()> {
+        let mut config = lock(&self.config)?;
+        let mut updated = config.clone();
+        updated.time_sync = enabled;
+        updated.validate()?;
+        *config = updated;
+        Ok(())
+    }
+
+    /// Returns the median offset of peers' clocks to the local one, in
+    /// milliseconds, once enough peers answered.
+    fn network_time_offset(&self) -> Result<Option<i64>> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.network_time_offset().await })
+            .context("failed to query network time offset")
+    }
+
+    /// Returns the identity keypair of the running node, protobuf-encoded.
+    fn export_keypair(&self) -> Result<Vec<u8>> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.export_keypair().await })
+            .context("failed to export keypair")
+    }
+
+    /// Exempts (or stops exempting) `peer_id` from the idle connection
+    /// timeout, on the running node and across restarts.
+    fn set_peer_pinned(&self, peer_id: PeerId, pinned: bool) -> Result<()> {
+        {
+            let mut config = lock(&self.config)?;
+            config
+                .pinned_peers
+                .retain(|pinned_peer| *pinned_peer != peer_id);
+            if pinned {
+                config.pinned_peers.push(peer_id);
+            }
+        }
+        if !self.is_running() {
+            return Ok(());
+        }
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.set_peer_pinned(peer_id, pinned).await })
+            .context("failed to update peer pin")
+    }
+
+    /// Applies a blocklist update on the running node, or to the stored list
+    /// of a stopped one, and persists it to the blocklist file, if any.
+    fn import_blocklist(&self, update: transport::BlocklistUpdate) -> Result<()> {
+        if self.is_running() {
+            let handle = self.peer_handle()?;
+            let blocklist = self
+                .run(async move {
+                    handle.import_blocklist(update).await?;
+                    handle.blocklist().await
+                })
+                .context("failed to import blocklist")?;
+            lock(&self.config)?.blocklist = blocklist;
+            return Ok(());
+        }
+        let mut config = lock(&self.config)?;
+        let mut blocklist = config.stored_blocklist()?;
+        blocklist.apply(update);
+        blocklist.prune_expired(transport::unix_time_secs());
+        if let Some(path) = &config.blocklist_file {
+            blocklist.save(path)?;
+        }
+        config.blocklist = blocklist;
+        Ok(())
+    }
+
+    /// Returns the blocklist of the running node, or the stored one of a
+    /// stopped node.
+    fn blocklist(&self) -> Result<transport::Blocklist> {
+        if self.is_running() {
+            let handle = self.peer_handle()?;
+            return self.run(async move { handle.blocklist().await });
+        }
+        lock(&self.config)?.stored_blocklist()
+    }
+
+    /// Switches the power profile, on the running node and across restarts.
+    fn set_power_profile(&self, profile: transport::PowerProfile) -> Result<()> {
+        lock(&self.config)?.power_profile = profile;
+        if !self.is_running() {
+            return Ok(());
+        }
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.set_power_profile(profile).await })
+            .context("failed to switch power profile")
+    }
+
+    fn is_running(&self) -> bool {
+        lock(&self.running)
+            .map(|running| running.is_some())
+            .unwrap_or(false)
+    }
+
+    /// Sets the retry policy of later dials, publishes and DHT queries.
+    fn set_retry_policy(&self, retry: RetryPolicy) -> Result<()> {
+        retry.validate()?;
+        *lock(&self.retry)? = retry;
+        Ok(())
+    }
+
+    /// Returns a handle to the running peer manager.
+    fn peer_handle(&self) -> Result<peer::PeerManagerHandle> {
+        let retry = *lock(&self.retry)?;
+        lock(&self.running)?
+            .as_ref()
+            .ok_or_else(|| anyhow!("node is not running"))?
+            .handle
+            .with_retry(retry)
+    }
+
+    fn reserve_relay(&self, address: Multiaddr) -> Result<()> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.reserve_relay(address).await.map(drop) })
             .context("failed to reserver relay")
     }
 
     /// Requests to start listening operation on provided address
     fn start_listening(&self, address: Multiaddr) -> Result<()> {
-        self.runtime
-            .block_on(self.handle.start_listening(address))
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.start_listening(address).await.map(drop) })
             .context("failed to start listening")
     }
 
     /// Requests to dial peer with provided address
     fn dial(&self, address: Multiaddr) -> Result<()> {
-        self.runtime
-            .block_on(self.handle.dial(address))
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.dial(address).await.map(drop) })
             .context("failed to dial remote")
     }
 
-    /// Publishes a binary payload to connected peers via gossipsub.
-    fn publish_message(&self, payload: Vec<u8>) -> Result<()> {
-        self.runtime
-            .block_on(self.handle.publish(payload))
+    /// Requests to dial `address`; the outcome is reported as a dial finished
+    /// node event carrying `request_id`.
+    fn dial_with_request_id(&self, address: Multiaddr, request_id: u64) -> Result<()> {
+        let handle = self.peer_handle()?;
+        self.run(async move {
+            handle
+                .dial_with_request_id(address, request_id)
+                .await
+                .map(drop)
+        })
+        .context("failed to dial remote")
+    }
+
+    /// Dials `address` over `transport` only and waits for the outcome.
+    fn dial_with_transport(
+        &self,
+        address: Multiaddr,
+        transport: peer::DialTransport,
+    ) -> Result<peer::ConnectOutcome> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.dial_with_transport(address, transport).await })
+            .context("failed to dial remote")
+    }
+
+    /// Connects to `peer_id` if needed and measures its latency with a burst
+    /// of `count` pings.
+    fn probe_peer(&self, peer_id: PeerId, count: u32) -> Result<peer::PeerProbeReport> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.probe_peer(peer_id, count).await })
+            .context("failed to probe peer")
+    }
+
+    /// Queues a binary payload for publishing via gossipsub without waiting
+    /// for it to leave the node.
+    fn enqueue_message(&self, payload: Vec<u8>) -> Result<()> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.enqueue(payload).await })
+            .context("failed to enqueue message")
+    }
+
+    /// Publishes a binary payload to connected peers via gossipsub and waits
+    /// for its delivery estimate.
+    fn publish_message(&self, payload: Vec<u8>) -> Result<PublishReceipt> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.publish(payload).await })
+            .context("failed to publish message")
+    }
+
+    /// Publishes a binary payload carrying `trace_id` in its envelope and
+    /// waits for its delivery estimate.
+    fn publish_traced(
+        &self,
+        payload: Vec<u8>,
+        trace_id: Option<messaging::TraceId>,
+    ) -> Result<PublishReceipt> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.publish_with_trace_id(payload, trace_id).await })
+            .context("failed to publish message")
+    }
+
+    /// Publishes a binary payload to `topic` and waits for its delivery estimate.
+    fn publish_to(&self, topic: String, payload: Vec<u8>) -> Result<PublishReceipt> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.publish_to(&topic, payload).await })
             .context("failed to publish message")
     }
 
+    /// Subscribes to `topic`; `false` when already subscribed.
+    fn subscribe(&self, topic: String) -> Result<bool> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.subscribe(&topic).await })
+            .context("failed to subscribe")
+    }
+
+    /// Unsubscribes from `topic`; `false` when not subscribed.
+    fn unsubscribe(&self, topic: String) -> Result<bool> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.unsubscribe(&topic).await })
+            .context("failed to unsubscribe")
+    }
+
+    /// Returns the configured default topic.
+    fn default_topic(&self) -> Result<String> {
+        Ok(lock(&self.config)?.gossipsub_topic.clone())
+    }
+
+    /// Schedules a publish to `topic` (the default topic when `None`) after
+    /// `delay` and returns its id.
+    fn publish_after(
+        &self,
+        topic: Option<String>,
+        payload: Vec<u8>,
+        delay: Duration,
+    ) -> Result<u64> {
+        let topic = topic.map_or_else(|| self.default_topic(), Ok)?;
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.publish_after(&topic, payload, delay).await })
+            .context("failed to schedule publish")
+    }
+
+    /// Schedules a publish to `topic` (the default topic when `None`) at the
+    /// wall-clock time `when` and returns its id.
+    fn publish_at(&self, topic: Option<String>, payload: Vec<u8>, when: SystemTime) -> Result<u64> {
+        let topic = topic.map_or_else(|| self.default_topic(), Ok)?;
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.publish_at(&topic, payload, when).await })
+            .context("failed to schedule publish")
+    }
+
+    /// Cancels a scheduled publish; `false` when it was not pending.
+    fn cancel_scheduled_publish(&self, id: u64) -> Result<bool> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.cancel_scheduled_publish(id).await })
+            .context("failed to cancel scheduled publish")
+    }
+
+    /// Passes a connectivity change reported by the host to the peer manager.
+    fn network_changed(&self, change: peer::NetworkChange) -> Result<()> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.network_changed(change).await })
+            .context("failed to handle network change")
+    }
+
+    /// Joins `group`, creating it when nobody is in it yet.
+    fn join_group(&self, group: String) -> Result<()> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.join_group(&group).await })
+            .context("failed to join group")
+    }
+
+    /// Leaves a joined group.
+    fn leave_group(&self, group: String) -> Result<()> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.leave_group(&group).await })
+            .context("failed to leave group")
+    }
+
+    /// Makes `topics` the exact set of subscribed topics in one step.
+    fn set_subscriptions(&self, topics: Vec<String>) -> Result<peer::SubscriptionChange> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.set_subscriptions(topics).await })
+            .context("failed to set subscriptions")
+    }
+
+    /// Publishes a payload to the members of a joined group.
+    fn publish_to_group(&self, group: String, payload: Vec<u8>) -> Result<()> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.publish_to_group(&group, payload).await })
+            .context("failed to publish to group")
+    }
+
     /// Initiates a Kademlia find_peer query and returns the request identifier.
     fn find_peer(&self, peer_id: PeerId) -> Result<u64> {
+        let handle = self.peer_handle()?;
         let request_id = self.next_discovery_request_id();
-        self.runtime
-            .block_on(self.handle.find_peer(peer_id, request_id))
+        self.run(async move { handle.find_peer(peer_id, request_id).await })
             .context("failed to start find_peer query")
             .map(|_| request_id)
     }
 
+    /// Initiates a Kademlia provider lookup and returns the request identifier.
+    fn get_providers(&self, key: Vec<u8>) -> Result<u64> {
+        let handle = self.peer_handle()?;
+        let request_id = self.next_discovery_request_id();
+        self.run(async move { handle.get_providers(key, request_id).await })
+            .context("failed to start get_providers query")
+            .map(|_| request_id)
+    }
+
+    /// Initiates a Kademlia provider lookup bounded by `limits` and returns
+    /// the request identifier.
+    fn get_providers_with_limits(&self, key: Vec<u8>, limits: peer::QueryLimits) -> Result<u64> {
+        let handle = self.peer_handle()?;
+        let request_id = self.next_discovery_request_id();
+        self.run(async move {
+            handle
+                .get_providers_with_limits(key, request_id, limits)
+                .await
+        })
+        .context("failed to start get_providers query")
+        .map(|_| request_id)
+    }
+
+    /// Stores a record on the DHT and returns the request identifier.
+    fn put_record(&self, key: Vec<u8>, value: Vec<u8>, quorum: kad::Quorum) -> Result<u64> {
+        let handle = self.peer_handle()?;
+        let request_id = self.next_discovery_request_id();
+        self.run(async move { handle.put_record(key, value, quorum, request_id).await })
+            .context("failed to start put_record query")
+            .map(|_| request_id)
+    }
+
+    /// Starts providing `key` and returns the request identifier.
+    fn start_providing(&self, key: Vec<u8>) -> Result<u64> {
+        let handle = self.peer_handle()?;
+        let request_id = self.next_discovery_request_id();
+        self.run(async move { handle.start_providing(key, request_id).await })
+            .context("failed to start start_providing query")
+            .map(|_| request_id)
+    }
+
+    /// Stops providing `key`; answers whether it was provided.
+    fn stop_providing(&self, key: Vec<u8>) -> Result<bool> {
+        let handle = self.peer_handle()?;
+        self.run(async move { handle.stop_providing(key).await })
+            .context("failed to stop providing")
+    }
+
+    /// Initiates a Kademlia record lookup and returns the request identifier.
+    fn get_record(&self, key: Vec<u8>) -> Result<u64> {
+        let handle = self.peer_handle()?;
+        let request_id = self.next_discovery_request_id();
+        self.run(async move { handle.get_record(key, request_id).await })
+            .context("failed to start get_record query")
+            .map(|_| request_id)
+    }
+
     /// Initiates a Kademlia get_closest_peers query and returns the request identifier.
     fn get_closest_peers(&self, peer_id: PeerId) -> Result<u64> {
+        let handle = self.peer_handle()?;
         let request_id = self.next_discovery_request_id();
-        self.runtime
-            .block_on(self.handle.get_closest_peers(peer_id, request_id))
+        self.run(async move { handle.get_closest_peers(peer_id, request_id).await })
             .context("failed to start get_closest_peers query")
             .map(|_| request_id)
     }
 
-    /// Attempts to dequeue the next discovery event without blocking.
-    fn try_dequeue_discovery(&mut self) -> Option<peer::DiscoveryEvent> {
-        self.discovery_queue.try_dequeue()
+    /// Attempts to dequeue the next discovery event without blocking. The
+    /// value of a record event is kept until taken with
+    /// [`cabi_node_take_record_value`].
+    fn try_dequeue_discovery(&self) -> Option<peer::DiscoveryEvent> {
+        let event = lock(&self.discovery_queue).ok()?.try_dequeue()?;
+        if let peer::DiscoveryEvent::Record {
+            request_id, value, ..
+        } = &event
+        {
+            if let Ok(mut values) = lock(&self.record_values) {
+                if values.len() >= CABI_MAX_PENDING_RECORD_VALUES {
+                    values.pop_front();
+                }
+                values.push_back((*request_id, value.clone()));
+            }
+        }
+        Some(event)
     }
 
     /// Attempts to pull a message from the internal queue without blocking.
-    fn try_dequeue_message(&mut self) -> Option<Vec<u8>> {
-        self.message_queue.try_dequeue()
+    fn try_dequeue_message(&self) -> Option<messaging::RoutedMessage> {
+        lock(&self.message_queue).ok()?.try_dequeue()
     }
 
-    /// Returns the local peer identifier.
-    fn local_peer_id(&self) -> PeerId {
-        self.handle.local_peer_id()
+    /// Registers a consumer receiving the messages of the topics matching
+    /// `pattern` instead of the default message queue.
+    fn register_topic_consumer(
+        &self,
+        pattern: messaging::TopicPattern,
+        capacity: usize,
+    ) -> Result<u64> {
+        let consumer = self
+            .topic_router
+            .register(vec![pattern], messaging::BusMode::bounded(capacity))?;
+        let id = consumer.id();
+        lock(&self.topic_consumers)?.insert(id, consumer);
+        Ok(id)
+    }
+
+    /// Attempts to pull a message of consumer `id` without blocking; `None`
+    /// when the consumer is unknown.
+    fn try_dequeue_topic_message(&self, id: u64) -> Option<Option<messaging::RoutedMessage>> {
+        let consumers = lock(&self.topic_consumers).ok()?;
+        let consumer = consumers.get(&id)?;
+        Some(consumer.try_recv())
+    }
+
+    /// Returns the queue counters of consumer `id`.
+    fn topic_consumer_stats(&self, id: u64) -> Option<messaging::BusStats> {
+        lock(&self.topic_consumers)
+            .ok()?
+            .get(&id)
+            .map(messaging::TopicConsumer::stats)
+    }
+
+    /// Unregisters consumer `id`; its queued messages are discarded.
+    fn unregister_topic_consumer(&self, id: u64) -> Result<bool> {
+        let removed = lock(&self.topic_consumers)?.remove(&id).is_some();
+        self.topic_router.unregister(id)?;
+        Ok(removed)
     }
 
-    /// Requsets to gracefully shutdown peer manager and joins the background tasks
-    fn shutdown(&mut self) {
-        if let Err(err) = self.runtime.block_on(self.handle.shutdown()) {
-            tracing::warn!(target: "ffi", %err, "node shutdown request failed");
+    /// Attempts to take the next event of the unified node event stream
+    /// without blocking. While stopped only queued discovery results and
+    /// messages are returned.
+    fn try_next_event(&self) -> Option<peer::NodeEvent> {
+        if let Some(event) = lock(&self.pending_event).ok()?.take() {
+            return Some(event);
         }
 
-        if let Some(worker) = self.worker.take() {
-            let _ = self.runtime.block_on(async {
-                if let Err(err) = worker.await {
-                    tracing::warn!(target: "ffi", %err, "peer manager task join failed");
-                }
-            });
+        let mut discovery = lock(&self.discovery_queue).ok()?;
+        let mut messages = lock(&self.message_queue).ok()?;
+        match lock(&self.running).ok()?.as_mut() {
+            Some(running) => running.events.try_next(&mut discovery, &mut messages),
+            None => peer::try_next_queued(&mut discovery, &mut messages),
+        }
+    }
+
+    /// Keeps an event that did not fit the caller's buffer for the next poll.
+    fn defer_event(&self, event: peer::NodeEvent) {
+        match lock(&self.pending_event) {
+            Ok(mut pending) => *pending = Some(event),
+            Err(err) => tracing::warn!(target: "ffi", %err, "node event dropped"),
         }
     }
 
+    /// Returns the local peer identifier.
+    fn local_peer_id(&self) -> Result<PeerId> {
+        Ok(self.peer_handle()?.local_peer_id())
+    }
+
+    /// Returns the open connections to `peer_id` and the stack each one
+    /// negotiated.
+    fn peer_connections(&self, peer_id: &PeerId) -> Result<Vec<peer::PeerConnection>> {
+        Ok(self.peer_handle()?.peer_connections(peer_id))
+    }
+
+    /// Returns whether the publish warm-up has finished and the topic is ready.
+    fn topic_ready(&self) -> Result<bool> {
+        let topic_ready = *self.peer_handle()?.topic_ready().borrow();
+        Ok(topic_ready)
+    }
+
+    /// Waits up to `timeout` for the default topic mesh to reach `min_peers`.
+    /// Returns `false` on timeout.
+    fn wait_mesh_ready(&self, min_peers: usize, timeout: Duration) -> Result<bool> {
+        let topic = self.default_topic()?;
+        let handle = self.peer_handle()?;
+        self.run(async move {
+            let wait = handle.wait_mesh_ready(&topic, min_peers);
+            match tokio::time::timeout(timeout, wait).await {
+                Ok(result) => result.map(|_| true),
+                Err(_) => Ok(false),
+            }
+        })
+    }
+
+    /// Returns the latest AutoNAT status, `Unknown` while stopped.
     fn autonat_status(&self) -> autonat::NatStatus {
-        self.autonat_status.borrow().clone()
+        lock(&self.running)
+            .ok()
+            .and_then(|running| {
+                running
+                    .as_ref()
+                    .map(|running| running.autonat_status.borrow().clone())
+            })
+            .unwrap_or(autonat::NatStatus::Unknown)
     }
 
     fn next_discovery_request_id(&self) -> u64 {
@@ -199,50 +1005,58 @@ impl ManagedNode {
 
 impl Drop for ManagedNode {
     fn drop(&mut self) {
-        self.shutdown();
+        self.stop();
     }
 }
 
-#[no_mangle]
-/// C-ABI. Inits tracing for the library in order to give more proper info on networking
-pub extern "C" fn cabi_init_tracing() -> c_int {
-    match config::init_tracing() {
-        Ok(_) => CABI_STATUS_SUCCESS,
-        Err(err) => {
-            eprintln!("fidonext: failed to init tracing: {err:?}");
-            CABI_STATUS_INTERNAL_ERROR
-        }
-    }
+/// Registry of live nodes keyed by the opaque handle value given to the host.
+///
+/// Calls clone the `Arc` for their duration, so destroying a handle while
+/// another thread is still using it never frees the node under that call.
+static NODES: Lazy<Mutex<HashMap<usize, Arc<ManagedNode>>>> = Lazy::new(Default::default);
+/// Source of handle values; never reused, so stale handles are detected.
+static NEXT_NODE_HANDLE: AtomicUsize = AtomicUsize::new(1);
+/// Worker threads of the runtimes of nodes created from now on; zero starts
+/// one per core.
+static WORKER_THREADS: AtomicUsize = AtomicUsize::new(0);
+/// Whether nodes created from now on run on a single thread.
+static CURRENT_THREAD_RUNTIME: AtomicBool = AtomicBool::new(false);
+
+/// Locks a mutex, mapping poisoning to an error.
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex.lock().map_err(|_| anyhow!("node lock poisoned"))
 }
 
-#[no_mangle]
-/// C-ABI. Returns the latest AutoNAT status observed for the node.
-/// Use it to detect the node is public or not, which can be a signal to recreate
-/// node as relay also
-pub extern "C" fn cabi_autonat_status(handle: *mut CabiNodeHandle) -> c_int {
-    let node = match node_from_ptr(handle) {
-        Ok(node) => node,
-        Err(status) => return status,
-    };
+/// Extracts the 32-byte seed of an Ed25519 identity.
+fn ed25519_seed(keypair: &identity::Keypair) -> Option<[u8; 32]> {
+    let keypair = keypair.clone().try_into_ed25519().ok()?;
+    keypair.secret().as_ref().try_into().ok()
+}
 
-    match node.autonat_status() {
-        autonat::NatStatus::Unknown => CABI_AUTONAT_UNKNOWN,
-        autonat::NatStatus::Private => CABI_AUTONAT_PRIVATE,
-        autonat::NatStatus::Public(_) => CABI_AUTONAT_PUBLIC,
+/// Registers a node and returns its opaque handle.
+fn register_node(node: ManagedNode) -> *mut CabiNodeHandle {
+    let id = NEXT_NODE_HANDLE.fetch_add(1, Ordering::Relaxed);
+    match NODES.lock() {
+        Ok(mut nodes) => {
+            nodes.insert(id, Arc::new(node));
+            id as *mut CabiNodeHandle
+        }
+        Err(_) => {
+            tracing::error!(target: "ffi", "node registry lock poisoned");
+            ptr::null_mut()
+        }
     }
 }
 
-#[no_mangle]
-/// C-ABI. Creates a new node instance and returns its handle with optional relay hop mode, bootstrap peers,
-/// and a fixed Ed25519 identity seed.
-pub extern "C" fn cabi_node_new(
+/// Builds a node from the raw `cabi_node_create`/`cabi_node_new` arguments.
+fn create_node(
     use_quic: bool,
     enable_relay_hop: bool,
     bootstrap_peers: *const *const c_char,
     bootstrap_peers_len: usize,
     identity_seed_ptr: *const u8,
     identity_seed_len: usize,
-) -> *mut CabiNodeHandle {
+) -> Option<ManagedNode> {
     let bootstrap_peers = match parse_bootstrap_peers(bootstrap_peers, bootstrap_peers_len) {
         Ok(peers) => peers,
         Err(status) => {
@@ -251,7 +1065,7 @@ pub extern "C" fn cabi_node_new(
                 status,
                 "failed to parse bootstrap peers; node creation aborted"
             );
-            return ptr::null_mut();
+            return None;
         }
     };
 
@@ -263,219 +1077,2398 @@ pub extern "C" fn cabi_node_new(
                 status,
                 "invalid identity seed provided; node creation aborted"
             );
-            return ptr::null_mut();
+            return None;
         }
     };
 
+    let worker_threads = WORKER_THREADS.load(Ordering::Relaxed);
     let config = transport::TransportConfig {
         use_quic,
         hop_relay: enable_relay_hop,
         identity_seed,
+        executor: transport::ExecutorSettings {
+            current_thread: CURRENT_THREAD_RUNTIME.load(Ordering::Relaxed),
+            worker_threads: (worker_threads > 0).then_some(worker_threads),
+            ..Default::default()
+        },
         ..Default::default()
     };
 
     match ManagedNode::new(config, bootstrap_peers) {
-        Ok(node) => {
-            let boxed = Box::new(node);
-            Box::into_raw(boxed) as *mut CabiNodeHandle
-        }
+        Ok(node) => Some(node),
         Err(err) => {
             tracing::error!(target: "ffi", %err, "failed to create node");
-            ptr::null_mut()
+            None
         }
     }
 }
 
 #[no_mangle]
-/// C-ABI. Writes the local PeerId into the provided buffer as a UTF-8 string.
-pub extern "C" fn cabi_node_local_peer_id(
-    handle: *mut CabiNodeHandle,
-    out_buffer: *mut c_char,
-    buffer_len: usize,
-    written_len: *mut usize,
-) -> c_int {
-    let node = match node_from_ptr(handle) {
-        Ok(node) => node,
-        Err(status) => return status,
-    };
-
-    let peer_id = node.local_peer_id().to_string();
+/// C-ABI. Inits tracing for the library in order to give more proper info on networking
+pub extern "C" fn cabi_init_tracing() -> c_int {
+    match config::init_tracing() {
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            eprintln!("fidonext: failed to init tracing: {err:?}");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Inits tracing like [`cabi_init_tracing`] but without the formatted
+/// stdout output, so logs only reach the callback set with
+/// [`cabi_set_log_callback`].
+pub extern "C" fn cabi_init_tracing_hook_only() -> c_int {
+    match config::init_tracing_with_output(config::TracingOutput::HookOnly) {
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            eprintln!("fidonext: failed to init tracing: {err:?}");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Routes the log events passing the log filter to `callback`, called
+/// with `user_data`, or stops routing them when `callback` is null.
+/// `user_data` must stay valid until the callback is replaced. Takes effect
+/// once tracing is initialized.
+pub extern "C" fn cabi_set_log_callback(
+    callback: Option<CabiLogCallback>,
+    user_data: *mut c_void,
+) -> c_int {
+    let Some(callback) = callback else {
+        config::set_log_hook(None);
+        return CABI_STATUS_SUCCESS;
+    };
+
+    let user_data = LogUserData(user_data);
+    config::set_log_hook(Some(Arc::new(move |record: &config::LogRecord<'_>| {
+        let level = if record.level == tracing::Level::ERROR {
+            CABI_LOG_LEVEL_ERROR
+        } else if record.level == tracing::Level::WARN {
+            CABI_LOG_LEVEL_WARN
+        } else if record.level == tracing::Level::INFO {
+            CABI_LOG_LEVEL_INFO
+        } else if record.level == tracing::Level::DEBUG {
+            CABI_LOG_LEVEL_DEBUG
+        } else {
+            CABI_LOG_LEVEL_TRACE
+        };
+        let mut message = record.message.clone();
+        for (name, value) in &record.fields {
+            message.push_str(&format!(" {name}={value}"));
+        }
+        let target = c_string_lossy(record.target);
+        let message = c_string_lossy(&message);
+        callback(level, target.as_ptr(), message.as_ptr(), user_data.get());
+    })));
+    CABI_STATUS_SUCCESS
+}
+
+#[no_mangle]
+/// C-ABI. Prepends the null-terminated `prefix` to the targets passed to the
+/// log callback (e.g. `pheonx/` for logcat tags), or removes it when null.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_set_log_target_prefix(prefix: *const c_char) -> c_int {
+    if prefix.is_null() {
+        config::set_log_target_prefix(None);
+        return CABI_STATUS_SUCCESS;
+    }
+
+    let c_str = unsafe { CStr::from_ptr(prefix) };
+    match c_str.to_str() {
+        Ok(prefix) => {
+            config::set_log_target_prefix(Some(prefix));
+            CABI_STATUS_SUCCESS
+        }
+        Err(_) => CABI_STATUS_INVALID_ARGUMENT,
+    }
+}
+
+/// Host pointer handed back to the [`CabiLogCallback`].
+struct LogUserData(*mut c_void);
+
+// The host owns the pointer and promised it stays valid until the callback is
+// replaced; the library never dereferences it.
+unsafe impl Send for LogUserData {}
+unsafe impl Sync for LogUserData {}
+
+impl LogUserData {
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+fn c_string_lossy(value: &str) -> CString {
+    CString::new(value.replace('\0', "")).unwrap_or_default()
+}
+
+#[no_mangle]
+/// C-ABI. Sets the worker threads of the runtime each node created from now
+/// on runs on, e.g. to pin nodes to few cores on shared hosts. Zero starts
+/// one thread per core, the default. Nodes already created keep theirs.
+pub extern "C" fn cabi_set_worker_threads(threads: usize) -> c_int {
+    WORKER_THREADS.store(threads, Ordering::Relaxed);
+    CABI_STATUS_SUCCESS
+}
+
+#[no_mangle]
+/// C-ABI. Runs each node created from now on on a current-thread runtime
+/// that starts no threads, instead of a worker pool; for hosts with strict
+/// threading rules (iOS, Android, plugins). Such a node only makes progress
+/// while a host thread is inside a `cabi_node_*` call, so the host drives it
+/// from its own loop with [`cabi_node_drive`]. Verifier and validator hooks
+/// are still called on whichever host thread drives the node, hence still
+/// need to be thread-safe. Node creation fails while worker threads are also
+/// set with [`cabi_set_worker_threads`].
+pub extern "C" fn cabi_set_current_thread_runtime(enable: bool) -> c_int {
+    CURRENT_THREAD_RUNTIME.store(enable, Ordering::Relaxed);
+    CABI_STATUS_SUCCESS
+}
+
+#[no_mangle]
+/// C-ABI. Returns the latest AutoNAT status observed for the node.
+/// Use it to detect the node is public or not, which can be a signal to recreate
+/// node as relay also
+pub extern "C" fn cabi_autonat_status(handle: *mut CabiNodeHandle) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    match node.autonat_status() {
+        autonat::NatStatus::Unknown => CABI_AUTONAT_UNKNOWN,
+        autonat::NatStatus::Private => CABI_AUTONAT_PRIVATE,
+        autonat::NatStatus::Public(_) => CABI_AUTONAT_PUBLIC,
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Reports whether the gossipsub topic is ready for publishing, i.e.
+/// the startup warm-up has finished. Publishes issued earlier are buffered.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_topic_ready(
+    handle: *mut CabiNodeHandle,
+    out_ready: *mut bool,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if out_ready.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+
+    match node.topic_ready() {
+        Ok(ready) => {
+            unsafe {
+                *out_ready = ready;
+            }
+            CABI_STATUS_SUCCESS
+        }
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "topic_ready failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Blocks until the default gossipsub topic has at least `min_peers`
+/// mesh peers, or `timeout_ms` elapses ([`CABI_STATUS_TIMEOUT`]).
+pub extern "C" fn cabi_node_wait_mesh_ready(
+    handle: *mut CabiNodeHandle,
+    min_peers: usize,
+    timeout_ms: u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    match node.wait_mesh_ready(min_peers, Duration::from_millis(timeout_ms)) {
+        Ok(true) => CABI_STATUS_SUCCESS,
+        Ok(false) => CABI_STATUS_TIMEOUT,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "wait_mesh_ready failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Creates a new node instance and returns its handle with optional relay hop mode, bootstrap peers,
+/// and a fixed Ed25519 identity seed. The node is started right away; this is
+/// [`cabi_node_create`] followed by [`cabi_node_start`].
+pub extern "C" fn cabi_node_new(
+    use_quic: bool,
+    enable_relay_hop: bool,
+    bootstrap_peers: *const *const c_char,
+    bootstrap_peers_len: usize,
+    identity_seed_ptr: *const u8,
+    identity_seed_len: usize,
+) -> *mut CabiNodeHandle {
+    let Some(node) = create_node(
+        use_quic,
+        enable_relay_hop,
+        bootstrap_peers,
+        bootstrap_peers_len,
+        identity_seed_ptr,
+        identity_seed_len,
+    ) else {
+        return ptr::null_mut();
+    };
+
+    if let Err(err) = node.start() {
+        tracing::error!(target: "ffi", %err, "failed to start node");
+        return ptr::null_mut();
+    }
+
+    register_node(node)
+}
+
+#[no_mangle]
+/// C-ABI. Creates a stopped node and returns its handle. Arguments match
+/// [`cabi_node_new`]. Call [`cabi_node_start`] before issuing commands and
+/// [`cabi_node_destroy`] to release it.
+pub extern "C" fn cabi_node_create(
+    use_quic: bool,
+    enable_relay_hop: bool,
+    bootstrap_peers: *const *const c_char,
+    bootstrap_peers_len: usize,
+    identity_seed_ptr: *const u8,
+    identity_seed_len: usize,
+) -> *mut CabiNodeHandle {
+    match create_node(
+        use_quic,
+        enable_relay_hop,
+        bootstrap_peers,
+        bootstrap_peers_len,
+        identity_seed_ptr,
+        identity_seed_len,
+    ) {
+        Some(node) => register_node(node),
+        None => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Starts the node's swarm and peer manager. Starting a running node is a no-op.
+/// A restarted node keeps the identity it used on its first start.
+pub extern "C" fn cabi_node_start(handle: *mut CabiNodeHandle) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    match node.start() {
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to start node");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Sets how long connections without open streams stay open, in
+/// milliseconds (0 restores the libp2p default). Takes effect on the next
+/// [`cabi_node_start`], so call it before starting or restart the node.
+pub extern "C" fn cabi_node_set_idle_connection_timeout(
+    handle: *mut CabiNodeHandle,
+    timeout_ms: u64,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let timeout = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms));
+    match node.set_idle_connection_timeout(timeout) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to set idle connection timeout");
+            CABI_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Keeps the node's identity in the keypair file at `path`: it is read
+/// on start, or created with a fresh Ed25519 keypair when missing, so the
+/// `PeerId` survives restarts of the process. Takes effect on the next
+/// [`cabi_node_start`], so call it before starting or restart the node.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_set_keypair_file(
+    handle: *mut CabiNodeHandle,
+    path: *const c_char,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if path.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    let c_str = unsafe { CStr::from_ptr(path) };
+    let path = match c_str.to_str() {
+        Ok(value) if !value.is_empty() => PathBuf::from(value),
+        _ => return CABI_STATUS_INVALID_ARGUMENT,
+    };
+
+    match node.set_keypair_file(path) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to set keypair file");
+            CABI_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Copies the running node's identity keypair, protobuf-encoded, into
+/// the provided buffer, e.g. to back it up. The bytes hold the secret key.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_export_keypair(
+    handle: *mut CabiNodeHandle,
+    out_buffer: *mut u8,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if out_buffer.is_null() || written_len.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+
+    let encoded = match node.export_keypair() {
+        Ok(encoded) => encoded,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to export keypair");
+            return CABI_STATUS_INTERNAL_ERROR;
+        }
+    };
+    unsafe {
+        *written_len = encoded.len();
+    }
+    if encoded.len() > buffer_len {
+        return CABI_STATUS_BUFFER_TOO_SMALL;
+    }
+    unsafe {
+        ptr::copy_nonoverlapping(encoded.as_ptr(), out_buffer, encoded.len());
+    }
+    CABI_STATUS_SUCCESS
+}
+
+#[no_mangle]
+/// C-ABI. Pings relayed connections every `interval_ms` so the NAT bindings
+/// on the way to the relay survive idle periods; 0, the default, leaves them
+/// to the regular pings. Takes effect on the next [`cabi_node_start`], so
+/// call it before starting or restart the node.
+pub extern "C" fn cabi_node_set_relay_ping_interval(
+    handle: *mut CabiNodeHandle,
+    interval_ms: u64,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let interval = (interval_ms > 0).then(|| Duration::from_millis(interval_ms));
+    match node.set_relay_ping_interval(interval) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to set relay ping interval");
+            CABI_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Lowers the gossip score of peers relaying more than `max_duplicates`
+/// duplicate or `max_invalid` invalid messages within `window_ms`, pruning
+/// them from the mesh until a whole window passes without offences; a
+/// `window_ms` of 0 turns throttling off. Duplicates are detected by replay
+/// protection, so starting a node with throttling but without a replay
+/// window fails. Takes effect on the next [`cabi_node_start`], so call it
+/// before starting or restart the node.
+pub extern "C" fn cabi_node_set_gossip_throttle(
+    handle: *mut CabiNodeHandle,
+    window_ms: u64,
+    max_duplicates: u32,
+    max_invalid: u32,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let settings = (window_ms > 0).then(|| GossipThrottleSettings {
+        window: Duration::from_millis(window_ms),
+        max_duplicates,
+        max_invalid,
+        ..GossipThrottleSettings::default()
+    });
+    match node.set_gossip_throttle(settings) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to set gossip throttle");
+            CABI_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Exchanges signed peer records via identify when `enabled`, and
+/// reports a [`CABI_DISCOVERY_EVENT_PEER_RECORD`] event before a find_peer
+/// finishes when the record of its target is known. Takes effect on the next
+/// [`cabi_node_start`], so call it before starting or restart the node.
+pub extern "C" fn cabi_node_set_signed_peer_records(
+    handle: *mut CabiNodeHandle,
+    enabled: bool,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    match node.set_signed_peer_records(enabled) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to set signed peer records");
+            CABI_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Copies the signed peer record of `peer_id` (protobuf signed
+/// envelope) into the provided buffer, e.g. to share it as a QR code. A null
+/// `peer_id` exports the record of the running node itself. Returns
+/// [`CABI_STATUS_NOT_FOUND`] when no record of the peer is known.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_export_peer_record(
+    handle: *mut CabiNodeHandle,
+    peer_id: *const c_char,
+    out_buffer: *mut u8,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if out_buffer.is_null() || written_len.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    let peer_id = if peer_id.is_null() {
+        None
+    } else {
+        match parse_peer_id(peer_id) {
+            Ok(peer_id) => Some(peer_id),
+            Err(status) => return status,
+        }
+    };
+
+    let record = match node.export_peer_record(peer_id) {
+        Ok(Some(record)) => record,
+        Ok(None) => return CABI_STATUS_NOT_FOUND,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to export peer record");
+            return CABI_STATUS_INTERNAL_ERROR;
+        }
+    };
+    unsafe {
+        *written_len = record.len();
+    }
+    if record.len() > buffer_len {
+        return CABI_STATUS_BUFFER_TOO_SMALL;
+    }
+    unsafe {
+        ptr::copy_nonoverlapping(record.as_ptr(), out_buffer, record.len());
+    }
+    CABI_STATUS_SUCCESS
+}
+
+#[no_mangle]
+/// C-ABI. Verifies a signed peer record exported by
+/// [`cabi_node_export_peer_record`] and learns the addresses it lists, then
+/// writes the peer id it describes into `out_buffer`.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_import_peer_record(
+    handle: *mut CabiNodeHandle,
+    record_ptr: *const u8,
+    record_len: usize,
+    out_buffer: *mut c_char,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if record_ptr.is_null() || out_buffer.is_null() || written_len.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if record_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let record = unsafe { slice::from_raw_parts(record_ptr, record_len) }.to_vec();
+    match node.import_peer_record(record) {
+        Ok(peer_id) => write_c_string(&peer_id.to_string(), out_buffer, buffer_len, written_len),
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to import peer record");
+            CABI_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Seeds the address book and routing table from a signed peer record
+/// or a text address book (one `/p2p/`-terminated multiaddr or hex-encoded
+/// signed peer record per line), without any network discovery, and writes
+/// the number of peers learnt into `imported`. Nothing is imported when any
+/// entry is invalid.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_import_peer_records(
+    handle: *mut CabiNodeHandle,
+    data_ptr: *const u8,
+    data_len: usize,
+    imported: *mut usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if data_ptr.is_null() || imported.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if data_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let bytes = unsafe { slice::from_raw_parts(data_ptr, data_len) }.to_vec();
+    match node.import_peer_records(bytes) {
+        Ok(peers) => unsafe {
+            *imported = peers.len();
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to import peer records");
+            CABI_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Samples the clock offset of every peer on each identify exchange
+/// when `enabled`, and stamps and checks replay envelopes with the median
+/// network time instead of the local clock. Takes effect on the next
+/// [`cabi_node_start`], so call it before starting or restart the node.
+pub extern "C" fn cabi_node_set_time_sync(handle: *mut CabiNodeHandle, enabled: bool) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    match node.set_time_sync(enabled) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to set time sync");
+            CABI_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Writes the median offset of connected peers' clocks to the local
+/// clock, in milliseconds (positive when the network is ahead), into
+/// `out_offset_ms`. Returns [`CABI_STATUS_NOT_FOUND`] while time sync is off
+/// or fewer than three peers answered.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_network_time_offset(
+    handle: *mut CabiNodeHandle,
+    out_offset_ms: *mut i64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if out_offset_ms.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+
+    match node.network_time_offset() {
+        Ok(Some(offset_ms)) => {
+            unsafe {
+                *out_offset_ms = offset_ms;
+            }
+            CABI_STATUS_SUCCESS
+        }
+        Ok(None) => CABI_STATUS_NOT_FOUND,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to query network time offset");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Keeps connections to `peer_id` open while idle when `pinned`, or
+/// subjects them to the idle connection timeout again. Applies to a running
+/// node right away and is kept across restarts.
+pub extern "C" fn cabi_node_set_peer_pinned(
+    handle: *mut CabiNodeHandle,
+    peer_id: *const c_char,
+    pinned: bool,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let peer_id = match parse_peer_id(peer_id) {
+        Ok(peer_id) => peer_id,
+        Err(status) => return status,
+    };
+
+    match node.set_peer_pinned(peer_id, pinned) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to update peer pin");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Switches the node to one of the `CABI_POWER_PROFILE_*` values, e.g.
+/// when the host goes on battery or a metered network. A running node
+/// rebuilds its swarm, re-establishing connections while keeping its routing
+/// table and re-dialing pinned peers; the profile is kept across restarts.
+pub extern "C" fn cabi_node_set_power_profile(
+    handle: *mut CabiNodeHandle,
+    profile: c_int,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let profile = match profile {
+        CABI_POWER_PROFILE_NORMAL => transport::PowerProfile::Normal,
+        CABI_POWER_PROFILE_LOW => transport::PowerProfile::LowPower,
+        _ => return CABI_STATUS_INVALID_ARGUMENT,
+    };
+
+    match node.set_power_profile(profile) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to switch power profile");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Applies a blocklist update in the JSON format documented in
+/// [`transport::blocklist`], e.g. one pushed by a central policy server.
+/// Connections the update blocks are closed. Applies to a running node right
+/// away, is kept across restarts and is written to the configured blocklist
+/// file. Returns [`CABI_STATUS_INVALID_ARGUMENT`] for a malformed update.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_import_blocklist(
+    handle: *mut CabiNodeHandle,
+    json: *const c_char,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if json.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    let c_str = unsafe { CStr::from_ptr(json) };
+    let update = match c_str
+        .to_str()
+        .map_err(anyhow::Error::from)
+        .and_then(transport::BlocklistUpdate::from_json)
+    {
+        Ok(update) => update,
+        Err(err) => {
+            tracing::warn!(target: "ffi", %err, "rejected blocklist update");
+            return CABI_STATUS_INVALID_ARGUMENT;
+        }
+    };
+
+    match node.import_blocklist(update) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to import blocklist");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Writes the node's blocklist as JSON, in the format documented in
+/// [`transport::blocklist`], into the provided buffer.
+pub extern "C" fn cabi_node_export_blocklist(
+    handle: *mut CabiNodeHandle,
+    out_buffer: *mut c_char,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let blocklist = match node.blocklist() {
+        Ok(blocklist) => blocklist,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to export blocklist");
+            return CABI_STATUS_INTERNAL_ERROR;
+        }
+    };
+    write_c_string(&blocklist.to_json(), out_buffer, buffer_len, written_len)
+}
+
+#[no_mangle]
+/// C-ABI. Writes the open connections to `peer_id` into the provided buffer,
+/// one line per connection in the form `address= transport=
+/// expected_security= expected_muxer= direction=`. libp2p does not report the
+/// negotiated security protocol and muxer; each transport offers exactly one
+/// of each, which an established connection therefore runs.
+/// Writes an empty string when the peer is not connected.
+pub extern "C" fn cabi_node_peer_connections(
+    handle: *mut CabiNodeHandle,
+    peer_id: *const c_char,
+    out_buffer: *mut c_char,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let peer_id = match parse_peer_id(peer_id) {
+        Ok(peer_id) => peer_id,
+        Err(status) => return status,
+    };
+
+    let connections = match node.peer_connections(&peer_id) {
+        Ok(connections) => connections,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to list peer connections");
+            return CABI_STATUS_INTERNAL_ERROR;
+        }
+    };
+    let text: Vec<String> = connections
+        .iter()
+        .map(|connection| {
+            format!(
+                "address={} transport={} expected_security={} expected_muxer={} direction={}",
+                connection.address,
+                connection.transport,
+                connection.expected_security,
+                connection.expected_muxer,
+                if connection.outbound {
+                    "outbound"
+                } else {
+                    "inbound"
+                },
+            )
+        })
+        .collect();
+    write_c_string(&text.join("\n"), out_buffer, buffer_len, written_len)
+}
+
+#[no_mangle]
+/// C-ABI. Drives a node created with [`cabi_set_current_thread_runtime`] on
+/// the calling thread for `duration_ms`, running its connections, timers and
+/// lookups; hosts call it from their own loop. Returns at once for nodes on a
+/// multi-threaded runtime. Must not be called from inside a tokio runtime.
+pub extern "C" fn cabi_node_drive(handle: *mut CabiNodeHandle, duration_ms: u64) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    match node.drive(Duration::from_millis(duration_ms)) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to drive node runtime");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Stops the node's peer manager, closing all connections. The handle
+/// stays valid: queued events can still be dequeued and the node restarted.
+/// Stopping a stopped node is a no-op.
+pub extern "C" fn cabi_node_stop(handle: *mut CabiNodeHandle) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    node.stop();
+    CABI_STATUS_SUCCESS
+}
+
+#[no_mangle]
+/// C-ABI. Stops and releases the node. Destroying an already destroyed (or
+/// never created) handle is a no-op, so finalizers may call it repeatedly.
+/// Calls still in flight on other threads finish before the node is freed.
+pub extern "C" fn cabi_node_destroy(handle: *mut CabiNodeHandle) {
+    if handle.is_null() {
+        return;
+    }
+
+    let node = match NODES.lock() {
+        Ok(mut nodes) => nodes.remove(&(handle as usize)),
+        Err(_) => {
+            tracing::error!(target: "ffi", "node registry lock poisoned");
+            return;
+        }
+    };
+
+    // The registry lock is released before dropping: stopping the node blocks.
+    drop(node);
+}
+
+#[no_mangle]
+/// C-ABI. Retries failed dials, publishes and DHT queries up to `max_attempts`
+/// attempts in total, waiting `initial_backoff_ms` before the first retry and
+/// doubling the wait up to `max_backoff_ms`. `jitter` (0.0 to 1.0) is the
+/// randomised fraction of each wait. A `max_attempts` of 1 turns retries off,
+/// which is the default. Applies to operations issued after the call, across
+/// restarts of the node.
+pub extern "C" fn cabi_node_set_retry_policy(
+    handle: *mut CabiNodeHandle,
+    max_attempts: u32,
+    initial_backoff_ms: u64,
+    max_backoff_ms: u64,
+    jitter: f64,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let retry = RetryPolicy {
+        max_attempts,
+        initial_backoff: Duration::from_millis(initial_backoff_ms),
+        max_backoff: Duration::from_millis(max_backoff_ms),
+        jitter,
+    };
+    match node.set_retry_policy(retry) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to set retry policy");
+            CABI_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Writes the local PeerId into the provided buffer as a UTF-8 string.
+pub extern "C" fn cabi_node_local_peer_id(
+    handle: *mut CabiNodeHandle,
+    out_buffer: *mut c_char,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let peer_id = match node.local_peer_id() {
+        Ok(peer_id) => peer_id.to_string(),
+        Err(_) => return CABI_STATUS_NOT_RUNNING,
+    };
     write_c_string(&peer_id, out_buffer, buffer_len, written_len)
 }
 
 #[no_mangle]
-/// C-ABI. Requests a circuit-relay reservation on the given relay address.
-pub extern "C" fn cabi_node_reserve_relay(
+/// C-ABI. Requests a circuit-relay reservation on the given relay address.
+pub extern "C" fn cabi_node_reserve_relay(
+    handle: *mut CabiNodeHandle,
+    address: *const c_char
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let multiaddr = match parse_multiaddr(address) {
+        Ok(addr) => addr,
+        Err(status) => return status,
+    };
+
+    match node.reserve_relay(multiaddr) {
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "reserve_relay failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Inits listening on the given address
+pub extern "C" fn cabi_node_listen(handle: *mut CabiNodeHandle, address: *const c_char) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let multiaddr = match parse_multiaddr(address) {
+        Ok(addr) => addr,
+        Err(status) => return status,
+    };
+
+    match node.start_listening(multiaddr) {
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "start_listening failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Inits a dial to the outbound peer with the specified address
+pub extern "C" fn cabi_node_dial(handle: *mut CabiNodeHandle, address: *const c_char) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let multiaddr = match parse_multiaddr(address) {
+        Ok(addr) => addr,
+        Err(status) => return status,
+    };
+
+    match node.dial(multiaddr) {
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "dial failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Inits a dial to the given address without relay fallback. Its
+/// outcome is reported by [`cabi_node_next_event`] as a
+/// [`CABI_NODE_EVENT_DIAL_FINISHED`] event carrying `request_id`.
+pub extern "C" fn cabi_node_dial_with_request_id(
+    handle: *mut CabiNodeHandle,
+    address: *const c_char,
+    request_id: u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let multiaddr = match parse_multiaddr(address) {
+        Ok(addr) => addr,
+        Err(status) => return status,
+    };
+
+    match node.dial_with_request_id(multiaddr, request_id) {
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, request_id, "dial failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Dials the address over the given `CABI_TRANSPORT_*` transport only,
+/// without relay fallback, and blocks until the connection is established
+/// ([`CABI_STATUS_SUCCESS`]) or fails ([`CABI_STATUS_INTERNAL_ERROR`]).
+pub extern "C" fn cabi_node_dial_with_transport(
+    handle: *mut CabiNodeHandle,
+    address: *const c_char,
+    transport: c_int,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let multiaddr = match parse_multiaddr(address) {
+        Ok(addr) => addr,
+        Err(status) => return status,
+    };
+
+    let transport = match transport {
+        CABI_TRANSPORT_TCP => peer::DialTransport::Tcp,
+        CABI_TRANSPORT_QUIC => peer::DialTransport::Quic,
+        CABI_TRANSPORT_RELAY => peer::DialTransport::Relay,
+        _ => return CABI_STATUS_INVALID_ARGUMENT,
+    };
+    if !transport.matches(&multiaddr) {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    match node.dial_with_transport(multiaddr, transport) {
+        Ok(peer::ConnectOutcome::Failed { reason }) => {
+            tracing::warn!(target: "ffi", %reason, "dial over required transport failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+        Ok(peer::ConnectOutcome::SelfDial) => CABI_STATUS_SELF_DIAL,
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "dial failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Pre-flight check of `peer_id`, e.g. before a large transfer:
+/// connects to it when needed, refreshes identify and sends `count` pings
+/// back to back (0 for the default of 5, at most 100), blocking until they
+/// are answered or for at most [`transport::MAX_PROBE_DURATION`] (30 s) once
+/// connected; later answers count as lost. Writes the result into the
+/// provided buffer as `key=value` text: `outcome` (`direct`, `relayed`,
+/// `failed` or `self_dial`), `address`, `count`, `received`, `min_us`,
+/// `mean_us` and `max_us`, and the free-form `error` last when pings went
+/// unanswered or the peer was unreachable.
+pub extern "C" fn cabi_node_probe_peer(
+    handle: *mut CabiNodeHandle,
+    peer_id: *const c_char,
+    count: u32,
+    out_buffer: *mut c_char,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let peer_id = match parse_peer_id(peer_id) {
+        Ok(peer_id) => peer_id,
+        Err(status) => return status,
+    };
+    let count = match count {
+        0 => transport::DEFAULT_PROBE_PINGS,
+        count if count > transport::MAX_PROBE_PINGS => return CABI_STATUS_INVALID_ARGUMENT,
+        count => count,
+    };
+
+    match node.probe_peer(peer_id, count) {
+        Ok(report) => write_c_string(
+            &probe_report_text(&report),
+            out_buffer,
+            buffer_len,
+            written_len,
+        ),
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "peer probe failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Starts a find_peer query for the given PeerId and returns a request identifier.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_find_peer(
+    handle: *mut CabiNodeHandle,
+    peer_id: *const c_char,
+    request_id: *mut u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if request_id.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+
+    let peer_id = match parse_peer_id(peer_id) {
+        Ok(id) => id,
+        Err(status) => return status,
+    };
+
+    match node.find_peer(peer_id) {
+        Ok(id) => unsafe {
+            *request_id = id;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "find_peer request failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Starts a get_closest_peers query for the given PeerId and returns a request identifier.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_get_closest_peers(
+    handle: *mut CabiNodeHandle,
+    peer_id: *const c_char,
+    request_id: *mut u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if request_id.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+
+    let peer_id = match parse_peer_id(peer_id) {
+        Ok(id) => id,
+        Err(status) => return status,
+    };
+
+    match node.get_closest_peers(peer_id) {
+        Ok(id) => unsafe {
+            *request_id = id;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "get_closest_peers request failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Starts a get_providers query for the given key and returns a request identifier.
+///
+/// Providers are reported through the discovery queue as
+/// [`CABI_DISCOVERY_EVENT_PROVIDER`] events while the query runs, followed by
+/// a [`CABI_DISCOVERY_EVENT_FINISHED`] event.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_get_providers(
+    handle: *mut CabiNodeHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    request_id: *mut u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if key_ptr.is_null() || request_id.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if key_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let key = unsafe { slice::from_raw_parts(key_ptr, key_len) }.to_vec();
+    match node.get_providers(key) {
+        Ok(id) => unsafe {
+            *request_id = id;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "get_providers request failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Announces the node as a provider of `key` and returns a request
+/// identifier. A [`CABI_DISCOVERY_EVENT_PROVIDING_STARTED`] event reports
+/// whether the announcement reached the DHT; the node keeps providing the
+/// key until [`cabi_node_stop_providing`].
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_start_providing(
+    handle: *mut CabiNodeHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    request_id: *mut u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if key_ptr.is_null() || request_id.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if key_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let key = unsafe { slice::from_raw_parts(key_ptr, key_len) }.to_vec();
+    match node.start_providing(key) {
+        Ok(id) => unsafe {
+            *request_id = id;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "start_providing request failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Stops announcing the node as a provider of `key`. Returns
+/// [`CABI_STATUS_NOT_FOUND`] when the key was not provided.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_stop_providing(
+    handle: *mut CabiNodeHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if key_ptr.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if key_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let key = unsafe { slice::from_raw_parts(key_ptr, key_len) }.to_vec();
+    match node.stop_providing(key) {
+        Ok(true) => CABI_STATUS_SUCCESS,
+        Ok(false) => CABI_STATUS_NOT_FOUND,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "stop_providing request failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Stores `value` under `key` locally and on the peers closest to the
+/// key, and returns a request identifier. A
+/// [`CABI_DISCOVERY_EVENT_RECORD_STORED`] event reports whether `quorum`
+/// peers stored it: 0 asks for a majority of the closest peers, 1 for any
+/// single peer.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_put_record(
+    handle: *mut CabiNodeHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    value_ptr: *const u8,
+    value_len: usize,
+    quorum: usize,
+    request_id: *mut u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if key_ptr.is_null() || value_ptr.is_null() || request_id.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if key_len == 0 || value_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let key = unsafe { slice::from_raw_parts(key_ptr, key_len) }.to_vec();
+    let value = unsafe { slice::from_raw_parts(value_ptr, value_len) }.to_vec();
+    let quorum = match NonZeroUsize::new(quorum) {
+        None => kad::Quorum::Majority,
+        Some(quorum) if quorum.get() == 1 => kad::Quorum::One,
+        Some(quorum) => kad::Quorum::N(quorum),
+    };
+    match node.put_record(key, value, quorum) {
+        Ok(id) => unsafe {
+            *request_id = id;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "put_record request failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Starts a record lookup for the given key and returns a request
+/// identifier.
+///
+/// Records are reported through the discovery queue as
+/// [`CABI_DISCOVERY_EVENT_RECORD`] events while the query runs, followed by a
+/// [`CABI_DISCOVERY_EVENT_FINISHED`] event. Their values are fetched with
+/// [`cabi_node_take_record_value`].
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_get_record(
+    handle: *mut CabiNodeHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    request_id: *mut u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if key_ptr.is_null() || request_id.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if key_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let key = unsafe { slice::from_raw_parts(key_ptr, key_len) }.to_vec();
+    match node.get_record(key) {
+        Ok(id) => unsafe {
+            *request_id = id;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "get_record request failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Copies the value of the oldest dequeued
+/// [`CABI_DISCOVERY_EVENT_RECORD`] event of `request_id` into the provided
+/// buffer and forgets it, so successive calls return the values in event
+/// order. When the buffer is too small, `written_len` receives the value
+/// length and the value is kept. Returns [`CABI_STATUS_NOT_FOUND`] when no
+/// value of the request is pending; at most
+/// [`CABI_MAX_PENDING_RECORD_VALUES`] values are kept across requests.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_take_record_value(
+    handle: *mut CabiNodeHandle,
+    request_id: u64,
+    out_buffer: *mut u8,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if out_buffer.is_null() || written_len.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+
+    let mut values = match lock(&node.record_values) {
+        Ok(values) => values,
+        Err(_) => return CABI_STATUS_INTERNAL_ERROR,
+    };
+    let Some(index) = values.iter().position(|(id, _)| *id == request_id) else {
+        return CABI_STATUS_NOT_FOUND;
+    };
+    let len = values[index].1.len();
+    unsafe {
+        *written_len = len;
+    }
+    if len > buffer_len {
+        return CABI_STATUS_BUFFER_TOO_SMALL;
+    }
+    if let Some((_, value)) = values.remove(index) {
+        unsafe {
+            ptr::copy_nonoverlapping(value.as_ptr(), out_buffer, len);
+        }
+    }
+    CABI_STATUS_SUCCESS
+}
+
+#[no_mangle]
+/// C-ABI. Like [`cabi_node_get_providers`], but the lookup finishes as soon
+/// as `max_results` providers were reported, or `deadline_ms` milliseconds
+/// after it started with the providers found so far. Zero disables either
+/// limit.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_get_providers_limited(
+    handle: *mut CabiNodeHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    max_results: usize,
+    deadline_ms: u64,
+    request_id: *mut u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if key_ptr.is_null() || request_id.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if key_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let key = unsafe { slice::from_raw_parts(key_ptr, key_len) }.to_vec();
+    let limits = peer::QueryLimits {
+        max_results: NonZeroUsize::new(max_results),
+        deadline: (deadline_ms > 0).then(|| Duration::from_millis(deadline_ms)),
+    };
+    match node.get_providers_with_limits(key, limits) {
+        Ok(id) => unsafe {
+            *request_id = id;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "get_providers request failed");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Enqueues a binary payload into the node's internal message queue.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_enqueue_message(
+    handle: *mut CabiNodeHandle,
+    data_ptr: *const u8,
+    data_len: usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if data_ptr.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if data_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let payload = unsafe { slice::from_raw_parts(data_ptr, data_len) }.to_vec();
+    match node.enqueue_message(payload) {
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to publish message");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Publishes a binary payload to the default topic and blocks until it
+/// left the node. `forwarded_to` receives the number of peers the message was
+/// sent to; 0 means it was published into the void.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_publish_confirmed(
+    handle: *mut CabiNodeHandle,
+    data_ptr: *const u8,
+    data_len: usize,
+    forwarded_to: *mut usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if data_ptr.is_null() || forwarded_to.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if data_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let payload = unsafe { slice::from_raw_parts(data_ptr, data_len) }.to_vec();
+    match node.publish_message(payload) {
+        Ok(receipt) => unsafe {
+            *forwarded_to = receipt.forwarded_to;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to publish message");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Publishes a binary payload to the default topic and blocks until it
+/// left the node, like [`cabi_node_publish_confirmed`], then reports the
+/// whole receipt: the null-terminated hex message id goes into
+/// `message_id_buffer`, `mesh_peers` receives the topic mesh size,
+/// `forwarded_to` the number of peers sent the message and `attempts` the
+/// publish attempts made. Fails with [`CABI_STATUS_INTERNAL_ERROR`] once
+/// every attempt allowed by the retry policy failed.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_publish_with_receipt(
+    handle: *mut CabiNodeHandle,
+    data_ptr: *const u8,
+    data_len: usize,
+    message_id_buffer: *mut c_char,
+    message_id_buffer_len: usize,
+    message_id_written_len: *mut usize,
+    mesh_peers: *mut usize,
+    forwarded_to: *mut usize,
+    attempts: *mut u32,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if data_ptr.is_null()
+        || message_id_buffer.is_null()
+        || message_id_written_len.is_null()
+        || mesh_peers.is_null()
+        || forwarded_to.is_null()
+        || attempts.is_null()
+    {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if data_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let payload = unsafe { slice::from_raw_parts(data_ptr, data_len) }.to_vec();
+    match node.publish_message(payload) {
+        Ok(receipt) => {
+            unsafe {
+                *mesh_peers = receipt.mesh_peers;
+                *forwarded_to = receipt.forwarded_to;
+                *attempts = receipt.attempts;
+            }
+            write_c_string(
+                &receipt.message_id.to_string(),
+                message_id_buffer,
+                message_id_buffer_len,
+                message_id_written_len,
+            )
+        }
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to publish message");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Publishes a binary payload to the default topic like
+/// [`cabi_node_publish_confirmed`], carrying the [`CABI_TRACE_ID_LEN`] bytes
+/// at `trace_id` in its envelope so receivers and their logs see the same
+/// id. With a null `trace_id` the node picks one when message tracing is on.
+/// The id used, if any, is written to `out_trace_id` with `has_trace_id`
+/// telling whether there was one. Needs replay protection.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_publish_traced(
+    handle: *mut CabiNodeHandle,
+    data_ptr: *const u8,
+    data_len: usize,
+    trace_id: *const u8,
+    forwarded_to: *mut usize,
+    out_trace_id: *mut u8,
+    has_trace_id: *mut bool,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if data_ptr.is_null()
+        || forwarded_to.is_null()
+        || out_trace_id.is_null()
+        || has_trace_id.is_null()
+    {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if data_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let payload = unsafe { slice::from_raw_parts(data_ptr, data_len) }.to_vec();
+    let trace_id = (!trace_id.is_null()).then(|| {
+        let mut id = [0u8; CABI_TRACE_ID_LEN];
+        id.copy_from_slice(unsafe { slice::from_raw_parts(trace_id, CABI_TRACE_ID_LEN) });
+        messaging::TraceId(id)
+    });
+    match node.publish_traced(payload, trace_id) {
+        Ok(receipt) => {
+            unsafe {
+                *forwarded_to = receipt.forwarded_to;
+            }
+            write_trace_id(receipt.trace_id, out_trace_id, has_trace_id);
+            CABI_STATUS_SUCCESS
+        }
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to publish message");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Publishes a binary payload to `topic` and blocks until it left the
+/// node, like [`cabi_node_publish_confirmed`] does for the default topic. The
+/// node does not need to be subscribed to `topic`.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_publish_to(
+    handle: *mut CabiNodeHandle,
+    topic: *const c_char,
+    data_ptr: *const u8,
+    data_len: usize,
+    forwarded_to: *mut usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let topic = match parse_topic(topic) {
+        Ok(topic) => topic,
+        Err(status) => return status,
+    };
+    if data_ptr.is_null() || forwarded_to.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if data_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let payload = unsafe { slice::from_raw_parts(data_ptr, data_len) }.to_vec();
+    match node.publish_to(topic, payload) {
+        Ok(receipt) => unsafe {
+            *forwarded_to = receipt.forwarded_to;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to publish message");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Subscribes the running node to `topic`; its messages arrive in the
+/// default message queue unless a topic consumer matches them. Group topics
+/// are refused with [`CABI_STATUS_INVALID_ARGUMENT`].
+pub extern "C" fn cabi_node_subscribe(handle: *mut CabiNodeHandle, topic: *const c_char) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let topic = match parse_topic(topic) {
+        Ok(topic) => topic,
+        Err(status) => return status,
+    };
+    if let Err(err) = peer::manager::validate_topic(&topic) {
+        tracing::warn!(target: "ffi", %err, "invalid subscription topic");
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    match node.subscribe(topic) {
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to subscribe");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Unsubscribes the running node from a topic. Returns
+/// [`CABI_STATUS_NOT_FOUND`] when it was not subscribed and
+/// [`CABI_STATUS_INVALID_ARGUMENT`] for the default or a group topic.
+pub extern "C" fn cabi_node_unsubscribe(
+    handle: *mut CabiNodeHandle,
+    topic: *const c_char,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let topic = match parse_topic(topic) {
+        Ok(topic) => topic,
+        Err(status) => return status,
+    };
+
+    match node.unsubscribe(topic) {
+        Ok(true) => CABI_STATUS_SUCCESS,
+        Ok(false) => CABI_STATUS_NOT_FOUND,
+        Err(err) => {
+            tracing::warn!(target: "ffi", %err, "failed to unsubscribe");
+            CABI_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Publishes the payload to `topic` (the default topic when null) once
+/// `delay_ms` elapsed. `schedule_id` receives the id accepted by
+/// [`cabi_node_cancel_scheduled_publish`].
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_publish_after(
+    handle: *mut CabiNodeHandle,
+    topic: *const c_char,
+    data_ptr: *const u8,
+    data_len: usize,
+    delay_ms: u64,
+    schedule_id: *mut u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let (topic, payload) = match parse_scheduled_publish(topic, data_ptr, data_len, schedule_id) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+
+    match node.publish_after(topic, payload, Duration::from_millis(delay_ms)) {
+        Ok(id) => unsafe {
+            *schedule_id = id;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to schedule publish");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Publishes the payload to `topic` (the default topic when null) at
+/// `unix_time_ms`, or right away if that time already passed. `schedule_id`
+/// receives the id accepted by [`cabi_node_cancel_scheduled_publish`].
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_publish_at(
+    handle: *mut CabiNodeHandle,
+    topic: *const c_char,
+    data_ptr: *const u8,
+    data_len: usize,
+    unix_time_ms: u64,
+    schedule_id: *mut u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let (topic, payload) = match parse_scheduled_publish(topic, data_ptr, data_len, schedule_id) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+
+    let when = UNIX_EPOCH + Duration::from_millis(unix_time_ms);
+    match node.publish_at(topic, payload, when) {
+        Ok(id) => unsafe {
+            *schedule_id = id;
+            CABI_STATUS_SUCCESS
+        },
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to schedule publish");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Cancels a scheduled publish. Returns [`CABI_STATUS_NOT_FOUND`] when
+/// it already fired or was never scheduled.
+pub extern "C" fn cabi_node_cancel_scheduled_publish(
+    handle: *mut CabiNodeHandle,
+    schedule_id: u64,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    match node.cancel_scheduled_publish(schedule_id) {
+        Ok(true) => CABI_STATUS_SUCCESS,
+        Ok(false) => CABI_STATUS_NOT_FOUND,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to cancel scheduled publish");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Tells the node the host's connectivity changed, with one of the
+/// `CABI_NETWORK_*` values, e.g. from the OS network callback on Android or
+/// iOS. After a switch or restore the node re-listens, re-dials pinned peers
+/// and probes its reachability again.
+pub extern "C" fn cabi_node_network_changed(handle: *mut CabiNodeHandle, change: c_int) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let change = match change {
+        CABI_NETWORK_SWITCHED => peer::NetworkChange::Switched,
+        CABI_NETWORK_LOST => peer::NetworkChange::Lost,
+        CABI_NETWORK_RESTORED => peer::NetworkChange::Restored,
+        _ => return CABI_STATUS_INVALID_ARGUMENT,
+    };
+
+    match node.network_changed(change) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to handle network change");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Joins `group`, creating it when nobody is in it yet. Membership
+/// changes and group messages arrive as [`CABI_NODE_EVENT_GROUP`] events.
+pub extern "C" fn cabi_node_join_group(handle: *mut CabiNodeHandle, group: *const c_char) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let group = match parse_group(group) {
+        Ok(group) => group,
+        Err(status) => return status,
+    };
+
+    match node.join_group(group) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to join group");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Leaves a joined group. Returns [`CABI_STATUS_NOT_FOUND`] when the
+/// node is not a member.
+pub extern "C" fn cabi_node_leave_group(
+    handle: *mut CabiNodeHandle,
+    group: *const c_char,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let group = match parse_group(group) {
+        Ok(group) => group,
+        Err(status) => return status,
+    };
+
+    match node.leave_group(group) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::warn!(target: "ffi", %err, "failed to leave group");
+            CABI_STATUS_NOT_FOUND
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Makes the `topics_len` topics of `topics` the exact set of topics
+/// subscribed next to the default one in one step: topics not subscribed yet
+/// are subscribed, subscribed topics missing from the list are unsubscribed,
+/// and a single [`CABI_NODE_EVENT_SUBSCRIPTIONS`] event reports the change.
+/// The update is all or nothing: an empty or group topic is refused with
+/// [`CABI_STATUS_INVALID_ARGUMENT`], and when a subscription fails nothing
+/// changes. An empty list unsubscribes from every topic but the default one.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_set_subscriptions(
+    handle: *mut CabiNodeHandle,
+    topics: *const *const c_char,
+    topics_len: usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let topics = if topics_len == 0 {
+        Vec::new()
+    } else {
+        if topics.is_null() {
+            return CABI_STATUS_NULL_POINTER;
+        }
+        let topic_slice = unsafe { slice::from_raw_parts(topics, topics_len) };
+        let mut parsed = Vec::with_capacity(topic_slice.len());
+        for &topic in topic_slice {
+            let topic = match parse_topic(topic) {
+                Ok(topic) => topic,
+                Err(status) => return status,
+            };
+            if let Err(err) = peer::manager::validate_topic(&topic) {
+                tracing::warn!(target: "ffi", %err, "invalid subscription topic");
+                return CABI_STATUS_INVALID_ARGUMENT;
+            }
+            parsed.push(topic);
+        }
+        parsed
+    };
+
+    match node.set_subscriptions(topics) {
+        Ok(_) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::error!(target: "ffi", %err, "failed to set subscriptions");
+            CABI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Publishes the payload to the members of a joined group. Returns
+/// [`CABI_STATUS_NOT_FOUND`] when the node is not a member.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_publish_to_group(
+    handle: *mut CabiNodeHandle,
+    group: *const c_char,
+    data_ptr: *const u8,
+    data_len: usize,
+) -> c_int {
+    let node = match running_node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    let group = match parse_group(group) {
+        Ok(group) => group,
+        Err(status) => return status,
+    };
+    if data_ptr.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+    if data_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+    let payload = unsafe { slice::from_raw_parts(data_ptr, data_len) }.to_vec();
+
+    match node.publish_to_group(group, payload) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(err) => {
+            tracing::warn!(target: "ffi", %err, "failed to publish to group");
+            CABI_STATUS_NOT_FOUND
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Attempts to dequeue the next message into the provided buffer.
+///
+/// Returns [`CABI_STATUS_QUEUE_EMPTY`] if no message is currently available,
+/// and [`CABI_STATUS_BUFFER_TOO_SMALL`] when the provided buffer is not large
+/// enough to hold the message (in that case `written_len` is set to the
+/// required length).
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_dequeue_message(
+    handle: *mut CabiNodeHandle,
+    out_buffer: *mut u8,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if out_buffer.is_null() || written_len.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+
+    if buffer_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    // Always clear the written_len output.
+    unsafe {
+        *written_len = 0;
+    }
+
+    match node.try_dequeue_message() {
+        None => CABI_STATUS_QUEUE_EMPTY,
+        Some(message) => {
+            let message = message.payload;
+            if message.len() > buffer_len {
+                unsafe {
+                    *written_len = message.len();
+                }
+                return CABI_STATUS_BUFFER_TOO_SMALL;
+            }
+
+            unsafe {
+                ptr::copy_nonoverlapping(message.as_ptr(), out_buffer, message.len());
+                *written_len = message.len();
+            }
+
+            CABI_STATUS_SUCCESS
+        }
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Like [`cabi_node_dequeue_message`], but also writes the topic the
+/// message was published to, null-terminated, into `topic_buffer`. When
+/// either buffer is too small, both written lengths are set to the required
+/// lengths (the topic's without the terminator), nothing is copied and
+/// [`CABI_STATUS_BUFFER_TOO_SMALL`] is returned.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_dequeue_message_with_topic(
     handle: *mut CabiNodeHandle,
-    address: *const c_char
+    out_buffer: *mut u8,
+    buffer_len: usize,
+    written_len: *mut usize,
+    topic_buffer: *mut c_char,
+    topic_buffer_len: usize,
+    topic_written_len: *mut usize,
 ) -> c_int {
     let node = match node_from_ptr(handle) {
         Ok(node) => node,
         Err(status) => return status,
     };
 
-    let multiaddr = match parse_multiaddr(address) {
-        Ok(addr) => addr,
-        Err(status) => return status,
-    };
+    if out_buffer.is_null()
+        || written_len.is_null()
+        || topic_buffer.is_null()
+        || topic_written_len.is_null()
+    {
+        return CABI_STATUS_NULL_POINTER;
+    }
 
-    match node.reserve_relay(multiaddr) {
-        Ok(_) => CABI_STATUS_SUCCESS,
-        Err(err) => {
-            tracing::error!(target: "ffi", %err, "reserve_relay failed");
-            CABI_STATUS_INTERNAL_ERROR
-        }
+    if buffer_len == 0 || topic_buffer_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    unsafe {
+        *written_len = 0;
+        *topic_written_len = 0;
+    }
+
+    match node.try_dequeue_message() {
+        None => CABI_STATUS_QUEUE_EMPTY,
+        Some(message) => write_routed_message(
+            &message,
+            out_buffer,
+            buffer_len,
+            written_len,
+            topic_buffer,
+            topic_buffer_len,
+            topic_written_len,
+        ),
     }
 }
 
 #[no_mangle]
-/// C-ABI. Inits listening on the given address
-pub extern "C" fn cabi_node_listen(handle: *mut CabiNodeHandle, address: *const c_char) -> c_int {
+/// C-ABI. Like [`cabi_node_dequeue_message_with_topic`], but also writes the
+/// trace id its publisher put in the envelope to the [`CABI_TRACE_ID_LEN`]
+/// bytes at `trace_id`, with `has_trace_id` telling whether it had one.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_dequeue_traced_message(
+    handle: *mut CabiNodeHandle,
+    out_buffer: *mut u8,
+    buffer_len: usize,
+    written_len: *mut usize,
+    topic_buffer: *mut c_char,
+    topic_buffer_len: usize,
+    topic_written_len: *mut usize,
+    trace_id: *mut u8,
+    has_trace_id: *mut bool,
+) -> c_int {
     let node = match node_from_ptr(handle) {
         Ok(node) => node,
         Err(status) => return status,
     };
 
-    let multiaddr = match parse_multiaddr(address) {
-        Ok(addr) => addr,
-        Err(status) => return status,
-    };
+    if out_buffer.is_null()
+        || written_len.is_null()
+        || topic_buffer.is_null()
+        || topic_written_len.is_null()
+        || trace_id.is_null()
+        || has_trace_id.is_null()
+    {
+        return CABI_STATUS_NULL_POINTER;
+    }
 
-    match node.start_listening(multiaddr) {
-        Ok(_) => CABI_STATUS_SUCCESS,
-        Err(err) => {
-            tracing::error!(target: "ffi", %err, "start_listening failed");
-            CABI_STATUS_INTERNAL_ERROR
-        }
+    if buffer_len == 0 || topic_buffer_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    unsafe {
+        *written_len = 0;
+        *topic_written_len = 0;
     }
+
+    let Some(message) = node.try_dequeue_message() else {
+        return CABI_STATUS_QUEUE_EMPTY;
+    };
+    let status = write_routed_message(
+        &message,
+        out_buffer,
+        buffer_len,
+        written_len,
+        topic_buffer,
+        topic_buffer_len,
+        topic_written_len,
+    );
+    if status == CABI_STATUS_SUCCESS {
+        write_trace_id(message.trace_id, trace_id, has_trace_id);
+    }
+    status
 }
 
 #[no_mangle]
-/// C-ABI. Inits a dial to the outbound peer with the specified address
-pub extern "C" fn cabi_node_dial(handle: *mut CabiNodeHandle, address: *const c_char) -> c_int {
+/// C-ABI. Like [`cabi_node_dequeue_topic_message`], but also writes the
+/// trace id of the message like [`cabi_node_dequeue_traced_message`].
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_dequeue_traced_topic_message(
+    handle: *mut CabiNodeHandle,
+    consumer_id: u64,
+    out_buffer: *mut u8,
+    buffer_len: usize,
+    written_len: *mut usize,
+    topic_buffer: *mut c_char,
+    topic_buffer_len: usize,
+    topic_written_len: *mut usize,
+    trace_id: *mut u8,
+    has_trace_id: *mut bool,
+) -> c_int {
     let node = match node_from_ptr(handle) {
         Ok(node) => node,
         Err(status) => return status,
     };
 
-    let multiaddr = match parse_multiaddr(address) {
-        Ok(addr) => addr,
-        Err(status) => return status,
-    };
+    if out_buffer.is_null()
+        || written_len.is_null()
+        || topic_buffer.is_null()
+        || topic_written_len.is_null()
+        || trace_id.is_null()
+        || has_trace_id.is_null()
+    {
+        return CABI_STATUS_NULL_POINTER;
+    }
 
-    match node.dial(multiaddr) {
-        Ok(_) => CABI_STATUS_SUCCESS,
-        Err(err) => {
-            tracing::error!(target: "ffi", %err, "dial failed");
-            CABI_STATUS_INTERNAL_ERROR
-        }
+    if buffer_len == 0 || topic_buffer_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    unsafe {
+        *written_len = 0;
+        *topic_written_len = 0;
+    }
+
+    let message = match node.try_dequeue_topic_message(consumer_id) {
+        None => return CABI_STATUS_NOT_FOUND,
+        Some(None) => return CABI_STATUS_QUEUE_EMPTY,
+        Some(Some(message)) => message,
+    };
+    let status = write_routed_message(
+        &message,
+        out_buffer,
+        buffer_len,
+        written_len,
+        topic_buffer,
+        topic_buffer_len,
+        topic_written_len,
+    );
+    if status == CABI_STATUS_SUCCESS {
+        write_trace_id(message.trace_id, trace_id, has_trace_id);
     }
+    status
 }
 
 #[no_mangle]
-/// C-ABI. Starts a find_peer query for the given PeerId and returns a request identifier.
-pub extern "C" fn cabi_node_find_peer(
+/// C-ABI. Registers a topic consumer with its own queue of `capacity`
+/// messages (the default capacity when zero). Inbound messages on topics
+/// matching `pattern`, a topic name or a `prefix*`, go to every matching
+/// consumer instead of the default message queue; the new consumer's id is
+/// written to `consumer_id`. Consumers are kept across restarts.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_register_topic_consumer(
     handle: *mut CabiNodeHandle,
-    peer_id: *const c_char,
-    request_id: *mut u64,
+    pattern: *const c_char,
+    capacity: usize,
+    consumer_id: *mut u64,
 ) -> c_int {
     let node = match node_from_ptr(handle) {
         Ok(node) => node,
         Err(status) => return status,
     };
 
-    if request_id.is_null() {
+    if pattern.is_null() || consumer_id.is_null() {
         return CABI_STATUS_NULL_POINTER;
     }
-
-    let peer_id = match parse_peer_id(peer_id) {
-        Ok(id) => id,
-        Err(status) => return status,
+    let c_str = unsafe { CStr::from_ptr(pattern) };
+    let pattern = match c_str
+        .to_str()
+        .map_err(anyhow::Error::from)
+        .and_then(messaging::TopicPattern::parse)
+    {
+        Ok(pattern) => pattern,
+        Err(_) => return CABI_STATUS_INVALID_ARGUMENT,
+    };
+    let capacity = if capacity == 0 {
+        messaging::DEFAULT_MESSAGE_QUEUE_CAPACITY
+    } else {
+        capacity
     };
 
-    match node.find_peer(peer_id) {
+    match node.register_topic_consumer(pattern, capacity) {
         Ok(id) => unsafe {
-            *request_id = id;
+            *consumer_id = id;
             CABI_STATUS_SUCCESS
         },
         Err(err) => {
-            tracing::error!(target: "ffi", %err, "find_peer request failed");
+            tracing::error!(target: "ffi", %err, "failed to register topic consumer");
             CABI_STATUS_INTERNAL_ERROR
         }
     }
 }
 
 #[no_mangle]
-/// C-ABI. Starts a get_closest_peers query for the given PeerId and returns a request identifier.
-pub extern "C" fn cabi_node_get_closest_peers(
+/// C-ABI. Attempts to dequeue the next message of a topic consumer into the
+/// provided buffers, like [`cabi_node_dequeue_message`], and its
+/// null-terminated topic into `topic_buffer`: the topic tells which of the
+/// topics matching the consumer's pattern the message was published to.
+/// Returns [`CABI_STATUS_NOT_FOUND`] for an unknown consumer.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_dequeue_topic_message(
     handle: *mut CabiNodeHandle,
-    peer_id: *const c_char,
-    request_id: *mut u64,
+    consumer_id: u64,
+    out_buffer: *mut u8,
+    buffer_len: usize,
+    written_len: *mut usize,
+    topic_buffer: *mut c_char,
+    topic_buffer_len: usize,
+    topic_written_len: *mut usize,
 ) -> c_int {
     let node = match node_from_ptr(handle) {
         Ok(node) => node,
         Err(status) => return status,
     };
 
-    if request_id.is_null() {
+    if out_buffer.is_null()
+        || written_len.is_null()
+        || topic_buffer.is_null()
+        || topic_written_len.is_null()
+    {
         return CABI_STATUS_NULL_POINTER;
     }
 
-    let peer_id = match parse_peer_id(peer_id) {
-        Ok(id) => id,
-        Err(status) => return status,
-    };
+    if buffer_len == 0 || topic_buffer_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
 
-    match node.get_closest_peers(peer_id) {
-        Ok(id) => unsafe {
-            *request_id = id;
-            CABI_STATUS_SUCCESS
-        },
-        Err(err) => {
-            tracing::error!(target: "ffi", %err, "get_closest_peers request failed");
-            CABI_STATUS_INTERNAL_ERROR
-        }
+    unsafe {
+        *written_len = 0;
+        *topic_written_len = 0;
+    }
+
+    match node.try_dequeue_topic_message(consumer_id) {
+        None => CABI_STATUS_NOT_FOUND,
+        Some(None) => CABI_STATUS_QUEUE_EMPTY,
+        Some(Some(message)) => write_routed_message(
+            &message,
+            out_buffer,
+            buffer_len,
+            written_len,
+            topic_buffer,
+            topic_buffer_len,
+            topic_written_len,
+        ),
     }
 }
 
 #[no_mangle]
-/// C-ABI. Enqueues a binary payload into the node's internal message queue.
-pub extern "C" fn cabi_node_enqueue_message(
+/// C-ABI. Writes the number of messages a topic consumer lost to its full
+/// queue to `out_dropped`. Returns [`CABI_STATUS_NOT_FOUND`] for an unknown
+/// consumer.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_topic_consumer_dropped(
     handle: *mut CabiNodeHandle,
-    data_ptr: *const u8,
-    data_len: usize,
+    consumer_id: u64,
+    out_dropped: *mut u64,
 ) -> c_int {
     let node = match node_from_ptr(handle) {
         Ok(node) => node,
         Err(status) => return status,
     };
 
-    if data_ptr.is_null() {
+    if out_dropped.is_null() {
         return CABI_STATUS_NULL_POINTER;
     }
-    if data_len == 0 {
-        return CABI_STATUS_INVALID_ARGUMENT;
+
+    match node.topic_consumer_stats(consumer_id) {
+        Some(stats) => unsafe {
+            *out_dropped = stats.dropped;
+            CABI_STATUS_SUCCESS
+        },
+        None => CABI_STATUS_NOT_FOUND,
     }
+}
 
-    let payload = unsafe { slice::from_raw_parts(data_ptr, data_len) }.to_vec();
-    match node.publish_message(payload) {
-        Ok(_) => CABI_STATUS_SUCCESS,
+#[no_mangle]
+/// C-ABI. Unregisters a topic consumer, discarding its queued messages.
+/// Returns [`CABI_STATUS_NOT_FOUND`] for an unknown consumer.
+pub extern "C" fn cabi_node_unregister_topic_consumer(
+    handle: *mut CabiNodeHandle,
+    consumer_id: u64,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    match node.unregister_topic_consumer(consumer_id) {
+        Ok(true) => CABI_STATUS_SUCCESS,
+        Ok(false) => CABI_STATUS_NOT_FOUND,
         Err(err) => {
-            tracing::error!(target: "ffi", %err, "failed to publish message");
+            tracing::error!(target: "ffi", %err, "failed to unregister topic consumer");
             CABI_STATUS_INTERNAL_ERROR
         }
     }
 }
 
 #[no_mangle]
-/// C-ABI. Attempts to dequeue the next message into the provided buffer.
+/// C-ABI. Polls the unified node event stream, an alternative to the separate
+/// message and discovery queues that also reports connection changes, AutoNAT
+/// status changes and errors.
 ///
-/// Returns [`CABI_STATUS_QUEUE_EMPTY`] if no message is currently available,
-/// and [`CABI_STATUS_BUFFER_TOO_SMALL`] when the provided buffer is not large
-/// enough to hold the message (in that case `written_len` is set to the
-/// required length).
-pub extern "C" fn cabi_node_dequeue_message(
+/// `event_kind` receives one of the `CABI_NODE_EVENT_*` kinds and
+/// `out_buffer` the event payload: the raw bytes for messages, UTF-8 text
+/// otherwise. Returns [`CABI_STATUS_QUEUE_EMPTY`] if no event is pending, and
+/// [`CABI_STATUS_BUFFER_TOO_SMALL`] with `written_len` set to the required
+/// length when the payload does not fit; the event is then kept for the next
+/// call. Messages and discovery results are taken from the same queues as
+/// [`cabi_node_dequeue_message`] and [`cabi_node_dequeue_discovery_event`].
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_next_event(
     handle: *mut CabiNodeHandle,
+    event_kind: *mut c_int,
     out_buffer: *mut u8,
     buffer_len: usize,
     written_len: *mut usize,
@@ -485,7 +3478,7 @@ pub extern "C" fn cabi_node_dequeue_message(
         Err(status) => return status,
     };
 
-    if out_buffer.is_null() || written_len.is_null() {
+    if event_kind.is_null() || out_buffer.is_null() || written_len.is_null() {
         return CABI_STATUS_NULL_POINTER;
     }
 
@@ -493,33 +3486,38 @@ pub extern "C" fn cabi_node_dequeue_message(
         return CABI_STATUS_INVALID_ARGUMENT;
     }
 
-    // Always clear the written_len output.
     unsafe {
         *written_len = 0;
     }
 
-    match node.try_dequeue_message() {
-        None => CABI_STATUS_QUEUE_EMPTY,
-        Some(message) => {
-            if message.len() > buffer_len {
-                unsafe {
-                    *written_len = message.len();
-                }
-                return CABI_STATUS_BUFFER_TOO_SMALL;
-            }
+    let Some(event) = node.try_next_event() else {
+        return CABI_STATUS_QUEUE_EMPTY;
+    };
 
-            unsafe {
-                ptr::copy_nonoverlapping(message.as_ptr(), out_buffer, message.len());
-                *written_len = message.len();
-            }
+    let (kind, payload) = node_event_fields(&event);
+    unsafe {
+        *event_kind = kind;
+    }
 
-            CABI_STATUS_SUCCESS
+    if payload.len() > buffer_len {
+        unsafe {
+            *written_len = payload.len();
         }
+        node.defer_event(event);
+        return CABI_STATUS_BUFFER_TOO_SMALL;
+    }
+
+    unsafe {
+        ptr::copy_nonoverlapping(payload.as_ptr(), out_buffer, payload.len());
+        *written_len = payload.len();
     }
+
+    CABI_STATUS_SUCCESS
 }
 
 #[no_mangle]
 /// C-ABI. Attempts to dequeue a discovery result produced by a Kademlia query.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn cabi_node_dequeue_discovery_event(
     handle: *mut CabiNodeHandle,
     event_kind: *mut c_int,
@@ -541,53 +3539,29 @@ pub extern "C" fn cabi_node_dequeue_discovery_event(
         || request_id.is_null()
         || status_code.is_null()
         || peer_id_buffer.is_null()
-        || peer_id_written_len.is_null()
-        || address_buffer.is_null()
-        || address_written_len.is_null()
-    {
-        return CABI_STATUS_NULL_POINTER;
-    }
-
-    if peer_id_buffer_len == 0 || address_buffer_len == 0 {
-        return CABI_STATUS_INVALID_ARGUMENT;
-    }
-
-    unsafe {
-        *peer_id_written_len = 0;
-        *address_written_len = 0;
-    }
-
-    let event = match node.try_dequeue_discovery() {
-        Some(event) => event,
-        None => return CABI_STATUS_QUEUE_EMPTY,
-    };
-
-    let (kind, req_id, status, peer_id, address) = match event {
-        peer::DiscoveryEvent::Address {
-            request_id,
-            peer_id,
-            address,
-            ..
-        } => (
-            CABI_DISCOVERY_EVENT_ADDRESS,
-            request_id,
-            CABI_STATUS_SUCCESS,
-            peer_id.to_string(),
-            address.to_string(),
-        ),
-        peer::DiscoveryEvent::Finished {
-            request_id,
-            target_peer_id,
-            status,
-        } => (
-            CABI_DISCOVERY_EVENT_FINISHED,
-            request_id,
-            discovery_status_to_code(&status),
-            target_peer_id.to_string(),
-            String::new(),
-        ),
+        || peer_id_written_len.is_null()
+        || address_buffer.is_null()
+        || address_written_len.is_null()
+    {
+        return CABI_STATUS_NULL_POINTER;
+    }
+
+    if peer_id_buffer_len == 0 || address_buffer_len == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    unsafe {
+        *peer_id_written_len = 0;
+        *address_written_len = 0;
+    }
+
+    let event = match node.try_dequeue_discovery() {
+        Some(event) => event,
+        None => return CABI_STATUS_QUEUE_EMPTY,
     };
 
+    let (kind, req_id, status, peer_id, address) = discovery_event_fields(event);
+
     unsafe {
         *event_kind = kind;
         *request_id = req_id;
@@ -613,6 +3587,80 @@ pub extern "C" fn cabi_node_dequeue_discovery_event(
 }
 
 #[no_mangle]
+/// C-ABI. Drains up to `capacity` pending discovery events into `out_events`.
+///
+/// `written_len` receives the number of filled entries. Returns
+/// [`CABI_STATUS_QUEUE_EMPTY`] when no event was pending. Events whose peer id
+/// or address do not fit the fixed-size fields are dropped with a warning.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cabi_node_drain_discovery_events(
+    handle: *mut CabiNodeHandle,
+    out_events: *mut CabiDiscoveryEvent,
+    capacity: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let node = match node_from_ptr(handle) {
+        Ok(node) => node,
+        Err(status) => return status,
+    };
+
+    if out_events.is_null() || written_len.is_null() {
+        return CABI_STATUS_NULL_POINTER;
+    }
+
+    if capacity == 0 {
+        return CABI_STATUS_INVALID_ARGUMENT;
+    }
+
+    let events = unsafe { slice::from_raw_parts_mut(out_events, capacity) };
+    let mut written = 0usize;
+
+    while written < capacity {
+        let Some(event) = node.try_dequeue_discovery() else {
+            break;
+        };
+
+        let peers_found = match &event {
+            peer::DiscoveryEvent::Finished { peers_found, .. } => *peers_found as u64,
+            peer::DiscoveryEvent::ProvidersFinished {
+                providers_found, ..
+            } => *providers_found as u64,
+            peer::DiscoveryEvent::RecordsFinished { records_found, .. } => *records_found as u64,
+            peer::DiscoveryEvent::Address { .. }
+            | peer::DiscoveryEvent::Provider { .. }
+            | peer::DiscoveryEvent::Record { .. }
+            | peer::DiscoveryEvent::RecordStored { .. }
+            | peer::DiscoveryEvent::PeerRecord { .. }
+            | peer::DiscoveryEvent::ProvidingStarted { .. } => 0,
+        };
+        let (kind, req_id, status, peer_id, address) = discovery_event_fields(event);
+        let slot = &mut events[written];
+        if !copy_c_string(&peer_id, &mut slot.peer_id)
+            || !copy_c_string(&address, &mut slot.address)
+        {
+            tracing::warn!(target: "ffi", %peer_id, %address, "discovery event too large; dropped");
+            continue;
+        }
+        slot.event_kind = kind;
+        slot.request_id = req_id;
+        slot.status_code = status;
+        slot.peers_found = peers_found;
+        written += 1;
+    }
+
+    unsafe {
+        *written_len = written;
+    }
+
+    if written == 0 {
+        CABI_STATUS_QUEUE_EMPTY
+    } else {
+        CABI_STATUS_SUCCESS
+    }
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn cabi_node_get_addrs_snapshot(
     handle: *mut CabiNodeHandle,
     out_version: *mut u64,
@@ -645,24 +3693,134 @@ pub extern "C" fn cabi_node_get_addrs_snapshot(
 }
 
 #[no_mangle]
-/// C-ABI. Frees node with specified handle
-pub extern "C" fn cabi_node_free(handle: *mut CabiNodeHandle) {
-    if handle.is_null() {
-        return;
+/// C-ABI. Writes the relayed multiaddr
+/// `<relay_addr>/p2p/<relay_peer_id>/p2p-circuit/p2p/<target_peer_id>` into the buffer.
+pub extern "C" fn cabi_multiaddr_relayed(
+    relay_addr: *const c_char,
+    relay_peer_id: *const c_char,
+    target_peer_id: *const c_char,
+    out_buffer: *mut c_char,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let relay_addr = match parse_multiaddr(relay_addr) {
+        Ok(addr) => addr,
+        Err(status) => return status,
+    };
+    let relay_peer_id = match parse_peer_id(relay_peer_id) {
+        Ok(peer_id) => peer_id,
+        Err(status) => return status,
+    };
+    let target_peer_id = match parse_peer_id(target_peer_id) {
+        Ok(peer_id) => peer_id,
+        Err(status) => return status,
+    };
+
+    match transport::relayed_address(&relay_addr, relay_peer_id, target_peer_id) {
+        Ok(address) => write_c_string(&address.to_string(), out_buffer, buffer_len, written_len),
+        Err(_) => CABI_STATUS_INVALID_ARGUMENT,
     }
+}
 
-    unsafe {
-        drop(Box::from_raw(handle as *mut ManagedNode));
+#[no_mangle]
+/// C-ABI. Writes the relay part of a relayed multiaddr (everything before
+/// `/p2p-circuit`, ending with `/p2p/<relay>`) into the buffer.
+/// Returns [`CABI_STATUS_INVALID_ARGUMENT`] when the address is not relayed.
+pub extern "C" fn cabi_multiaddr_relay_address(
+    address: *const c_char,
+    out_buffer: *mut c_char,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let address = match parse_multiaddr(address) {
+        Ok(addr) => addr,
+        Err(status) => return status,
+    };
+
+    match transport::decompose_relayed(&address) {
+        Some(relayed) => write_c_string(
+            &relayed.relay_addr.to_string(),
+            out_buffer,
+            buffer_len,
+            written_len,
+        ),
+        None => CABI_STATUS_INVALID_ARGUMENT,
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Writes the peer reached through a relayed multiaddr into the buffer.
+/// Returns [`CABI_STATUS_INVALID_ARGUMENT`] when the address is not relayed and
+/// [`CABI_STATUS_NOT_FOUND`] when it names no target peer.
+pub extern "C" fn cabi_multiaddr_circuit_target(
+    address: *const c_char,
+    out_buffer: *mut c_char,
+    buffer_len: usize,
+    written_len: *mut usize,
+) -> c_int {
+    let address = match parse_multiaddr(address) {
+        Ok(addr) => addr,
+        Err(status) => return status,
+    };
+
+    match transport::decompose_relayed(&address) {
+        Some(RelayedAddress {
+            target_peer_id: Some(peer_id),
+            ..
+        }) => write_c_string(&peer_id.to_string(), out_buffer, buffer_len, written_len),
+        Some(_) => CABI_STATUS_NOT_FOUND,
+        None => CABI_STATUS_INVALID_ARGUMENT,
+    }
+}
+
+#[no_mangle]
+/// C-ABI. Checks that the multiaddr ends with `/p2p/<peer_id>` before dialing.
+/// Returns [`CABI_STATUS_SUCCESS`] on match and [`CABI_STATUS_NOT_FOUND`] otherwise.
+pub extern "C" fn cabi_multiaddr_ends_with_peer(
+    address: *const c_char,
+    peer_id: *const c_char,
+) -> c_int {
+    let address = match parse_multiaddr(address) {
+        Ok(addr) => addr,
+        Err(status) => return status,
+    };
+    let peer_id = match parse_peer_id(peer_id) {
+        Ok(peer_id) => peer_id,
+        Err(status) => return status,
+    };
+
+    match transport::ensure_ends_with_peer(&address, &peer_id) {
+        Ok(()) => CABI_STATUS_SUCCESS,
+        Err(_) => CABI_STATUS_NOT_FOUND,
     }
 }
 
-/// Converts pointer into node reference
-fn node_from_ptr(handle: *mut CabiNodeHandle) -> FfiResult<&'static mut ManagedNode> {
+#[no_mangle]
+/// C-ABI. Frees node with specified handle. Alias of [`cabi_node_destroy`].
+pub extern "C" fn cabi_node_free(handle: *mut CabiNodeHandle) {
+    cabi_node_destroy(handle);
+}
+
+/// Resolves a handle into a shared reference to its node.
+fn node_from_ptr(handle: *mut CabiNodeHandle) -> FfiResult<Arc<ManagedNode>> {
     if handle.is_null() {
         return Err(CABI_STATUS_NULL_POINTER);
     }
 
-    Ok(unsafe { &mut *(handle as *mut ManagedNode) })
+    let nodes = NODES.lock().map_err(|_| CABI_STATUS_INTERNAL_ERROR)?;
+    nodes
+        .get(&(handle as usize))
+        .cloned()
+        .ok_or(CABI_STATUS_INVALID_HANDLE)
+}
+
+/// Resolves a handle into its node, requiring the node to be started.
+fn running_node_from_ptr(handle: *mut CabiNodeHandle) -> FfiResult<Arc<ManagedNode>> {
+    let node = node_from_ptr(handle)?;
+    if !node.is_running() {
+        return Err(CABI_STATUS_NOT_RUNNING);
+    }
+    Ok(node)
 }
 
 /// Parses a c string into a libp2p multiaddr. Returns additional status codes on error.
@@ -680,6 +3838,57 @@ fn parse_multiaddr(address: *const c_char) -> FfiResult<Multiaddr> {
     Multiaddr::from_str(addr_str).map_err(|_| CABI_STATUS_INVALID_ARGUMENT)
 }
 
+/// Parses the topic (`None` for the default one when null) and payload of a
+/// scheduled publish.
+fn parse_scheduled_publish(
+    topic: *const c_char,
+    data_ptr: *const u8,
+    data_len: usize,
+    schedule_id: *mut u64,
+) -> FfiResult<(Option<String>, Vec<u8>)> {
+    if data_ptr.is_null() || schedule_id.is_null() {
+        return Err(CABI_STATUS_NULL_POINTER);
+    }
+    if data_len == 0 {
+        return Err(CABI_STATUS_INVALID_ARGUMENT);
+    }
+
+    let topic = if topic.is_null() {
+        None
+    } else {
+        Some(parse_topic(topic)?)
+    };
+
+    let payload = unsafe { slice::from_raw_parts(data_ptr, data_len) }.to_vec();
+    Ok((topic, payload))
+}
+
+/// Parses a non-empty topic name.
+fn parse_topic(topic: *const c_char) -> FfiResult<String> {
+    if topic.is_null() {
+        return Err(CABI_STATUS_NULL_POINTER);
+    }
+
+    let c_str = unsafe { CStr::from_ptr(topic) };
+    match c_str.to_str() {
+        Ok(value) if !value.is_empty() => Ok(value.to_string()),
+        _ => Err(CABI_STATUS_INVALID_ARGUMENT),
+    }
+}
+
+/// Parses a non-empty group name.
+fn parse_group(group: *const c_char) -> FfiResult<String> {
+    if group.is_null() {
+        return Err(CABI_STATUS_NULL_POINTER);
+    }
+
+    let c_str = unsafe { CStr::from_ptr(group) };
+    match c_str.to_str() {
+        Ok(value) if !value.is_empty() => Ok(value.to_string()),
+        _ => Err(CABI_STATUS_INVALID_ARGUMENT),
+    }
+}
+
 // Parses a c string into vector with bootstraps.
 fn parse_bootstrap_peers(
     peers: *const *const c_char,
@@ -775,11 +3984,327 @@ fn write_c_string(
     CABI_STATUS_SUCCESS
 }
 
+/// Copies the payload and the null-terminated topic of `message` into the
+/// caller's buffers, or only reports the required lengths when either
+/// buffer is too small. Pointers are checked by the caller.
+fn write_routed_message(
+    message: &messaging::RoutedMessage,
+    out_buffer: *mut u8,
+    buffer_len: usize,
+    written_len: *mut usize,
+    topic_buffer: *mut c_char,
+    topic_buffer_len: usize,
+    topic_written_len: *mut usize,
+) -> c_int {
+    let payload = &message.payload;
+    let topic = message.topic.as_bytes();
+    unsafe {
+        *written_len = payload.len();
+        *topic_written_len = topic.len();
+    }
+    if payload.len() > buffer_len || topic.len() + 1 > topic_buffer_len {
+        return CABI_STATUS_BUFFER_TOO_SMALL;
+    }
+
+    unsafe {
+        ptr::copy_nonoverlapping(payload.as_ptr(), out_buffer, payload.len());
+        ptr::copy_nonoverlapping(topic.as_ptr(), topic_buffer as *mut u8, topic.len());
+        *topic_buffer.add(topic.len()) = 0;
+    }
+    CABI_STATUS_SUCCESS
+}
+
+/// Writes `trace_id` to the [`CABI_TRACE_ID_LEN`] bytes at `out`, zeroed when
+/// there is none. Pointers are checked by the caller.
+fn write_trace_id(trace_id: Option<messaging::TraceId>, out: *mut u8, has_trace_id: *mut bool) {
+    let bytes = trace_id.map(|trace_id| trace_id.0).unwrap_or_default();
+    unsafe {
+        ptr::copy_nonoverlapping(bytes.as_ptr(), out, CABI_TRACE_ID_LEN);
+        *has_trace_id = trace_id.is_some();
+    }
+}
+
+/// Copies `value` plus a null terminator into a fixed-size buffer.
+/// Returns `false` (leaving the buffer untouched) when it does not fit.
+fn copy_c_string(value: &str, out: &mut [c_char]) -> bool {
+    let bytes = value.as_bytes();
+    if bytes.len() + 1 > out.len() {
+        return false;
+    }
+
+    for (dst, src) in out.iter_mut().zip(bytes) {
+        *dst = *src as c_char;
+    }
+    out[bytes.len()] = 0;
+    true
+}
+
+/// Flattens a discovery event into `(kind, request_id, status, peer_id, address)`.
+fn discovery_event_fields(event: peer::DiscoveryEvent) -> (c_int, u64, c_int, String, String) {
+    match event {
+        peer::DiscoveryEvent::Address {
+            request_id,
+            peer_id,
+            address,
+            ..
+        } => (
+            CABI_DISCOVERY_EVENT_ADDRESS,
+            request_id,
+            CABI_STATUS_SUCCESS,
+            peer_id.to_string(),
+            address.to_string(),
+        ),
+        peer::DiscoveryEvent::Finished {
+            request_id,
+            target_peer_id,
+            status,
+            ..
+        } => (
+            CABI_DISCOVERY_EVENT_FINISHED,
+            request_id,
+            discovery_status_to_code(&status),
+            target_peer_id.to_string(),
+            String::new(),
+        ),
+        peer::DiscoveryEvent::Provider {
+            request_id,
+            peer_id,
+            addresses,
+            ..
+        } => (
+            CABI_DISCOVERY_EVENT_PROVIDER,
+            request_id,
+            CABI_STATUS_SUCCESS,
+            peer_id.to_string(),
+            addresses
+                .first()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+        ),
+        peer::DiscoveryEvent::ProvidersFinished {
+            request_id, status, ..
+        }
+        | peer::DiscoveryEvent::RecordsFinished {
+            request_id, status, ..
+        } => (
+            CABI_DISCOVERY_EVENT_FINISHED,
+            request_id,
+            discovery_status_to_code(&status),
+            String::new(),
+            String::new(),
+        ),
+        peer::DiscoveryEvent::Record {
+            request_id,
+            value,
+            publisher,
+            ..
+        } => (
+            CABI_DISCOVERY_EVENT_RECORD,
+            request_id,
+            CABI_STATUS_SUCCESS,
+            publisher
+                .map(|peer_id| peer_id.to_string())
+                .unwrap_or_default(),
+            value.len().to_string(),
+        ),
+        peer::DiscoveryEvent::RecordStored {
+            request_id, status, ..
+        } => (
+            CABI_DISCOVERY_EVENT_RECORD_STORED,
+            request_id,
+            discovery_status_to_code(&status),
+            String::new(),
+            String::new(),
+        ),
+        peer::DiscoveryEvent::ProvidingStarted {
+            request_id, status, ..
+        } => (
+            CABI_DISCOVERY_EVENT_PROVIDING_STARTED,
+            request_id,
+            discovery_status_to_code(&status),
+            String::new(),
+            String::new(),
+        ),
+        peer::DiscoveryEvent::PeerRecord {
+            request_id,
+            target_peer_id,
+            ..
+        } => (
+            CABI_DISCOVERY_EVENT_PEER_RECORD,
+            request_id,
+            CABI_STATUS_SUCCESS,
+            target_peer_id.to_string(),
+            String::new(),
+        ),
+    }
+}
+
+/// Formats a peer probe report as the `key=value` text of
+/// [`cabi_node_probe_peer`].
+fn probe_report_text(report: &peer::PeerProbeReport) -> String {
+    let micros = |rtt: Option<Duration>| rtt.map(|rtt| rtt.as_micros()).unwrap_or_default();
+    let (outcome, address, reason) = match &report.reachability {
+        peer::ConnectOutcome::Direct { address } => ("direct", address.to_string(), None),
+        peer::ConnectOutcome::Relayed { address } => ("relayed", address.to_string(), None),
+        peer::ConnectOutcome::Failed { reason } => ("failed", String::new(), Some(reason.clone())),
+        peer::ConnectOutcome::SelfDial => ("self_dial", String::new(), None),
+    };
+    let error = reason.or_else(|| report.error.clone()).unwrap_or_default();
+    format!(
+        "outcome={outcome} address={address} count={} received={} min_us={} mean_us={} max_us={} error={error}",
+        report.count,
+        report.rtts.len(),
+        micros(report.min_rtt()),
+        micros(report.mean_rtt()),
+        micros(report.max_rtt()),
+    )
+}
+
+/// Splits a node event into its `CABI_NODE_EVENT_*` kind and payload.
+fn node_event_fields(event: &peer::NodeEvent) -> (c_int, Vec<u8>) {
+    match event {
+        peer::NodeEvent::Message(message) => (CABI_NODE_EVENT_MESSAGE, message.payload.clone()),
+        peer::NodeEvent::Discovery(event) => {
+            let peers_found = match event {
+                peer::DiscoveryEvent::Finished { peers_found, .. } => *peers_found,
+                peer::DiscoveryEvent::ProvidersFinished {
+                    providers_found, ..
+                } => *providers_found,
+                peer::DiscoveryEvent::RecordsFinished { records_found, .. } => *records_found,
+                peer::DiscoveryEvent::Address { .. }
+                | peer::DiscoveryEvent::Provider { .. }
+                | peer::DiscoveryEvent::Record { .. }
+                | peer::DiscoveryEvent::RecordStored { .. }
+                | peer::DiscoveryEvent::PeerRecord { .. }
+                | peer::DiscoveryEvent::ProvidingStarted { .. } => 0,
+            };
+            let (kind, request_id, status, peer_id, address) =
+                discovery_event_fields(event.clone());
+            let text = format!(
+                "kind={kind} request_id={request_id} status={status} peer_id={peer_id} address={address} peers_found={peers_found}"
+            );
+            (CABI_NODE_EVENT_DISCOVERY, text.into_bytes())
+        }
+        peer::NodeEvent::Network(peer::NetworkEvent::ConnectionEstablished(info)) => {
+            let text = format!(
+                "peer_id={} address={} expected_security={} expected_muxer={} agent_version={}",
+                info.peer_id,
+                info.address,
+                info.expected_security,
+                info.expected_muxer,
+                info.agent_version.as_deref().unwrap_or_default()
+            );
+            (CABI_NODE_EVENT_CONNECTION_ESTABLISHED, text.into_bytes())
+        }
+        peer::NodeEvent::Network(peer::NetworkEvent::ConnectionClosed(info)) => {
+            let text = format!(
+                "peer_id={} address={} remaining={} error={}",
+                info.peer_id,
+                info.address,
+                info.remaining,
+                info.error.as_deref().unwrap_or_default()
+            );
+            (CABI_NODE_EVENT_CONNECTION_CLOSED, text.into_bytes())
+        }
+        peer::NodeEvent::Network(peer::NetworkEvent::DialFinished(finished)) => {
+            let text = match &finished.result {
+                peer::ConnectOutcome::Direct { address } => {
+                    format!(
+                        "request_id={} outcome=direct address={address}",
+                        finished.request_id
+                    )
+                }
+                peer::ConnectOutcome::Relayed { address } => {
+                    format!(
+                        "request_id={} outcome=relayed address={address}",
+                        finished.request_id
+                    )
+                }
+                peer::ConnectOutcome::Failed { reason } => {
+                    format!(
+                        "request_id={} outcome=failed reason={reason}",
+                        finished.request_id
+                    )
+                }
+                peer::ConnectOutcome::SelfDial => {
+                    format!("request_id={} outcome=self_dial", finished.request_id)
+                }
+            };
+            (CABI_NODE_EVENT_DIAL_FINISHED, text.into_bytes())
+        }
+        peer::NodeEvent::Network(peer::NetworkEvent::Group(event)) => {
+            let text = match event {
+                peer::GroupEvent::MemberJoined { group, peer_id } => {
+                    format!("group={group} kind=joined peer_id={peer_id}")
+                }
+                peer::GroupEvent::MemberLeft { group, peer_id } => {
+                    format!("group={group} kind=left peer_id={peer_id}")
+                }
+                peer::GroupEvent::Message {
+                    group,
+                    from,
+                    payload,
+                } => format!(
+                    "group={group} kind=message peer_id={from} payload={}",
+                    hex::encode(payload)
+                ),
+            };
+            (CABI_NODE_EVENT_GROUP, text.into_bytes())
+        }
+        peer::NodeEvent::Network(peer::NetworkEvent::SubscriptionsChanged(change)) => {
+            let text = format!(
+                "subscribed={} unsubscribed={}",
+                change.subscribed.join(","),
+                change.unsubscribed.join(",")
+            );
+            (CABI_NODE_EVENT_SUBSCRIPTIONS, text.into_bytes())
+        }
+        peer::NodeEvent::Network(peer::NetworkEvent::IncompatiblePeer(info)) => {
+            let text = format!(
+                "peer_id={} address={} kind={} announced={} required={}",
+                info.peer_id,
+                info.address,
+                info.mismatch.kind.as_str(),
+                info.mismatch.announced,
+                info.mismatch.required
+            );
+            (CABI_NODE_EVENT_INCOMPATIBLE_PEER, text.into_bytes())
+        }
+        peer::NodeEvent::Network(peer::NetworkEvent::PeerPenalized(info)) => {
+            let text = format!(
+                "peer_id={} reason={} banned={} offences={} threshold={} detail={}",
+                info.peer_id,
+                info.reason.as_str(),
+                info.banned,
+                info.offences,
+                info.threshold,
+                info.detail
+            );
+            (CABI_NODE_EVENT_PEER_PENALIZED, text.into_bytes())
+        }
+        peer::NodeEvent::Network(event) => {
+            (CABI_NODE_EVENT_NETWORK, format!("{event:?}").into_bytes())
+        }
+        peer::NodeEvent::NatStatus(status) => {
+            let text = match status {
+                autonat::NatStatus::Public(address) => {
+                    format!("status={CABI_AUTONAT_PUBLIC} address={address}")
+                }
+                autonat::NatStatus::Private => format!("status={CABI_AUTONAT_PRIVATE}"),
+                autonat::NatStatus::Unknown => format!("status={CABI_AUTONAT_UNKNOWN}"),
+            };
+            (CABI_NODE_EVENT_NAT_STATUS, text.into_bytes())
+        }
+        peer::NodeEvent::Error(message) => (CABI_NODE_EVENT_ERROR, message.clone().into_bytes()),
+    }
+}
+
 fn discovery_status_to_code(status: &peer::DiscoveryStatus) -> c_int {
     match status {
         peer::DiscoveryStatus::Success => CABI_STATUS_SUCCESS,
         peer::DiscoveryStatus::NotFound => CABI_STATUS_NOT_FOUND,
         peer::DiscoveryStatus::Timeout => CABI_STATUS_TIMEOUT,
+        peer::DiscoveryStatus::PartialSuccess { .. } => CABI_STATUS_PARTIAL_SUCCESS,
         peer::DiscoveryStatus::InternalError => CABI_STATUS_INTERNAL_ERROR,
     }
 }
\ No newline at end of file
@@ -0,0 +1,450 @@
+//! Generic in-process event bus behind every queue handed to the host.
+//!
+//! An [`EventBus`] is the consuming end and [`EventSender`]s are the cheap,
+//! cloneable producing ends. Bounded buses apply a [`DropPolicy`] when full,
+//! and every bus counts published, delivered and dropped events, so a new
+//! event type only needs `EventBus<NewEvent>` instead of a bespoke sender.
+//!
+//! Events may carry a time to live. An event still queued when it expires is
+//! never delivered; it is counted and kept as a [`DeadLetter`] instead.
+
+use anyhow::{anyhow, Result};
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, Notify};
+
+/// Number of expired events a bus keeps for inspection; older ones are discarded.
+pub const DEAD_LETTER_CAPACITY: usize = 64;
+
+/// What a full bounded bus does with an event sent without waiting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Reject the new event; `try_send` fails.
+    #[default]
+    DropNewest,
+    /// Evict the oldest queued event to make room for the new one.
+    DropOldest,
+}
+
+/// Capacity mode of an [`EventBus`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusMode {
+    /// Hold at most `capacity` events, applying `policy` when full.
+    Bounded { capacity: usize, policy: DropPolicy },
+    /// Hold any number of events; nothing is ever dropped.
+    Unbounded,
+}
+
+impl BusMode {
+    /// Bounded mode rejecting new events when full.
+    pub fn bounded(capacity: usize) -> Self {
+        Self::Bounded {
+            capacity,
+            policy: DropPolicy::DropNewest,
+        }
+    }
+}
+
+/// Counters of an [`EventBus`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BusStats {
+    /// Events accepted into the queue.
+    pub published: u64,
+    /// Events taken out of the queue by the consumer.
+    pub delivered: u64,
+    /// Events rejected because the queue was full, or evicted by [`DropPolicy::DropOldest`].
+    pub dropped: u64,
+    /// Events whose time to live ran out before they were delivered.
+    pub expired: u64,
+    /// Events currently waiting in the queue, including expired ones not
+    /// purged yet.
+    pub depth: usize,
+}
+
+/// Event that expired in the queue before the consumer took it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter<T> {
+    pub event: T,
+    /// Time the event spent queued.
+    pub queued_for: Duration,
+}
+
+/// Queued event with its expiry.
+struct Entry<T> {
+    event: T,
+    queued_at: Instant,
+    expires_at: Option<Instant>,
+}
+
+impl<T> Entry<T> {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Outcome of queueing an event; an event rejected by a full queue is handed back.
+enum Push<T> {
+    Queued,
+    Full(T),
+    Closed,
+}
+
+struct Shared<T> {
+    queue: Mutex<VecDeque<Entry<T>>>,
+    dead_letters: Mutex<VecDeque<DeadLetter<T>>>,
+    capacity: Option<usize>,
+    policy: DropPolicy,
+    /// Signalled when an event was queued or the last sender went away.
+    readable: Notify,
+    /// Signalled when space was freed or the consumer went away.
+    writable: Notify,
+    receiver_alive: AtomicBool,
+    senders: AtomicUsize,
+    published: AtomicU64,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    expired: AtomicU64,
+}
+
+impl<T> Shared<T> {
+    fn push(&self, event: T, ttl: Option<Duration>) -> Push<T> {
+        if !self.receiver_alive.load(Ordering::Acquire) {
+            return Push::Closed;
+        }
+        let Ok(mut queue) = self.queue.lock() else {
+            tracing::warn!(target: "messaging", "event bus lock poisoned");
+            return Push::Closed;
+        };
+        let now = Instant::now();
+        if let Some(capacity) = self.capacity {
+            if queue.len() >= capacity {
+                // Expired events must not take space from live ones.
+                self.purge_expired(&mut queue, now);
+            }
+            if queue.len() >= capacity {
+                match self.policy {
+                    DropPolicy::DropNewest => return Push::Full(event),
+                    DropPolicy::DropOldest => {
+                        if queue.pop_front().is_none() {
+                            return Push::Full(event);
+                        }
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+        queue.push_back(Entry {
+            event,
+            queued_at: now,
+            expires_at: ttl.map(|ttl| now + ttl),
+        });
+        drop(queue);
+
+        self.published.fetch_add(1, Ordering::Relaxed);
+        self.readable.notify_one();
+        Push::Queued
+    }
+
+    fn pop(&self) -> Option<T> {
+        let now = Instant::now();
+        let mut queue = self.queue.lock().ok()?;
+        loop {
+            let entry = queue.pop_front()?;
+            self.writable.notify_one();
+            if entry.is_expired(now) {
+                self.bury(entry, now);
+                continue;
+            }
+            self.delivered.fetch_add(1, Ordering::Relaxed);
+            return Some(entry.event);
+        }
+    }
+
+    /// Moves every expired event out of `queue` into the dead letters.
+    fn purge_expired(&self, queue: &mut VecDeque<Entry<T>>, now: Instant) {
+        if !queue.iter().any(|entry| entry.is_expired(now)) {
+            return;
+        }
+        let (expired, live): (VecDeque<_>, VecDeque<_>) =
+            queue.drain(..).partition(|entry| entry.is_expired(now));
+        *queue = live;
+        for entry in expired {
+            self.bury(entry, now);
+        }
+    }
+
+    /// Counts an expired event and keeps it as a dead letter.
+    fn bury(&self, entry: Entry<T>, now: Instant) {
+        self.expired.fetch_add(1, Ordering::Relaxed);
+        let Ok(mut dead_letters) = self.dead_letters.lock() else {
+            tracing::warn!(target: "messaging", "dead letter lock poisoned");
+            return;
+        };
+        if dead_letters.len() >= DEAD_LETTER_CAPACITY {
+            dead_letters.pop_front();
+        }
+        dead_letters.push_back(DeadLetter {
+            event: entry.event,
+            queued_for: now.saturating_duration_since(entry.queued_at),
+        });
+    }
+
+    fn depth(&self) -> usize {
+        self.queue
+            .lock()
+            .map(|queue| queue.len())
+            .unwrap_or_default()
+    }
+
+    fn stats(&self) -> BusStats {
+        BusStats {
+            published: self.published.load(Ordering::Relaxed),
+            delivered: self.delivered.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            depth: self.depth(),
+        }
+    }
+}
+
+/// Consuming end of a queue of `T` events.
+pub struct EventBus<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> EventBus<T> {
+    /// Creates an empty bus with the given capacity mode.
+    pub fn new(mode: BusMode) -> Self {
+        let (capacity, policy) = match mode {
+            BusMode::Bounded { capacity, policy } => (Some(capacity), policy),
+            BusMode::Unbounded => (None, DropPolicy::default()),
+        };
+        Self {
+            shared: Arc::new(Shared {
+                queue: Mutex::new(VecDeque::new()),
+                dead_letters: Mutex::new(VecDeque::new()),
+                capacity,
+                policy,
+                readable: Notify::new(),
+                writable: Notify::new(),
+                receiver_alive: AtomicBool::new(true),
+                senders: AtomicUsize::new(0),
+                published: AtomicU64::new(0),
+                delivered: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+                expired: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Returns a new sender publishing into this bus.
+    pub fn sender(&self) -> EventSender<T> {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        EventSender {
+            inner: SenderInner::Bus(self.shared.clone()),
+        }
+    }
+
+    /// Takes the oldest event without waiting.
+    pub fn try_recv(&self) -> Option<T> {
+        self.shared.pop()
+    }
+
+    /// Waits for the next event. Returns `None` once the queue is empty and
+    /// every sender has been dropped.
+    pub async fn recv(&self) -> Option<T> {
+        loop {
+            if let Some(event) = self.shared.pop() {
+                return Some(event);
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return None;
+            }
+            self.shared.readable.notified().await;
+        }
+    }
+
+    /// Returns the bus counters.
+    pub fn stats(&self) -> BusStats {
+        self.shared.stats()
+    }
+
+    /// Takes the most recent events that expired before delivery, oldest first.
+    pub fn take_dead_letters(&self) -> Vec<DeadLetter<T>> {
+        match self.shared.dead_letters.lock() {
+            Ok(mut dead_letters) => dead_letters.drain(..).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl<T> Drop for EventBus<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Release);
+        self.shared.writable.notify_waiters();
+        self.shared.writable.notify_one();
+    }
+}
+
+impl<T> fmt::Debug for EventBus<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("capacity", &self.shared.capacity)
+            .field("policy", &self.shared.policy)
+            .field("stats", &self.shared.stats())
+            .finish()
+    }
+}
+
+enum SenderInner<T> {
+    Bus(Arc<Shared<T>>),
+    /// Forwards into a caller-provided tokio channel, e.g. for streaming results.
+    Channel(mpsc::Sender<T>),
+}
+
+/// Cloneable producing end of an [`EventBus`].
+pub struct EventSender<T> {
+    inner: SenderInner<T>,
+}
+
+impl<T> EventSender<T> {
+    /// Publishes an event without waiting. A full bus applies its
+    /// [`DropPolicy`]; with [`DropPolicy::DropNewest`] the event is rejected.
+    pub fn try_send(&self, event: T) -> Result<()> {
+        self.try_send_with_ttl(event, None)
+    }
+
+    /// Publishes an event without waiting, like [`Self::try_send`]. The event
+    /// is not delivered once `ttl` elapsed; senders forwarding into a tokio
+    /// channel ignore the time to live.
+    pub fn try_send_with_ttl(&self, event: T, ttl: Option<Duration>) -> Result<()> {
+        match &self.inner {
+            SenderInner::Bus(shared) => match shared.push(event, ttl) {
+                Push::Queued => Ok(()),
+                Push::Full(_) => {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    Err(anyhow!("event bus full"))
+                }
+                Push::Closed => Err(anyhow!("event bus closed")),
+            },
+            SenderInner::Channel(sender) => sender.try_send(event).map_err(|err| anyhow!("{err}")),
+        }
+    }
+
+    /// Publishes an event, waiting for space while the bus is full.
+    pub async fn send(&self, mut event: T) -> Result<()> {
+        match &self.inner {
+            SenderInner::Bus(shared) => loop {
+                match shared.push(event, None) {
+                    Push::Queued => return Ok(()),
+                    Push::Full(rejected) => {
+                        event = rejected;
+                        shared.writable.notified().await;
+                    }
+                    Push::Closed => return Err(anyhow!("event bus closed")),
+                }
+            },
+            SenderInner::Channel(sender) => {
+                sender.send(event).await.map_err(|err| anyhow!("{err}"))
+            }
+        }
+    }
+
+    /// Publishes an event, waiting up to `timeout` for space. The event is
+    /// dropped if the bus is still full at the deadline.
+    pub async fn send_timeout(&self, event: T, timeout: Duration) -> Result<()> {
+        match tokio::time::timeout(timeout, self.send(event)).await {
+            Ok(result) => result,
+            Err(_) => {
+                if let SenderInner::Bus(shared) = &self.inner {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(anyhow!("event bus full after {timeout:?}"))
+            }
+        }
+    }
+
+    /// Number of events currently waiting in the queue.
+    pub fn depth(&self) -> usize {
+        match &self.inner {
+            SenderInner::Bus(shared) => shared.depth(),
+            SenderInner::Channel(sender) => sender.max_capacity() - sender.capacity(),
+        }
+    }
+
+    /// Returns `true` once the consumer is gone and events can no longer be
+    /// delivered.
+    pub fn is_closed(&self) -> bool {
+        match &self.inner {
+            SenderInner::Bus(shared) => !shared.receiver_alive.load(Ordering::Acquire),
+            SenderInner::Channel(sender) => sender.is_closed(),
+        }
+    }
+
+    /// Returns the counters of the bus; only `depth` is known for senders
+    /// forwarding into a tokio channel.
+    pub fn stats(&self) -> BusStats {
+        match &self.inner {
+            SenderInner::Bus(shared) => shared.stats(),
+            SenderInner::Channel(_) => BusStats {
+                depth: self.depth(),
+                ..BusStats::default()
+            },
+        }
+    }
+}
+
+impl<T> Clone for EventSender<T> {
+    fn clone(&self) -> Self {
+        let inner = match &self.inner {
+            SenderInner::Bus(shared) => {
+                shared.senders.fetch_add(1, Ordering::AcqRel);
+                SenderInner::Bus(shared.clone())
+            }
+            SenderInner::Channel(sender) => SenderInner::Channel(sender.clone()),
+        };
+        Self { inner }
+    }
+}
+
+impl<T> Drop for EventSender<T> {
+    fn drop(&mut self) {
+        if let SenderInner::Bus(shared) = &self.inner {
+            if shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+                shared.readable.notify_waiters();
+                shared.readable.notify_one();
+            }
+        }
+    }
+}
+
+impl<T> From<mpsc::Sender<T>> for EventSender<T> {
+    fn from(sender: mpsc::Sender<T>) -> Self {
+        Self {
+            inner: SenderInner::Channel(sender),
+        }
+    }
+}
+
+impl<T> fmt::Debug for EventSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.inner {
+            SenderInner::Bus(shared) => f
+                .debug_struct("EventSender")
+                .field("capacity", &shared.capacity)
+                .field("stats", &shared.stats())
+                .finish(),
+            SenderInner::Channel(sender) => f
+                .debug_struct("EventSender")
+                .field("channel", sender)
+                .finish(),
+        }
+    }
+}
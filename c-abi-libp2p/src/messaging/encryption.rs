@@ -0,0 +1,92 @@
+//! Optional per-topic payload encryption with a shared secret.
+//!
+//! Wire format: `b"pxc1" | nonce (24 bytes) | XChaCha20-Poly1305 ciphertext`.
+//! The topic name is authenticated as associated data, so a ciphertext cannot
+//! be replayed on another topic sharing the same key.
+
+use anyhow::{anyhow, Result};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use std::fmt;
+
+const CIPHER_MAGIC: &[u8; 4] = b"pxc1";
+const NONCE_LEN: usize = 24;
+const CIPHER_HEADER_LEN: usize = CIPHER_MAGIC.len() + NONCE_LEN;
+
+/// Length in bytes of a topic key.
+pub const TOPIC_KEY_LEN: usize = 32;
+
+/// Symmetric cipher sealing the payloads of one topic.
+pub struct TopicCipher {
+    topic: String,
+    cipher: XChaCha20Poly1305,
+}
+
+impl TopicCipher {
+    /// Creates a cipher for `topic` keyed with the shared secret `key`.
+    pub fn new(topic: impl Into<String>, key: &[u8; TOPIC_KEY_LEN]) -> Self {
+        Self {
+            topic: topic.into(),
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    /// Name of the topic the cipher is bound to.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Encrypts `payload` under a fresh random nonce.
+    pub fn encrypt(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: payload,
+                    aad: self.topic.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("failed to encrypt payload for topic {}", self.topic))?;
+
+        let mut out = Vec::with_capacity(CIPHER_HEADER_LEN + ciphertext.len());
+        out.extend_from_slice(CIPHER_MAGIC);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    /// Decrypts and authenticates a payload produced by [`Self::encrypt`]
+    /// with the same key and topic.
+    pub fn decrypt(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        if bytes.len() < CIPHER_HEADER_LEN {
+            return Err(anyhow!("ciphertext too short: {} bytes", bytes.len()));
+        }
+        let (magic, rest) = bytes.split_at(CIPHER_MAGIC.len());
+        if magic != CIPHER_MAGIC {
+            return Err(anyhow!("ciphertext magic mismatch"));
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        self.cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: self.topic.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("failed to decrypt payload for topic {}", self.topic))
+    }
+}
+
+impl fmt::Debug for TopicCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TopicCipher")
+            .field("topic", &self.topic)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,368 @@
+//! Optional replay-protection envelope for gossip payloads.
+//!
+//! Wire format: `b"pxe1" | nonce (u64 BE) | timestamp ms (u64 BE) |
+//! [trace id (16 bytes)] | payload`.
+//!
+//! The trace id is optional: the top bit of the timestamp word marks its
+//! presence, so envelopes without one are byte-for-byte those of nodes that
+//! predate tracing.
+
+use anyhow::{anyhow, Result};
+use libp2p::PeerId;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const ENVELOPE_MAGIC: &[u8; 4] = b"pxe1";
+const ENVELOPE_HEADER_LEN: usize = ENVELOPE_MAGIC.len() + 8 + 8;
+/// Bit of the timestamp word telling that a trace id follows the header;
+/// real timestamps never reach it.
+const TRACE_ID_FLAG: u64 = 1 << 63;
+/// Length of a [`TraceId`] in bytes.
+pub const TRACE_ID_LEN: usize = 16;
+
+/// Identifier following a message across nodes in its envelope, so the logs
+/// of its publisher and receivers can be correlated.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceId(pub [u8; TRACE_ID_LEN]);
+
+impl TraceId {
+    /// Derives an id for the envelope with `nonce` published by the identity
+    /// `origin`, for publishers that were not given one.
+    pub fn derive(origin: &[u8], nonce: u64) -> Self {
+        let digest = Sha256::new()
+            .chain_update(origin)
+            .chain_update(nonce.to_be_bytes())
+            .finalize();
+        let mut id = [0u8; TRACE_ID_LEN];
+        id.copy_from_slice(&digest[..TRACE_ID_LEN]);
+        Self(id)
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TraceId({self})")
+    }
+}
+
+/// Envelopes a [`ReplayCache`] remembers unless configured otherwise.
+pub const DEFAULT_REPLAY_CACHE_ENTRIES: usize = 65_536;
+
+/// Payload wrapped with the sender's nonce and timestamp.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// Sender-chosen value unique per message.
+    pub nonce: u64,
+    /// Sender wall-clock time in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// Trace id set by the publisher, carried unchanged to every receiver.
+    pub trace_id: Option<TraceId>,
+    /// Application payload.
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    /// Wraps `payload` with the given nonce and the current time.
+    pub fn new(nonce: u64, payload: Vec<u8>) -> Self {
+        Self {
+            nonce,
+            timestamp_ms: unix_time_ms(),
+            trace_id: None,
+            payload,
+        }
+    }
+
+    /// Serializes the envelope into its wire format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(ENVELOPE_HEADER_LEN + TRACE_ID_LEN + self.payload.len());
+        out.extend_from_slice(ENVELOPE_MAGIC);
+        out.extend_from_slice(&self.nonce.to_be_bytes());
+        match &self.trace_id {
+            Some(trace_id) => {
+                out.extend_from_slice(&(self.timestamp_ms | TRACE_ID_FLAG).to_be_bytes());
+                out.extend_from_slice(&trace_id.0);
+            }
+            None => out.extend_from_slice(&self.timestamp_ms.to_be_bytes()),
+        }
+        out.extend_from_slice(&self.payload);
+        out
+    }
+
+    /// Parses an envelope from its wire format.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < ENVELOPE_HEADER_LEN {
+            return Err(anyhow!("envelope too short: {} bytes", bytes.len()));
+        }
+        let (magic, rest) = bytes.split_at(ENVELOPE_MAGIC.len());
+        if magic != ENVELOPE_MAGIC {
+            return Err(anyhow!("envelope magic mismatch"));
+        }
+        let (nonce, rest) = rest.split_at(8);
+        let (timestamp, payload) = rest.split_at(8);
+        let timestamp = u64::from_be_bytes(timestamp.try_into()?);
+        let (trace_id, payload) = if timestamp & TRACE_ID_FLAG == 0 {
+            (None, payload)
+        } else {
+            if payload.len() < TRACE_ID_LEN {
+                return Err(anyhow!("envelope too short for its trace id"));
+            }
+            let (trace_id, payload) = payload.split_at(TRACE_ID_LEN);
+            (Some(TraceId(trace_id.try_into()?)), payload)
+        };
+
+        Ok(Self {
+            nonce: u64::from_be_bytes(nonce.try_into()?),
+            timestamp_ms: timestamp & !TRACE_ID_FLAG,
+            trace_id,
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// Reasons an envelope is refused by the [`ReplayCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The timestamp is older than the replay window.
+    Expired,
+    /// The timestamp is further in the future than the replay window.
+    FromFuture,
+    /// The same envelope was already accepted.
+    Replayed,
+}
+
+/// Sender of an envelope and its nonce; unsigned messages have no sender and
+/// share one nonce space.
+type ReplayKey = (Option<PeerId>, u64);
+
+/// Sliding cache of recently accepted envelopes, keyed by sender and nonce.
+///
+/// Entries are kept until their timestamp falls out of the window, after which
+/// the envelope is refused as [`ReplayError::Expired`] instead. The cache holds
+/// at most its maximum number of entries: when full, the entry closest to
+/// expiring is forgotten first, so a flood of messages can only make the
+/// oldest envelopes replayable again, not grow memory.
+#[derive(Debug)]
+pub struct ReplayCache {
+    window_ms: u64,
+    max_entries: usize,
+    seen: HashSet<ReplayKey>,
+    expiry: BTreeSet<(u64, ReplayKey)>,
+}
+
+impl ReplayCache {
+    /// Creates a cache accepting timestamps within `window` of the local clock
+    /// and remembering up to [`DEFAULT_REPLAY_CACHE_ENTRIES`] envelopes.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window_ms: window.as_millis() as u64,
+            max_entries: DEFAULT_REPLAY_CACHE_ENTRIES,
+            seen: HashSet::new(),
+            expiry: BTreeSet::new(),
+        }
+    }
+
+    /// Sets the number of envelopes remembered, at least one.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// Accepts the envelope of `sender` if it is fresh and has not been seen
+    /// before.
+    pub fn check(
+        &mut self,
+        sender: Option<PeerId>,
+        envelope: &Envelope,
+    ) -> Result<(), ReplayError> {
+        self.check_at(sender, envelope, unix_time_ms())
+    }
+
+    pub(crate) fn check_at(
+        &mut self,
+        sender: Option<PeerId>,
+        envelope: &Envelope,
+        now_ms: u64,
+    ) -> Result<(), ReplayError> {
+        self.evict(now_ms);
+
+        let expires_at = envelope.timestamp_ms.saturating_add(self.window_ms);
+        if expires_at <= now_ms {
+            return Err(ReplayError::Expired);
+        }
+        if envelope.timestamp_ms > now_ms.saturating_add(self.window_ms) {
+            return Err(ReplayError::FromFuture);
+        }
+
+        let key = (sender, envelope.nonce);
+        if self.seen.contains(&key) {
+            return Err(ReplayError::Replayed);
+        }
+        while self.seen.len() >= self.max_entries {
+            let Some((_, oldest)) = self.expiry.pop_first() else {
+                break;
+            };
+            self.seen.remove(&oldest);
+        }
+        self.seen.insert(key);
+        self.expiry.insert((expires_at, key));
+        Ok(())
+    }
+
+    /// Number of envelopes currently remembered.
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Returns `true` when no envelope is remembered.
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    fn evict(&mut self, now_ms: u64) {
+        while let Some(&(expires_at, key)) = self.expiry.first() {
+            if expires_at > now_ms {
+                break;
+            }
+            self.expiry.pop_first();
+            self.seen.remove(&key);
+        }
+    }
+}
+
+/// Current wall-clock time in milliseconds since the Unix epoch.
+pub(crate) fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW_MS: u64 = 1_700_000_000_000;
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    fn envelope(nonce: u64, timestamp_ms: u64) -> Envelope {
+        Envelope {
+            nonce,
+            timestamp_ms,
+            trace_id: None,
+            payload: b"payload".to_vec(),
+        }
+    }
+
+    #[test]
+    fn envelope_round_trips() {
+        let original = envelope(7, NOW_MS);
+        assert_eq!(Envelope::decode(&original.encode()).unwrap(), original);
+        assert!(Envelope::decode(b"pxe1").is_err());
+        assert!(Envelope::decode(&[0; ENVELOPE_HEADER_LEN]).is_err());
+    }
+
+    #[test]
+    fn trace_id_is_carried_only_when_set() {
+        let untraced = envelope(7, NOW_MS);
+        assert_eq!(
+            untraced.encode().len(),
+            ENVELOPE_HEADER_LEN + untraced.payload.len()
+        );
+
+        let traced = Envelope {
+            trace_id: Some(TraceId([9; TRACE_ID_LEN])),
+            ..untraced
+        };
+        let encoded = traced.encode();
+        assert_eq!(
+            encoded.len(),
+            ENVELOPE_HEADER_LEN + TRACE_ID_LEN + traced.payload.len()
+        );
+        assert_eq!(Envelope::decode(&encoded).unwrap(), traced);
+        assert!(Envelope::decode(&encoded[..ENVELOPE_HEADER_LEN + 4]).is_err());
+    }
+
+    #[test]
+    fn replayed_envelope_is_refused() {
+        let mut cache = ReplayCache::new(WINDOW);
+        let sender = Some(PeerId::random());
+        assert_eq!(cache.check_at(sender, &envelope(1, NOW_MS), NOW_MS), Ok(()));
+        assert_eq!(
+            cache.check_at(sender, &envelope(1, NOW_MS), NOW_MS + 1),
+            Err(ReplayError::Replayed)
+        );
+        // The nonce alone identifies the envelope of a sender.
+        let mut altered = envelope(1, NOW_MS + 5);
+        altered.payload = b"other".to_vec();
+        assert_eq!(
+            cache.check_at(sender, &altered, NOW_MS + 10),
+            Err(ReplayError::Replayed)
+        );
+    }
+
+    #[test]
+    fn nonces_are_scoped_per_sender() {
+        let mut cache = ReplayCache::new(WINDOW);
+        let (first, second) = (Some(PeerId::random()), Some(PeerId::random()));
+        assert_eq!(cache.check_at(first, &envelope(1, NOW_MS), NOW_MS), Ok(()));
+        assert_eq!(cache.check_at(second, &envelope(1, NOW_MS), NOW_MS), Ok(()));
+        assert_eq!(cache.check_at(None, &envelope(1, NOW_MS), NOW_MS), Ok(()));
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn timestamps_outside_the_window_are_refused() {
+        let mut cache = ReplayCache::new(WINDOW);
+        let window_ms = WINDOW.as_millis() as u64;
+        assert_eq!(
+            cache.check_at(None, &envelope(1, NOW_MS - window_ms), NOW_MS),
+            Err(ReplayError::Expired)
+        );
+        assert_eq!(
+            cache.check_at(None, &envelope(2, NOW_MS + window_ms + 1), NOW_MS),
+            Err(ReplayError::FromFuture)
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn expired_entries_are_evicted() {
+        let mut cache = ReplayCache::new(WINDOW);
+        assert_eq!(cache.check_at(None, &envelope(1, NOW_MS), NOW_MS), Ok(()));
+        let later = NOW_MS + WINDOW.as_millis() as u64;
+        assert_eq!(cache.check_at(None, &envelope(2, later), later), Ok(()));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn full_cache_forgets_the_oldest_entry() {
+        let mut cache = ReplayCache::new(WINDOW).with_max_entries(2);
+        let sender = Some(PeerId::random());
+        for nonce in 0..3 {
+            let sent = NOW_MS + nonce;
+            assert_eq!(
+                cache.check_at(sender, &envelope(nonce, sent), NOW_MS + 3),
+                Ok(())
+            );
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.check_at(sender, &envelope(2, NOW_MS + 2), NOW_MS + 3),
+            Err(ReplayError::Replayed)
+        );
+        assert_eq!(
+            cache.check_at(sender, &envelope(0, NOW_MS), NOW_MS + 3),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Wire format of group frames.
+//!
+//! A group rides on the gossipsub topic `pxg/<name>`. Every frame starts with
+//! `b"pxg1"` and a kind byte:
+//! - `0` message: application payload,
+//! - `1` join and `2` leave: no body,
+//! - `3` roster: member count (u16 BE), then `len (u8) | peer id` per member.
+//!
+//! Frames are authored by the gossipsub message source, so the sender of a
+//! join, leave or roster is the member it speaks for.
+
+use anyhow::{anyhow, Result};
+use libp2p::PeerId;
+
+const GROUP_MAGIC: &[u8; 4] = b"pxg1";
+const GROUP_HEADER_LEN: usize = GROUP_MAGIC.len() + 1;
+
+const KIND_MESSAGE: u8 = 0;
+const KIND_JOIN: u8 = 1;
+const KIND_LEAVE: u8 = 2;
+const KIND_ROSTER: u8 = 3;
+
+/// Prefix of the gossipsub topics carrying group frames.
+pub const GROUP_TOPIC_PREFIX: &str = "pxg/";
+
+/// Largest roster a frame may carry.
+pub const MAX_GROUP_ROSTER: usize = 1024;
+
+/// Largest encoded peer id accepted in a roster: a multihash with a 64-byte
+/// digest and its two header bytes.
+const MAX_PEER_ID_LEN: usize = 66;
+
+/// Name of the gossipsub topic of `group`.
+pub fn group_topic(group: &str) -> String {
+    format!("{GROUP_TOPIC_PREFIX}{group}")
+}
+
+/// Frame exchanged on a group topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupFrame {
+    /// Application payload for every member.
+    Message(Vec<u8>),
+    /// The sender joined the group.
+    Join,
+    /// The sender left the group.
+    Leave,
+    /// Members known to the sender, announced periodically.
+    Roster(Vec<PeerId>),
+}
+
+impl GroupFrame {
+    /// Serializes the frame into its wire format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(GROUP_HEADER_LEN);
+        out.extend_from_slice(GROUP_MAGIC);
+        match self {
+            GroupFrame::Message(payload) => {
+                out.push(KIND_MESSAGE);
+                out.extend_from_slice(payload);
+            }
+            GroupFrame::Join => out.push(KIND_JOIN),
+            GroupFrame::Leave => out.push(KIND_LEAVE),
+            GroupFrame::Roster(members) => {
+                out.push(KIND_ROSTER);
+                let members = &members[..members.len().min(MAX_GROUP_ROSTER)];
+                out.extend_from_slice(&(members.len() as u16).to_be_bytes());
+                for member in members {
+                    let bytes = member.to_bytes();
+                    out.push(bytes.len() as u8);
+                    out.extend_from_slice(&bytes);
+                }
+            }
+        }
+        out
+    }
+
+    /// Parses a frame from its wire format.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < GROUP_HEADER_LEN {
+            return Err(anyhow!("group frame too short: {} bytes", bytes.len()));
+        }
+        let (magic, rest) = bytes.split_at(GROUP_MAGIC.len());
+        if magic != GROUP_MAGIC {
+            return Err(anyhow!("group frame magic mismatch"));
+        }
+        let (kind, body) = (rest[0], &rest[1..]);
+
+        match kind {
+            KIND_MESSAGE => Ok(GroupFrame::Message(body.to_vec())),
+            KIND_JOIN => Ok(GroupFrame::Join),
+            KIND_LEAVE => Ok(GroupFrame::Leave),
+            KIND_ROSTER => decode_roster(body).map(GroupFrame::Roster),
+            other => Err(anyhow!("unknown group frame kind {other}")),
+        }
+    }
+}
+
+fn decode_roster(body: &[u8]) -> Result<Vec<PeerId>> {
+    let count = body
+        .get(..2)
+        .ok_or_else(|| anyhow!("group roster missing member count"))?;
+    let count = u16::from_be_bytes([count[0], count[1]]) as usize;
+    if count > MAX_GROUP_ROSTER {
+        return Err(anyhow!("group roster too large: {count} members"));
+    }
+    let mut rest = &body[2..];
+    // Every member takes at least a length byte and one id byte.
+    if rest.len() < count * 2 {
+        return Err(anyhow!(
+            "group roster of {count} members truncated to {} bytes",
+            rest.len()
+        ));
+    }
+
+    let mut members = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (&len, tail) = rest
+            .split_first()
+            .ok_or_else(|| anyhow!("group roster truncated"))?;
+        let len = len as usize;
+        if len == 0 || len > MAX_PEER_ID_LEN {
+            return Err(anyhow!("group roster member id of {len} bytes"));
+        }
+        if tail.len() < len {
+            return Err(anyhow!("group roster truncated"));
+        }
+        let (bytes, tail) = tail.split_at(len);
+        members.push(PeerId::from_bytes(bytes)?);
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        return Err(anyhow!("group roster has {} trailing bytes", rest.len()));
+    }
+    Ok(members)
+}
@@ -1,28 +1,53 @@
 use anyhow::{anyhow, Result};
-use tokio::sync::mpsc;
+use std::time::Duration;
+
+use super::{
+    bus::{BusMode, BusStats, DeadLetter, EventBus, EventSender},
+    router::RoutedMessage,
+};
 
 /// Default capacity for the message queue.
 pub const DEFAULT_MESSAGE_QUEUE_CAPACITY: usize = 64;
 
-/// Thin wrapper around a bounded channel used for passing payloads into the core.
+/// Thresholds after which the consumer of the inbound queue is considered slow.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SlowConsumerSettings {
+    /// The consumer is slow when the queue stayed non-empty without any
+    /// dequeue for this long. Also the window the drop rate is measured over.
+    pub stall_threshold: Duration,
+    /// The consumer is slow when more than this fraction (0.0 to 1.0) of the
+    /// inbound messages of a window was dropped because the queue was full.
+    pub max_drop_rate: f64,
+    /// While the consumer is slow, ignore inbound gossip instead of queueing
+    /// it, so the consumer can catch up.
+    pub pause_gossip: bool,
+}
+
+/// Bounded queue of inbound messages, each tagged with its topic.
 #[derive(Debug)]
 pub struct MessageQueue {
-    sender: mpsc::Sender<Vec<u8>>,
-    receiver: mpsc::Receiver<Vec<u8>>,
+    bus: EventBus<RoutedMessage>,
+    sender: EventSender<RoutedMessage>,
 }
 
 #[derive(Clone, Debug)]
 
 // Multiple producer, single consumer queue
 pub struct MessageQueueSender {
-    sender: mpsc::Sender<Vec<u8>>,
+    sender: EventSender<RoutedMessage>,
 }
 
 impl MessageQueue {
     /// Creates a new queue with the given capacity.
     pub fn new(capacity: usize) -> Self {
-        let (sender, receiver) = mpsc::channel(capacity);
-        Self { sender, receiver }
+        Self::with_mode(BusMode::bounded(capacity))
+    }
+
+    /// Creates a new queue with an explicit capacity mode and drop policy.
+    pub fn with_mode(mode: BusMode) -> Self {
+        let bus = EventBus::new(mode);
+        let sender = bus.sender();
+        Self { bus, sender }
     }
 
     /// Returns a clone of the sender so producers can enqueue messages.
@@ -32,33 +57,85 @@ impl MessageQueue {
         }
     }
 
-    /// Enqueues a payload, waiting if the bounded channel is full.
-    pub async fn enqueue(&self, payload: Vec<u8>) -> Result<()> {
+    /// Enqueues a message, waiting if the bounded queue is full.
+    pub async fn enqueue(&self, message: RoutedMessage) -> Result<()> {
         self.sender
-            .send(payload)
+            .send(message)
             .await
             .map_err(|err| anyhow!("failed to enqueue message: {err}"))
     }
 
-    /// Attempts to dequeue a payload without blocking.
-    pub fn try_dequeue(&mut self) -> Option<Vec<u8>> {
-        self.receiver.try_recv().ok()
+    /// Attempts to dequeue a message without blocking.
+    pub fn try_dequeue(&mut self) -> Option<RoutedMessage> {
+        self.bus.try_recv()
+    }
+
+    /// Waits for the next message.
+    pub async fn dequeue(&self) -> Option<RoutedMessage> {
+        self.bus.recv().await
+    }
+
+    /// Returns the published, delivered and dropped counters of the queue.
+    pub fn stats(&self) -> BusStats {
+        self.bus.stats()
+    }
+
+    /// Takes the most recent messages that expired before being dequeued.
+    pub fn take_dead_letters(&self) -> Vec<DeadLetter<RoutedMessage>> {
+        self.bus.take_dead_letters()
     }
 }
 
 impl MessageQueueSender {
-    /// Enqueues a payload, waiting if the bounded channel is full.
-    pub async fn enqueue(&self, payload: Vec<u8>) -> Result<()> {
+    /// Enqueues a message, waiting if the bounded queue is full.
+    pub async fn enqueue(&self, message: RoutedMessage) -> Result<()> {
         self.sender
-            .send(payload)
+            .send(message)
             .await
             .map_err(|err| anyhow!("failed to enqueue message: {err}"))
     }
 
-    /// Attempts to enqueue without awaiting; returns Err if the channel is full or closed.
-    pub fn try_enqueue(&self, payload: Vec<u8>) -> Result<()> {
+    /// Enqueues a message, waiting up to `timeout` for space in the queue.
+    /// Returns Err, dropping the message, if the queue is still full at the
+    /// deadline or is closed.
+    pub async fn enqueue_timeout(&self, message: RoutedMessage, timeout: Duration) -> Result<()> {
         self.sender
-            .try_send(payload)
+            .send_timeout(message, timeout)
+            .await
             .map_err(|err| anyhow!("failed to enqueue message: {err}"))
     }
-}
\ No newline at end of file
+
+    /// Attempts to enqueue without awaiting; returns Err if the queue is full or closed.
+    pub fn try_enqueue(&self, message: RoutedMessage) -> Result<()> {
+        self.sender
+            .try_send(message)
+            .map_err(|err| anyhow!("failed to enqueue message: {err}"))
+    }
+
+    /// Attempts to enqueue without awaiting, like [`Self::try_enqueue`]. The
+    /// message is dropped as a dead letter if still queued once `ttl` elapsed.
+    pub fn try_enqueue_with_ttl(
+        &self,
+        message: RoutedMessage,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        self.sender
+            .try_send_with_ttl(message, ttl)
+            .map_err(|err| anyhow!("failed to enqueue message: {err}"))
+    }
+
+    /// Number of messages currently waiting in the queue.
+    pub fn depth(&self) -> usize {
+        self.sender.depth()
+    }
+
+    /// Total number of messages the consumer has dequeued so far.
+    pub fn dequeued(&self) -> u64 {
+        self.sender.stats().delivered
+    }
+
+    /// Returns the published, delivered and dropped counters of the queue.
+    pub fn stats(&self) -> BusStats {
+        self.sender.stats()
+    }
+}
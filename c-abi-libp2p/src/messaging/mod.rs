@@ -3,6 +3,31 @@
 //! For now we expose a simple in-memory queue that can be used by the FFI
 //! surface to pass binary payloads between the host runtime and the Rust core.
 
+pub mod bus;
+pub mod encryption;
+pub mod envelope;
+pub mod group;
+#[allow(clippy::module_inception)]
 pub mod messaging;
+pub mod router;
+pub mod signed;
+#[cfg(any(test, feature = "test_support"))]
+pub mod simulation;
+pub mod verification;
 
-pub use messaging::{ MessageQueue, MessageQueueSender, DEFAULT_MESSAGE_QUEUE_CAPACITY};
\ No newline at end of file
+pub use bus::{
+    BusMode, BusStats, DeadLetter, DropPolicy, EventBus, EventSender, DEAD_LETTER_CAPACITY,
+};
+pub use encryption::{TopicCipher, TOPIC_KEY_LEN};
+pub use envelope::{
+    Envelope, ReplayCache, ReplayError, TraceId, DEFAULT_REPLAY_CACHE_ENTRIES, TRACE_ID_LEN,
+};
+pub use group::{group_topic, GroupFrame, GROUP_TOPIC_PREFIX, MAX_GROUP_ROSTER};
+pub use messaging::{
+    MessageQueue, MessageQueueSender, SlowConsumerSettings, DEFAULT_MESSAGE_QUEUE_CAPACITY,
+};
+pub use router::{ConsumerStats, RoutedMessage, TopicConsumer, TopicPattern, TopicRouter};
+pub use signed::{AppIdentity, SignedPayload, SignedPayloadVerifier};
+#[cfg(any(test, feature = "test_support"))]
+pub use simulation::{QueueSimulation, SimulatedEvent, SimulationReport};
+pub use verification::{MessageVerifier, VerificationResult};
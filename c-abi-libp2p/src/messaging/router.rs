@@ -0,0 +1,204 @@
+//! Fan-out of inbound messages to consumers registered for topic patterns.
+//!
+//! Every consumer registered with a [`TopicRouter`] gets its own queue and
+//! receives each accepted inbound message whose topic matches one of its
+//! patterns, so several consumers can share a node without draining each
+//! other's messages. Messages no consumer matches go to the node's default
+//! inbound queue as before. A full consumer queue applies its own
+//! [`DropPolicy`](super::DropPolicy) and counts the drop in its stats,
+//! without affecting other consumers.
+
+use anyhow::{anyhow, Result};
+use libp2p::PeerId;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+use super::{
+    bus::{BusMode, BusStats, EventBus, EventSender},
+    envelope::TraceId,
+};
+
+/// Topics a consumer is interested in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopicPattern {
+    /// This topic only.
+    Exact(String),
+    /// Every topic starting with this prefix.
+    Prefix(String),
+}
+
+impl TopicPattern {
+    /// Parses `topic` as an exact pattern, or `prefix*` as a prefix pattern.
+    /// A lone `*` matches every topic.
+    pub fn parse(spec: &str) -> Result<Self> {
+        match spec.strip_suffix('*') {
+            Some(prefix) => Ok(Self::Prefix(prefix.to_string())),
+            None if spec.is_empty() => Err(anyhow!("topic pattern is empty")),
+            None => Ok(Self::Exact(spec.to_string())),
+        }
+    }
+
+    pub fn matches(&self, topic: &str) -> bool {
+        match self {
+            Self::Exact(exact) => topic == exact,
+            Self::Prefix(prefix) => topic.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for TopicPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact(exact) => f.write_str(exact),
+            Self::Prefix(prefix) => write!(f, "{prefix}*"),
+        }
+    }
+}
+
+/// Inbound message delivered to a [`TopicConsumer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutedMessage {
+    pub topic: String,
+    /// Author of the message, when signed.
+    pub source: Option<PeerId>,
+    /// Trace id the publisher put in the replay envelope, if any.
+    pub trace_id: Option<TraceId>,
+    pub payload: Vec<u8>,
+}
+
+/// Receiving end of a consumer registered with a [`TopicRouter`]. Dropping
+/// it unregisters the consumer.
+#[derive(Debug)]
+pub struct TopicConsumer {
+    id: u64,
+    bus: EventBus<RoutedMessage>,
+}
+
+impl TopicConsumer {
+    /// Identifier of the consumer within its router.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Takes the next message without waiting.
+    pub fn try_recv(&self) -> Option<RoutedMessage> {
+        self.bus.try_recv()
+    }
+
+    /// Waits for the next message.
+    pub async fn recv(&self) -> Option<RoutedMessage> {
+        self.bus.recv().await
+    }
+
+    /// Returns the delivered and dropped counters of the consumer's queue.
+    pub fn stats(&self) -> BusStats {
+        self.bus.stats()
+    }
+}
+
+/// Counters of a registered consumer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerStats {
+    pub id: u64,
+    pub patterns: Vec<TopicPattern>,
+    /// `dropped` counts messages lost to the consumer's full queue.
+    pub stats: BusStats,
+}
+
+struct Route {
+    id: u64,
+    patterns: Vec<TopicPattern>,
+    sender: EventSender<RoutedMessage>,
+}
+
+/// Registry of topic consumers, shared between the application and the
+/// peer manager. Clones share the same registrations.
+#[derive(Clone, Default)]
+pub struct TopicRouter {
+    routes: Arc<Mutex<Vec<Route>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl TopicRouter {
+    /// Registers a consumer receiving messages matching any of `patterns`
+    /// into a queue of the given mode.
+    pub fn register(&self, patterns: Vec<TopicPattern>, mode: BusMode) -> Result<TopicConsumer> {
+        if patterns.is_empty() {
+            return Err(anyhow!("a topic consumer needs at least one pattern"));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let bus = EventBus::new(mode);
+        self.routes()?.push(Route {
+            id,
+            patterns,
+            sender: bus.sender(),
+        });
+        Ok(TopicConsumer { id, bus })
+    }
+
+    /// Unregisters consumer `id`, returning `true` when it was registered.
+    pub fn unregister(&self, id: u64) -> Result<bool> {
+        let mut routes = self.routes()?;
+        let len = routes.len();
+        routes.retain(|route| route.id != id);
+        Ok(routes.len() != len)
+    }
+
+    /// Returns the counters of every registered consumer.
+    pub fn stats(&self) -> Result<Vec<ConsumerStats>> {
+        let mut routes = self.routes()?;
+        routes.retain(|route| !route.sender.is_closed());
+        Ok(routes
+            .iter()
+            .map(|route| ConsumerStats {
+                id: route.id,
+                patterns: route.patterns.clone(),
+                stats: route.sender.stats(),
+            })
+            .collect())
+    }
+
+    /// Hands a copy of the message to every consumer with a matching
+    /// pattern, returning how many matched. Consumers whose queue was
+    /// dropped are unregistered.
+    pub(crate) fn route(&self, message: &RoutedMessage, ttl: Option<Duration>) -> usize {
+        let Ok(mut routes) = self.routes() else {
+            return 0;
+        };
+        routes.retain(|route| !route.sender.is_closed());
+
+        let mut matched = 0;
+        for route in routes.iter() {
+            let topic = message.topic.as_str();
+            if !route.patterns.iter().any(|pattern| pattern.matches(topic)) {
+                continue;
+            }
+            matched += 1;
+            if let Err(err) = route.sender.try_send_with_ttl(message.clone(), ttl) {
+                tracing::debug!(target: "messaging", consumer = route.id, %topic, %err, "topic consumer dropped message");
+            }
+        }
+        matched
+    }
+
+    fn routes(&self) -> Result<MutexGuard<'_, Vec<Route>>> {
+        self.routes
+            .lock()
+            .map_err(|_| anyhow!("topic router lock poisoned"))
+    }
+}
+
+impl fmt::Debug for TopicRouter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let consumers = self.routes().map(|routes| routes.len()).unwrap_or_default();
+        f.debug_struct("TopicRouter")
+            .field("consumers", &consumers)
+            .finish()
+    }
+}
@@ -0,0 +1,166 @@
+//! Application-level identity carried inside gossip payloads.
+//!
+//! With an ephemeral transport identity the `PeerId` changes every session, so
+//! it cannot identify a user. An [`AppIdentity`] is a separate long-lived key:
+//! payloads signed with it prove their author regardless of which transport
+//! identity relayed them.
+//!
+//! Wire format: `b"pxs1" | key len (u16 BE) | protobuf public key |
+//! signature len (u16 BE) | signature | payload`.
+
+use anyhow::{anyhow, Result};
+use libp2p::{identity, PeerId};
+
+use super::verification::{MessageVerifier, VerificationResult};
+
+const SIGNED_MAGIC: &[u8; 4] = b"pxs1";
+/// Largest accepted encoded public key; fits RSA-4096 keys.
+const MAX_PUBLIC_KEY_LEN: usize = 1024;
+/// Largest accepted signature; fits RSA-4096 signatures.
+const MAX_SIGNATURE_LEN: usize = 1024;
+
+/// Long-lived application identity used to sign payloads.
+#[derive(Debug, Clone)]
+pub struct AppIdentity {
+    keypair: identity::Keypair,
+}
+
+impl AppIdentity {
+    /// Generates a new random Ed25519 application identity.
+    pub fn generate() -> Self {
+        Self {
+            keypair: identity::Keypair::generate_ed25519(),
+        }
+    }
+
+    /// Restores an application identity from its 32-byte Ed25519 seed.
+    pub fn from_ed25519_seed(seed: [u8; 32]) -> Result<Self> {
+        let secret = identity::ed25519::SecretKey::try_from_bytes(seed)
+            .map_err(|err| anyhow!("invalid ed25519 seed provided: {err}"))?;
+        let keypair = identity::ed25519::Keypair::from(secret);
+        Ok(Self {
+            keypair: identity::Keypair::from(keypair),
+        })
+    }
+
+    /// Public key other nodes use to verify this identity's payloads.
+    pub fn public_key(&self) -> identity::PublicKey {
+        self.keypair.public()
+    }
+
+    /// Stable identifier derived from the public key.
+    pub fn id(&self) -> PeerId {
+        self.keypair.public().to_peer_id()
+    }
+
+    /// Signs `payload` and wraps it into a [`SignedPayload`].
+    pub fn sign(&self, payload: Vec<u8>) -> Result<SignedPayload> {
+        let signature = self
+            .keypair
+            .sign(&signing_bytes(&payload))
+            .map_err(|err| anyhow!("failed to sign payload: {err}"))?;
+        Ok(SignedPayload {
+            public_key: self.keypair.public(),
+            signature,
+            payload,
+        })
+    }
+}
+
+/// Payload signed by an [`AppIdentity`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedPayload {
+    /// Public key of the signing application identity.
+    pub public_key: identity::PublicKey,
+    /// Signature over the magic bytes followed by the payload.
+    pub signature: Vec<u8>,
+    /// Application payload.
+    pub payload: Vec<u8>,
+}
+
+impl SignedPayload {
+    /// Stable identifier of the application identity that signed the payload.
+    pub fn author(&self) -> PeerId {
+        self.public_key.to_peer_id()
+    }
+
+    /// Returns `true` when the signature matches the payload and public key.
+    pub fn verify(&self) -> bool {
+        self.public_key
+            .verify(&signing_bytes(&self.payload), &self.signature)
+    }
+
+    /// Serializes the signed payload into its wire format.
+    pub fn encode(&self) -> Vec<u8> {
+        let public_key = self.public_key.encode_protobuf();
+        let mut out = Vec::with_capacity(
+            SIGNED_MAGIC.len() + 4 + public_key.len() + self.signature.len() + self.payload.len(),
+        );
+        out.extend_from_slice(SIGNED_MAGIC);
+        out.extend_from_slice(&(public_key.len() as u16).to_be_bytes());
+        out.extend_from_slice(&public_key);
+        out.extend_from_slice(&(self.signature.len() as u16).to_be_bytes());
+        out.extend_from_slice(&self.signature);
+        out.extend_from_slice(&self.payload);
+        out
+    }
+
+    /// Parses a signed payload from its wire format. The signature is not
+    /// checked; call [`SignedPayload::verify`].
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let rest = bytes
+            .strip_prefix(SIGNED_MAGIC.as_slice())
+            .ok_or_else(|| anyhow!("signed payload magic mismatch"))?;
+        let (public_key, rest) = split_prefixed(rest, "public key", MAX_PUBLIC_KEY_LEN)?;
+        let (signature, payload) = split_prefixed(rest, "signature", MAX_SIGNATURE_LEN)?;
+
+        Ok(Self {
+            public_key: identity::PublicKey::try_decode_protobuf(public_key)
+                .map_err(|err| anyhow!("invalid signed payload public key: {err}"))?,
+            signature: signature.to_vec(),
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// [`MessageVerifier`] accepting only payloads with a valid [`SignedPayload`]
+/// signature and rejecting everything else.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignedPayloadVerifier;
+
+impl MessageVerifier for SignedPayloadVerifier {
+    fn verify(&self, payload: &[u8], _author: Option<&PeerId>) -> VerificationResult {
+        match SignedPayload::decode(payload) {
+            Ok(signed) if signed.verify() => VerificationResult::Accept,
+            _ => VerificationResult::Reject,
+        }
+    }
+}
+
+fn signing_bytes(payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(SIGNED_MAGIC.len() + payload.len());
+    bytes.extend_from_slice(SIGNED_MAGIC);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+fn split_prefixed<'a>(
+    bytes: &'a [u8],
+    field: &str,
+    max_len: usize,
+) -> Result<(&'a [u8], &'a [u8])> {
+    if bytes.len() < 2 {
+        return Err(anyhow!("signed payload truncated before {field} length"));
+    }
+    let (len, rest) = bytes.split_at(2);
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    if len == 0 || len > max_len {
+        return Err(anyhow!(
+            "signed payload {field} length {len} outside 1 to {max_len} bytes"
+        ));
+    }
+    if rest.len() < len {
+        return Err(anyhow!("signed payload truncated inside {field}"));
+    }
+    Ok(rest.split_at(len))
+}
@@ -0,0 +1,303 @@
+//! Deterministic overflow simulation of the [`EventBus`], enabled with the
+//! `test_support` feature.
+//!
+//! Producers and the consumer run in lockstep ticks instead of real time, so
+//! a run only depends on its parameters and a failing case can be replayed.
+//!
+//! Events are sent without a time to live, so nothing expires during a run.
+
+use anyhow::{anyhow, Result};
+
+use super::bus::{BusMode, BusStats, DropPolicy, EventBus};
+
+/// Event of a simulation: producer index and its per-producer sequence number.
+pub type SimulatedEvent = (usize, u64);
+
+/// Parameters of a simulated producer/consumer run over one bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueSimulation {
+    pub mode: BusMode,
+    /// Producers, each with its own sender.
+    pub producers: usize,
+    /// Events each producer sends per tick, without waiting.
+    pub produce_per_tick: usize,
+    /// Events the consumer takes per tick, at most.
+    pub consume_per_tick: usize,
+    pub ticks: usize,
+    /// Let the consumer empty the queue after the last tick.
+    pub drain: bool,
+}
+
+impl QueueSimulation {
+    /// One producer sending two events per tick to a consumer taking one, for
+    /// 100 ticks, then draining: a steady overload.
+    pub fn new(mode: BusMode) -> Self {
+        Self {
+            mode,
+            producers: 1,
+            produce_per_tick: 2,
+            consume_per_tick: 1,
+            ticks: 100,
+            drain: true,
+        }
+    }
+
+    pub fn with_producers(mut self, producers: usize) -> Self {
+        self.producers = producers;
+        self
+    }
+
+    /// Sets the events sent per producer and taken by the consumer per tick.
+    pub fn with_rates(mut self, produce_per_tick: usize, consume_per_tick: usize) -> Self {
+        self.produce_per_tick = produce_per_tick;
+        self.consume_per_tick = consume_per_tick;
+        self
+    }
+
+    pub fn with_ticks(mut self, ticks: usize) -> Self {
+        self.ticks = ticks;
+        self
+    }
+
+    pub fn with_drain(mut self, drain: bool) -> Self {
+        self.drain = drain;
+        self
+    }
+
+    /// Runs the simulation. Within a tick producers send round-robin, then
+    /// the consumer takes its share.
+    pub fn run(&self) -> SimulationReport {
+        let bus = EventBus::new(self.mode);
+        let senders: Vec<_> = (0..self.producers).map(|_| bus.sender()).collect();
+        let mut next_seq = vec![0u64; self.producers];
+        let mut report = SimulationReport {
+            mode: self.mode,
+            ..SimulationReport::default()
+        };
+
+        for _ in 0..self.ticks {
+            for _ in 0..self.produce_per_tick {
+                for (producer, sender) in senders.iter().enumerate() {
+                    let event = (producer, next_seq[producer]);
+                    next_seq[producer] += 1;
+                    report.sent += 1;
+                    if sender.try_send(event).is_err() {
+                        report.rejected += 1;
+                    }
+                    report.max_depth = report.max_depth.max(sender.depth());
+                }
+            }
+            for _ in 0..self.consume_per_tick {
+                match bus.try_recv() {
+                    Some(event) => report.delivered.push(event),
+                    None => break,
+                }
+            }
+        }
+        if self.drain {
+            while let Some(event) = bus.try_recv() {
+                report.delivered.push(event);
+            }
+        }
+
+        report.stats = bus.stats();
+        report
+    }
+}
+
+/// Outcome of a [`QueueSimulation`] run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationReport {
+    pub mode: BusMode,
+    /// Events producers attempted to send.
+    pub sent: u64,
+    /// Sends that failed because the bus was full.
+    pub rejected: u64,
+    /// Events the consumer took, in delivery order.
+    pub delivered: Vec<SimulatedEvent>,
+    /// Largest queue depth seen right after a send.
+    pub max_depth: usize,
+    /// Bus counters at the end of the run.
+    pub stats: BusStats,
+}
+
+impl Default for SimulationReport {
+    fn default() -> Self {
+        Self {
+            mode: BusMode::Unbounded,
+            sent: 0,
+            rejected: 0,
+            delivered: Vec::new(),
+            max_depth: 0,
+            stats: BusStats::default(),
+        }
+    }
+}
+
+impl SimulationReport {
+    /// Checks the invariants every run must keep, returning the first one
+    /// violated:
+    /// - every sent event is delivered, dropped or still queued, exactly once,
+    /// - the queue never holds more than its capacity,
+    /// - each producer's events are delivered in the order they were sent,
+    /// - an unbounded bus drops nothing, and only a full bus drops,
+    /// - [`DropPolicy::DropNewest`] never evicts a queued event,
+    /// - [`DropPolicy::DropOldest`] only rejects when it has no room at all.
+    pub fn check(&self) -> Result<()> {
+        let delivered = self.delivered.len() as u64;
+        if self.stats.delivered != delivered {
+            return Err(anyhow!(
+                "bus counted {} deliveries, consumer took {delivered}",
+                self.stats.delivered
+            ));
+        }
+        let accounted =
+            delivered + self.stats.dropped + self.stats.expired + self.stats.depth as u64;
+        if accounted != self.sent {
+            return Err(anyhow!(
+                "{} events sent but {accounted} delivered, dropped, expired or queued",
+                self.sent
+            ));
+        }
+        if self.stats.published != self.sent - self.rejected {
+            return Err(anyhow!(
+                "{} events published, expected {} sent minus {} rejected",
+                self.stats.published,
+                self.sent,
+                self.rejected
+            ));
+        }
+
+        let mut last_seq: Vec<Option<u64>> = Vec::new();
+        for &(producer, seq) in &self.delivered {
+            if last_seq.len() <= producer {
+                last_seq.resize(producer + 1, None);
+            }
+            if last_seq[producer].is_some_and(|last| last >= seq) {
+                return Err(anyhow!(
+                    "producer {producer} event {seq} delivered out of order"
+                ));
+            }
+            last_seq[producer] = Some(seq);
+        }
+
+        match self.mode {
+            BusMode::Unbounded => {
+                if self.stats.dropped > 0 || self.rejected > 0 {
+                    return Err(anyhow!(
+                        "unbounded bus dropped {} events",
+                        self.stats.dropped
+                    ));
+                }
+            }
+            BusMode::Bounded { capacity, policy } => {
+                if self.max_depth > capacity {
+                    return Err(anyhow!(
+                        "queue reached depth {} over capacity {capacity}",
+                        self.max_depth
+                    ));
+                }
+                if self.stats.dropped > 0 && self.max_depth < capacity {
+                    return Err(anyhow!("events dropped although the queue never filled up"));
+                }
+                match policy {
+                    DropPolicy::DropNewest => {
+                        if self.stats.dropped != self.rejected {
+                            return Err(anyhow!(
+                                "drop-newest bus evicted {} queued events",
+                                self.stats.dropped - self.rejected
+                            ));
+                        }
+                    }
+                    DropPolicy::DropOldest => {
+                        if capacity > 0 && self.rejected > 0 {
+                            return Err(anyhow!(
+                                "drop-oldest bus rejected {} events",
+                                self.rejected
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounded(capacity: usize, policy: DropPolicy) -> BusMode {
+        BusMode::Bounded { capacity, policy }
+    }
+
+    #[test]
+    fn unbounded_run_keeps_invariants() {
+        let report = QueueSimulation::new(BusMode::Unbounded)
+            .with_producers(3)
+            .run();
+        report.check().unwrap();
+        assert_eq!(report.delivered.len() as u64, report.sent);
+        assert_eq!(report.stats.dropped, 0);
+    }
+
+    #[test]
+    fn drop_newest_run_keeps_invariants() {
+        let report = QueueSimulation::new(bounded(8, DropPolicy::DropNewest))
+            .with_producers(2)
+            .run();
+        report.check().unwrap();
+        assert!(report.rejected > 0);
+        assert_eq!(report.max_depth, 8);
+    }
+
+    #[test]
+    fn drop_oldest_run_keeps_invariants() {
+        let report = QueueSimulation::new(bounded(8, DropPolicy::DropOldest))
+            .with_producers(2)
+            .run();
+        report.check().unwrap();
+        assert_eq!(report.rejected, 0);
+        assert!(report.stats.dropped > 0);
+    }
+
+    #[test]
+    fn runs_without_drain_keep_invariants() {
+        for mode in [
+            BusMode::Unbounded,
+            bounded(0, DropPolicy::DropNewest),
+            bounded(1, DropPolicy::DropOldest),
+            bounded(4, DropPolicy::DropNewest),
+        ] {
+            for (produce, consume) in [(0, 1), (1, 1), (3, 2), (1, 4)] {
+                QueueSimulation::new(mode)
+                    .with_rates(produce, consume)
+                    .with_ticks(20)
+                    .with_drain(false)
+                    .run()
+                    .check()
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn check_rejects_broken_reports() {
+        let report = QueueSimulation::new(bounded(4, DropPolicy::DropNewest)).run();
+        report.check().unwrap();
+
+        let mut reordered = report.clone();
+        reordered.delivered.swap(0, 1);
+        assert!(reordered.check().is_err());
+
+        let mut lost = report.clone();
+        lost.delivered.pop();
+        lost.stats.delivered -= 1;
+        assert!(lost.check().is_err());
+
+        let mut overfull = report;
+        overfull.max_depth = 5;
+        assert!(overfull.check().is_err());
+    }
+}
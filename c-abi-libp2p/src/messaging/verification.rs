@@ -0,0 +1,42 @@
+//! Application-level verification of inbound gossip payloads.
+
+use libp2p::{gossipsub::MessageAcceptance, PeerId};
+
+/// Outcome of verifying an inbound message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationResult {
+    /// The message is valid: deliver it and keep propagating it.
+    Accept,
+    /// The message is invalid: drop it and penalize the peer that relayed it.
+    Reject,
+    /// Drop the message without penalizing anyone.
+    Ignore,
+}
+
+impl From<VerificationResult> for MessageAcceptance {
+    fn from(result: VerificationResult) -> Self {
+        match result {
+            VerificationResult::Accept => MessageAcceptance::Accept,
+            VerificationResult::Reject => MessageAcceptance::Reject,
+            VerificationResult::Ignore => MessageAcceptance::Ignore,
+        }
+    }
+}
+
+/// Pluggable verifier invoked before an inbound message is enqueued.
+///
+/// `author` is the peer claimed as the message source (present because
+/// gossipsub messages are signed by their author).
+pub trait MessageVerifier: Send + Sync {
+    /// Checks the payload and returns whether it should be delivered.
+    fn verify(&self, payload: &[u8], author: Option<&PeerId>) -> VerificationResult;
+}
+
+impl<F> MessageVerifier for F
+where
+    F: Fn(&[u8], Option<&PeerId>) -> VerificationResult + Send + Sync,
+{
+    fn verify(&self, payload: &[u8], author: Option<&PeerId>) -> VerificationResult {
+        self(payload, author)
+    }
+}
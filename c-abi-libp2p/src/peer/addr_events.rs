@@ -84,6 +84,13 @@ impl AddrState {
         }
     }
 
+    /// Local listen addresses, sorted.
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        let mut addrs: Vec<Multiaddr> = self.listen.iter().cloned().collect();
+        addrs.sort();
+        addrs
+    }
+
     // Public function to get cur version of snapshot for ABI
     pub fn version(&self) -> u64 { self.version }
 
@@ -0,0 +1,113 @@
+//! Audit trail of outbound messages, for deployments that must prove what a
+//! node transmitted.
+//!
+//! Every message the node publishes is appended to a file as one logfmt line
+//! with the SHA-256 of its application payload, the topic, the time and the
+//! outcome. Once the file exceeds its size limit it is rotated: `audit.log`
+//! becomes `audit.log.1`, the previous `audit.log.1` becomes `audit.log.2`,
+//! and so on up to the number of rotated files kept. Lines are written from a
+//! background thread, so a slow disk never delays publishing.
+//!
+//! Publishes are recorded once gossipsub accepts or finally refuses them;
+//! those dropped earlier, e.g. while held during the publish warm-up, are not.
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+use crate::{messaging::envelope::unix_time_ms, peer::file_writer::FileWriter};
+
+/// Default size in bytes after which the audit file is rotated.
+pub const DEFAULT_AUDIT_FILE_BYTES: u64 = 10 * 1024 * 1024;
+/// Default number of rotated audit files kept.
+pub const DEFAULT_AUDIT_ROTATED_FILES: usize = 5;
+
+/// Settings of the outbound message audit trail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditLogConfig {
+    /// File the trail is appended to.
+    pub path: PathBuf,
+    /// Size in bytes after which the file is rotated.
+    pub max_file_bytes: u64,
+    /// Rotated files kept besides the current one; older ones are deleted.
+    pub max_rotated_files: usize,
+}
+
+impl AuditLogConfig {
+    /// Audits to `path` with the default rotation limits.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_file_bytes: DEFAULT_AUDIT_FILE_BYTES,
+            max_rotated_files: DEFAULT_AUDIT_ROTATED_FILES,
+        }
+    }
+
+    pub fn with_max_file_bytes(mut self, max_file_bytes: u64) -> Self {
+        self.max_file_bytes = max_file_bytes;
+        self
+    }
+
+    pub fn with_max_rotated_files(mut self, max_rotated_files: usize) -> Self {
+        self.max_rotated_files = max_rotated_files;
+        self
+    }
+}
+
+/// Final outcome of an outbound message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum AuditOutcome {
+    /// Handed to gossipsub, which forwards it to `forwarded_to` peers.
+    Published {
+        message_id: String,
+        forwarded_to: usize,
+    },
+    /// Never sent.
+    Failed { reason: String },
+}
+
+/// Hands audit lines to a background writer that rotates the file when full.
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    writer: FileWriter,
+}
+
+impl AuditLog {
+    pub(crate) fn new(config: AuditLogConfig) -> Result<Self> {
+        let writer = FileWriter::spawn(
+            "audit",
+            config.path,
+            config.max_file_bytes,
+            config.max_rotated_files,
+        )?;
+        Ok(Self { writer })
+    }
+
+    /// Records a message published to `topic`.
+    pub(crate) fn record_publish(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        outcome: &AuditOutcome,
+    ) -> Result<()> {
+        let mut line = format!(
+            "ts_ms={} kind=publish topic={topic:?} sha256={} bytes={}",
+            unix_time_ms(),
+            hex::encode(Sha256::digest(payload)),
+            payload.len(),
+        );
+        match outcome {
+            AuditOutcome::Published {
+                message_id,
+                forwarded_to,
+            } => line.push_str(&format!(
+                " outcome=published message_id={message_id} forwarded_to={forwarded_to}"
+            )),
+            AuditOutcome::Failed { reason } => {
+                line.push_str(&format!(" outcome=failed reason={reason:?}"))
+            }
+        }
+        line.push('\n');
+        self.writer.write(line.into_bytes())
+    }
+}
@@ -0,0 +1,279 @@
+//! Active connection counts broken down by transport and direction, and the
+//! security protocol and muxer expected on every open connection.
+
+use libp2p::{swarm::ConnectionId, Multiaddr, PeerId};
+use prometheus_client::{
+    metrics::{family::Family, gauge::Gauge},
+    registry::Registry,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+};
+
+use crate::transport::{expected_connection_stack, transport_label};
+
+/// How redundant direct connections to one peer, e.g. from both ends dialing
+/// at once, are resolved. Relayed connections are left to the DCUtR upgrade.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateConnectionPolicy {
+    /// Keep every connection.
+    #[default]
+    KeepAll,
+    /// Keep the connection dialed by the peer with the lower peer id, the
+    /// oldest one if there are several. Both ends pick the same connection
+    /// without coordinating.
+    KeepLowestPeerIdInitiator,
+    /// Keep the most recently established connection. During simultaneous
+    /// dials both ends may keep different ones, so prefer
+    /// [`Self::KeepLowestPeerIdInitiator`] when the remote applies a policy too.
+    KeepNewest,
+}
+
+impl DuplicateConnectionPolicy {
+    /// Picks the connection to keep among the direct `connections` to
+    /// `remote`, oldest first, each with whether it was dialed by this node.
+    /// `None` keeps them all.
+    pub(crate) fn keep(
+        &self,
+        local: &PeerId,
+        remote: &PeerId,
+        connections: &[(ConnectionId, bool)],
+    ) -> Option<ConnectionId> {
+        if connections.len() < 2 {
+            return None;
+        }
+        match self {
+            Self::KeepAll => None,
+            Self::KeepLowestPeerIdInitiator => {
+                let keep_outbound = local < remote;
+                connections
+                    .iter()
+                    .find(|(_, outbound)| *outbound == keep_outbound)
+                    .or(connections.first())
+                    .map(|(id, _)| *id)
+            }
+            Self::KeepNewest => connections.last().map(|(id, _)| *id),
+        }
+    }
+}
+
+/// Active connections of one transport.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionCounts {
+    /// Connections accepted from remote peers.
+    pub inbound: usize,
+    /// Connections dialed by this node.
+    pub outbound: usize,
+}
+
+impl ConnectionCounts {
+    /// Inbound and outbound connections together.
+    pub fn total(&self) -> usize {
+        self.inbound + self.outbound
+    }
+}
+
+/// Transport of an open connection, with the security protocol and muxer
+/// that transport offers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerConnection {
+    /// Remote address of the connection.
+    pub address: Multiaddr,
+    /// Transport label (`tcp`, `quic` or `relay`).
+    pub transport: &'static str,
+    /// Security protocol the transport offers (e.g. `/noise`), see
+    /// [`expected_connection_stack`].
+    pub expected_security: &'static str,
+    /// Stream multiplexer the transport offers (e.g. `/yamux/1.0.0`).
+    pub expected_muxer: &'static str,
+    /// `true` when this node dialed the connection.
+    pub outbound: bool,
+}
+
+/// Shared counters of active connections keyed by transport label
+/// (`tcp`, `quic`, `relay`), mirrored into Prometheus gauges, along with the
+/// stack of every open connection.
+#[derive(Debug, Default)]
+pub struct ConnectionMetrics {
+    counts: RwLock<BTreeMap<&'static str, ConnectionCounts>>,
+    gauge: Family<Vec<(String, String)>, Gauge>,
+    open: RwLock<HashMap<ConnectionId, (PeerId, PeerConnection)>>,
+    stack_gauge: Family<Vec<(String, String)>, Gauge>,
+}
+
+impl ConnectionMetrics {
+    /// Returns the active connection counts of every transport seen so far.
+    pub fn snapshot(&self) -> BTreeMap<String, ConnectionCounts> {
+        let Ok(counts) = self.counts.read() else {
+            tracing::warn!(target: "peer", "connection metrics lock poisoned");
+            return BTreeMap::new();
+        };
+
+        counts
+            .iter()
+            .map(|(transport, counts)| (transport.to_string(), *counts))
+            .collect()
+    }
+
+    /// Returns the open connections to `peer_id` with the stack each one is
+    /// expected to run; empty when the peer is not connected.
+    pub fn peer_connections(&self, peer_id: &PeerId) -> Vec<PeerConnection> {
+        let Ok(open) = self.open.read() else {
+            tracing::warn!(target: "peer", "connection metrics lock poisoned");
+            return Vec::new();
+        };
+
+        open.values()
+            .filter(|(peer, _)| peer == peer_id)
+            .map(|(_, connection)| connection.clone())
+            .collect()
+    }
+
+    /// Returns the number of open connections keyed by expected security
+    /// protocol and muxer, e.g. to follow the rollout of new transport
+    /// settings.
+    pub fn stack_snapshot(&self) -> BTreeMap<(String, String), usize> {
+        let Ok(open) = self.open.read() else {
+            tracing::warn!(target: "peer", "connection metrics lock poisoned");
+            return BTreeMap::new();
+        };
+
+        let mut stacks = BTreeMap::new();
+        for (_, connection) in open.values() {
+            *stacks
+                .entry((
+                    connection.expected_security.to_string(),
+                    connection.expected_muxer.to_string(),
+                ))
+                .or_default() += 1;
+        }
+        stacks
+    }
+
+    /// Registers the `connections` and `connection_stacks` gauges in `registry`.
+    pub(crate) fn register(&self, registry: &mut Registry) {
+        registry.register(
+            "connections",
+            "Active connections by transport and direction",
+            self.gauge.clone(),
+        );
+        registry.register(
+            "connection_stacks",
+            "Active connections by expected security protocol and muxer",
+            self.stack_gauge.clone(),
+        );
+    }
+
+    /// Records connection `connection_id` to `peer_id` being established.
+    /// `address` is the remote address, `transport_address` the one revealing
+    /// its transport.
+    pub(crate) fn opened(
+        &self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+        address: &Multiaddr,
+        transport_address: &Multiaddr,
+        outbound: bool,
+    ) {
+        self.update(transport_address, outbound, true);
+
+        let (security, muxer) = expected_connection_stack(transport_address);
+        let connection = PeerConnection {
+            address: address.clone(),
+            transport: transport_label(transport_address),
+            expected_security: security,
+            expected_muxer: muxer,
+            outbound,
+        };
+        match self.open.write() {
+            Ok(mut open) => {
+                open.insert(connection_id, (peer_id, connection));
+                self.update_stack(&open, security, muxer);
+            }
+            Err(_) => tracing::warn!(target: "peer", "connection metrics lock poisoned"),
+        }
+    }
+
+    /// Records connection `connection_id` over `transport_address` being closed.
+    pub(crate) fn closed(
+        &self,
+        connection_id: ConnectionId,
+        transport_address: &Multiaddr,
+        outbound: bool,
+    ) {
+        self.update(transport_address, outbound, false);
+
+        match self.open.write() {
+            Ok(mut open) => {
+                if let Some((_, connection)) = open.remove(&connection_id) {
+                    self.update_stack(
+                        &open,
+                        connection.expected_security,
+                        connection.expected_muxer,
+                    );
+                }
+            }
+            Err(_) => tracing::warn!(target: "peer", "connection metrics lock poisoned"),
+        }
+    }
+
+    /// Forgets every connection, e.g. after the swarm was replaced.
+    pub(crate) fn reset(&self) {
+        if let Ok(mut counts) = self.counts.write() {
+            counts.clear();
+        }
+        if let Ok(mut open) = self.open.write() {
+            open.clear();
+        }
+        self.gauge.clear();
+        self.stack_gauge.clear();
+    }
+
+    fn update_stack(
+        &self,
+        open: &HashMap<ConnectionId, (PeerId, PeerConnection)>,
+        security: &str,
+        muxer: &str,
+    ) {
+        let count = open
+            .values()
+            .filter(|(_, connection)| {
+                connection.expected_security == security && connection.expected_muxer == muxer
+            })
+            .count();
+        self.stack_gauge
+            .get_or_create(&vec![
+                ("security".to_string(), security.to_string()),
+                ("muxer".to_string(), muxer.to_string()),
+            ])
+            .set(count as i64);
+    }
+
+    fn update(&self, address: &Multiaddr, outbound: bool, opened: bool) {
+        let Ok(mut counts) = self.counts.write() else {
+            tracing::warn!(target: "peer", "connection metrics lock poisoned");
+            return;
+        };
+
+        let transport = transport_label(address);
+        let entry = counts.entry(transport).or_default();
+        let (count, direction) = if outbound {
+            (&mut entry.outbound, "outbound")
+        } else {
+            (&mut entry.inbound, "inbound")
+        };
+        *count = if opened {
+            *count + 1
+        } else {
+            count.saturating_sub(1)
+        };
+
+        self.gauge
+            .get_or_create(&vec![
+                ("transport".to_string(), transport.to_string()),
+                ("direction".to_string(), direction.to_string()),
+            ])
+            .set(*count as i64);
+    }
+}
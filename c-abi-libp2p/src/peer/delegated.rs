@@ -0,0 +1,373 @@
+//! Delegated routing over HTTP, asked when the DHT cannot locate a peer or
+//! provider, and first on client-only nodes.
+//!
+//! Speaks the read-only `GET /routing/v1/peers/{peer-id}` and
+//! `GET /routing/v1/providers/{cid}` endpoints of the delegated routing HTTP
+//! API served by indexers and well-connected nodes. Providers are indexed by
+//! multihash, so only provider keys that are multihashes can be looked up.
+//! Only plain `http://` endpoints are supported; put a TLS-terminating proxy
+//! in front of an `https://` one.
+
+use anyhow::{anyhow, Context, Result};
+use libp2p::{multihash::Multihash, Multiaddr, PeerId};
+use multibase::Base;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+/// Default time a delegated lookup may take, connection included.
+pub const DEFAULT_DELEGATED_ROUTING_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest response body read from the endpoint.
+pub const MAX_DELEGATED_RESPONSE_BYTES: u64 = 1024 * 1024;
+
+/// Endpoint of a delegated routing server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DelegatedRoutingSettings {
+    /// Base URL, e.g. `http://indexer.example:8080`; the API paths are
+    /// appended to it.
+    pub endpoint: String,
+    /// Time a lookup may take, connection included.
+    pub timeout: Duration,
+}
+
+impl DelegatedRoutingSettings {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            timeout: DEFAULT_DELEGATED_ROUTING_TIMEOUT,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Splits the endpoint into `host:port` and path prefix, or explains why
+    /// it is unusable.
+    pub fn parse_endpoint(&self) -> Result<(String, String)> {
+        let rest = self.endpoint.strip_prefix("http://").ok_or_else(|| {
+            anyhow!(
+                "delegated routing endpoint `{}` must start with http://",
+                self.endpoint
+            )
+        })?;
+        let (authority, prefix) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(anyhow!(
+                "delegated routing endpoint `{}` has no host",
+                self.endpoint
+            ));
+        }
+        let authority = if authority.rsplit_once(':').is_some_and(|(_, port)| {
+            !port.is_empty() && port.bytes().all(|byte| byte.is_ascii_digit())
+        }) {
+            authority.to_string()
+        } else {
+            format!("{authority}:80")
+        };
+        Ok((authority, prefix.trim_end_matches('/').to_string()))
+    }
+}
+
+/// Asks the delegated routing server for the addresses of `peer_id`. A peer
+/// the server does not know yields no address.
+pub async fn find_peer(
+    settings: &DelegatedRoutingSettings,
+    peer_id: PeerId,
+) -> Result<Vec<Multiaddr>> {
+    let (authority, prefix) = settings.parse_endpoint()?;
+    let path = format!("{prefix}/routing/v1/peers/{peer_id}");
+    let (status, body) = timeout(settings.timeout, http_get(&authority, &path))
+        .await
+        .map_err(|_| anyhow!("delegated routing lookup timed out"))??;
+    match status {
+        200 => parse_peer_records(&body, &peer_id),
+        404 => Ok(Vec::new()),
+        status => Err(anyhow!(
+            "delegated routing server answered with status {status}"
+        )),
+    }
+}
+
+/// Asks the delegated routing server for the providers of `key`, a
+/// multihash, with the addresses it knows for each. An unknown key yields no
+/// provider.
+pub async fn find_providers(
+    settings: &DelegatedRoutingSettings,
+    key: &[u8],
+) -> Result<Vec<(PeerId, Vec<Multiaddr>)>> {
+    let cid = provider_cid(key)
+        .ok_or_else(|| anyhow!("provider key is not a multihash, which delegated routing needs"))?;
+    let (authority, prefix) = settings.parse_endpoint()?;
+    let path = format!("{prefix}/routing/v1/providers/{cid}");
+    let (status, body) = timeout(settings.timeout, http_get(&authority, &path))
+        .await
+        .map_err(|_| anyhow!("delegated routing lookup timed out"))??;
+    match status {
+        200 => parse_provider_records(&body),
+        404 => Ok(Vec::new()),
+        status => Err(anyhow!(
+            "delegated routing server answered with status {status}"
+        )),
+    }
+}
+
+/// Sends an HTTP/1.0 GET, so the body is neither chunked nor kept alive, and
+/// returns the status code and body.
+async fn http_get(authority: &str, path: &str) -> Result<(u16, Vec<u8>)> {
+    let mut stream = TcpStream::connect(authority)
+        .await
+        .with_context(|| format!("failed to connect to delegated routing server {authority}"))?;
+    let host = authority
+        .rsplit_once(':')
+        .map_or(authority, |(host, _)| host);
+    let request = format!(
+        "GET {path} HTTP/1.0\r\nHost: {host}\r\nAccept: application/json\r\nUser-Agent: cabi-rust-libp2p\r\n\r\n"
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .context("failed to send delegated routing request")?;
+
+    let mut response = Vec::new();
+    stream
+        .take(MAX_DELEGATED_RESPONSE_BYTES + 1)
+        .read_to_end(&mut response)
+        .await
+        .context("failed to read delegated routing response")?;
+    parse_response(&response)
+}
+
+/// Splits a raw HTTP response into its status code and body.
+fn parse_response(response: &[u8]) -> Result<(u16, Vec<u8>)> {
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("delegated routing response has no header end"))?;
+    if (response.len() - header_end - 4) as u64 > MAX_DELEGATED_RESPONSE_BYTES {
+        return Err(anyhow!(
+            "delegated routing response exceeds {MAX_DELEGATED_RESPONSE_BYTES} bytes"
+        ));
+    }
+    let status_line = response[..header_end]
+        .split(|byte| *byte == b'\n')
+        .next()
+        .unwrap_or_default();
+    let status = std::str::from_utf8(status_line)
+        .ok()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| anyhow!("malformed delegated routing status line"))?;
+    Ok((status, response[header_end + 4..].to_vec()))
+}
+
+/// Collects the addresses of the `peer` records about `peer_id` in a
+/// `{"Peers": [{"Schema": "peer", "ID": ..., "Addrs": [...]}]}` body.
+/// Unparsable addresses and records of other schemas are skipped.
+fn parse_peer_records(body: &[u8], peer_id: &PeerId) -> Result<Vec<Multiaddr>> {
+    let body: Value =
+        serde_json::from_slice(body).context("malformed delegated routing response")?;
+    let expected_id = peer_id.to_string();
+
+    let mut addresses = Vec::new();
+    for record in peer_records(&body, "Peers") {
+        if record.get("ID").and_then(Value::as_str) != Some(expected_id.as_str()) {
+            continue;
+        }
+        for address in record_addresses(record) {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+    Ok(addresses)
+}
+
+/// Collects the providers and their addresses in a
+/// `{"Providers": [{"Schema": "peer", "ID": ..., "Addrs": [...]}]}` body.
+/// Records with an unparsable ID and records of other schemas are skipped.
+fn parse_provider_records(body: &[u8]) -> Result<Vec<(PeerId, Vec<Multiaddr>)>> {
+    let body: Value =
+        serde_json::from_slice(body).context("malformed delegated routing response")?;
+
+    let mut providers: Vec<(PeerId, Vec<Multiaddr>)> = Vec::new();
+    for record in peer_records(&body, "Providers") {
+        let Some(peer_id) = record
+            .get("ID")
+            .and_then(Value::as_str)
+            .and_then(|id| id.parse::<PeerId>().ok())
+        else {
+            tracing::debug!(target: "peer", "skipping delegated provider without a valid ID");
+            continue;
+        };
+        let addresses = record_addresses(record);
+        match providers
+            .iter_mut()
+            .find(|(provider, _)| *provider == peer_id)
+        {
+            Some((_, known)) => {
+                for address in addresses {
+                    if !known.contains(&address) {
+                        known.push(address);
+                    }
+                }
+            }
+            None => providers.push((peer_id, addresses)),
+        }
+    }
+    Ok(providers)
+}
+
+/// Records of the `peer` schema in the `field` array of `body`.
+fn peer_records<'a>(body: &'a Value, field: &str) -> impl Iterator<Item = &'a Value> {
+    body.get(field)
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter(|record| record.get("Schema").and_then(Value::as_str) == Some("peer"))
+}
+
+/// Parsable addresses of a `peer` record; the others are skipped.
+fn record_addresses(record: &Value) -> Vec<Multiaddr> {
+    let addrs = record
+        .get("Addrs")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    addrs
+        .iter()
+        .filter_map(Value::as_str)
+        .filter_map(|address| match address.parse::<Multiaddr>() {
+            Ok(address) => Some(address),
+            Err(err) => {
+                tracing::debug!(target: "peer", %err, "skipping unparsable delegated address");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Multicodec of raw binary content, the codec of the CIDs providers are
+/// looked up by.
+const RAW_CODEC: u8 = 0x55;
+
+/// Encodes the multihash `key` as the base32 CIDv1 of raw content the
+/// providers endpoint expects; `None` when `key` is no multihash.
+fn provider_cid(key: &[u8]) -> Option<String> {
+    Multihash::<64>::from_bytes(key).ok()?;
+    // Version 1 and the raw codec are both single-byte varints.
+    let mut cid = vec![0x01, RAW_CODEC];
+    cid.extend_from_slice(key);
+    Some(multibase::encode(Base::Base32Lower, cid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn endpoint(url: &str) -> Result<(String, String)> {
+        DelegatedRoutingSettings::new(url).parse_endpoint()
+    }
+
+    #[test]
+    fn provider_cid_of_sha256_multihash() {
+        let mut key = vec![0x12, 0x20];
+        key.extend_from_slice(&Sha256::digest(b"hello world"));
+        assert_eq!(
+            provider_cid(&key).as_deref(),
+            Some("bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e")
+        );
+    }
+
+    #[test]
+    fn provider_cid_rejects_malformed_multihashes() {
+        // Digest shorter and longer than its announced length.
+        assert_eq!(provider_cid(&[0x12, 0x20, 0xab]), None);
+        assert_eq!(provider_cid(&[0x00, 0x01, 0xab, 0xcd]), None);
+        // Varint code cut off, and one running past 64 bits.
+        assert_eq!(provider_cid(&[]), None);
+        assert_eq!(provider_cid(&[0x80]), None);
+        assert_eq!(provider_cid(&[0xff; 11]), None);
+        // Identity multihash with an empty digest is still a multihash.
+        assert_eq!(provider_cid(&[0x00, 0x00]).as_deref(), Some("bafkqaaa"));
+    }
+
+    #[test]
+    fn parse_endpoint_defaults_port_and_trims_path() {
+        assert_eq!(
+            endpoint("http://indexer.example").unwrap(),
+            ("indexer.example:80".to_string(), String::new())
+        );
+        assert_eq!(
+            endpoint("http://indexer.example:8080/").unwrap(),
+            ("indexer.example:8080".to_string(), String::new())
+        );
+        assert_eq!(
+            endpoint("http://10.0.0.1:8080/api/").unwrap(),
+            ("10.0.0.1:8080".to_string(), "/api".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_endpoint_rejects_https_and_missing_host() {
+        assert!(endpoint("https://indexer.example").is_err());
+        assert!(endpoint("indexer.example:8080").is_err());
+        assert!(endpoint("http:///routing").is_err());
+    }
+
+    #[test]
+    fn canned_response_yields_peer_addresses() {
+        let peer_id = PeerId::random();
+        let other = PeerId::random();
+        let body = format!(
+            r#"{{"Peers": [
+                {{"Schema": "peer", "ID": "{peer_id}", "Addrs": ["/ip4/1.2.3.4/tcp/4001", "not an address", "/ip4/1.2.3.4/tcp/4001"]}},
+                {{"Schema": "peer", "ID": "{other}", "Addrs": ["/ip4/5.6.7.8/tcp/4001"]}},
+                {{"Schema": "bitswap", "ID": "{peer_id}", "Addrs": ["/ip4/9.9.9.9/tcp/4001"]}}
+            ]}}"#
+        );
+        let response = format!("HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n{body}");
+
+        let (status, body) = parse_response(response.as_bytes()).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(
+            parse_peer_records(&body, &peer_id).unwrap(),
+            vec!["/ip4/1.2.3.4/tcp/4001".parse::<Multiaddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn canned_response_merges_provider_records() {
+        let provider = PeerId::random();
+        let body = format!(
+            r#"{{"Providers": [
+                {{"Schema": "peer", "ID": "{provider}", "Addrs": ["/ip4/1.2.3.4/tcp/4001"]}},
+                {{"Schema": "peer", "ID": "{provider}", "Addrs": ["/ip4/5.6.7.8/tcp/4001"]}},
+                {{"Schema": "peer", "ID": "garbage", "Addrs": []}}
+            ]}}"#
+        );
+        let providers = parse_provider_records(body.as_bytes()).unwrap();
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].0, provider);
+        assert_eq!(providers[0].1.len(), 2);
+    }
+
+    #[test]
+    fn malformed_responses_are_rejected() {
+        assert!(parse_response(b"HTTP/1.0 200 OK\r\n").is_err());
+        assert!(parse_response(b"garbage\r\n\r\n{}").is_err());
+        let (status, body) = parse_response(b"HTTP/1.1 404 Not Found\r\n\r\n").unwrap();
+        assert_eq!((status, body.len()), (404, 0));
+        assert!(parse_peer_records(b"not json", &PeerId::random()).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! Aggregated statistics about finished Kademlia queries and the local
+//! record store.
+
+use libp2p::kad;
+use std::{collections::BTreeMap, sync::RwLock, time::Duration};
+
+/// Aggregated counters for one kind of DHT query (e.g. `get_closest_peers`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DhtQueryStats {
+    /// Queries that finished, successfully or not.
+    pub queries: u64,
+    /// Queries that finished with a successful result.
+    pub succeeded: u64,
+    /// Queries that failed or timed out.
+    pub failed: u64,
+    /// Sum of the durations of all finished queries.
+    pub total_duration: Duration,
+    /// Longest duration seen for a single query.
+    pub max_duration: Duration,
+    /// Sum of the progress steps reported across all queries.
+    pub total_steps: u64,
+    /// Sum of the requests sent to remote peers across all queries.
+    pub peers_contacted: u64,
+    /// Requests that received a successful response.
+    pub peer_successes: u64,
+    /// Requests that failed or timed out.
+    pub peer_failures: u64,
+}
+
+impl DhtQueryStats {
+    /// Fraction of queries that succeeded, `None` before the first query finishes.
+    pub fn success_rate(&self) -> Option<f64> {
+        (self.queries > 0).then(|| self.succeeded as f64 / self.queries as f64)
+    }
+
+    /// Mean query duration, `None` before the first query finishes.
+    pub fn average_duration(&self) -> Option<Duration> {
+        let queries = u32::try_from(self.queries)
+            .ok()
+            .filter(|queries| *queries > 0)?;
+        Some(self.total_duration / queries)
+    }
+
+    /// Mean number of peers contacted per query.
+    pub fn average_peers_contacted(&self) -> Option<f64> {
+        (self.queries > 0).then(|| self.peers_contacted as f64 / self.queries as f64)
+    }
+}
+
+/// Counters of records the local Kademlia store could not keep as offered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DhtStoreStats {
+    /// Records of remote publishers evicted to make room for newer ones.
+    pub records_evicted: u64,
+    /// Inbound records refused because they were too large or nothing could
+    /// be evicted.
+    pub records_rejected: u64,
+    /// Inbound provider records refused because the store was full.
+    pub providers_rejected: u64,
+}
+
+/// Shared registry of DHT query statistics keyed by query kind.
+#[derive(Debug, Default)]
+pub struct DhtMetrics {
+    queries: RwLock<BTreeMap<&'static str, DhtQueryStats>>,
+    store: RwLock<DhtStoreStats>,
+}
+
+impl DhtMetrics {
+    /// Returns the statistics of every query kind seen so far.
+    pub fn snapshot(&self) -> BTreeMap<String, DhtQueryStats> {
+        let Ok(queries) = self.queries.read() else {
+            tracing::warn!(target: "peer", "dht metrics lock poisoned");
+            return BTreeMap::new();
+        };
+
+        queries
+            .iter()
+            .map(|(kind, stats)| (kind.to_string(), *stats))
+            .collect()
+    }
+
+    /// Returns the record store counters.
+    pub fn store_snapshot(&self) -> DhtStoreStats {
+        match self.store.read() {
+            Ok(store) => *store,
+            Err(_) => {
+                tracing::warn!(target: "peer", "dht metrics lock poisoned");
+                DhtStoreStats::default()
+            }
+        }
+    }
+
+    /// Applies `update` to the record store counters.
+    pub(crate) fn record_store(&self, update: impl FnOnce(&mut DhtStoreStats)) {
+        let Ok(mut store) = self.store.write() else {
+            tracing::warn!(target: "peer", "dht metrics lock poisoned");
+            return;
+        };
+        update(&mut store);
+    }
+
+    /// Records a finished query of the given kind.
+    pub(crate) fn record(
+        &self,
+        kind: &'static str,
+        succeeded: bool,
+        stats: &kad::QueryStats,
+        steps: usize,
+    ) {
+        let Ok(mut queries) = self.queries.write() else {
+            tracing::warn!(target: "peer", "dht metrics lock poisoned");
+            return;
+        };
+
+        let entry = queries.entry(kind).or_default();
+        let duration = stats.duration().unwrap_or_default();
+        entry.queries += 1;
+        if succeeded {
+            entry.succeeded += 1;
+        } else {
+            entry.failed += 1;
+        }
+        entry.total_duration += duration;
+        entry.max_duration = entry.max_duration.max(duration);
+        entry.total_steps += steps as u64;
+        entry.peers_contacted += u64::from(stats.num_requests());
+        entry.peer_successes += u64::from(stats.num_successes());
+        entry.peer_failures += u64::from(stats.num_failures());
+    }
+}
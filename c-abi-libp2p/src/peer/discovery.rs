@@ -2,8 +2,11 @@
 
 use anyhow::{anyhow, Result};
 use libp2p::{core::Multiaddr, PeerId};
+use std::{num::NonZeroUsize, time::Duration};
 use tokio::sync::mpsc;
 
+use crate::messaging::{BusMode, BusStats, EventBus, EventSender};
+
 /// Default capacity for the discovery event queue.
 pub const DEFAULT_DISCOVERY_QUEUE_CAPACITY: usize = 64;
 
@@ -14,8 +17,10 @@ pub enum DiscoveryStatus {
     Success,
     /// The requested peer was not found.
     NotFound,
-    /// The query timed out.
+    /// The query timed out without reporting any peer.
     Timeout,
+    /// The query timed out after reporting some peers; results are incomplete.
+    PartialSuccess { peers_found: usize },
     /// An internal error occurred.
     InternalError,
 }
@@ -35,27 +40,114 @@ pub enum DiscoveryEvent {
         request_id: u64,
         target_peer_id: PeerId,
         status: DiscoveryStatus,
+        /// Number of distinct peers reported through `Address` events.
+        peers_found: usize,
+    },
+    /// A provider of the looked-up key, reported as soon as the query learns
+    /// about it.
+    Provider {
+        request_id: u64,
+        key: Vec<u8>,
+        peer_id: PeerId,
+        /// Addresses of the provider known locally; may be empty.
+        addresses: Vec<Multiaddr>,
+    },
+    /// A provider lookup finished.
+    ProvidersFinished {
+        request_id: u64,
+        key: Vec<u8>,
+        status: DiscoveryStatus,
+        /// Number of distinct providers reported through `Provider` events.
+        providers_found: usize,
+    },
+    /// A record stored under the looked-up key, reported as soon as the
+    /// query finds it.
+    Record {
+        request_id: u64,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        /// Peer that originally stored the record, when it says so.
+        publisher: Option<PeerId>,
+    },
+    /// A record lookup finished.
+    RecordsFinished {
+        request_id: u64,
+        key: Vec<u8>,
+        status: DiscoveryStatus,
+        /// Number of records reported through `Record` events.
+        records_found: usize,
+    },
+    /// A start_providing query finished: `Success` once the provider record
+    /// reached the peers closest to the key. The node keeps providing the key
+    /// either way until `stop_providing`.
+    ProvidingStarted {
+        request_id: u64,
+        key: Vec<u8>,
+        status: DiscoveryStatus,
     },
+    /// Signed peer record of the target of a find_peer, reported before the
+    /// query finishes when the node holds one. `record` is the protobuf
+    /// signed envelope, importable with `import_peer_record`.
+    PeerRecord {
+        request_id: u64,
+        target_peer_id: PeerId,
+        record: Vec<u8>,
+    },
+    /// A record put finished. `PartialSuccess` counts the peers that stored
+    /// the record when fewer than the quorum did.
+    RecordStored {
+        request_id: u64,
+        key: Vec<u8>,
+        status: DiscoveryStatus,
+    },
+}
+
+/// Bounds on a DHT lookup, keeping its latency predictable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryLimits {
+    /// Finish the lookup as successful once this many results were reported.
+    pub max_results: Option<NonZeroUsize>,
+    /// Finish the lookup this long after it was started, retries included.
+    /// Results reported by then make it a partial success.
+    pub deadline: Option<Duration>,
+}
+
+impl QueryLimits {
+    pub fn with_max_results(mut self, max_results: NonZeroUsize) -> Self {
+        self.max_results = Some(max_results);
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
 }
 
 /// Queue used to pass discovery events from the peer manager to the C-ABI.
 #[derive(Debug)]
 pub struct DiscoveryQueue {
-    sender: mpsc::Sender<DiscoveryEvent>,
-    receiver: mpsc::Receiver<DiscoveryEvent>,
+    bus: EventBus<DiscoveryEvent>,
+    sender: EventSender<DiscoveryEvent>,
 }
 
 /// Cloneable sender handle for enqueuing discovery events.
 #[derive(Clone, Debug)]
 pub struct DiscoveryEventSender {
-    sender: mpsc::Sender<DiscoveryEvent>,
+    sender: EventSender<DiscoveryEvent>,
 }
 
 impl DiscoveryQueue {
     /// Creates a new queue with the given capacity.
     pub fn new(capacity: usize) -> Self {
-        let (sender, receiver) = mpsc::channel(capacity);
-        Self { sender, receiver }
+        Self::with_mode(BusMode::bounded(capacity))
+    }
+
+    /// Creates a new queue with an explicit capacity mode and drop policy.
+    pub fn with_mode(mode: BusMode) -> Self {
+        let bus = EventBus::new(mode);
+        let sender = bus.sender();
+        Self { bus, sender }
     }
 
     /// Returns a clone of the sender.
@@ -67,7 +159,20 @@ impl DiscoveryQueue {
 
     /// Attempts to dequeue a discovery event without blocking.
     pub fn try_dequeue(&mut self) -> Option<DiscoveryEvent> {
-        self.receiver.try_recv().ok()
+        self.bus.try_recv()
+    }
+
+    /// Returns the published, delivered and dropped counters of the queue.
+    pub fn stats(&self) -> BusStats {
+        self.bus.stats()
+    }
+}
+
+impl From<mpsc::Sender<DiscoveryEvent>> for DiscoveryEventSender {
+    fn from(sender: mpsc::Sender<DiscoveryEvent>) -> Self {
+        Self {
+            sender: sender.into(),
+        }
     }
 }
 
@@ -78,4 +183,9 @@ impl DiscoveryEventSender {
             .try_send(event)
             .map_err(|err| anyhow!("failed to enqueue discovery event: {err}"))
     }
-}
\ No newline at end of file
+
+    /// Number of events currently waiting in the queue.
+    pub fn depth(&self) -> usize {
+        self.sender.depth()
+    }
+}
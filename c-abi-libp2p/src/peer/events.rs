@@ -0,0 +1,211 @@
+//! Structured network events broadcast to subscribers of
+//! [`crate::peer::PeerManagerHandle::subscribe_events`].
+
+use libp2p::{Multiaddr, PeerId};
+use std::time::Duration;
+
+use super::{ConnectOutcome, DuplicateConnectionPolicy, RelayReservationEvent, VersionMismatch};
+
+/// Capacity of the network event broadcast channel. Subscribers lagging more
+/// than this many events behind miss the oldest ones.
+pub const DEFAULT_NETWORK_EVENT_CAPACITY: usize = 256;
+
+/// Events emitted by the peer manager for applications and support tooling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// Progress of a DCUtR hole-punch attempt.
+    HolePunch(HolePunchEvent),
+    /// Acceptance, renewal failure or loss of a relay reservation.
+    RelayReservation(RelayReservationEvent),
+    /// A connection was established and identify completed (or failed) on it.
+    ConnectionEstablished(ConnectionInfo),
+    /// A connection was closed.
+    ConnectionClosed(ConnectionClosedInfo),
+    /// A redundant connection to a peer is being closed by the configured
+    /// [`DuplicateConnectionPolicy`]; its closure follows as
+    /// [`NetworkEvent::ConnectionClosed`].
+    ConnectionDeduplicated(ConnectionDeduplicatedInfo),
+    /// A peer announced a version failing the configured
+    /// [`crate::peer::VersionGate`] and is being disconnected.
+    IncompatiblePeer(IncompatiblePeerInfo),
+    /// The peer manager disconnected or banned a peer on its own, e.g. for
+    /// relaying invalid messages. Reported alongside any reason-specific
+    /// event such as [`NetworkEvent::IncompatiblePeer`], so every automated
+    /// enforcement can be audited from this one event.
+    PeerPenalized(PeerPenalizedInfo),
+    /// The application stopped keeping up with the inbound message queue, or
+    /// caught up again.
+    SlowConsumer(SlowConsumerEvent),
+    /// A dial tagged with a caller-supplied request id finished.
+    DialFinished(DialFinishedEvent),
+    /// Membership change or message in a joined group.
+    Group(GroupEvent),
+    /// The subscribed topics were replaced in one step, see
+    /// [`crate::peer::PeerManagerHandle::set_subscriptions`].
+    SubscriptionsChanged(SubscriptionChange),
+}
+
+/// Events of a group joined with [`crate::peer::PeerManagerHandle::join_group`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupEvent {
+    /// `peer_id` joined `group`, or was first heard of in a member's roster.
+    MemberJoined { group: String, peer_id: PeerId },
+    /// `peer_id` left `group`, unsubscribed from it or went silent.
+    MemberLeft { group: String, peer_id: PeerId },
+    /// Payload published to `group` by the member `from`.
+    Message {
+        group: String,
+        from: PeerId,
+        payload: Vec<u8>,
+    },
+}
+
+/// Topics subscribed and unsubscribed by one
+/// [`crate::peer::PeerManagerHandle::set_subscriptions`] call.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubscriptionChange {
+    /// Topics newly subscribed, in the requested order.
+    pub subscribed: Vec<String>,
+    /// Topics unsubscribed because they were missing from the requested set.
+    pub unsubscribed: Vec<String>,
+}
+
+/// Outcome of a dial started with a request id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialFinishedEvent {
+    /// Identifier the caller supplied when starting the dial.
+    pub request_id: u64,
+    pub result: ConnectOutcome,
+}
+
+/// Slow-consumer state changes of the inbound message queue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlowConsumerEvent {
+    /// The queue was not drained for `stalled_for`, or `dropped` of the
+    /// `offered` messages of the last window were lost to a full queue.
+    Detected {
+        queue_depth: usize,
+        stalled_for: Duration,
+        dropped: u64,
+        offered: u64,
+        /// Whether inbound gossip is ignored until the consumer recovers.
+        gossip_paused: bool,
+    },
+    /// The consumer drains the queue again; gossip is accepted again.
+    Recovered,
+}
+
+/// Protocols negotiated on an established connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub peer_id: PeerId,
+    /// Remote address of the connection.
+    pub address: Multiaddr,
+    /// Security protocol the connection's transport offers (e.g. `/noise`).
+    /// libp2p does not report the negotiated one; each transport offers a
+    /// single one, so a connection that got established negotiated it.
+    pub expected_security: &'static str,
+    /// Stream multiplexer the connection's transport offers (e.g.
+    /// `/yamux/1.0.0`), see [`Self::expected_security`].
+    pub expected_muxer: &'static str,
+    /// Application protocols the remote advertised via identify; empty when
+    /// identify failed.
+    pub protocols: Vec<String>,
+    /// Agent version the remote advertised via identify.
+    pub agent_version: Option<String>,
+}
+
+/// A closed connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionClosedInfo {
+    pub peer_id: PeerId,
+    /// Remote address of the connection.
+    pub address: Multiaddr,
+    /// Connections still open to the peer.
+    pub remaining: u32,
+    /// Error that closed the connection, if any.
+    pub error: Option<String>,
+}
+
+/// Redundant connection closed in favour of another one to the same peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionDeduplicatedInfo {
+    pub peer_id: PeerId,
+    /// Remote address of the connection kept open.
+    pub kept: Multiaddr,
+    /// Remote address of the connection being closed.
+    pub closed: Multiaddr,
+    /// Whether the closed connection was dialed by this node.
+    pub closed_outbound: bool,
+    /// Policy that picked the connection to keep.
+    pub policy: DuplicateConnectionPolicy,
+}
+
+/// Peer disconnected for running an incompatible version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncompatiblePeerInfo {
+    pub peer_id: PeerId,
+    /// Remote address of the connection identify ran on.
+    pub address: Multiaddr,
+    pub mismatch: VersionMismatch,
+}
+
+/// Why the peer manager penalized a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PenaltyReason {
+    /// Relayed too many messages that failed verification.
+    InvalidMessages,
+    /// Announced a version failing the configured version gate.
+    IncompatibleVersion,
+    /// Matched by an entry of the blocklist.
+    Blocklisted,
+    /// Relayed too many duplicate or invalid messages within the throttle
+    /// window; its gossip score is lowered instead of disconnecting it.
+    ExcessiveGossip,
+}
+
+impl PenaltyReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidMessages => "invalid_messages",
+            Self::IncompatibleVersion => "incompatible_version",
+            Self::Blocklisted => "blocklisted",
+            Self::ExcessiveGossip => "excessive_gossip",
+        }
+    }
+}
+
+/// Peer disconnected or banned by the peer manager, with the evidence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerPenalizedInfo {
+    pub peer_id: PeerId,
+    pub reason: PenaltyReason,
+    /// Whether the peer is also kept from coming back: banned from gossip or
+    /// refused by the blocklist. Otherwise it is only disconnected.
+    pub banned: bool,
+    /// Offences counted against the peer when it was penalized.
+    pub offences: u32,
+    /// Offences at which the penalty applies.
+    pub threshold: u32,
+    /// Reason-specific details, e.g. the failing version or blocklist entry.
+    pub detail: String,
+}
+
+/// Outcome of a DCUtR attempt to upgrade a relayed connection to a direct one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HolePunchEvent {
+    /// A relayed connection to the peer was established; DCUtR attempts to
+    /// upgrade every relayed connection.
+    Initiated {
+        peer_id: PeerId,
+        relayed_address: Multiaddr,
+    },
+    /// A direct connection replaced the relayed one. `address` is the remote
+    /// address of the direct connection when still known.
+    Succeeded {
+        peer_id: PeerId,
+        address: Option<Multiaddr>,
+    },
+    /// The hole punch failed; the connection stays relayed.
+    Failed { peer_id: PeerId, reason: String },
+}
@@ -0,0 +1,128 @@
+//! Size-rotated append-only files written off the event loop.
+//!
+//! Lines are handed to a dedicated thread over a channel, so a slow disk
+//! never stalls the swarm. Once the file exceeds its size limit it is
+//! rotated: `name` becomes `name.1`, the previous `name.1` becomes `name.2`,
+//! and so on up to the number of rotated files kept.
+
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+};
+
+/// Appends lines to a rotated file from a background thread.
+#[derive(Debug)]
+pub(crate) struct FileWriter {
+    lines: mpsc::Sender<Vec<u8>>,
+}
+
+impl FileWriter {
+    /// Starts the writer thread. `kind` names the file in logs, e.g. "metrics".
+    /// The thread exits once the writer is dropped and its queue is drained.
+    pub(crate) fn spawn(
+        kind: &'static str,
+        path: PathBuf,
+        max_file_bytes: u64,
+        max_rotated_files: usize,
+    ) -> Result<Self> {
+        let (lines, queue) = mpsc::channel::<Vec<u8>>();
+        let mut file = RotatingFile {
+            kind,
+            path,
+            max_file_bytes,
+            max_rotated_files,
+            file: None,
+            size: 0,
+        };
+        thread::Builder::new()
+            .name(format!("{kind}-writer"))
+            .spawn(move || {
+                for line in queue {
+                    if let Err(err) = file.append(&line) {
+                        tracing::warn!(target: "peer", %err, kind, "failed to write file");
+                    }
+                }
+            })
+            .with_context(|| format!("failed to start {kind} writer"))?;
+        Ok(Self { lines })
+    }
+
+    /// Queues `line`, which must end with a newline, for writing.
+    pub(crate) fn write(&self, line: Vec<u8>) -> Result<()> {
+        self.lines
+            .send(line)
+            .map_err(|_| anyhow::anyhow!("file writer stopped"))
+    }
+}
+
+/// Append-only file rotated once it exceeds its size limit.
+#[derive(Debug)]
+struct RotatingFile {
+    kind: &'static str,
+    path: PathBuf,
+    max_file_bytes: u64,
+    max_rotated_files: usize,
+    file: Option<File>,
+    /// Bytes in the current file.
+    size: u64,
+}
+
+impl RotatingFile {
+    fn append(&mut self, line: &[u8]) -> Result<()> {
+        if self.file.is_some() && self.size + line.len() as u64 > self.max_file_bytes {
+            self.rotate()?;
+        }
+        let (kind, path) = (self.kind, &self.path);
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open {kind} file {}", path.display()))?;
+                self.size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+                self.file.insert(file)
+            }
+        };
+        file.write_all(line)
+            .with_context(|| format!("failed to write {kind} file {}", path.display()))?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts the rotated files up by one, dropping the oldest, and moves the
+    /// current file to `.1`. The next line opens a fresh file.
+    fn rotate(&mut self) -> Result<()> {
+        self.file = None;
+        self.size = 0;
+        let (kind, path) = (self.kind, &self.path);
+        if self.max_rotated_files == 0 {
+            return ignore_missing(fs::remove_file(path))
+                .with_context(|| format!("failed to remove {kind} file {}", path.display()));
+        }
+        for index in (1..self.max_rotated_files).rev() {
+            ignore_missing(fs::rename(self.rotated(index), self.rotated(index + 1)))
+                .with_context(|| format!("failed to rotate {kind} file {}", path.display()))?;
+        }
+        ignore_missing(fs::rename(path, self.rotated(1)))
+            .with_context(|| format!("failed to rotate {kind} file {}", path.display()))
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+}
+
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
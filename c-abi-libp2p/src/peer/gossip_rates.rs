@@ -0,0 +1,206 @@
+//! Per-peer gossip delivery counters and the throttling of noisy peers.
+
+use libp2p::PeerId;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+/// Window over which duplicates and invalid messages are counted by default.
+pub const DEFAULT_GOSSIP_THROTTLE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits past which a peer relaying duplicate or invalid gossip is throttled.
+///
+/// A throttled peer gets a negative application score, so gossipsub prunes it
+/// from the mesh and stops gossiping to it until the score is reset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GossipThrottleSettings {
+    /// Window the limits apply to. A throttled peer is released once a whole
+    /// window passes without it exceeding a limit again.
+    pub window: Duration,
+    /// Duplicates relayed within a window before the peer is throttled.
+    /// Duplicates are envelopes refused by replay protection, which is why
+    /// throttling needs a replay window configured.
+    pub max_duplicates: u32,
+    /// Invalid messages relayed within a window before the peer is throttled.
+    pub max_invalid: u32,
+    /// Application score given to a throttled peer; must be negative.
+    pub score_penalty: f64,
+}
+
+impl Default for GossipThrottleSettings {
+    fn default() -> Self {
+        Self {
+            window: DEFAULT_GOSSIP_THROTTLE_WINDOW,
+            max_duplicates: 20,
+            // Below the invalid message count that blacklists the peer.
+            max_invalid: 1,
+            // Under the default gossip threshold of -10.
+            score_penalty: -20.0,
+        }
+    }
+}
+
+/// How an inbound message relayed by a peer was judged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GossipVerdict {
+    /// The message was accepted.
+    Delivered,
+    /// The message was already seen.
+    Duplicate,
+    /// The message failed verification.
+    Invalid,
+}
+
+/// Gossip counters of one connected peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerGossipStats {
+    /// Messages accepted from the peer.
+    pub delivered: u64,
+    /// Duplicate messages relayed by the peer.
+    pub duplicates: u64,
+    /// Invalid messages relayed by the peer.
+    pub invalid: u64,
+    /// Times the peer was throttled.
+    pub throttles: u32,
+    /// Whether the peer is currently throttled.
+    pub throttled: bool,
+}
+
+#[derive(Debug)]
+struct PeerRates {
+    stats: PeerGossipStats,
+    window_start: Instant,
+    window_duplicates: u32,
+    window_invalid: u32,
+    /// When a throttled peer is released unless it offends again.
+    release_at: Option<Instant>,
+}
+
+impl PeerRates {
+    fn new(now: Instant) -> Self {
+        Self {
+            stats: PeerGossipStats::default(),
+            window_start: now,
+            window_duplicates: 0,
+            window_invalid: 0,
+            release_at: None,
+        }
+    }
+}
+
+/// Shared per-peer gossip counters, updated by the peer manager and read by
+/// its handles.
+#[derive(Debug, Default)]
+pub struct GossipRates {
+    peers: RwLock<HashMap<PeerId, PeerRates>>,
+}
+
+impl GossipRates {
+    /// Returns the counters of every peer that relayed gossip since it connected.
+    pub fn snapshot(&self) -> BTreeMap<PeerId, PeerGossipStats> {
+        let Ok(peers) = self.peers.read() else {
+            tracing::warn!(target: "peer", "gossip rates lock poisoned");
+            return BTreeMap::new();
+        };
+        peers
+            .iter()
+            .map(|(peer_id, rates)| (*peer_id, rates.stats))
+            .collect()
+    }
+
+    /// Returns the counters of `peer_id`, if it relayed gossip since it connected.
+    pub fn peer(&self, peer_id: &PeerId) -> Option<PeerGossipStats> {
+        let Ok(peers) = self.peers.read() else {
+            tracing::warn!(target: "peer", "gossip rates lock poisoned");
+            return None;
+        };
+        peers.get(peer_id).map(|rates| rates.stats)
+    }
+
+    /// Counts a message relayed by `peer_id`. With `settings`, answers the
+    /// offences of the current window when they just pushed the peer past a
+    /// limit; the peer is then throttled until released.
+    pub(crate) fn record(
+        &self,
+        peer_id: PeerId,
+        verdict: GossipVerdict,
+        settings: Option<&GossipThrottleSettings>,
+        now: Instant,
+    ) -> Option<(u32, u32)> {
+        let Ok(mut peers) = self.peers.write() else {
+            tracing::warn!(target: "peer", "gossip rates lock poisoned");
+            return None;
+        };
+        let rates = peers.entry(peer_id).or_insert_with(|| PeerRates::new(now));
+        match verdict {
+            GossipVerdict::Delivered => {
+                rates.stats.delivered += 1;
+                return None;
+            }
+            GossipVerdict::Duplicate => rates.stats.duplicates += 1,
+            GossipVerdict::Invalid => rates.stats.invalid += 1,
+        }
+        let settings = settings?;
+
+        if now.duration_since(rates.window_start) >= settings.window {
+            rates.window_start = now;
+            rates.window_duplicates = 0;
+            rates.window_invalid = 0;
+        }
+        match verdict {
+            GossipVerdict::Duplicate => rates.window_duplicates += 1,
+            _ => rates.window_invalid += 1,
+        }
+        if rates.window_duplicates <= settings.max_duplicates
+            && rates.window_invalid <= settings.max_invalid
+        {
+            return None;
+        }
+
+        // Every offence past a limit postpones the release.
+        rates.release_at = Some(now + settings.window);
+        if rates.stats.throttled {
+            return None;
+        }
+        rates.stats.throttled = true;
+        rates.stats.throttles += 1;
+        Some((rates.window_duplicates, rates.window_invalid))
+    }
+
+    /// Releases the throttled peers whose release time passed and returns them.
+    pub(crate) fn release_expired(&self, now: Instant) -> Vec<PeerId> {
+        let Ok(mut peers) = self.peers.write() else {
+            tracing::warn!(target: "peer", "gossip rates lock poisoned");
+            return Vec::new();
+        };
+        let mut released = Vec::new();
+        for (peer_id, rates) in peers.iter_mut() {
+            if rates.release_at.is_some_and(|release_at| release_at <= now) {
+                rates.release_at = None;
+                rates.stats.throttled = false;
+                released.push(*peer_id);
+            }
+        }
+        released
+    }
+
+    /// Drops the counters of a peer whose last connection closed.
+    pub(crate) fn peer_disconnected(&self, peer_id: &PeerId) {
+        let Ok(mut peers) = self.peers.write() else {
+            tracing::warn!(target: "peer", "gossip rates lock poisoned");
+            return;
+        };
+        peers.remove(peer_id);
+    }
+
+    /// Drops the counters of every peer, e.g. when the swarm is rebuilt.
+    pub(crate) fn clear(&self) {
+        let Ok(mut peers) = self.peers.write() else {
+            tracing::warn!(target: "peer", "gossip rates lock poisoned");
+            return;
+        };
+        peers.clear();
+    }
+}
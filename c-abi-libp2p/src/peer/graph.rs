@@ -0,0 +1,129 @@
+//! Live graph of the peers known to the node and how they relate to it.
+//!
+//! The peer manager refreshes the graph from the swarm every
+//! [`PEER_GRAPH_INTERVAL`] and broadcasts what changed as a batch of
+//! [`PeerGraphDiff`]s. Diffs carry the full relations of a peer, so applying
+//! one twice is harmless and a subscriber can start from
+//! [`crate::peer::PeerManagerHandle::peer_graph`] taken after subscribing.
+
+use libp2p::PeerId;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::RwLock,
+    time::Duration,
+};
+use tokio::sync::broadcast;
+
+/// Interval at which the graph is refreshed.
+pub const PEER_GRAPH_INTERVAL: Duration = Duration::from_secs(1);
+/// Capacity of the diff broadcast channel. Subscribers lagging more than this
+/// many batches behind miss the oldest ones and should re-read the graph.
+pub const DEFAULT_PEER_GRAPH_CAPACITY: usize = 64;
+
+/// How a known peer relates to the local node.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerRelations {
+    /// At least one connection is open.
+    pub connected: bool,
+    /// Topics whose gossipsub mesh includes the peer.
+    pub mesh_topics: BTreeSet<String>,
+    /// The peer has an entry in the Kademlia routing table.
+    pub in_routing_table: bool,
+}
+
+impl PeerRelations {
+    fn is_empty(&self) -> bool {
+        !self.connected && self.mesh_topics.is_empty() && !self.in_routing_table
+    }
+}
+
+/// Change of one peer between two refreshes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerGraphDiff {
+    /// The peer became known, with its relations.
+    Added {
+        peer_id: PeerId,
+        relations: PeerRelations,
+    },
+    /// The relations of a known peer changed; `relations` is the new state.
+    Changed {
+        peer_id: PeerId,
+        relations: PeerRelations,
+    },
+    /// The peer has no relation left.
+    Removed { peer_id: PeerId },
+}
+
+/// Graph of known peers, keyed by peer id.
+pub type PeerGraph = BTreeMap<PeerId, PeerRelations>;
+
+/// Shared graph read by handles and its diff broadcast.
+#[derive(Debug)]
+pub struct PeerGraphState {
+    graph: RwLock<PeerGraph>,
+    diffs: broadcast::Sender<Vec<PeerGraphDiff>>,
+}
+
+impl Default for PeerGraphState {
+    fn default() -> Self {
+        let (diffs, _) = broadcast::channel(DEFAULT_PEER_GRAPH_CAPACITY);
+        Self {
+            graph: RwLock::new(PeerGraph::new()),
+            diffs,
+        }
+    }
+}
+
+impl PeerGraphState {
+    /// Returns the graph as of the last refresh.
+    pub fn snapshot(&self) -> PeerGraph {
+        match self.graph.read() {
+            Ok(graph) => graph.clone(),
+            Err(_) => {
+                tracing::warn!(target: "peer", "peer graph lock poisoned");
+                PeerGraph::new()
+            }
+        }
+    }
+
+    /// Subscribes to the batches of diffs of future refreshes.
+    pub fn subscribe(&self) -> broadcast::Receiver<Vec<PeerGraphDiff>> {
+        self.diffs.subscribe()
+    }
+
+    /// Replaces the graph with `next`, dropping peers without relations, and
+    /// broadcasts the differences when there are any.
+    pub(crate) fn update(&self, mut next: PeerGraph) {
+        next.retain(|_, relations| !relations.is_empty());
+        let Ok(mut graph) = self.graph.write() else {
+            tracing::warn!(target: "peer", "peer graph lock poisoned");
+            return;
+        };
+
+        let mut diffs = Vec::new();
+        for (peer_id, relations) in &next {
+            match graph.get(peer_id) {
+                None => diffs.push(PeerGraphDiff::Added {
+                    peer_id: *peer_id,
+                    relations: relations.clone(),
+                }),
+                Some(previous) if previous != relations => diffs.push(PeerGraphDiff::Changed {
+                    peer_id: *peer_id,
+                    relations: relations.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        for peer_id in graph.keys() {
+            if !next.contains_key(peer_id) {
+                diffs.push(PeerGraphDiff::Removed { peer_id: *peer_id });
+            }
+        }
+
+        *graph = next;
+        if !diffs.is_empty() {
+            // Without subscribers the diffs are simply dropped.
+            let _ = self.diffs.send(diffs);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Namespaced keys for the flat DHT keyspace.
+//!
+//! Encoding: `/<namespace>/<id>`, where the namespace is 1 to 64 bytes of
+//! lowercase ASCII letters, digits, `.`, `_` or `-`, and the id is 1 to
+//! [`MAX_KEY_ID_LEN`] arbitrary bytes.
+
+use anyhow::{anyhow, Result};
+use libp2p::kad;
+use std::fmt;
+
+/// Longest accepted namespace in bytes.
+pub const MAX_NAMESPACE_LEN: usize = 64;
+/// Longest accepted key id in bytes.
+pub const MAX_KEY_ID_LEN: usize = 256;
+
+/// DHT key scoped to an application namespace, so applications sharing the
+/// DHT do not collide.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    namespace: String,
+    id: Vec<u8>,
+}
+
+impl Key {
+    /// Creates a key for `id` in `namespace`, validating both.
+    pub fn new(namespace: impl Into<String>, id: impl Into<Vec<u8>>) -> Result<Self> {
+        let namespace = namespace.into();
+        let id = id.into();
+        validate_namespace(&namespace)?;
+        if id.is_empty() || id.len() > MAX_KEY_ID_LEN {
+            return Err(anyhow!(
+                "key id must be 1 to {MAX_KEY_ID_LEN} bytes, got {}",
+                id.len()
+            ));
+        }
+        Ok(Self { namespace, id })
+    }
+
+    /// Parses an encoded key.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let rest = bytes
+            .strip_prefix(b"/")
+            .ok_or_else(|| anyhow!("namespaced key must start with '/'"))?;
+        let separator = rest
+            .iter()
+            .position(|byte| *byte == b'/')
+            .ok_or_else(|| anyhow!("namespaced key has no id separator"))?;
+        let (namespace, id) = (&rest[..separator], &rest[separator + 1..]);
+        let namespace = std::str::from_utf8(namespace)
+            .map_err(|_| anyhow!("key namespace is not valid UTF-8"))?;
+        Self::new(namespace, id)
+    }
+
+    /// Namespace of the key.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Id of the key within its namespace.
+    pub fn id(&self) -> &[u8] {
+        &self.id
+    }
+
+    /// Encodes the key as stored in the DHT.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.namespace.len() + self.id.len() + 2);
+        out.push(b'/');
+        out.extend_from_slice(self.namespace.as_bytes());
+        out.push(b'/');
+        out.extend_from_slice(&self.id);
+        out
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match std::str::from_utf8(&self.id) {
+            Ok(id) => write!(f, "/{}/{id}", self.namespace),
+            Err(_) => write!(f, "/{}/0x{}", self.namespace, hex::encode(&self.id)),
+        }
+    }
+}
+
+impl From<Key> for Vec<u8> {
+    fn from(key: Key) -> Self {
+        key.to_bytes()
+    }
+}
+
+impl From<&Key> for Vec<u8> {
+    fn from(key: &Key) -> Self {
+        key.to_bytes()
+    }
+}
+
+impl From<&Key> for kad::RecordKey {
+    fn from(key: &Key) -> Self {
+        kad::RecordKey::new(&key.to_bytes())
+    }
+}
+
+fn validate_namespace(namespace: &str) -> Result<()> {
+    if namespace.is_empty() || namespace.len() > MAX_NAMESPACE_LEN {
+        return Err(anyhow!(
+            "key namespace must be 1 to {MAX_NAMESPACE_LEN} bytes, got {}",
+            namespace.len()
+        ));
+    }
+    let valid = namespace
+        .bytes()
+        .all(|byte| matches!(byte, b'a'..=b'z' | b'0'..=b'9' | b'.' | b'_' | b'-'));
+    if !valid {
+        return Err(anyhow!(
+            "key namespace {namespace:?} may only contain lowercase letters, digits, '.', '_' and '-'"
+        ));
+    }
+    Ok(())
+}
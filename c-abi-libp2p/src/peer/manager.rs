@@ -8,65 +8,630 @@
 use anyhow::{anyhow, Result};
 use futures::StreamExt;
 use libp2p::{
-    core::Multiaddr,
-    gossipsub,
-    identity,
-    swarm::{DialError, Swarm, SwarmEvent},
-    PeerId,
     autonat,
-    kad::{self, QueryResult},
-    relay,
+    core::{transport::ListenerId, ConnectedPoint, Multiaddr, PeerRecord, SignedEnvelope},
+    dcutr, gossipsub, identify, identity,
+    kad::{self, store::RecordStore, QueryResult},
     multiaddr::Protocol,
+    relay, rendezvous,
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionId, DialError, Swarm, SwarmEvent,
+    },
+    PeerId,
 };
-use std::collections::{HashMap, HashSet};
-use std::time::{Duration, Instant};
+use prometheus_client::registry::Registry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::num::NonZeroUsize;
 use std::sync::{Arc, RwLock};
-use tokio::sync::{mpsc, watch};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot, watch},
+    time::MissedTickBehavior,
+};
 
 const DISCOVERY_DIAL_BACKOFF: Duration = Duration::from_secs(30);
+/// Number of rejected messages after which the relaying peer is blacklisted.
+const INVALID_MESSAGE_THRESHOLD: u32 = 3;
+/// Period of the housekeeping tick driving timers in the event loop.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_millis(250);
+/// Maximum number of publishes buffered during the startup warm-up.
+const MAX_WARMUP_PUBLISHES: usize = 64;
+/// Time a `connect` request may take before it is reported as failed.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time a relayed `connect` waits for DCUtR to upgrade it to a direct connection.
+const CONNECT_UPGRADE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Period between AutoNAT probes of configured external address candidates
+/// that are not confirmed yet.
+const EXTERNAL_CANDIDATE_PROBE_INTERVAL: Duration = Duration::from_secs(60);
+/// Number of listener failures after which the watchdog rebuilds the swarm.
+const WATCHDOG_LISTENER_ERROR_THRESHOLD: u32 = 5;
+/// Fraction of the reservation lifetime, before expiry, at which a relay
+/// reservation the relay client did not renew yet is re-requested.
+const RELAY_RENEWAL_MARGIN_DIVISOR: u32 = 10;
+/// Time an on-demand AutoNAT probe may take before it is reported as inconclusive.
+const ADDRESS_PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum number of publishes waiting for their scheduled time.
+const MAX_SCHEDULED_PUBLISHES: usize = 1024;
+/// Time after which a discovery query that never produced its last step is
+/// finished as timed out. Well above the Kademlia query timeout.
+const DISCOVERY_QUERY_DEADLINE: Duration = Duration::from_secs(60);
+/// Period at which the rosters of joined groups are announced to their members.
+const GROUP_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30);
+/// Group members not heard from for this long are considered gone.
+const GROUP_MEMBER_TIMEOUT: Duration = Duration::from_secs(90);
+/// Attempts at announcing a join while the group topic mesh forms.
+const GROUP_JOIN_ATTEMPTS: u32 = 5;
 
 use crate::{
-    addr_events::{AddrState, AddrEvent}, 
-    messaging::MessageQueueSender, 
-    discovery::{DiscoveryEvent, DiscoveryEventSender, DiscoveryStatus}, 
-    transport::{BehaviourEvent, NetworkBehaviour, TransportConfig},
+    addr_events::{AddrEvent, AddrState},
+    audit::{AuditLog, AuditOutcome},
+    connections::{ConnectionCounts, ConnectionMetrics, PeerConnection},
+    delegated,
+    dht_stats::{DhtMetrics, DhtQueryStats, DhtStoreStats},
+    discovery::{DiscoveryEvent, DiscoveryEventSender, DiscoveryStatus, QueryLimits},
+    events::{
+        ConnectionClosedInfo, ConnectionDeduplicatedInfo, ConnectionInfo, DialFinishedEvent,
+        GroupEvent, HolePunchEvent, IncompatiblePeerInfo, NetworkEvent, PeerPenalizedInfo,
+        PenaltyReason, SlowConsumerEvent, SubscriptionChange, DEFAULT_NETWORK_EVENT_CAPACITY,
+    },
+    gossip_rates::{GossipRates, GossipVerdict, PeerGossipStats},
+    graph::{PeerGraph, PeerGraphDiff, PeerGraphState, PEER_GRAPH_INTERVAL},
+    messaging::{
+        envelope::unix_time_ms, group_topic, BusMode, DeadLetter, DropPolicy, Envelope, EventBus,
+        EventSender, GroupFrame, MessageQueueSender, MessageVerifier, ReplayCache, ReplayError,
+        RoutedMessage, TopicCipher, TopicRouter, TraceId, VerificationResult, GROUP_TOPIC_PREFIX,
+        MAX_GROUP_ROSTER,
+    },
+    metrics::{MetricsRecorder, MetricsSnapshot},
+    node_events::NodeEventStream,
+    observed::{ObservedAddress, ObservedAddrs},
+    peer::redact::LogRedactor,
+    peer_records::{decode_peer_record, encode_peer_record, parse_address_book, PeerRecordCache},
+    prometheus::PrometheusMetrics,
+    records::RecordValidator,
+    relay_events::RelayReservationEvent,
+    relay_stats::{RelayMetrics, RelayServerStats},
+    retry::RetryPolicy,
+    transport::{
+        expected_connection_stack, is_relayed, unix_time_secs, BehaviourEvent, Blocked, Blocklist,
+        BlocklistUpdate, ClockOffsets, NetworkBehaviour, PeerStats, PowerProfile, ProbeEvent,
+        SubstreamMetrics, SubstreamStats, TimeSyncEvent, TransportConfig, MAX_PROBE_DURATION,
+        MAX_PROBE_PINGS, PROBE_PING_TIMEOUT, TIME_PROTOCOL,
+    },
     //config::DEFAULT_BOOTSTRAP_PEERS, // Dunno. Its empty should be here
 };
 
 /// Commands supported by the [`PeerManager`] event loop.
 #[derive(Debug)]
 pub enum PeerCommand {
-    /// Start listening on the provided multi-address.
-    StartListening(Multiaddr),
+    /// Start listening on the provided multi-address and answer with the
+    /// listener id.
+    StartListening {
+        address: Multiaddr,
+        responder: oneshot::Sender<Result<ListenerId>>,
+    },
     /// Initiate a Kademlia find peer query for the provided target.
-    FindPeer { peer_id: PeerId, request_id: u64 },
+    /// Events go to `results` when set, otherwise to the shared discovery queue.
+    FindPeer {
+        peer_id: PeerId,
+        request_id: u64,
+        results: Option<DiscoveryEventSender>,
+        retry: RetryPolicy,
+    },
     /// Initiate a Kademlia get_closest_peers query for the provided target.
-    GetClosestPeers { peer_id: PeerId, request_id: u64 },
-    /// Dial the given remote multi-address.
-    Dial(Multiaddr),
-    /// Dial a public relay and request a reservation.
-    ReserveRelay(Multiaddr),
-    /// Publish a payload to the gossipsub topic.
-    Publish(Vec<u8>),
+    /// Events go to `results` when set, otherwise to the shared discovery queue.
+    GetClosestPeers {
+        peer_id: PeerId,
+        request_id: u64,
+        results: Option<DiscoveryEventSender>,
+        retry: RetryPolicy,
+    },
+    /// Initiate a Kademlia provider lookup for `key`, bounded by `limits`.
+    /// Providers are reported as they are found, to `results` when set,
+    /// otherwise to the shared discovery queue.
+    GetProviders {
+        key: Vec<u8>,
+        request_id: u64,
+        results: Option<DiscoveryEventSender>,
+        retry: RetryPolicy,
+        limits: QueryLimits,
+    },
+    /// Announce the local node as a provider of `key` to the peers closest to
+    /// it, reporting a `ProvidingStarted` event once the announcement finished,
+    /// to `results` when set, otherwise to the shared discovery queue.
+    StartProviding {
+        key: Vec<u8>,
+        request_id: u64,
+        results: Option<DiscoveryEventSender>,
+    },
+    /// Stop announcing the local node as a provider of `key` and answer
+    /// whether it was provided.
+    StopProviding {
+        key: Vec<u8>,
+        responder: oneshot::Sender<bool>,
+    },
+    /// Store `value` under `key` locally and on the peers closest to the key,
+    /// reporting a `RecordStored` event once `quorum` of them acknowledged it
+    /// or the put failed, to `results` when set, otherwise to the shared
+    /// discovery queue.
+    PutRecord {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        quorum: kad::Quorum,
+        request_id: u64,
+        results: Option<DiscoveryEventSender>,
+    },
+    /// Initiate a Kademlia record lookup for `key`. Records are reported as
+    /// they are found, to `results` when set, otherwise to the shared
+    /// discovery queue.
+    GetRecord {
+        key: Vec<u8>,
+        request_id: u64,
+        results: Option<DiscoveryEventSender>,
+    },
+    /// Answer with the peers closest to `peer_id` from the local routing
+    /// table, without issuing any network query.
+    GetClosestPeersLocal {
+        peer_id: PeerId,
+        responder: oneshot::Sender<Vec<PeerId>>,
+    },
+    /// Connect to `peer_id` directly, falling back to a relay circuit and
+    /// upgrading through DCUtR, and answer with the final outcome.
+    Connect {
+        peer_id: PeerId,
+        responder: oneshot::Sender<ConnectOutcome>,
+    },
+    /// Ask AutoNAT servers to dial `address` back now and answer with the result.
+    ProbeAddress {
+        address: Multiaddr,
+        responder: oneshot::Sender<AddressProbeOutcome>,
+    },
+    /// Push identify info to the connected `peer_id` and send it `count` pings
+    /// back to back, answering with the round-trip times.
+    ProbePeer {
+        peer_id: PeerId,
+        count: u32,
+        responder: oneshot::Sender<PeerProbeReport>,
+    },
+    /// Dial the given remote multi-address. With a `request_id` or a retry
+    /// policy, the dial gets no relay fallback; with a `request_id` its outcome
+    /// is reported as [`NetworkEvent::DialFinished`]. Answers with the id of
+    /// the connection being dialed, or why no dial could be started.
+    Dial {
+        address: Multiaddr,
+        request_id: Option<u64>,
+        retry: RetryPolicy,
+        responder: oneshot::Sender<Result<ConnectionId>>,
+    },
+    /// Dial `address` only if it uses `transport`, without relay fallback, and
+    /// answer once the connection is established or failed.
+    DialWithTransport {
+        address: Multiaddr,
+        transport: DialTransport,
+        retry: RetryPolicy,
+        responder: oneshot::Sender<ConnectOutcome>,
+    },
+    /// Dial `peer_id` on its known addresses, restricted to `transport` when
+    /// set, without relay fallback, and answer with the outcome. Without a
+    /// `transport`, the addresses from the routing table, open connections
+    /// and identify, plus those the behaviours know, are dialed
+    /// concurrently, up to the swarm's dial concurrency at a time, starting
+    /// with direct QUIC, then direct TCP, then relay circuits; the first
+    /// connection established wins. When no address is known, they are
+    /// looked up in the DHT first.
+    /// With a `request_id`, the outcome is also reported as
+    /// [`NetworkEvent::DialFinished`].
+    DialPeer {
+        peer_id: PeerId,
+        transport: Option<DialTransport>,
+        request_id: Option<u64>,
+        retry: RetryPolicy,
+        responder: oneshot::Sender<ConnectOutcome>,
+    },
+    /// Dial a public relay and request a reservation, answering with the id
+    /// of the relayed listener.
+    ReserveRelay {
+        address: Multiaddr,
+        responder: oneshot::Sender<Result<ListenerId>>,
+    },
+    /// Request a reservation on `relay_peer` reachable at `relay_addr`, answer
+    /// with the id of the relayed listener and report the reservation's
+    /// acceptance, renewals and expiry to `events`.
+    ListenViaRelay {
+        relay_peer: PeerId,
+        relay_addr: Multiaddr,
+        events: mpsc::Sender<RelayReservationEvent>,
+        responder: oneshot::Sender<Result<ListenerId>>,
+    },
+    /// Push the current identify info to every connected peer now and answer
+    /// with the number of peers it was pushed to.
+    PushIdentify {
+        responder: oneshot::Sender<Result<usize>>,
+    },
+    /// Exempt (or stop exempting) the connections to `peer_id` from the idle timeout.
+    SetPeerPinned {
+        peer_id: PeerId,
+        pinned: bool,
+        responder: oneshot::Sender<Result<()>>,
+    },
+    /// Apply a blocklist update, answering with the resulting entry count.
+    ImportBlocklist {
+        update: BlocklistUpdate,
+        responder: oneshot::Sender<Result<usize>>,
+    },
+    /// Answer with the median offset of peers' clocks to the local one, in
+    /// milliseconds, once enough peers answered a time request.
+    NetworkTimeOffset {
+        responder: oneshot::Sender<Option<i64>>,
+    },
+    /// Answer with the protobuf-encoded identity keypair in use.
+    ExportKeypair {
+        responder: oneshot::Sender<Result<Vec<u8>>>,
+    },
+    /// Answer with the signed peer record of `peer_id`, or of the local node
+    /// when unset; `None` when no record of the peer is known.
+    ExportPeerRecord {
+        peer_id: Option<PeerId>,
+        responder: oneshot::Sender<Result<Option<Vec<u8>>>>,
+    },
+    /// Verify a signed peer record and learn the addresses it lists.
+    ImportPeerRecord {
+        record: Vec<u8>,
+        responder: oneshot::Sender<Result<PeerId>>,
+    },
+    /// Seed the swarm and routing table from signed peer records or a text
+    /// address book and answer with the peers learnt.
+    ImportPeerRecords {
+        bytes: Vec<u8>,
+        responder: oneshot::Sender<Result<Vec<PeerId>>>,
+    },
+    /// Answer with the current blocklist.
+    Blocklist {
+        responder: oneshot::Sender<Blocklist>,
+    },
+    /// Switch the battery and data trade-off, rebuilding the swarm.
+    SetPowerProfile {
+        profile: PowerProfile,
+        responder: oneshot::Sender<Result<()>>,
+    },
+    /// React to a connectivity change reported by the host.
+    NetworkChanged {
+        change: NetworkChange,
+        responder: oneshot::Sender<Result<()>>,
+    },
+    /// Serve (or stop serving) AutoNAT dial-back requests on new inbound connections.
+    SetAutonatServer {
+        enabled: bool,
+        responder: oneshot::Sender<Result<()>>,
+    },
+    /// Accept (or stop accepting) relay reservations and circuits on new connections.
+    SetRelayServer {
+        enabled: bool,
+        responder: oneshot::Sender<Result<()>>,
+    },
+    /// Publish a payload to the gossipsub topic, answering with the delivery
+    /// estimate once it left the node when a receipt channel is given.
+    Publish {
+        payload: Vec<u8>,
+        retry: RetryPolicy,
+        trace_id: Option<TraceId>,
+        receipt: Option<oneshot::Sender<Result<PublishReceipt>>>,
+    },
+    /// Publish `payload` to `topic` and answer with the delivery estimate
+    /// once it left the node.
+    PublishTo {
+        topic: gossipsub::IdentTopic,
+        payload: Vec<u8>,
+        retry: RetryPolicy,
+        responder: oneshot::Sender<Result<PublishReceipt>>,
+    },
+    /// Subscribe to `topic`; answers whether the node was not subscribed yet.
+    Subscribe {
+        topic: String,
+        responder: oneshot::Sender<Result<bool>>,
+    },
+    /// Unsubscribe from `topic`; answers whether the node was subscribed.
+    Unsubscribe {
+        topic: String,
+        responder: oneshot::Sender<Result<bool>>,
+    },
+    /// Publish `payload` to `topic` once `at` is reached and answer with the
+    /// id cancelling it.
+    SchedulePublish {
+        topic: gossipsub::IdentTopic,
+        payload: Vec<u8>,
+        at: Instant,
+        retry: RetryPolicy,
+        responder: oneshot::Sender<Result<u64>>,
+    },
+    /// Answer with the publishes that expired while held by the warm-up.
+    TakeOutboundDeadLetters {
+        responder: oneshot::Sender<Vec<DeadLetter<Vec<u8>>>>,
+    },
+    /// Drop a scheduled publish before it fires; answers whether it was pending.
+    CancelScheduledPublish {
+        id: u64,
+        responder: oneshot::Sender<bool>,
+    },
+    /// Resolve `responder` once `topic` has at least `min_peers` mesh peers.
+    WaitMeshReady {
+        topic: gossipsub::TopicHash,
+        min_peers: usize,
+        responder: oneshot::Sender<()>,
+    },
+    /// Subscribe to the topic of `group` and announce the local node to its
+    /// members.
+    JoinGroup {
+        group: String,
+        responder: oneshot::Sender<Result<()>>,
+    },
+    /// Announce leaving `group` and unsubscribe from its topic.
+    LeaveGroup {
+        group: String,
+        responder: oneshot::Sender<Result<()>>,
+    },
+    /// Subscribe to every topic of `topics` not subscribed yet and unsubscribe
+    /// from every subscribed topic missing from it, all or nothing, and answer
+    /// with the change.
+    SetSubscriptions {
+        topics: Vec<String>,
+        responder: oneshot::Sender<Result<SubscriptionChange>>,
+    },
+    /// Publish `payload` to the members of a joined group.
+    PublishToGroup {
+        group: String,
+        payload: Vec<u8>,
+        retry: RetryPolicy,
+        responder: oneshot::Sender<Result<()>>,
+    },
+    /// Answer with the known remote members of a joined group.
+    GroupMembers {
+        group: String,
+        responder: oneshot::Sender<Result<Vec<PeerId>>>,
+    },
+    /// Answer with the addresses connected peers observe us at.
+    ObservedAddresses {
+        responder: oneshot::Sender<Vec<ObservedAddress>>,
+    },
+    /// Answer with the subscribed topics and their peer counts.
+    Subscriptions {
+        responder: oneshot::Sender<Vec<TopicSubscription>>,
+    },
+    /// Apply the runtime-tunable settings of `config` without rebuilding the
+    /// swarm and answer with what was applied.
+    ReloadConfig {
+        config: Box<RuntimeConfig>,
+        responder: oneshot::Sender<Result<ReloadReport>>,
+    },
     /// Shut the manager down gracefully.
     Shutdown,
 }
 
+/// Final outcome of [`PeerManagerHandle::connect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectOutcome {
+    /// Connected directly, possibly after a DCUtR upgrade.
+    Direct { address: Multiaddr },
+    /// Connected through a relay circuit; the direct upgrade did not succeed.
+    Relayed { address: Multiaddr },
+    /// No connection could be established.
+    Failed { reason: String },
+    /// The target is this node, reached through one of its own addresses;
+    /// no connection was attempted.
+    SelfDial,
+}
+
+/// Transport an explicit dial is restricted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DialTransport {
+    /// Direct TCP connections only.
+    Tcp,
+    /// Direct QUIC connections only.
+    Quic,
+    /// Relay circuits only.
+    Relay,
+}
+
+impl DialTransport {
+    /// Returns `true` when dialing `address` uses this transport.
+    pub fn matches(&self, address: &Multiaddr) -> bool {
+        let relayed = is_relayed(address);
+        match self {
+            Self::Tcp => !relayed && address.iter().any(|p| matches!(p, Protocol::Tcp(_))),
+            Self::Quic => {
+                !relayed
+                    && address
+                        .iter()
+                        .any(|p| matches!(p, Protocol::QuicV1 | Protocol::Quic))
+            }
+            Self::Relay => relayed,
+        }
+    }
+}
+
+/// Connectivity change reported by the host, see
+/// [`PeerManagerHandle::network_changed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkChange {
+    /// The active network moved, e.g. from Wi-Fi to cellular; connections
+    /// and addresses of the previous one are stale.
+    Switched,
+    /// No network is available, e.g. in airplane mode.
+    Lost,
+    /// A network is available again after [`Self::Lost`].
+    Restored,
+}
+
+/// Result of [`PeerManagerHandle::probe_address`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressProbeOutcome {
+    /// An AutoNAT server dialed the address back successfully.
+    Reachable,
+    /// The AutoNAT server could not dial the address back.
+    Unreachable { reason: String },
+    /// No verdict: no AutoNAT server was available, the server confirmed
+    /// another address, or the probe timed out.
+    Inconclusive { reason: String },
+}
+
+/// Result of [`PeerManagerHandle::probe_peer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerProbeReport {
+    /// How the peer is connected, or why it could not be reached; no ping
+    /// is sent to an unreachable peer.
+    pub reachability: ConnectOutcome,
+    /// Pings requested.
+    pub count: u32,
+    /// Round-trip time of every answered ping, in order.
+    pub rtts: Vec<Duration>,
+    /// Why the burst stopped before every ping was answered, if it did.
+    pub error: Option<String>,
+}
+
+impl PeerProbeReport {
+    /// Returns `true` when the peer was reached and answered every ping.
+    pub fn is_healthy(&self) -> bool {
+        matches!(
+            self.reachability,
+            ConnectOutcome::Direct { .. } | ConnectOutcome::Relayed { .. }
+        ) && self.lost() == 0
+    }
+
+    /// Pings left unanswered.
+    pub fn lost(&self) -> u32 {
+        self.count.saturating_sub(self.rtts.len() as u32)
+    }
+
+    /// Fastest round trip, `None` when no ping was answered.
+    pub fn min_rtt(&self) -> Option<Duration> {
+        self.rtts.iter().min().copied()
+    }
+
+    /// Slowest round trip, `None` when no ping was answered.
+    pub fn max_rtt(&self) -> Option<Duration> {
+        self.rtts.iter().max().copied()
+    }
+
+    /// Average round trip of the answered pings.
+    pub fn mean_rtt(&self) -> Option<Duration> {
+        let total: Duration = self.rtts.iter().sum();
+        (!self.rtts.is_empty()).then(|| total / self.rtts.len() as u32)
+    }
+}
+
+/// Delivery estimate of a publish, see [`PeerManagerHandle::publish`].
+///
+/// Counts come from the local gossipsub state right after the publish, so
+/// they tell how many peers were sent the message, not how many received it.
+/// A receipt with `forwarded_to == 0` means the message went nowhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishReceipt {
+    /// Gossipsub id of the published message.
+    pub message_id: gossipsub::MessageId,
+    /// Peers in the topic mesh when the message was published.
+    pub mesh_peers: usize,
+    /// Peers the message was sent to: every known topic peer with flood
+    /// publishing, the mesh otherwise.
+    pub forwarded_to: usize,
+    /// Publish attempts made, retries included.
+    pub attempts: u32,
+    /// Trace id carried in the envelope of the message, if any.
+    pub trace_id: Option<TraceId>,
+}
+
+/// Gossip health of one subscribed topic, see [`PeerManagerHandle::subscriptions`].
+///
+/// Fanout peers only exist for topics published to without subscribing, so
+/// subscribed topics report their mesh instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicSubscription {
+    /// Topic hash as a string.
+    pub topic: String,
+    /// Peers in the topic mesh, i.e. those full messages are forwarded to.
+    pub mesh_peers: usize,
+    /// Known peers subscribed to the topic, mesh peers included.
+    pub topic_peers: usize,
+}
+
+/// Configuration applied to a running node by [`PeerManagerHandle::reload_config`].
+#[derive(Debug, Clone)]
+pub struct RuntimeConfig {
+    /// Configuration to move to. Settings that only take effect on a new
+    /// swarm keep their current value and are reported in
+    /// [`ReloadReport::restart_required`].
+    pub transport: TransportConfig,
+    /// New log filter directives (e.g. `info,peer=debug`) for the subscriber
+    /// installed by [`crate::config::init_tracing`]; `None` keeps the filter.
+    pub log_filter: Option<String>,
+}
+
+/// Outcome of [`PeerManagerHandle::reload_config`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// Settings that changed and are in effect now.
+    pub applied: Vec<&'static str>,
+    /// Settings that changed but need a restart of the node to take effect.
+    pub restart_required: Vec<&'static str>,
+}
+
 /// Handle that allows callers to enqueue [`PeerCommand`]s.
 #[derive(Clone, Debug)]
 pub struct PeerManagerHandle {
     command_sender: mpsc::Sender<PeerCommand>,
     autonat_status: watch::Receiver<autonat::NatStatus>,
-    local_peer_id: PeerId,
+    local_peer_id: watch::Receiver<PeerId>,
+    substream_metrics: Arc<SubstreamMetrics>,
+    dht_metrics: Arc<DhtMetrics>,
+    peer_graph: Arc<PeerGraphState>,
+    relay_metrics: Arc<RelayMetrics>,
+    gossip_rates: Arc<GossipRates>,
+    prometheus: Arc<PrometheusMetrics>,
+    connection_metrics: Arc<ConnectionMetrics>,
+    metrics_recorder: Option<Arc<MetricsRecorder>>,
+    topic_ready: watch::Receiver<bool>,
+    network_events: broadcast::Sender<NetworkEvent>,
+    /// Policy attached to the dials, publishes and DHT queries sent through this handle.
+    retry: RetryPolicy,
 }
 
 impl PeerManagerHandle {
-    /// Enqueues a command to start listening on the given address.
-    pub async fn start_listening(&self, address: Multiaddr) -> Result<()> {
+    /// Returns a handle whose dials, publishes and DHT queries are retried
+    /// inside the manager according to `retry`. Fails when the policy does
+    /// not pass [`RetryPolicy::validate`].
+    pub fn with_retry(&self, retry: RetryPolicy) -> Result<Self> {
+        retry.validate()?;
+        Ok(Self {
+            retry,
+            ..self.clone()
+        })
+    }
+
+    /// Starts listening on the given address and returns the listener id.
+    /// Fails when the address is not usable with the transport config or
+    /// the transport refused it.
+    pub async fn start_listening(&self, address: Multiaddr) -> Result<ListenerId> {
+        let (responder, receiver) = oneshot::channel();
         self.command_sender
-            .send(PeerCommand::StartListening(address))
+            .send(PeerCommand::StartListening { address, responder })
             .await
-            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped listen request: {err}"))?
+    }
+
+    /// Subscribes to structured network events (hole-punch outcomes, ...).
+    /// Only events emitted after the call are received.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<NetworkEvent> {
+        self.network_events.subscribe()
+    }
+
+    /// Returns a stream merging AutoNAT status changes and network events
+    /// with the node's discovery and message queues. Only events emitted
+    /// after the call are received.
+    pub fn node_events(&self) -> NodeEventStream {
+        NodeEventStream::new(self.network_events.subscribe(), self.autonat_status.clone())
     }
 
     /// Returns a watch channel receiver that yields AutoNAT status updates.
@@ -74,9 +639,114 @@ impl PeerManagerHandle {
         self.autonat_status.clone()
     }
 
-    /// Returns the local peer identifier.
+    /// Returns a watch channel receiver that turns `true` once the publish
+    /// warm-up has ended and the gossipsub topic is ready.
+    pub fn topic_ready(&self) -> watch::Receiver<bool> {
+        self.topic_ready.clone()
+    }
+
+    /// Waits until the gossipsub topic is ready for publishing.
+    pub async fn wait_topic_ready(&self) -> Result<()> {
+        let mut topic_ready = self.topic_ready.clone();
+        topic_ready
+            .wait_for(|ready| *ready)
+            .await
+            .map(|_| ())
+            .map_err(|err| anyhow!("peer manager stopped: {err}"))
+    }
+
+    /// Returns the local peer identifier. With identity rotation enabled this
+    /// is the identity currently in use.
     pub fn local_peer_id(&self) -> PeerId {
-        self.local_peer_id.clone()
+        *self.local_peer_id.borrow()
+    }
+
+    /// Returns substream open/close counters keyed by negotiated protocol, e.g.
+    /// `/meshsub/1.1.0` or `/ipfs/kad/1.0.0`.
+    pub fn substream_stats(&self) -> BTreeMap<String, SubstreamStats> {
+        self.substream_metrics.snapshot()
+    }
+
+    /// Returns the traffic exchanged with `peer_id` per protocol (gossipsub,
+    /// Kademlia, ping, identify, custom protocols), or `None` when the peer is
+    /// not connected.
+    pub fn peer_stats(&self, peer_id: &PeerId) -> Option<PeerStats> {
+        self.substream_metrics.peer_stats(peer_id)
+    }
+
+    /// Returns aggregated statistics of finished DHT queries keyed by query
+    /// kind (`get_closest_peers`, `bootstrap`, ...).
+    pub fn dht_stats(&self) -> BTreeMap<String, DhtQueryStats> {
+        self.dht_metrics.snapshot()
+    }
+
+    /// Returns how many records and provider records the local Kademlia store
+    /// evicted or refused because of its configured limits.
+    pub fn dht_store_stats(&self) -> DhtStoreStats {
+        self.dht_metrics.store_snapshot()
+    }
+
+    /// Returns the known peers and how each relates to the node, as of the
+    /// last refresh.
+    pub fn peer_graph(&self) -> PeerGraph {
+        self.peer_graph.snapshot()
+    }
+
+    /// Subscribes to the changes of the peer graph, one batch per refresh.
+    /// Subscribe first, then read [`Self::peer_graph`] as the starting state.
+    pub fn subscribe_peer_graph(&self) -> broadcast::Receiver<Vec<PeerGraphDiff>> {
+        self.peer_graph.subscribe()
+    }
+
+    /// Returns the number of active inbound and outbound connections keyed by
+    /// transport (`tcp`, `quic`, `relay`).
+    pub fn connection_stats(&self) -> BTreeMap<String, ConnectionCounts> {
+        self.connection_metrics.snapshot()
+    }
+
+    /// Returns the open connections to `peer_id` with their transport and the
+    /// security protocol and muxer it offers; empty when not connected.
+    pub fn peer_connections(&self, peer_id: &PeerId) -> Vec<PeerConnection> {
+        self.connection_metrics.peer_connections(peer_id)
+    }
+
+    /// Returns the number of open connections keyed by expected security
+    /// protocol and muxer.
+    pub fn connection_stack_stats(&self) -> BTreeMap<(String, String), usize> {
+        self.connection_metrics.stack_snapshot()
+    }
+
+    /// Returns the Prometheus metrics in the OpenMetrics text format, ready to
+    /// be served to a scraper.
+    pub fn prometheus_metrics(&self) -> Result<String> {
+        self.prometheus.encode()
+    }
+
+    /// Returns relay server statistics: active reservations and circuits,
+    /// limit rejections and bytes exchanged with each relay client.
+    pub fn relay_stats(&self) -> RelayServerStats {
+        self.relay_metrics.snapshot(&self.substream_metrics)
+    }
+
+    /// Returns the gossip counters of every connected peer that relayed
+    /// messages: deliveries, duplicates, invalid messages and throttling.
+    pub fn gossip_peer_stats(&self) -> BTreeMap<PeerId, PeerGossipStats> {
+        self.gossip_rates.snapshot()
+    }
+
+    /// Returns the gossip counters of `peer_id`, if it relayed messages since
+    /// it connected.
+    pub fn gossip_stats(&self, peer_id: &PeerId) -> Option<PeerGossipStats> {
+        self.gossip_rates.peer(peer_id)
+    }
+
+    /// Returns the metrics snapshots kept in the ring buffer, oldest first.
+    /// Empty unless snapshots are configured with [`crate::peer::MetricsSink::RingBuffer`].
+    pub fn metrics_history(&self) -> Vec<MetricsSnapshot> {
+        self.metrics_recorder
+            .as_ref()
+            .map(|recorder| recorder.history())
+            .unwrap_or_default()
     }
 
     /// Initiates a find_peer query against the DHT.
@@ -85,6 +755,27 @@ impl PeerManagerHandle {
             .send(PeerCommand::FindPeer {
                 peer_id,
                 request_id,
+                results: None,
+                retry: self.retry,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
+    }
+
+    /// Initiates a find_peer query whose events are streamed to `results`
+    /// as they arrive instead of the shared discovery queue.
+    pub async fn find_peer_streaming(
+        &self,
+        peer_id: PeerId,
+        request_id: u64,
+        results: mpsc::Sender<DiscoveryEvent>,
+    ) -> Result<()> {
+        self.command_sender
+            .send(PeerCommand::FindPeer {
+                peer_id,
+                request_id,
+                results: Some(results.into()),
+                retry: self.retry,
             })
             .await
             .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
@@ -96,515 +787,6108 @@ impl PeerManagerHandle {
             .send(PeerCommand::GetClosestPeers {
                 peer_id,
                 request_id,
+                results: None,
+                retry: self.retry,
             })
             .await
             .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
     }
 
-    /// Enqueues a command to dial the provided address.
-    pub async fn dial(&self, address: Multiaddr) -> Result<()> {
+    /// Initiates a get_closest_peers query whose events are streamed to
+    /// `results` as they arrive instead of the shared discovery queue.
+    pub async fn get_closest_peers_streaming(
+        &self,
+        peer_id: PeerId,
+        request_id: u64,
+        results: mpsc::Sender<DiscoveryEvent>,
+    ) -> Result<()> {
         self.command_sender
-            .send(PeerCommand::Dial(address))
+            .send(PeerCommand::GetClosestPeers {
+                peer_id,
+                request_id,
+                results: Some(results.into()),
+                retry: self.retry,
+            })
             .await
             .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
     }
 
-    /// Requests a reservation on a relay reachable at the given address.
-    pub async fn reserve_relay(&self, address: Multiaddr) -> Result<()> {
+    /// Starts dialing the provided address and returns the id of the
+    /// connection being dialed. Fails when no dial could be started, e.g.
+    /// for one of the node's own addresses.
+    pub async fn dial(&self, address: Multiaddr) -> Result<ConnectionId> {
+        self.send_dial(address, None).await
+    }
+
+    /// Dials `address` and reports the outcome as a
+    /// [`NetworkEvent::DialFinished`] carrying `request_id`. Returns the id
+    /// of the first connection attempt, see [`Self::dial`].
+    pub async fn dial_with_request_id(
+        &self,
+        address: Multiaddr,
+        request_id: u64,
+    ) -> Result<ConnectionId> {
+        self.send_dial(address, Some(request_id)).await
+    }
+
+    async fn send_dial(&self, address: Multiaddr, request_id: Option<u64>) -> Result<ConnectionId> {
+        let (responder, receiver) = oneshot::channel();
         self.command_sender
-            .send(PeerCommand::ReserveRelay(address))
+            .send(PeerCommand::Dial {
+                address,
+                request_id,
+                retry: self.retry,
+                responder,
+            })
             .await
-            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped dial request: {err}"))?
     }
 
-    /// Publishes a message to connected peers via gossipsub.
-    pub async fn publish(&self, payload: Vec<u8>) -> Result<()> {
+    /// Initiates a provider lookup for `key`, raw bytes or a namespaced
+    /// [`crate::peer::Key`], against the DHT.
+    pub async fn get_providers(&self, key: impl Into<Vec<u8>>, request_id: u64) -> Result<()> {
         self.command_sender
-            .send(PeerCommand::Publish(payload))
+            .send(PeerCommand::GetProviders {
+                key: key.into(),
+                request_id,
+                results: None,
+                retry: self.retry,
+                limits: QueryLimits::default(),
+            })
             .await
             .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
     }
 
-    /// Enqueues the shutdown command.
-    pub async fn shutdown(&self) -> Result<()> {
+    /// Initiates a provider lookup bounded by `limits`: it finishes as soon as
+    /// `max_results` providers were reported, or once `deadline` elapsed
+    /// with the providers found so far.
+    pub async fn get_providers_with_limits(
+        &self,
+        key: impl Into<Vec<u8>>,
+        request_id: u64,
+        limits: QueryLimits,
+    ) -> Result<()> {
         self.command_sender
-            .send(PeerCommand::Shutdown)
+            .send(PeerCommand::GetProviders {
+                key: key.into(),
+                request_id,
+                results: None,
+                retry: self.retry,
+                limits,
+            })
             .await
             .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
     }
-}
 
-#[derive(Debug, Clone)]
-struct DiscoveryRequest {
-    request_id: u64,
-    target_peer_id: PeerId,
-    kind: DiscoveryKind,
-}
+    /// Initiates a provider lookup whose events are streamed to `results` as
+    /// they arrive instead of the shared discovery queue.
+    pub async fn get_providers_streaming(
+        &self,
+        key: impl Into<Vec<u8>>,
+        request_id: u64,
+        results: mpsc::Sender<DiscoveryEvent>,
+    ) -> Result<()> {
+        self.command_sender
+            .send(PeerCommand::GetProviders {
+                key: key.into(),
+                request_id,
+                results: Some(results.into()),
+                retry: self.retry,
+                limits: QueryLimits::default(),
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
+    }
 
-#[derive(Debug, Clone, Copy)]
-enum DiscoveryKind {
-    FindPeer,
-    GetClosestPeers,
-}
+    /// Announces the local node as a provider of `key`, raw bytes or a
+    /// namespaced [`crate::peer::Key`], so [`Self::get_providers`] lookups
+    /// elsewhere find it. A [`DiscoveryEvent::ProvidingStarted`] reports
+    /// whether the announcement reached the DHT; the record is republished
+    /// until [`Self::stop_providing`], across swarm rebuilds too.
+    pub async fn start_providing(&self, key: impl Into<Vec<u8>>, request_id: u64) -> Result<()> {
+        self.command_sender
+            .send(PeerCommand::StartProviding {
+                key: key.into(),
+                request_id,
+                results: None,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
+    }
 
-/// Manages the libp2p swarm (peer orchestrator) and exposes a command-driven control loop.
-pub struct PeerManager {
-    swarm: Swarm<NetworkBehaviour>,
-    command_receiver: mpsc::Receiver<PeerCommand>,
-    local_peer_id: PeerId,
-    keypair: identity::Keypair,
-    inbound_sender: MessageQueueSender,
-    gossipsub_topic: gossipsub::IdentTopic,
-    autonat_status: watch::Sender<autonat::NatStatus>,
-    discovery_sender: DiscoveryEventSender,
-    discovery_queries: HashMap<kad::QueryId, DiscoveryRequest>,
-    discovery_dial_backoff: HashMap<PeerId, HashMap<Multiaddr, Instant>>,
-    relay_base_address: Option<Multiaddr>,
-    relay_peer_id: Option<PeerId>,
-    addr_state: Arc<RwLock<AddrState>>,
-}
+    /// Like [`Self::start_providing`], but the completion is sent to
+    /// `results` instead of the shared discovery queue.
+    pub async fn start_providing_streaming(
+        &self,
+        key: impl Into<Vec<u8>>,
+        request_id: u64,
+        results: mpsc::Sender<DiscoveryEvent>,
+    ) -> Result<()> {
+        self.command_sender
+            .send(PeerCommand::StartProviding {
+                key: key.into(),
+                request_id,
+                results: Some(results.into()),
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
+    }
 
-impl PeerManager {
-    /// Creates a new [`PeerManager`] instance alongside a [`PeerManagerHandle`].
-    pub fn new(
-        config: TransportConfig,
-        inbound_sender: MessageQueueSender,
-        discovery_sender: DiscoveryEventSender,
-        addr_state: Arc<RwLock<AddrState>>,
-        bootstrap_peers: Vec<Multiaddr>,
-    ) -> Result<(Self, PeerManagerHandle)> {
-        let (keypair, swarm) = config.build()?;
-        let local_peer_id = PeerId::from(keypair.public());
-        let (command_sender, command_receiver) = mpsc::channel(32);
-        let (autonat_status, autonat_status_receiver) = watch::channel(autonat::NatStatus::Unknown);
+    /// Stops announcing the local node as a provider of `key`. The provider
+    /// records already held by other peers expire on their own. Returns
+    /// whether the key was provided.
+    pub async fn stop_providing(&self, key: impl Into<Vec<u8>>) -> Result<bool> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::StopProviding {
+                key: key.into(),
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped stop_providing request: {err}"))
+    }
 
-        let mut swarm = swarm;
-        let gossipsub_topic = gossipsub::IdentTopic::new("echo");
-        swarm
-            .behaviour_mut()
-            .gossipsub
-            .subscribe(&gossipsub_topic)
-            .map_err(|err| anyhow!("failed to subscribe to gossipsub topic: {err}"))?;
+    /// Stores `value` under `key`, raw bytes or a namespaced
+    /// [`crate::peer::Key`], locally and on the peers closest to the key. A
+    /// [`DiscoveryEvent::RecordStored`] reports whether `quorum` of them
+    /// acknowledged it.
+    pub async fn put_record(
+        &self,
+        key: impl Into<Vec<u8>>,
+        value: Vec<u8>,
+        quorum: kad::Quorum,
+        request_id: u64,
+    ) -> Result<()> {
+        self.command_sender
+            .send(PeerCommand::PutRecord {
+                key: key.into(),
+                value,
+                quorum,
+                request_id,
+                results: None,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
+    }
 
-        /* These are not needed as DEFAULT_BOOTSTRAP_PEERS should be empty
-        bootstrap_peers.extend(
-            DEFAULT_BOOTSTRAP_PEERS
-                .iter()
-                .filter_map(|value| match value.parse::<Multiaddr>() {
-                    Ok(addr) => Some(addr),
-                    Err(err) => {
-                        tracing::warn!(target: "peer", %err, value, "invalid default bootstrap peer; skipping");
-                        None
-                    }
-                }),
-        );
-        */
+    /// Initiates a record lookup for `key` against the DHT. Records are
+    /// reported as [`DiscoveryEvent::Record`] events, followed by a
+    /// [`DiscoveryEvent::RecordsFinished`].
+    pub async fn get_record(&self, key: impl Into<Vec<u8>>, request_id: u64) -> Result<()> {
+        self.command_sender
+            .send(PeerCommand::GetRecord {
+                key: key.into(),
+                request_id,
+                results: None,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
+    }
 
-        let mut manager = Self {
-            swarm,
-            command_receiver,
-            local_peer_id,
-            keypair,
-            inbound_sender,
-            gossipsub_topic,
-            autonat_status,
-            discovery_sender,
-            discovery_queries: HashMap::new(),
-            discovery_dial_backoff: HashMap::new(),
-            relay_base_address: None,
-            relay_peer_id: None,
-            addr_state,
-        };
+    /// Returns the peers closest to `peer_id` known to the local routing table.
+    ///
+    /// No network query is issued, so the answer is immediate but only as good
+    /// as the current contents of the routing table.
+    pub async fn get_closest_peers_local(&self, peer_id: PeerId) -> Result<Vec<PeerId>> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::GetClosestPeersLocal { peer_id, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped closest peers request: {err}"))
+    }
 
-        manager.add_bootstrap_peers(bootstrap_peers);
+    /// Connects to `peer_id` using its known addresses, falls back to a relay
+    /// circuit when a direct dial fails, and waits for DCUtR to upgrade a
+    /// relayed connection. Resolves with a single final outcome.
+    pub async fn connect(&self, peer_id: PeerId) -> Result<ConnectOutcome> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::Connect { peer_id, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped connect request: {err}"))
+    }
 
-        let handle = PeerManagerHandle {
-            command_sender,
-            autonat_status: autonat_status_receiver,
-            local_peer_id: local_peer_id.clone(),
-        };
-        Ok((manager, handle))
+    /// Dials `address` only if it uses `transport` and waits for the outcome.
+    /// No other transport and no relay fallback is tried, so test harnesses
+    /// can verify that a specific path works.
+    pub async fn dial_with_transport(
+        &self,
+        address: Multiaddr,
+        transport: DialTransport,
+    ) -> Result<ConnectOutcome> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::DialWithTransport {
+                address,
+                transport,
+                retry: self.retry,
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped dial request: {err}"))
     }
 
-    /// Returns the local peer identifier.
-    pub fn peer_id(&self) -> PeerId {
-        self.local_peer_id
+    /// Dials `peer_id` on the addresses known from the routing table, keeping
+    /// only those using `transport` when set, and waits for the outcome.
+    /// [`DialTransport::Relay`] dials through the current relay reservation.
+    /// Without a `transport`, the behaviours may add addresses of their own
+    /// and several addresses are tried at once, so the preference order only
+    /// decides which are dialed first; a peer with no known address is
+    /// looked up in the DHT before dialing.
+    /// With a `request_id`, the outcome is also broadcast as
+    /// [`NetworkEvent::DialFinished`].
+    pub async fn dial_peer(
+        &self,
+        peer_id: PeerId,
+        transport: Option<DialTransport>,
+        request_id: Option<u64>,
+    ) -> Result<ConnectOutcome> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::DialPeer {
+                peer_id,
+                transport,
+                request_id,
+                retry: self.retry,
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped dial request: {err}"))
     }
 
-    /// Provides access to the node's identity keypair.
-    pub fn keypair(&self) -> &identity::Keypair {
-        &self.keypair
+    /// Asks AutoNAT servers to probe `address` right away instead of waiting
+    /// for the periodic schedule. A reachable address is also confirmed as external.
+    pub async fn probe_address(&self, address: Multiaddr) -> Result<AddressProbeOutcome> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ProbeAddress { address, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped address probe: {err}"))
     }
 
-    /// Runs the peer manager control loop until shutdown is requested.
-    pub async fn run(mut self) -> Result<()> {
-        loop {
-            tokio::select! {
-                Some(command) = self.command_receiver.recv() => {
+    /// Pre-flight check of `peer_id`, e.g. before a large transfer: connects
+    /// to it when needed, pushes the local identify info to it and sends it
+    /// `count` pings back to back. The report tells how the peer was reached
+    /// and the round-trip time of every answered ping.
+    pub async fn probe_peer(&self, peer_id: PeerId, count: u32) -> Result<PeerProbeReport> {
+        if count == 0 || count > MAX_PROBE_PINGS {
+            return Err(anyhow!(
+                "a peer probe sends between 1 and {MAX_PROBE_PINGS} pings, not {count}"
+            ));
+        }
+        let reachability = self.connect(peer_id).await?;
+        if matches!(
+            reachability,
+            ConnectOutcome::Failed { .. } | ConnectOutcome::SelfDial
+        ) {
+            return Ok(PeerProbeReport {
+                reachability,
+                count,
+                rtts: Vec::new(),
+                error: None,
+            });
+        }
+
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ProbePeer {
+                peer_id,
+                count,
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped peer probe: {err}"))
+    }
+
+    /// Requests a reservation on a relay reachable at the given address and
+    /// returns the id of the relayed listener. The reservation's acceptance
+    /// is reported through the address events.
+    pub async fn reserve_relay(&self, address: Multiaddr) -> Result<ListenerId> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ReserveRelay { address, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped relay reservation request: {err}"))?
+    }
+
+    /// Requests a reservation on a specific relay, listens through it and
+    /// returns the id of the relayed listener. Reservation acceptance,
+    /// renewals and expiry are reported to `events`.
+    pub async fn listen_via_relay(
+        &self,
+        relay_peer: PeerId,
+        relay_addr: Multiaddr,
+        events: mpsc::Sender<RelayReservationEvent>,
+    ) -> Result<ListenerId> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ListenViaRelay {
+                relay_peer,
+                relay_addr,
+                events,
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped relay listen request: {err}"))?
+    }
+
+    /// Pushes the current identify info (listen addresses, protocols) to all
+    /// connected peers right away instead of waiting for the periodic exchange.
+    /// Returns the number of peers it was pushed to.
+    pub async fn push_identify(&self) -> Result<usize> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::PushIdentify { responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped identify push request: {err}"))?
+    }
+
+    /// Queues a message for publishing to connected peers via gossipsub
+    /// without waiting for it to leave the node; failures are only logged.
+    pub async fn enqueue(&self, payload: Vec<u8>) -> Result<()> {
+        self.command_sender
+            .send(PeerCommand::Publish {
+                payload,
+                retry: self.retry,
+                trace_id: None,
+                receipt: None,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
+    }
+
+    /// Publishes a message to connected peers via gossipsub and waits until
+    /// it left the node, returning its id and how many peers it was forwarded
+    /// to. Fails once every attempt allowed by the retry policy failed.
+    pub async fn publish(&self, payload: Vec<u8>) -> Result<PublishReceipt> {
+        self.publish_with_trace_id(payload, None).await
+    }
+
+    /// Publishes a message like [`Self::publish`], carrying `trace_id` in its
+    /// replay envelope instead of the id the node would pick, so a message
+    /// can be correlated with the host's own traces. Needs replay protection.
+    pub async fn publish_with_trace_id(
+        &self,
+        payload: Vec<u8>,
+        trace_id: Option<TraceId>,
+    ) -> Result<PublishReceipt> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::Publish {
+                payload,
+                retry: self.retry,
+                trace_id,
+                receipt: Some(responder),
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped publish request: {err}"))?
+    }
+
+    /// Publishes `payload` to `topic` and waits until it left the node, like
+    /// [`Self::publish`] does for the default topic. The node does not need
+    /// to be subscribed to `topic`; group topics are refused, use
+    /// [`Self::publish_to_group`] for them.
+    pub async fn publish_to(&self, topic: &str, payload: Vec<u8>) -> Result<PublishReceipt> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::PublishTo {
+                topic: gossipsub::IdentTopic::new(topic),
+                payload,
+                retry: self.retry,
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped publish request: {err}"))?
+    }
+
+    /// Subscribes to `topic` at runtime. Its messages are delivered with the
+    /// others, tagged with the topic. Returns `false` when the node was
+    /// already subscribed.
+    pub async fn subscribe(&self, topic: &str) -> Result<bool> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::Subscribe {
+                topic: topic.to_string(),
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped subscribe request: {err}"))?
+    }
+
+    /// Unsubscribes from a topic subscribed by [`Self::subscribe`] or the
+    /// `gossipsub_topics` config. Returns `false` when the node was not
+    /// subscribed. The default topic cannot be left.
+    pub async fn unsubscribe(&self, topic: &str) -> Result<bool> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::Unsubscribe {
+                topic: topic.to_string(),
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped unsubscribe request: {err}"))?
+    }
+
+    /// Publishes `payload` to `topic` at the wall-clock time `when`, or right
+    /// away if it already passed. Returns the id cancelling the publish.
+    pub async fn publish_at(&self, topic: &str, payload: Vec<u8>, when: SystemTime) -> Result<u64> {
+        let delay = when.duration_since(SystemTime::now()).unwrap_or_default();
+        self.publish_after(topic, payload, delay).await
+    }
+
+    /// Publishes `payload` to `topic` once `delay` elapsed. Returns the id
+    /// cancelling the publish.
+    pub async fn publish_after(
+        &self,
+        topic: &str,
+        payload: Vec<u8>,
+        delay: Duration,
+    ) -> Result<u64> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::SchedulePublish {
+                topic: gossipsub::IdentTopic::new(topic),
+                payload,
+                at: Instant::now() + delay,
+                responder,
+                retry: self.retry,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped schedule publish request: {err}"))?
+    }
+
+    /// Cancels a publish scheduled by [`Self::publish_at`] or
+    /// [`Self::publish_after`]. Returns `false` when it already fired or was
+    /// never scheduled.
+    pub async fn cancel_scheduled_publish(&self, id: u64) -> Result<bool> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::CancelScheduledPublish { id, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped cancel publish request: {err}"))
+    }
+
+    /// Takes the publishes that outlived `outbound_message_ttl` while held by
+    /// the startup warm-up and were never sent.
+    pub async fn take_outbound_dead_letters(&self) -> Result<Vec<DeadLetter<Vec<u8>>>> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::TakeOutboundDeadLetters { responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped dead letter request: {err}"))
+    }
+
+    /// Resolves once `topic` has at least `min_peers` peers in its gossipsub
+    /// mesh, i.e. once publishing is likely to reach the network.
+    pub async fn wait_mesh_ready(&self, topic: &str, min_peers: usize) -> Result<()> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::WaitMeshReady {
+                topic: gossipsub::IdentTopic::new(topic).hash(),
+                min_peers,
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped mesh readiness request: {err}"))
+    }
+
+    /// Returns the addresses connected peers reported observing us at via
+    /// identify, with the number of distinct reporters and whether the address
+    /// reached `observed_addr_confirmations` and is advertised as external.
+    pub async fn observed_addresses(&self) -> Result<Vec<ObservedAddress>> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ObservedAddresses { responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped observed addresses request: {err}"))
+    }
+
+    /// Returns the topics the node is subscribed to with their mesh and
+    /// subscribed peer counts.
+    pub async fn subscriptions(&self) -> Result<Vec<TopicSubscription>> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::Subscriptions { responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped subscriptions request: {err}"))
+    }
+
+    /// Joins `group`: subscribes to its topic and announces the local node to
+    /// the other members. Groups have no owner, so joining a group nobody is
+    /// in yet creates it. Membership changes and group messages arrive as
+    /// [`NetworkEvent::Group`] events. Configure a topic key for
+    /// [`group_topic`]`(group)` to keep a group private.
+    pub async fn join_group(&self, group: &str) -> Result<()> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::JoinGroup {
+                group: group.to_string(),
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped join group request: {err}"))?
+    }
+
+    /// Leaves a joined group, announcing it to the remaining members.
+    pub async fn leave_group(&self, group: &str) -> Result<()> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::LeaveGroup {
+                group: group.to_string(),
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped leave group request: {err}"))?
+    }
+
+    /// Makes `topics` the exact set of topics subscribed next to the default
+    /// one in one step: topics not subscribed yet are subscribed, subscribed
+    /// topics missing from `topics` are unsubscribed. Group topics are
+    /// refused; the default topic is always kept. The update is all or
+    /// nothing: when a subscription fails, the subscriptions changed by this
+    /// call are undone and the error is answered. The change is answered and
+    /// also broadcast as a single [`NetworkEvent::SubscriptionsChanged`].
+    pub async fn set_subscriptions(&self, topics: Vec<String>) -> Result<SubscriptionChange> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::SetSubscriptions { topics, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped subscriptions update: {err}"))?
+    }
+
+    /// Publishes `payload` to the members of a joined group.
+    pub async fn publish_to_group(&self, group: &str, payload: Vec<u8>) -> Result<()> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::PublishToGroup {
+                group: group.to_string(),
+                payload,
+                retry: self.retry,
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped group publish request: {err}"))?
+    }
+
+    /// Returns the remote members of a joined group known from their
+    /// announcements and the rosters gossiped by other members.
+    pub async fn group_members(&self, group: &str) -> Result<Vec<PeerId>> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::GroupMembers {
+                group: group.to_string(),
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped group members request: {err}"))?
+    }
+
+    /// Applies the runtime-tunable settings of `config` (substream limit,
+    /// watchdog, relay reservation TTL, slow-consumer detection, replay
+    /// window, log redaction and filter, external address candidates,
+    /// identity rotation) without restarting, and reports the settings that
+    /// need a restart instead. Nothing is applied when `config` is invalid or
+    /// the reload fails.
+    pub async fn reload_config(&self, config: RuntimeConfig) -> Result<ReloadReport> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ReloadConfig {
+                config: Box::new(config),
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped reload config request: {err}"))?
+    }
+
+    /// Keeps the connections to `peer_id` open while idle when `pinned`, or
+    /// subjects them to the idle connection timeout again.
+    pub async fn set_peer_pinned(&self, peer_id: PeerId, pinned: bool) -> Result<()> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::SetPeerPinned {
+                peer_id,
+                pinned,
+                responder,
+            })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped peer pin request: {err}"))?
+    }
+
+    /// Applies a blocklist update, e.g. one pushed by a policy server, and
+    /// closes the open connections it blocks. Expired entries are dropped
+    /// and the result is written to the blocklist file, if configured.
+    /// Returns the number of entries in the resulting list.
+    pub async fn import_blocklist(&self, update: BlocklistUpdate) -> Result<usize> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ImportBlocklist { update, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped blocklist import request: {err}"))?
+    }
+
+    /// Returns the identity keypair in use, protobuf-encoded, e.g. to back
+    /// it up or to move the node's `PeerId` to another device. The bytes
+    /// hold the secret key.
+    pub async fn export_keypair(&self) -> Result<Vec<u8>> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ExportKeypair { responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped keypair export: {err}"))?
+    }
+
+    /// Returns the signed peer record of `peer_id` as a protobuf signed
+    /// envelope, to be shared out-of-band (e.g. as a QR code) and imported
+    /// with [`Self::import_peer_record`]. Without `peer_id` the record of the
+    /// local node is signed afresh over its external addresses, or its
+    /// listen addresses while none is confirmed. Records of other peers are
+    /// only known once imported or received via identify with
+    /// [`TransportConfig::signed_peer_records`] on; `None` otherwise.
+    pub async fn export_peer_record(&self, peer_id: Option<PeerId>) -> Result<Option<Vec<u8>>> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ExportPeerRecord { peer_id, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped peer record export: {err}"))?
+    }
+
+    /// Verifies a signed peer record exported by [`Self::export_peer_record`]
+    /// and adds its addresses to the routing table, so the peer can be dialed
+    /// without a lookup. Returns the peer the record describes; fails when a
+    /// newer record of that peer is known.
+    pub async fn import_peer_record(&self, record: Vec<u8>) -> Result<PeerId> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ImportPeerRecord { record, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped peer record import: {err}"))?
+    }
+
+    /// Seeds the swarm's address book and the routing table from `bytes`,
+    /// without any network discovery, for first contact on air-gapped or
+    /// firewalled networks. `bytes` is a signed peer record or a text address
+    /// book, see [`crate::peer::parse_address_book`]. Records older than the
+    /// known ones are skipped, and the imported addresses survive swarm
+    /// rebuilds. Returns the peers learnt; nothing is imported when any entry
+    /// is invalid.
+    pub async fn import_peer_records(&self, bytes: Vec<u8>) -> Result<Vec<PeerId>> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::ImportPeerRecords { bytes, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped peer records import: {err}"))?
+    }
+
+    /// Returns the median offset of connected peers' clocks to the local
+    /// one, in milliseconds, or `None` until enough peers answered or when
+    /// [`TransportConfig::time_sync`] is off.
+    pub async fn network_time_offset(&self) -> Result<Option<i64>> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::NetworkTimeOffset { responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped time offset request: {err}"))
+    }
+
+    /// Returns the peers and ranges the node currently refuses.
+    pub async fn blocklist(&self) -> Result<Blocklist> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::Blocklist { responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped blocklist request: {err}"))
+    }
+
+    /// Switches the battery and data trade-off, e.g. when the host goes on
+    /// battery or a metered network. libp2p fixes the ping and identify
+    /// intervals and the mesh size when the swarm is built, so the swarm is
+    /// rebuilt with the profile's: connections are re-established and
+    /// pending discovery queries fail, while the routing table is carried
+    /// over and pinned peers are re-dialed. Nothing happens when the profile
+    /// is already active.
+    pub async fn set_power_profile(&self, profile: PowerProfile) -> Result<()> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::SetPowerProfile { profile, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped power profile request: {err}"))?
+    }
+
+    /// Tells the node the host's connectivity changed, as mobile hosts learn
+    /// from the OS. On [`NetworkChange::Switched`] and
+    /// [`NetworkChange::Restored`] the swarm is rebuilt, re-listening on the
+    /// new interfaces, pinned peers are re-dialed and reachability is probed
+    /// again; pending discovery queries fail. On [`NetworkChange::Lost`] the
+    /// addresses confirmed on the old network are dropped.
+    pub async fn network_changed(&self, change: NetworkChange) -> Result<()> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::NetworkChanged { change, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped network change: {err}"))?
+    }
+
+    /// Turns the AutoNAT server role on or off. While off, peers connecting
+    /// from then on cannot ask for dial-backs; connections already open keep
+    /// serving them until they close. The node's own probes are unaffected.
+    pub async fn set_autonat_server(&self, enabled: bool) -> Result<()> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::SetAutonatServer { enabled, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped autonat server request: {err}"))?
+    }
+
+    /// Turns relay server acceptance on or off, e.g. to stop relaying on
+    /// metered bandwidth. While off, new connections get no relay service;
+    /// reservations and circuits on connections already open run until those
+    /// connections close. Fails when the node was not started as a hop relay.
+    pub async fn set_relay_server(&self, enabled: bool) -> Result<()> {
+        let (responder, receiver) = oneshot::channel();
+        self.command_sender
+            .send(PeerCommand::SetRelayServer { enabled, responder })
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))?;
+        receiver
+            .await
+            .map_err(|err| anyhow!("peer manager dropped relay server request: {err}"))?
+    }
+
+    /// Enqueues the shutdown command.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.command_sender
+            .send(PeerCommand::Shutdown)
+            .await
+            .map_err(|err| anyhow!("peer manager command channel closed: {err}"))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DiscoveryRequest {
+    request_id: u64,
+    target_peer_id: PeerId,
+    kind: DiscoveryKind,
+    peers_found: usize,
+    /// Dedicated destination for this query's events, if the caller gave one.
+    results: Option<DiscoveryEventSender>,
+    /// Time after which the query is finished even without a last step.
+    deadline: Instant,
+    retry: RetryPolicy,
+    /// Number of the current attempt, starting at 1.
+    attempt: u32,
+    /// Whether the delegated routing server was asked already.
+    delegated: bool,
+}
+
+/// Answer of the delegated routing server to a lookup the DHT could not
+/// resolve, or was skipped for on a client-only node, finished as the
+/// Kademlia query `query_id`. With `dht_next` set, the DHT is queried after
+/// all when the server knows nothing.
+#[derive(Debug)]
+enum DelegatedAnswer {
+    Peer {
+        query_id: kad::QueryId,
+        request: DiscoveryRequest,
+        status: DiscoveryStatus,
+        dht_next: bool,
+        addresses: Result<Vec<Multiaddr>>,
+    },
+    Providers {
+        query_id: kad::QueryId,
+        request: ProviderRequest,
+        status: DiscoveryStatus,
+        dht_next: bool,
+        providers: Result<Vec<(PeerId, Vec<Multiaddr>)>>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DiscoveryKind {
+    FindPeer,
+    GetClosestPeers,
+}
+
+/// In-flight [`PeerCommand::GetProviders`] request.
+#[derive(Debug)]
+struct ProviderRequest {
+    request_id: u64,
+    key: kad::RecordKey,
+    /// Providers already reported, so every one is reported once.
+    providers: HashSet<PeerId>,
+    /// Dedicated destination for this query's events, if the caller gave one.
+    results: Option<DiscoveryEventSender>,
+    /// Time after which the query is finished even without a last step.
+    deadline: Instant,
+    retry: RetryPolicy,
+    /// Number of the current attempt, starting at 1.
+    attempt: u32,
+    /// Providers after which the lookup is finished early.
+    max_results: Option<NonZeroUsize>,
+    /// Time after which the lookup is finished, retries included.
+    limit_deadline: Option<Instant>,
+    /// Whether the delegated routing server was asked already.
+    delegated: bool,
+}
+
+/// In-flight [`PeerCommand::StartProviding`] request.
+#[derive(Debug)]
+struct ProvideRequest {
+    request_id: u64,
+    key: kad::RecordKey,
+    /// Dedicated destination for this query's events, if the caller gave one.
+    results: Option<DiscoveryEventSender>,
+}
+
+/// In-flight [`PeerCommand::PutRecord`] or [`PeerCommand::GetRecord`] request.
+#[derive(Debug)]
+struct RecordRequest {
+    request_id: u64,
+    key: kad::RecordKey,
+    /// Dedicated destination for this query's events, if the caller gave one.
+    results: Option<DiscoveryEventSender>,
+    /// Whether the query stores the record rather than looking it up.
+    put: bool,
+    /// Records already reported by a lookup.
+    records_found: usize,
+}
+
+impl ProviderRequest {
+    fn is_satisfied(&self) -> bool {
+        self.max_results
+            .is_some_and(|max_results| self.providers.len() >= max_results.get())
+    }
+
+    /// Final status of the lookup given whether it ran out of time.
+    fn status(&self, timed_out: bool) -> DiscoveryStatus {
+        match (timed_out, self.providers.len()) {
+            (false, 0) => DiscoveryStatus::NotFound,
+            (false, _) => DiscoveryStatus::Success,
+            (true, 0) => DiscoveryStatus::Timeout,
+            (true, peers_found) => DiscoveryStatus::PartialSuccess { peers_found },
+        }
+    }
+}
+
+/// Relay listener opened by [`PeerCommand::ListenViaRelay`].
+#[derive(Debug)]
+struct RelayListener {
+    address: Multiaddr,
+    relay_peer_id: PeerId,
+    events: mpsc::Sender<RelayReservationEvent>,
+}
+
+/// Reservation held on a relay, tracked to renew it before it expires.
+#[derive(Debug)]
+struct RelayReservation {
+    listener_id: ListenerId,
+    address: Multiaddr,
+    /// Estimated expiry, set once the relay accepted the reservation.
+    expires_at: Option<Instant>,
+    /// Whether the manager already re-requested the current reservation.
+    renewal_requested: bool,
+}
+
+/// In-flight [`PeerCommand::Connect`] requests for one peer.
+#[derive(Debug)]
+struct PendingConnect {
+    responders: Vec<oneshot::Sender<ConnectOutcome>>,
+    /// Address of the relayed connection while waiting for a DCUtR upgrade.
+    relayed: Option<Multiaddr>,
+    deadline: Instant,
+}
+
+/// What an explicit dial connects to; kept to dial again on retry.
+#[derive(Debug, Clone)]
+enum DialTarget {
+    Address(Multiaddr),
+    Peer {
+        peer_id: PeerId,
+        transport: Option<DialTransport>,
+    },
+}
+
+/// Explicit dial from [`PeerCommand::DialWithTransport`], [`PeerCommand::DialPeer`],
+/// or [`PeerCommand::Dial`] with a request id or retry policy.
+#[derive(Debug)]
+struct DialAttempt {
+    target: DialTarget,
+    responder: Option<oneshot::Sender<ConnectOutcome>>,
+    request_id: Option<u64>,
+    retry: RetryPolicy,
+    /// Number of the current attempt, starting at 1.
+    attempt: u32,
+    /// Answered with the id of the first connection attempt the swarm
+    /// accepted, or with the error ending the dial before any was.
+    started: Option<oneshot::Sender<Result<ConnectionId>>>,
+}
+
+/// In-flight explicit dial.
+#[derive(Debug)]
+struct PendingDial {
+    dial: DialAttempt,
+    deadline: Instant,
+}
+
+/// Publish attempt, kept unsealed to publish again on retry.
+#[derive(Debug)]
+struct PublishAttempt {
+    topic: gossipsub::IdentTopic,
+    payload: Vec<u8>,
+    retry: RetryPolicy,
+    /// Number of the current attempt, starting at 1.
+    attempt: u32,
+    /// Envelope nonce shared by every attempt, assigned on the first one
+    /// when replay protection is on.
+    nonce: Option<u64>,
+    /// Trace id carried in the envelope, given by the publisher or picked on
+    /// the first attempt when message tracing is on.
+    trace_id: Option<TraceId>,
+    /// Requester of a [`PublishReceipt`], answered on success or final failure.
+    receipt: Option<oneshot::Sender<Result<PublishReceipt>>>,
+}
+
+/// Failed operation waiting for its next attempt.
+#[derive(Debug)]
+enum RetryTask {
+    Dial(DialAttempt),
+    Publish(PublishAttempt),
+    Discovery(DiscoveryRequest),
+    Providers(ProviderRequest),
+}
+
+/// Publish held until the startup warm-up ends.
+#[derive(Debug)]
+struct HeldPublish {
+    payload: Vec<u8>,
+    retry: RetryPolicy,
+    trace_id: Option<TraceId>,
+    receipt: Option<oneshot::Sender<Result<PublishReceipt>>>,
+}
+
+/// In-flight [`PeerCommand::ProbeAddress`] request.
+#[derive(Debug)]
+struct AddressProbe {
+    address: Multiaddr,
+    responder: oneshot::Sender<AddressProbeOutcome>,
+    /// AutoNAT probe carrying the address, once the request was sent.
+    probe_id: Option<autonat::ProbeId>,
+    deadline: Instant,
+}
+
+/// In-flight [`PeerCommand::ProbePeer`] request.
+#[derive(Debug)]
+struct PeerProbe {
+    responder: oneshot::Sender<PeerProbeReport>,
+    reachability: ConnectOutcome,
+    count: u32,
+    /// Round-trip times of the pings answered so far.
+    rtts: Vec<Duration>,
+    deadline: Instant,
+}
+
+/// Progress of the inbound queue consumer, see
+/// [`crate::messaging::SlowConsumerSettings`].
+#[derive(Debug)]
+struct ConsumerWatch {
+    last_dequeued: u64,
+    last_progress: Instant,
+    window_start: Instant,
+    /// Messages offered to the queue and dropped in the current window.
+    offered: u64,
+    dropped: u64,
+    /// Whether the last finished window exceeded the drop rate.
+    over_drop_rate: bool,
+    slow: bool,
+}
+
+impl ConsumerWatch {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            last_dequeued: 0,
+            last_progress: now,
+            window_start: now,
+            offered: 0,
+            dropped: 0,
+            over_drop_rate: false,
+            slow: false,
+        }
+    }
+}
+
+/// Publish waiting for its scheduled time.
+#[derive(Debug)]
+struct ScheduledPublish {
+    topic: gossipsub::IdentTopic,
+    payload: Vec<u8>,
+    retry: RetryPolicy,
+}
+
+/// Group joined by the local node.
+#[derive(Debug)]
+struct Group {
+    name: String,
+    topic: gossipsub::IdentTopic,
+    /// Remote members and when they were last heard from.
+    members: HashMap<PeerId, Instant>,
+    /// Members that recently left; rosters of other members do not bring
+    /// them back until they announce themselves again.
+    departed: HashMap<PeerId, Instant>,
+}
+
+impl Group {
+    fn roster(&self) -> GroupFrame {
+        GroupFrame::Roster(self.members.keys().copied().collect())
+    }
+}
+
+/// Caller waiting for a topic mesh to reach a minimum size.
+#[derive(Debug)]
+struct MeshWaiter {
+    topic: gossipsub::TopicHash,
+    min_peers: usize,
+    responder: oneshot::Sender<()>,
+}
+
+/// Manages the libp2p swarm (peer orchestrator) and exposes a command-driven control loop.
+pub struct PeerManager {
+    swarm: Swarm<NetworkBehaviour>,
+    command_receiver: mpsc::Receiver<PeerCommand>,
+    delegated_sender: mpsc::UnboundedSender<DelegatedAnswer>,
+    delegated_receiver: mpsc::UnboundedReceiver<DelegatedAnswer>,
+    /// Delegated routing lookups not answered yet.
+    delegated_in_flight: usize,
+    local_peer_id: PeerId,
+    keypair: identity::Keypair,
+    inbound_sender: MessageQueueSender,
+    gossipsub_topic: gossipsub::IdentTopic,
+    autonat_status: watch::Sender<autonat::NatStatus>,
+    discovery_sender: DiscoveryEventSender,
+    discovery_queries: HashMap<kad::QueryId, DiscoveryRequest>,
+    provider_queries: HashMap<kad::QueryId, ProviderRequest>,
+    record_queries: HashMap<kad::QueryId, RecordRequest>,
+    provide_queries: HashMap<kad::QueryId, ProvideRequest>,
+    /// Keys the local node provides, announced again on swarm rebuilds.
+    provided_keys: HashSet<kad::RecordKey>,
+    discovery_dial_backoff: HashMap<PeerId, HashMap<Multiaddr, Instant>>,
+    relay_base_address: Option<Multiaddr>,
+    relay_peer_id: Option<PeerId>,
+    relay_listeners: HashMap<ListenerId, RelayListener>,
+    relay_reservations: HashMap<PeerId, RelayReservation>,
+    addr_state: Arc<RwLock<AddrState>>,
+    message_verifier: Option<Arc<dyn MessageVerifier>>,
+    /// Consumers receiving the inbound messages of their topics instead of
+    /// the default inbound queue.
+    topic_router: TopicRouter,
+    record_validator: Option<Arc<dyn RecordValidator>>,
+    invalid_messages: HashMap<PeerId, u32>,
+    replay_cache: Option<ReplayCache>,
+    next_nonce: u64,
+    /// Ciphers of the topics configured with a shared secret.
+    topic_ciphers: HashMap<gossipsub::TopicHash, TopicCipher>,
+    /// Topics subscribed next to `gossipsub_topic`, by their hash.
+    topics: HashMap<gossipsub::TopicHash, gossipsub::IdentTopic>,
+    /// Joined groups by the hash of their topic.
+    groups: HashMap<gossipsub::TopicHash, Group>,
+    next_group_announce: Instant,
+    warmup_deadline: Option<Instant>,
+    /// Publishes held during the warm-up; the oldest is evicted when full.
+    warmup_publishes: EventBus<HeldPublish>,
+    warmup_sender: EventSender<HeldPublish>,
+    /// Failed operations ordered by the time of their next attempt, then id.
+    retries: BTreeMap<(Instant, u64), RetryTask>,
+    next_retry_id: u64,
+    /// Scheduled publishes ordered by due time, then id.
+    scheduled_publishes: BTreeMap<(Instant, u64), ScheduledPublish>,
+    /// Due time of every scheduled publish, by id.
+    scheduled_publish_times: HashMap<u64, Instant>,
+    next_scheduled_publish_id: u64,
+    topic_ready: watch::Sender<bool>,
+    mesh_waiters: Vec<MeshWaiter>,
+    dht_metrics: Arc<DhtMetrics>,
+    peer_graph: Arc<PeerGraphState>,
+    substream_metrics: Arc<SubstreamMetrics>,
+    relay_metrics: Arc<RelayMetrics>,
+    gossip_rates: Arc<GossipRates>,
+    prometheus: Arc<PrometheusMetrics>,
+    connection_metrics: Arc<ConnectionMetrics>,
+    metrics_recorder: Option<Arc<MetricsRecorder>>,
+    metrics_interval: Duration,
+    next_metrics_snapshot: Instant,
+    audit_log: Option<AuditLog>,
+    redact: LogRedactor,
+    config: TransportConfig,
+    bootstrap_peers: Vec<Multiaddr>,
+    /// Bootstrap query started from the configured bootstrap peers.
+    bootstrap_query: Option<kad::QueryId>,
+    /// Whether a configured bootstrap peer connected since that query started.
+    bootstrap_reached: bool,
+    /// Bootstrap query run through connected peers after the configured
+    /// bootstrap peers were unreachable.
+    exchange_query: Option<kad::QueryId>,
+    /// Listen addresses advertised via identify by connected Kademlia peers.
+    exchange_addresses: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Blocklist after the last import, kept across reloads like the
+    /// blocklist file when none is configured.
+    imported_blocklist: Blocklist,
+    /// Latest signed records of other peers, imported or received via identify.
+    peer_records: PeerRecordCache,
+    /// Addresses imported out of band, taught again to rebuilt swarms.
+    imported_addresses: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Addresses connected peers observe us at, reported via identify.
+    observed_addrs: ObservedAddrs,
+    listen_addrs: Vec<Multiaddr>,
+    next_identity_rotation: Option<Instant>,
+    local_peer_id_sender: watch::Sender<PeerId>,
+    network_events: broadcast::Sender<NetworkEvent>,
+    /// Remote address of every open connection, used to report where a
+    /// hole-punched connection ended up.
+    connection_addrs: HashMap<ConnectionId, (PeerId, Multiaddr)>,
+    /// Open direct connections per peer, oldest first, each with whether
+    /// this node dialed it; input of the duplicate connection policy.
+    direct_connections: HashMap<PeerId, Vec<(ConnectionId, bool)>>,
+    /// Connections already reported as [`NetworkEvent::ConnectionEstablished`].
+    reported_connections: HashSet<ConnectionId>,
+    pending_connects: HashMap<PeerId, PendingConnect>,
+    address_probes: Vec<AddressProbe>,
+    /// Peer probes keyed by the id of their ping burst.
+    peer_probes: HashMap<u64, PeerProbe>,
+    next_peer_probe: u64,
+    pending_dials: HashMap<ConnectionId, PendingDial>,
+    /// Clock offsets of peers, sampled when [`TransportConfig::time_sync`] is on.
+    clock_offsets: ClockOffsets,
+    /// Dials waiting for a DHT lookup of their peer's addresses.
+    dial_lookups: HashMap<kad::QueryId, DialAttempt>,
+    consumer_watch: ConsumerWatch,
+    next_candidate_probe: Instant,
+    next_peer_graph: Instant,
+    /// Role switches applied to the swarm, kept across swarm rebuilds.
+    autonat_server_enabled: bool,
+    relay_server_enabled: bool,
+    last_swarm_event: Instant,
+    listener_failures: u32,
+}
+
+impl PeerManager {
+    /// Creates a new [`PeerManager`] instance alongside a [`PeerManagerHandle`].
+    pub fn new(
+        mut config: TransportConfig,
+        inbound_sender: MessageQueueSender,
+        discovery_sender: DiscoveryEventSender,
+        addr_state: Arc<RwLock<AddrState>>,
+        bootstrap_peers: Vec<Multiaddr>,
+    ) -> Result<(Self, PeerManagerHandle)> {
+        let substream_metrics = Arc::new(SubstreamMetrics::default());
+        let dht_metrics = Arc::new(DhtMetrics::default());
+        let peer_graph = Arc::new(PeerGraphState::default());
+        let autonat_server_enabled = !config.observer;
+        let relay_metrics = Arc::new(RelayMetrics::default());
+        let gossip_rates = Arc::new(GossipRates::default());
+        let metrics_recorder = config
+            .metrics_snapshots
+            .as_ref()
+            .map(|snapshots| MetricsRecorder::new(snapshots.sink.clone()).map(Arc::new))
+            .transpose()?;
+        let metrics_interval = config
+            .metrics_snapshots
+            .as_ref()
+            .map_or(Duration::ZERO, |snapshots| snapshots.interval);
+        let connection_metrics = Arc::new(ConnectionMetrics::default());
+        let prometheus = Arc::new(PrometheusMetrics::default());
+        let mut registry = prometheus.new_registry();
+        connection_metrics.register(&mut registry);
+        config.blocklist = config.stored_blocklist()?;
+        let (keypair, swarm) =
+            config.build_with_metrics(substream_metrics.clone(), &mut registry)?;
+        prometheus.install(registry);
+        let replay_cache = config.replay_window.map(ReplayCache::new);
+        let topic_ciphers = topic_ciphers(&config);
+        let warmup_deadline = config
+            .gossipsub
+            .publish_warmup
+            .map(|warmup| Instant::now() + warmup);
+        let (topic_ready, topic_ready_receiver) = watch::channel(warmup_deadline.is_none());
+        let warmup_publishes = EventBus::new(BusMode::Bounded {
+            capacity: MAX_WARMUP_PUBLISHES,
+            policy: DropPolicy::DropOldest,
+        });
+        let local_peer_id = PeerId::from(keypair.public());
+        let (local_peer_id_sender, local_peer_id_receiver) = watch::channel(local_peer_id);
+        let (network_events, _) = broadcast::channel(DEFAULT_NETWORK_EVENT_CAPACITY);
+        let next_identity_rotation = config
+            .identity_rotation
+            .filter(|_| config.ephemeral_identity)
+            .map(|rotation| Instant::now() + rotation);
+        let (command_sender, command_receiver) = mpsc::channel(32);
+        let (delegated_sender, delegated_receiver) = mpsc::unbounded_channel();
+        let (autonat_status, autonat_status_receiver) = watch::channel(autonat::NatStatus::Unknown);
+
+        let mut swarm = swarm;
+        let gossipsub_topic = gossipsub::IdentTopic::new(&config.gossipsub_topic);
+        swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&gossipsub_topic)
+            .map_err(|err| anyhow!("failed to subscribe to gossipsub topic: {err}"))?;
+        let mut topics = HashMap::new();
+        for name in &config.gossipsub_topics {
+            let topic = gossipsub::IdentTopic::new(name);
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .subscribe(&topic)
+                .map_err(|err| anyhow!("failed to subscribe to topic {name}: {err}"))?;
+            topics.insert(topic.hash(), topic);
+        }
+
+        /* These are not needed as DEFAULT_BOOTSTRAP_PEERS should be empty
+        bootstrap_peers.extend(
+            DEFAULT_BOOTSTRAP_PEERS
+                .iter()
+                .filter_map(|value| match value.parse::<Multiaddr>() {
+                    Ok(addr) => Some(addr),
+                    Err(err) => {
+                        tracing::warn!(target: "peer", %err, value, "invalid default bootstrap peer; skipping");
+                        None
+                    }
+                }),
+        );
+        */
+
+        let mut manager = Self {
+            swarm,
+            command_receiver,
+            delegated_sender,
+            delegated_receiver,
+            delegated_in_flight: 0,
+            local_peer_id,
+            keypair,
+            inbound_sender,
+            gossipsub_topic,
+            autonat_status,
+            discovery_sender,
+            discovery_queries: HashMap::new(),
+            provider_queries: HashMap::new(),
+            record_queries: HashMap::new(),
+            provide_queries: HashMap::new(),
+            provided_keys: HashSet::new(),
+            discovery_dial_backoff: HashMap::new(),
+            relay_base_address: None,
+            relay_peer_id: None,
+            relay_listeners: HashMap::new(),
+            relay_reservations: HashMap::new(),
+            addr_state,
+            message_verifier: None,
+            topic_router: TopicRouter::default(),
+            record_validator: None,
+            invalid_messages: HashMap::new(),
+            replay_cache,
+            // Seeded from the clock so nonces keep increasing across restarts.
+            next_nonce: unix_time_ms(),
+            topic_ciphers,
+            topics,
+            groups: HashMap::new(),
+            next_group_announce: Instant::now(),
+            warmup_deadline,
+            warmup_sender: warmup_publishes.sender(),
+            warmup_publishes,
+            scheduled_publishes: BTreeMap::new(),
+            retries: BTreeMap::new(),
+            next_retry_id: 0,
+            scheduled_publish_times: HashMap::new(),
+            next_scheduled_publish_id: 0,
+            topic_ready,
+            mesh_waiters: Vec::new(),
+            dht_metrics: dht_metrics.clone(),
+            peer_graph: peer_graph.clone(),
+            substream_metrics: substream_metrics.clone(),
+            relay_metrics: relay_metrics.clone(),
+            gossip_rates: gossip_rates.clone(),
+            prometheus: prometheus.clone(),
+            connection_metrics: connection_metrics.clone(),
+            metrics_recorder: metrics_recorder.clone(),
+            audit_log: config.audit_log.clone().map(AuditLog::new).transpose()?,
+            metrics_interval,
+            next_metrics_snapshot: Instant::now() + metrics_interval,
+            redact: LogRedactor::new(config.redact_logs),
+            bootstrap_peers: bootstrap_peers.clone(),
+            bootstrap_query: None,
+            bootstrap_reached: false,
+            exchange_query: None,
+            exchange_addresses: HashMap::new(),
+            imported_blocklist: Blocklist::default(),
+            peer_records: PeerRecordCache::default(),
+            imported_addresses: HashMap::new(),
+            observed_addrs: ObservedAddrs::new(config.observed_addr_confirmations),
+            listen_addrs: Vec::new(),
+            next_identity_rotation,
+            local_peer_id_sender,
+            network_events: network_events.clone(),
+            connection_addrs: HashMap::new(),
+            direct_connections: HashMap::new(),
+            reported_connections: HashSet::new(),
+            pending_connects: HashMap::new(),
+            address_probes: Vec::new(),
+            peer_probes: HashMap::new(),
+            next_peer_probe: 0,
+            pending_dials: HashMap::new(),
+            clock_offsets: ClockOffsets::default(),
+            dial_lookups: HashMap::new(),
+            consumer_watch: ConsumerWatch::new(),
+            next_candidate_probe: Instant::now(),
+            next_peer_graph: Instant::now(),
+            autonat_server_enabled,
+            relay_server_enabled: true,
+            last_swarm_event: Instant::now(),
+            listener_failures: 0,
+            config,
+        };
+
+        manager.add_bootstrap_peers(bootstrap_peers);
+
+        let handle = PeerManagerHandle {
+            command_sender,
+            autonat_status: autonat_status_receiver,
+            local_peer_id: local_peer_id_receiver,
+            network_events,
+            substream_metrics,
+            dht_metrics,
+            peer_graph,
+            relay_metrics,
+            gossip_rates,
+            prometheus,
+            connection_metrics,
+            metrics_recorder,
+            topic_ready: topic_ready_receiver,
+            retry: RetryPolicy::none(),
+        };
+        Ok((manager, handle))
+    }
+
+    /// Returns the local peer identifier.
+    pub fn peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+
+    /// Provides access to the node's identity keypair.
+    pub fn keypair(&self) -> &identity::Keypair {
+        &self.keypair
+    }
+
+    /// Installs a verifier consulted for every inbound gossip message before it
+    /// is enqueued. Without a verifier all messages are accepted.
+    pub fn set_message_verifier(&mut self, verifier: impl MessageVerifier + 'static) {
+        self.message_verifier = Some(Arc::new(verifier));
+    }
+
+    /// Routes inbound messages through `router`, e.g. one shared with the
+    /// application before the manager is started so its consumers survive
+    /// restarts. Without consumers every message goes to the inbound queue.
+    pub fn set_topic_router(&mut self, router: TopicRouter) {
+        self.topic_router = router;
+    }
+
+    /// Returns the router consumers register with for their topics.
+    pub fn topic_router(&self) -> &TopicRouter {
+        &self.topic_router
+    }
+
+    /// Installs a validator consulted for every record or provider
+    /// announcement a remote peer asks us to store. Without a validator all
+    /// of them are stored.
+    pub fn set_record_validator(&mut self, validator: impl RecordValidator + 'static) {
+        self.record_validator = Some(Arc::new(validator));
+    }
+
+    /// Runs the peer manager control loop until shutdown is requested.
+    ///
+    /// Needs no thread of its own: it runs on a current-thread runtime as
+    /// well, e.g. driven by `Runtime::block_on` or a `LocalSet`, alongside
+    /// the connection and lookup tasks it spawns there.
+    pub async fn run(mut self) -> Result<()> {
+        let mut maintenance = tokio::time::interval(MAINTENANCE_INTERVAL);
+        maintenance.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // Swarm events handled in a row, bounded by the task budget.
+        let mut swarm_streak = 0usize;
+
+        loop {
+            tokio::select! {
+                Some(command) = self.command_receiver.recv() => {
+                    swarm_streak = 0;
                     if self.handle_command(command)? {
                         break;
                     }
                 }
-                event = self.swarm.select_next_some() => {
-                    self.handle_swarm_event(event);
+                event = self.swarm.select_next_some() => {
+                    self.handle_swarm_event(event);
+                    swarm_streak += 1;
+                    if swarm_streak >= self.config.executor.budgets.swarm_events_per_turn {
+                        swarm_streak = 0;
+                        tokio::task::yield_now().await;
+                    }
+                }
+                Some(answer) = self.delegated_receiver.recv() => {
+                    swarm_streak = 0;
+                    self.handle_delegated_answer(answer);
+                }
+                _ = maintenance.tick() => {
+                    swarm_streak = 0;
+                    self.on_maintenance_tick();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drives time-based state: ends the publish warm-up and resolves mesh waiters.
+    fn on_maintenance_tick(&mut self) {
+        self.poll_publish_warmup();
+        self.poll_scheduled_publishes();
+        self.poll_retries();
+        self.poll_mesh_waiters();
+        self.poll_groups();
+        self.poll_metrics_snapshot();
+        self.poll_identity_rotation();
+        self.poll_pending_connects();
+        self.poll_address_probes();
+        self.poll_peer_probes();
+        self.poll_pending_dials();
+        self.poll_discovery_deadlines();
+        self.poll_slow_consumer();
+        self.poll_external_candidates();
+        self.poll_watchdog();
+        self.poll_relay_reservations();
+        self.poll_peer_graph();
+        self.poll_gossip_throttle();
+    }
+
+    /// Restores the gossip score of throttled peers that stayed within the
+    /// limits for a whole window.
+    fn poll_gossip_throttle(&mut self) {
+        if self.config.gossip_throttle.is_none() {
+            return;
+        }
+        for peer_id in self.gossip_rates.release_expired(Instant::now()) {
+            tracing::info!(target: "peer", peer_id = %self.redact.display(&peer_id), "released gossip throttle");
+            self.swarm
+                .behaviour_mut()
+                .gossipsub
+                .set_application_score(&peer_id, 0.0);
+        }
+    }
+
+    /// Refreshes the peer graph from the swarm every [`PEER_GRAPH_INTERVAL`].
+    fn poll_peer_graph(&mut self) {
+        let now = Instant::now();
+        if now < self.next_peer_graph {
+            return;
+        }
+        self.next_peer_graph = now + PEER_GRAPH_INTERVAL;
+
+        let mut graph = PeerGraph::new();
+        for peer_id in self.swarm.connected_peers() {
+            graph.entry(*peer_id).or_default().connected = true;
+        }
+        let behaviour = self.swarm.behaviour_mut();
+        let topics: Vec<gossipsub::TopicHash> = behaviour.gossipsub.topics().cloned().collect();
+        for topic in topics {
+            for peer_id in behaviour.gossipsub.mesh_peers(&topic) {
+                graph
+                    .entry(*peer_id)
+                    .or_default()
+                    .mesh_topics
+                    .insert(topic.to_string());
+            }
+        }
+        for bucket in behaviour.kademlia.kbuckets() {
+            for entry in bucket.iter() {
+                graph
+                    .entry(*entry.node.key.preimage())
+                    .or_default()
+                    .in_routing_table = true;
+            }
+        }
+        self.peer_graph.update(graph);
+    }
+
+    /// Finishes discovery queries past their deadline, so queries that never
+    /// report a last step do not keep their state (and callers) forever.
+    fn poll_discovery_deadlines(&mut self) {
+        let now = Instant::now();
+        let overdue: Vec<kad::QueryId> = self
+            .discovery_queries
+            .iter()
+            .filter(|(_, request)| request.deadline <= now)
+            .map(|(query_id, _)| *query_id)
+            .collect();
+
+        for query_id in overdue {
+            let Some(request) = self.discovery_queries.get(&query_id).cloned() else {
+                continue;
+            };
+            if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&query_id) {
+                query.finish();
+            }
+            tracing::warn!(
+                target: "peer",
+                ?query_id,
+                request_id = request.request_id,
+                target = %self.redact.display(&request.target_peer_id),
+                "discovery query overdue; finishing it"
+            );
+            let status = if request.peers_found > 0 {
+                DiscoveryStatus::PartialSuccess {
+                    peers_found: request.peers_found,
+                }
+            } else {
+                DiscoveryStatus::Timeout
+            };
+            self.finish_discovery(query_id, request, status);
+        }
+
+        let overdue: Vec<kad::QueryId> = self
+            .provider_queries
+            .iter()
+            .filter(|(_, request)| request.deadline <= now)
+            .map(|(query_id, _)| *query_id)
+            .collect();
+        for query_id in overdue {
+            if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&query_id) {
+                query.finish();
+            }
+            tracing::warn!(target: "peer", ?query_id, "provider query overdue; finishing it");
+            self.finish_provider_query(query_id, true);
+        }
+    }
+
+    /// Re-requests relay reservations that were not renewed shortly before
+    /// their expiry and drops those that expired anyway.
+    fn poll_relay_reservations(&mut self) {
+        let now = Instant::now();
+        let margin = self.config.relay_reservation_ttl / RELAY_RENEWAL_MARGIN_DIVISOR;
+        let mut renew = Vec::new();
+        let mut expired = Vec::new();
+        for (relay_peer_id, reservation) in &self.relay_reservations {
+            let Some(expires_at) = reservation.expires_at else {
+                continue;
+            };
+            if now >= expires_at {
+                expired.push(*relay_peer_id);
+            } else if !reservation.renewal_requested && now + margin >= expires_at {
+                renew.push(*relay_peer_id);
+            }
+        }
+
+        for relay_peer_id in renew {
+            self.renew_relay_reservation(relay_peer_id);
+        }
+        for relay_peer_id in expired {
+            let Some(reservation) = self.relay_reservations.remove(&relay_peer_id) else {
+                continue;
+            };
+            tracing::warn!(
+                target: "peer",
+                relay_id = %self.redact.display(&relay_peer_id),
+                "relay reservation expired without renewal"
+            );
+            self.notify_relay_listener(
+                reservation.listener_id,
+                RelayReservationEvent::Closed {
+                    relay_peer_id,
+                    reason: Some("reservation expired without renewal".into()),
+                },
+            );
+            // Already notified; ListenerClosed must not report it again.
+            self.relay_listeners.remove(&reservation.listener_id);
+            self.swarm.remove_listener(reservation.listener_id);
+            self.forget_listen_address(&reservation.address);
+        }
+    }
+
+    /// Stops restoring `address` as a listener when the swarm is rebuilt.
+    fn forget_listen_address(&mut self, address: &Multiaddr) {
+        self.listen_addrs
+            .retain(|listen_addr| listen_addr != address);
+    }
+
+    /// Requests a fresh reservation on a new relay listener and retires the
+    /// listener of the reservation being replaced.
+    fn renew_relay_reservation(&mut self, relay_peer_id: PeerId) {
+        let Some(reservation) = self.relay_reservations.get_mut(&relay_peer_id) else {
+            return;
+        };
+        reservation.renewal_requested = true;
+        let old_listener = reservation.listener_id;
+        let address = reservation.address.clone();
+
+        tracing::info!(
+            target: "peer",
+            relay_id = %self.redact.display(&relay_peer_id),
+            "relay reservation not renewed in time, requesting a new one"
+        );
+        match self.swarm.listen_on(address) {
+            Ok(listener_id) => {
+                if let Some(reservation) = self.relay_reservations.get_mut(&relay_peer_id) {
+                    reservation.listener_id = listener_id;
+                }
+                if let Some(listener) = self.relay_listeners.remove(&old_listener) {
+                    self.relay_listeners.insert(listener_id, listener);
+                }
+                self.swarm.remove_listener(old_listener);
+            }
+            Err(err) => {
+                let event = self.relay_failure_event(relay_peer_id, self.redact.display(&err));
+                self.notify_relay_listener(old_listener, event);
+            }
+        }
+    }
+
+    /// Starts tracking the reservation requested by the relay listener `listener_id`.
+    fn track_relay_reservation(&mut self, listener_id: ListenerId, address: Multiaddr) {
+        let Some(relay_peer_id) = extract_peer_id(&address) else {
+            return;
+        };
+        self.relay_reservations.insert(
+            relay_peer_id,
+            RelayReservation {
+                listener_id,
+                address,
+                expires_at: None,
+                renewal_requested: false,
+            },
+        );
+    }
+
+    /// Returns the relay whose reservation is served by `listener_id`.
+    fn relay_of_listener(&self, listener_id: ListenerId) -> Option<PeerId> {
+        self.relay_listeners
+            .get(&listener_id)
+            .map(|listener| listener.relay_peer_id)
+            .or_else(|| {
+                self.relay_reservations
+                    .iter()
+                    .find(|(_, reservation)| reservation.listener_id == listener_id)
+                    .map(|(relay_peer_id, _)| *relay_peer_id)
+            })
+    }
+
+    /// Event reported when a reservation request fails: a renewal failure
+    /// while an accepted reservation is still running, a plain failure otherwise.
+    fn relay_failure_event(&self, relay_peer_id: PeerId, reason: String) -> RelayReservationEvent {
+        match self
+            .relay_reservations
+            .get(&relay_peer_id)
+            .and_then(|reservation| reservation.expires_at)
+        {
+            Some(expires_at) => RelayReservationEvent::RenewalFailed {
+                relay_peer_id,
+                reason,
+                expires_in: expires_at.saturating_duration_since(Instant::now()),
+            },
+            None => RelayReservationEvent::Failed {
+                relay_peer_id,
+                reason,
+            },
+        }
+    }
+
+    /// Reports a reservation event to the requester of the relay listener, if
+    /// any, and to network event subscribers.
+    fn notify_relay_listener(&self, listener_id: ListenerId, event: RelayReservationEvent) {
+        if let Some(listener) = self.relay_listeners.get(&listener_id) {
+            let _ = listener.events.try_send(event.clone());
+        }
+        self.emit_network_event(NetworkEvent::RelayReservation(event));
+    }
+
+    /// Rebuilds the swarm, keeping the identity, when it looks dead: no swarm
+    /// events for the watchdog timeout while self-checks fail, or repeated
+    /// listener failures.
+    fn poll_watchdog(&mut self) {
+        let Some(stall_timeout) = self.config.watchdog_timeout else {
+            return;
+        };
+        let reason = if self.listener_failures >= WATCHDOG_LISTENER_ERROR_THRESHOLD {
+            "repeated listener failures"
+        } else if self.last_swarm_event.elapsed() >= stall_timeout && !self.self_check() {
+            "swarm stalled"
+        } else {
+            return;
+        };
+
+        tracing::warn!(target: "peer", reason, "watchdog rebuilding swarm");
+        if let Err(err) = self.rebuild_swarm(self.keypair.clone()) {
+            tracing::error!(target: "peer", %err, "watchdog failed to rebuild swarm");
+        }
+        self.listener_failures = 0;
+        self.last_swarm_event = Instant::now();
+    }
+
+    /// Applies `update` to the blocklist, persisting it before it takes effect.
+    fn import_blocklist(&mut self, update: BlocklistUpdate) -> Result<usize> {
+        let mut blocklist = self.config.blocklist.clone();
+        blocklist.apply(update);
+        blocklist.prune_expired(unix_time_secs());
+        match &self.config.blocklist_file {
+            Some(path) => blocklist.save(path)?,
+            None => self.imported_blocklist = blocklist.clone(),
+        }
+        let entries = blocklist.len();
+        self.set_blocklist(blocklist);
+        tracing::info!(target: "peer", entries, "blocklist updated");
+        Ok(entries)
+    }
+
+    /// Replaces the blocklist; the blocker closes the connections it now
+    /// blocks, reported here as penalties.
+    fn set_blocklist(&mut self, blocklist: Blocklist) {
+        let now = unix_time_secs();
+        let mut penalized: Vec<(PeerId, String)> = Vec::new();
+        for (peer_id, address) in self.connection_addrs.values() {
+            if penalized.iter().any(|(penalized, _)| penalized == peer_id) {
+                continue;
+            }
+            if let Some(entry) = blocklist.blocking(Some(peer_id), Some(address), now) {
+                penalized.push((*peer_id, Blocked(entry.clone()).to_string()));
+            }
+        }
+
+        // Recorded in the config so a swarm rebuild keeps the list.
+        self.config.blocklist = blocklist.clone();
+        self.swarm.behaviour_mut().blocker.set_blocklist(blocklist);
+        for (peer_id, detail) in penalized {
+            self.emit_network_event(NetworkEvent::PeerPenalized(PeerPenalizedInfo {
+                peer_id,
+                reason: PenaltyReason::Blocklisted,
+                banned: true,
+                offences: 1,
+                threshold: 1,
+                detail,
+            }));
+        }
+    }
+
+    /// Rebuilds the swarm for `profile` unless it is already active, keeping
+    /// the routing table and the connections to pinned peers.
+    fn set_power_profile(&mut self, profile: PowerProfile) -> Result<()> {
+        if profile == self.config.power_profile {
+            return Ok(());
+        }
+        let config = TransportConfig {
+            power_profile: profile,
+            ..self.config.clone()
+        };
+        let (swarm, registry) = self.build_power_profile_swarm(&config)?;
+        self.config = config;
+        self.switch_power_profile(swarm, registry);
+        Ok(())
+    }
+
+    /// Builds the swarm of a power profile switch to `config`, so a switch
+    /// that cannot happen fails before anything changes.
+    fn build_power_profile_swarm(
+        &self,
+        config: &TransportConfig,
+    ) -> Result<(Swarm<NetworkBehaviour>, Registry)> {
+        self.build_swarm(config, &self.keypair)
+            .map_err(|err| err.context("failed to switch power profile"))
+    }
+
+    /// Installs `swarm` built by [`Self::build_power_profile_swarm`] for the
+    /// configured profile, keeping the routing table and pinned peers.
+    fn switch_power_profile(&mut self, swarm: Swarm<NetworkBehaviour>, registry: Registry) {
+        let routes = self.routing_table_entries();
+        let pinned = self.pinned_peer_addresses();
+        self.install_swarm(self.keypair.clone(), swarm, registry);
+        let profile = self.config.power_profile;
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        for (peer_id, addresses) in routes {
+            for address in addresses {
+                kademlia.add_address(&peer_id, address);
+            }
+        }
+        self.redial_pinned_peers(pinned);
+        tracing::info!(target: "peer", ?profile, "power profile switched");
+    }
+
+    /// Every peer of the routing table with its addresses.
+    fn routing_table_entries(&mut self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let mut entries = Vec::new();
+        for bucket in self.swarm.behaviour_mut().kademlia.kbuckets() {
+            for entry in bucket.iter() {
+                entries.push((
+                    *entry.node.key.preimage(),
+                    entry.node.value.iter().cloned().collect(),
+                ));
+            }
+        }
+        entries
+    }
+
+    /// Re-establishes listeners, pinned peers and reachability after the host
+    /// switched networks, or forgets the old network's addresses when it
+    /// lost connectivity.
+    fn handle_network_change(&mut self, change: NetworkChange) -> Result<()> {
+        tracing::info!(target: "peer", ?change, "host reported network change");
+        if change == NetworkChange::Lost {
+            let external: Vec<Multiaddr> = self.swarm.external_addresses().cloned().collect();
+            for address in &external {
+                self.swarm.remove_external_address(address);
+            }
+            self.observed_addrs = ObservedAddrs::new(self.config.observed_addr_confirmations);
+            return Ok(());
+        }
+
+        // Connections, the routing table and identify results die with the
+        // old swarm; remember where the pinned peers were.
+        let pinned = self.pinned_peer_addresses();
+        self.rebuild_swarm(self.keypair.clone())?;
+        // Reachability was judged on the previous network.
+        self.autonat_status
+            .send_replace(autonat::NatStatus::Unknown);
+        self.next_candidate_probe = Instant::now();
+        self.redial_pinned_peers(pinned);
+        Ok(())
+    }
+
+    /// Every pinned peer with the addresses it is known at, whether it sits
+    /// in the routing table or not.
+    fn pinned_peer_addresses(&mut self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let pinned = self.config.pinned_peers.clone();
+        pinned
+            .into_iter()
+            .map(|peer_id| (peer_id, self.dial_addresses(&peer_id)))
+            .collect()
+    }
+
+    /// Re-adds the pinned peers to the routing table of a rebuilt swarm and
+    /// dials them. Peers with no known address are left to the bootstrap.
+    fn redial_pinned_peers(&mut self, pinned: Vec<(PeerId, Vec<Multiaddr>)>) {
+        for (peer_id, addresses) in pinned {
+            if addresses.is_empty() {
+                tracing::debug!(
+                    target: "peer",
+                    peer_id = %self.redact.display(&peer_id),
+                    "no known address to re-dial pinned peer"
+                );
+                continue;
+            }
+            for address in &addresses {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, address.clone());
+            }
+            let opts = DialOpts::peer_id(peer_id).addresses(addresses).build();
+            if let Err(err) = self.swarm.dial(opts) {
+                tracing::warn!(
+                    target: "peer",
+                    peer_id = %self.redact.display(&peer_id),
+                    err = %self.redact.display(&err),
+                    "failed to re-dial pinned peer"
+                );
+            }
+        }
+    }
+
+    /// Passes when the requested listeners are active and, with bootstrap
+    /// peers configured, at least one peer is connected.
+    fn self_check(&self) -> bool {
+        let listening = self.listen_addrs.is_empty() || self.swarm.listeners().next().is_some();
+        let connected =
+            self.bootstrap_peers.is_empty() || self.swarm.connected_peers().next().is_some();
+        listening && connected
+    }
+
+    /// Disconnects `peer_id` when the versions it announced via identify fail
+    /// the version gate. Returns `true` when it was disconnected.
+    fn reject_incompatible(
+        &mut self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+        info: &identify::Info,
+    ) -> bool {
+        let Some(mismatch) = self
+            .config
+            .version_gate
+            .check(&info.protocol_version, &info.agent_version)
+        else {
+            return false;
+        };
+        let address = self
+            .connection_addrs
+            .get(&connection_id)
+            .map(|(_, address)| address.clone())
+            .unwrap_or_else(Multiaddr::empty);
+        tracing::warn!(
+            target: "peer",
+            peer_id = %self.redact.display(&peer_id),
+            kind = mismatch.kind.as_str(),
+            announced = %mismatch.announced,
+            required = %mismatch.required,
+            "disconnecting peer running an incompatible version"
+        );
+
+        // Kademlia would otherwise keep handing the peer out and dialing it.
+        self.swarm.behaviour_mut().kademlia.remove_peer(&peer_id);
+        self.exchange_addresses.remove(&peer_id);
+        let detail = format!(
+            "incompatible {} version {}, requires {}",
+            mismatch.kind.as_str(),
+            mismatch.announced,
+            mismatch.required
+        );
+        self.resolve_connect(
+            &peer_id,
+            ConnectOutcome::Failed {
+                reason: detail.clone(),
+            },
+        );
+        let _ = self.swarm.disconnect_peer_id(peer_id);
+        self.emit_network_event(NetworkEvent::IncompatiblePeer(IncompatiblePeerInfo {
+            peer_id,
+            address,
+            mismatch,
+        }));
+        self.emit_network_event(NetworkEvent::PeerPenalized(PeerPenalizedInfo {
+            peer_id,
+            reason: PenaltyReason::IncompatibleVersion,
+            banned: false,
+            offences: 1,
+            threshold: 1,
+            detail,
+        }));
+        true
+    }
+
+    /// Closes the redundant direct connections to `peer_id` the duplicate
+    /// connection policy does not keep.
+    fn dedup_connections(&mut self, peer_id: PeerId) {
+        let policy = self.config.duplicate_connections;
+        let Some(connections) = self.direct_connections.get(&peer_id) else {
+            return;
+        };
+        let Some(kept) = policy.keep(&self.local_peer_id, &peer_id, connections) else {
+            return;
+        };
+        let redundant: Vec<(ConnectionId, bool)> = connections
+            .iter()
+            .filter(|(id, _)| *id != kept)
+            .copied()
+            .collect();
+        let Some((_, kept_address)) = self.connection_addrs.get(&kept).cloned() else {
+            return;
+        };
+
+        for (connection_id, outbound) in redundant {
+            // Forgotten right away so a connection is only closed once.
+            self.forget_direct_connection(&peer_id, connection_id);
+            if !self.swarm.close_connection(connection_id) {
+                continue;
+            }
+            let Some((_, closed)) = self.connection_addrs.get(&connection_id).cloned() else {
+                continue;
+            };
+            tracing::info!(
+                target: "peer",
+                peer_id = %self.redact.display(&peer_id),
+                closed = %self.redact.display(&closed),
+                kept = %self.redact.display(&kept_address),
+                ?policy,
+                "closing duplicate connection"
+            );
+            self.emit_network_event(NetworkEvent::ConnectionDeduplicated(
+                ConnectionDeduplicatedInfo {
+                    peer_id,
+                    kept: kept_address.clone(),
+                    closed,
+                    closed_outbound: outbound,
+                    policy,
+                },
+            ));
+        }
+    }
+
+    fn forget_direct_connection(&mut self, peer_id: &PeerId, connection_id: ConnectionId) {
+        if let Some(connections) = self.direct_connections.get_mut(peer_id) {
+            connections.retain(|(id, _)| *id != connection_id);
+            if connections.is_empty() {
+                self.direct_connections.remove(peer_id);
+            }
+        }
+    }
+
+    /// Advertises observed addresses that reached the confirmation threshold
+    /// and withdraws those that fell below it.
+    fn apply_observed_addr_changes(&mut self) {
+        for (address, confirmed) in self.observed_addrs.take_changes() {
+            if confirmed {
+                tracing::info!(target: "peer", address = %self.redact.display(&address), "observed address confirmed by peers");
+                // Reported back as `ExternalAddrConfirmed`, which updates the address state.
+                self.swarm.add_external_address(address);
+            } else {
+                tracing::info!(target: "peer", address = %self.redact.display(&address), "observed address lost its confirmations");
+                self.swarm.remove_external_address(&address);
+            }
+        }
+    }
+
+    /// Asks AutoNAT to probe configured external address candidates that are
+    /// not confirmed yet. Confirmed candidates are reported by AutoNAT as
+    /// `ExternalAddrConfirmed` and advertised from there.
+    fn poll_external_candidates(&mut self) {
+        if self.config.external_address_candidates.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        if now < self.next_candidate_probe {
+            return;
+        }
+        self.next_candidate_probe = now + EXTERNAL_CANDIDATE_PROBE_INTERVAL;
+
+        let confirmed: HashSet<Multiaddr> = self.swarm.external_addresses().cloned().collect();
+        for candidate in &self.config.external_address_candidates {
+            if confirmed.contains(candidate) {
+                continue;
+            }
+            tracing::debug!(
+                target: "peer",
+                address = %self.redact.display(candidate),
+                "probing external address candidate"
+            );
+            if let Some(autonat) = self.swarm.behaviour_mut().autonat.as_mut() {
+                autonat.probe_address(candidate.clone());
+            }
+        }
+    }
+
+    /// Detects a consumer that stopped draining the inbound queue or loses
+    /// too many messages to a full queue, and reports when it recovers.
+    fn poll_slow_consumer(&mut self) {
+        let Some(settings) = self.config.slow_consumer else {
+            return;
+        };
+        let now = Instant::now();
+        let queue_depth = self.inbound_sender.depth();
+        let dequeued = self.inbound_sender.dequeued();
+        let watch = &mut self.consumer_watch;
+
+        if dequeued != watch.last_dequeued || queue_depth == 0 {
+            watch.last_dequeued = dequeued;
+            watch.last_progress = now;
+        }
+        let stalled_for = now.duration_since(watch.last_progress);
+
+        let (offered, dropped) = (watch.offered, watch.dropped);
+        if now.duration_since(watch.window_start) >= settings.stall_threshold {
+            watch.over_drop_rate =
+                offered > 0 && dropped as f64 / offered as f64 > settings.max_drop_rate;
+            watch.window_start = now;
+            watch.offered = 0;
+            watch.dropped = 0;
+        }
+
+        let slow = stalled_for >= settings.stall_threshold || watch.over_drop_rate;
+        if slow == watch.slow {
+            return;
+        }
+        watch.slow = slow;
+
+        if slow {
+            tracing::warn!(
+                target: "peer",
+                queue_depth,
+                ?stalled_for,
+                dropped,
+                offered,
+                gossip_paused = settings.pause_gossip,
+                "inbound queue consumer is too slow"
+            );
+            self.emit_network_event(NetworkEvent::SlowConsumer(SlowConsumerEvent::Detected {
+                queue_depth,
+                stalled_for,
+                dropped,
+                offered,
+                gossip_paused: settings.pause_gossip,
+            }));
+        } else {
+            tracing::info!(target: "peer", "inbound queue consumer recovered");
+            self.emit_network_event(NetworkEvent::SlowConsumer(SlowConsumerEvent::Recovered));
+        }
+    }
+
+    /// Reports explicit dials still pending after their deadline as failed.
+    fn poll_pending_dials(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<ConnectionId> = self
+            .pending_dials
+            .iter()
+            .filter(|(_, dial)| dial.deadline <= now)
+            .map(|(connection_id, _)| *connection_id)
+            .collect();
+        for connection_id in expired {
+            self.resolve_dial(
+                connection_id,
+                ConnectOutcome::Failed {
+                    reason: "dial timed out".into(),
+                },
+            );
+        }
+    }
+
+    /// Answers the explicit dial that opened `connection_id`, if any.
+    /// Returns `true` when the connection belonged to such a dial.
+    fn resolve_dial(&mut self, connection_id: ConnectionId, outcome: ConnectOutcome) -> bool {
+        let Some(pending) = self.pending_dials.remove(&connection_id) else {
+            return false;
+        };
+        self.conclude_dial(pending.dial, outcome);
+        true
+    }
+
+    /// Retries a failed dial while its policy allows, otherwise reports the outcome.
+    fn conclude_dial(&mut self, mut dial: DialAttempt, outcome: ConnectOutcome) {
+        if let ConnectOutcome::Failed { reason } = &outcome {
+            if dial.retry.allows_retry(dial.attempt) {
+                let backoff = dial.retry.backoff(dial.attempt);
+                tracing::debug!(target: "peer", attempt = dial.attempt, ?backoff, reason = %self.redact.display(reason), "dial failed; retrying");
+                dial.attempt += 1;
+                self.schedule_retry(backoff, RetryTask::Dial(dial));
+                return;
+            }
+        }
+        if let Some(started) = dial.started.take() {
+            let err = match &outcome {
+                ConnectOutcome::Failed { reason } => anyhow!("failed to dial: {reason}"),
+                ConnectOutcome::SelfDial => anyhow!("refusing to dial the local node"),
+                other => anyhow!("dial ended before starting: {other:?}"),
+            };
+            let _ = started.send(Err(err));
+        }
+        self.finish_dial(dial.responder, dial.request_id, outcome);
+    }
+
+    /// Hands a dial outcome to the waiting caller and, for dials tagged with a
+    /// request id, to event subscribers.
+    fn finish_dial(
+        &self,
+        responder: Option<oneshot::Sender<ConnectOutcome>>,
+        request_id: Option<u64>,
+        outcome: ConnectOutcome,
+    ) {
+        if let Some(request_id) = request_id {
+            tracing::debug!(target: "peer", request_id, outcome = %self.redact.debug(&outcome), "tracked dial finished");
+            self.emit_network_event(NetworkEvent::DialFinished(DialFinishedEvent {
+                request_id,
+                result: outcome.clone(),
+            }));
+        }
+        if let Some(responder) = responder {
+            let _ = responder.send(outcome);
+        }
+    }
+
+    /// Starts an explicit dial and tracks it until its connection is
+    /// established or fails.
+    fn start_dial(&mut self, dial: DialAttempt) {
+        let targets_self = match &dial.target {
+            DialTarget::Address(address) => self.is_own_address(address),
+            DialTarget::Peer { peer_id, .. } => *peer_id == self.local_peer_id,
+        };
+        if targets_self {
+            tracing::warn!(target: "peer", "refusing to dial the local node");
+            self.conclude_dial(dial, ConnectOutcome::SelfDial);
+            return;
+        }
+        if let DialTarget::Peer {
+            peer_id,
+            transport: None,
+        } = dial.target
+        {
+            if self.dial_addresses(&peer_id).is_empty() {
+                self.start_dial_lookup(peer_id, dial);
+                return;
+            }
+        }
+        let opts = match self.dial_opts(&dial.target) {
+            Ok(opts) => opts,
+            Err(reason) => {
+                self.conclude_dial(dial, ConnectOutcome::Failed { reason });
+                return;
+            }
+        };
+        self.launch_dial(dial, opts);
+    }
+
+    /// Dials `opts` and tracks the connection until it is established or fails.
+    fn launch_dial(&mut self, mut dial: DialAttempt, opts: DialOpts) {
+        let connection_id = opts.connection_id();
+        match self.swarm.dial(opts) {
+            Ok(()) => {
+                if let Some(started) = dial.started.take() {
+                    let _ = started.send(Ok(connection_id));
+                }
+                self.pending_dials.insert(
+                    connection_id,
+                    PendingDial {
+                        dial,
+                        deadline: Instant::now() + CONNECT_TIMEOUT,
+                    },
+                );
+            }
+            Err(err) => {
+                let reason = self.redact.display(&err);
+                self.conclude_dial(dial, ConnectOutcome::Failed { reason });
+            }
+        }
+    }
+
+    /// Builds the dial options for `target`, without relay fallback.
+    fn dial_opts(&mut self, target: &DialTarget) -> std::result::Result<DialOpts, String> {
+        let (peer_id, transport) = match target {
+            DialTarget::Address(address) => {
+                return Ok(DialOpts::unknown_peer_id().address(address.clone()).build());
+            }
+            DialTarget::Peer { peer_id, transport } => (*peer_id, *transport),
+        };
+        let opts = match transport {
+            None => DialOpts::peer_id(peer_id)
+                .condition(PeerCondition::Always)
+                .addresses(self.dial_addresses(&peer_id))
+                .extend_addresses_through_behaviour()
+                .build(),
+            Some(DialTransport::Relay) => {
+                let Some(mut address) = self.relay_base_address.clone() else {
+                    return Err("no relay reservation available".into());
+                };
+                address.push(Protocol::P2pCircuit);
+                DialOpts::peer_id(peer_id)
+                    .condition(PeerCondition::Always)
+                    .addresses(vec![address])
+                    .build()
+            }
+            Some(transport) => {
+                let addresses: Vec<Multiaddr> = self
+                    .known_addresses(&peer_id)
+                    .into_iter()
+                    .filter(|address| transport.matches(address))
+                    .collect();
+                if addresses.is_empty() {
+                    return Err(format!("no known {transport:?} address for peer"));
+                }
+                DialOpts::peer_id(peer_id)
+                    .condition(PeerCondition::Always)
+                    .addresses(addresses)
+                    .build()
+            }
+        };
+        Ok(opts)
+    }
+
+    /// Returns `true` when `address` leads back to this node: it names the
+    /// local peer id, or is one of its listen or confirmed external
+    /// addresses, e.g. a hairpinned public address. Relay circuits the node
+    /// listens on are among its listen addresses. Merely observed addresses
+    /// are not trusted, since any peer can report one.
+    fn is_own_address(&self, address: &Multiaddr) -> bool {
+        if extract_peer_id(address) == Some(self.local_peer_id) {
+            return true;
+        }
+        let mut bare = address.clone();
+        if matches!(bare.iter().last(), Some(Protocol::P2p(_))) {
+            bare.pop();
+        }
+        self.swarm.listeners().any(|own| *own == bare)
+            || self.swarm.external_addresses().any(|own| *own == bare)
+    }
+
+    /// Addresses of `peer_id` known from the routing table, open connections
+    /// and identify, in the order a dial without transport tries them.
+    fn dial_addresses(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        let mut addresses = self.known_addresses(peer_id);
+        if let Some(advertised) = self.exchange_addresses.get(peer_id) {
+            addresses.extend(advertised.iter().cloned());
+        }
+        order_dial_addresses(addresses)
+    }
+
+    /// Looks `peer_id` up in the DHT and dials the addresses found, see
+    /// [`Self::resolve_dial_lookup`].
+    fn start_dial_lookup(&mut self, peer_id: PeerId, dial: DialAttempt) {
+        tracing::debug!(target: "peer", peer_id = %self.redact.display(&peer_id), "no known address; looking peer up in the dht");
+        let query_id = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .get_closest_peers(peer_id);
+        self.dial_lookups.insert(query_id, dial);
+    }
+
+    /// Dials the addresses a DHT lookup found for the target of the dial
+    /// waiting on `query_id`, or fails it once the lookup ends without them.
+    fn resolve_dial_lookup(
+        &mut self,
+        query_id: kad::QueryId,
+        result: &kad::GetClosestPeersResult,
+        is_last: bool,
+    ) {
+        let Some(DialTarget::Peer { peer_id, .. }) = self
+            .dial_lookups
+            .get(&query_id)
+            .map(|dial| dial.target.clone())
+        else {
+            return;
+        };
+        let peers = match result {
+            Ok(ok) => &ok.peers,
+            Err(kad::GetClosestPeersError::Timeout { peers, .. }) => peers,
+        };
+        let addresses = peers
+            .iter()
+            .find(|info| info.peer_id == peer_id)
+            .map(|info| order_dial_addresses(info.addrs.clone()))
+            .unwrap_or_default();
+        if addresses.is_empty() && !is_last {
+            return;
+        }
+        let Some(dial) = self.dial_lookups.remove(&query_id) else {
+            return;
+        };
+        if addresses.is_empty() {
+            self.conclude_dial(
+                dial,
+                ConnectOutcome::Failed {
+                    reason: "peer not found in the dht".into(),
+                },
+            );
+            return;
+        }
+        if !is_last {
+            if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&query_id) {
+                query.finish();
+            }
+        }
+        let opts = DialOpts::peer_id(peer_id)
+            .condition(PeerCondition::Always)
+            .addresses(addresses)
+            .extend_addresses_through_behaviour()
+            .build();
+        self.launch_dial(dial, opts);
+    }
+
+    /// Addresses of `peer_id` known from the routing table and open connections.
+    fn known_addresses(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        let mut addresses: Vec<Multiaddr> = self
+            .connection_addrs
+            .values()
+            .filter(|(peer, _)| peer == peer_id)
+            .map(|(_, address)| address.clone())
+            .collect();
+        if let Some(bucket) = self.swarm.behaviour_mut().kademlia.kbucket(*peer_id) {
+            for entry in bucket.iter() {
+                if entry.node.key.preimage() == peer_id {
+                    addresses.extend(entry.node.value.iter().cloned());
+                }
+            }
+        }
+        addresses.sort();
+        addresses.dedup();
+        addresses
+    }
+
+    /// Reports address probes without an AutoNAT answer as inconclusive.
+    fn poll_address_probes(&mut self) {
+        if self.address_probes.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let (expired, pending) = std::mem::take(&mut self.address_probes)
+            .into_iter()
+            .partition(|probe| probe.deadline <= now);
+        self.address_probes = pending;
+        for probe in expired {
+            let _ = probe.responder.send(AddressProbeOutcome::Inconclusive {
+                reason: "probe timed out".into(),
+            });
+        }
+    }
+
+    /// Starts a ping burst to the connected `peer_id`, answering right away
+    /// when it is not connected.
+    fn start_peer_probe(
+        &mut self,
+        peer_id: PeerId,
+        count: u32,
+        responder: oneshot::Sender<PeerProbeReport>,
+    ) {
+        let id = self.next_peer_probe;
+        self.next_peer_probe += 1;
+        let reachability = self.current_connection(&peer_id);
+        let started =
+            reachability.is_some() && self.swarm.behaviour_mut().prober.probe(peer_id, id, count);
+        let Some(reachability) = reachability.filter(|_| started) else {
+            let _ = responder.send(PeerProbeReport {
+                reachability: ConnectOutcome::Failed {
+                    reason: "peer is not connected".into(),
+                },
+                count,
+                rtts: Vec::new(),
+                error: None,
+            });
+            return;
+        };
+        tracing::debug!(target: "peer", peer_id = %self.redact.display(&peer_id), count, "probing peer");
+        // Lets the peer refresh what it knows of this node before the transfer.
+        self.swarm.behaviour_mut().identify.push([peer_id]);
+        self.peer_probes.insert(
+            id,
+            PeerProbe {
+                responder,
+                reachability,
+                count,
+                rtts: Vec::new(),
+                deadline: Instant::now()
+                    + (PROBE_PING_TIMEOUT * (count + 1)).min(MAX_PROBE_DURATION),
+            },
+        );
+    }
+
+    /// Records an answered ping of a peer probe, and answers the probe once
+    /// its burst finished.
+    fn resolve_peer_probe(&mut self, event: ProbeEvent) {
+        let Some(probe) = self.peer_probes.get_mut(&event.id) else {
+            return;
+        };
+        probe.rtts.extend(event.rtt);
+        if !event.finished {
+            return;
+        }
+        let Some(probe) = self.peer_probes.remove(&event.id) else {
+            return;
+        };
+        tracing::debug!(
+            target: "peer",
+            peer_id = %self.redact.display(&event.peer_id),
+            answered = probe.rtts.len(),
+            error = ?event.error,
+            "peer probe finished"
+        );
+        let _ = probe.responder.send(PeerProbeReport {
+            reachability: probe.reachability,
+            count: probe.count,
+            rtts: probe.rtts,
+            error: event.error,
+        });
+    }
+
+    /// Answers peer probes whose burst never finished in time with the pings
+    /// answered so far.
+    fn poll_peer_probes(&mut self) {
+        if self.peer_probes.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let expired: Vec<u64> = self
+            .peer_probes
+            .iter()
+            .filter(|(_, probe)| probe.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            if let Some(probe) = self.peer_probes.remove(&id) {
+                let _ = probe.responder.send(PeerProbeReport {
+                    reachability: probe.reachability,
+                    count: probe.count,
+                    rtts: probe.rtts,
+                    error: Some("probe timed out".into()),
+                });
+            }
+        }
+    }
+
+    /// How `peer_id` is connected right now, preferring a direct connection
+    /// over a relayed one; `None` when it is not connected.
+    fn current_connection(&self, peer_id: &PeerId) -> Option<ConnectOutcome> {
+        let mut relayed = None;
+        for (peer, address) in self.connection_addrs.values() {
+            if peer != peer_id {
+                continue;
+            }
+            if !is_relayed(address) {
+                return Some(ConnectOutcome::Direct {
+                    address: address.clone(),
+                });
+            }
+            relayed = Some(ConnectOutcome::Relayed {
+                address: address.clone(),
+            });
+        }
+        relayed
+    }
+
+    /// Answers the address probes carried by the AutoNAT probe `probe_id`.
+    /// Probes rejected before any request was sent (e.g. no server) carry no
+    /// id yet and are answered by the first result with an unknown id.
+    fn resolve_address_probes(
+        &mut self,
+        probe_id: autonat::ProbeId,
+        outcome: impl Fn(&Multiaddr) -> AddressProbeOutcome,
+    ) {
+        let known = self
+            .address_probes
+            .iter()
+            .any(|probe| probe.probe_id == Some(probe_id));
+        let (resolved, pending) = std::mem::take(&mut self.address_probes)
+            .into_iter()
+            .partition(|probe| {
+                if known {
+                    probe.probe_id == Some(probe_id)
+                } else {
+                    probe.probe_id.is_none()
+                }
+            });
+        self.address_probes = pending;
+        for probe in resolved {
+            let _ = probe.responder.send(outcome(&probe.address));
+        }
+    }
+
+    /// Resolves connect requests whose deadline has passed.
+    fn poll_pending_connects(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<PeerId> = self
+            .pending_connects
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+
+        for peer_id in expired {
+            let outcome = match self
+                .pending_connects
+                .get(&peer_id)
+                .and_then(|p| p.relayed.clone())
+            {
+                Some(address) => ConnectOutcome::Relayed { address },
+                None => ConnectOutcome::Failed {
+                    reason: "connect timed out".into(),
+                },
+            };
+            self.resolve_connect(&peer_id, outcome);
+        }
+    }
+
+    /// Answers every pending connect request for `peer_id` with `outcome`.
+    fn resolve_connect(&mut self, peer_id: &PeerId, outcome: ConnectOutcome) {
+        let Some(pending) = self.pending_connects.remove(peer_id) else {
+            return;
+        };
+        tracing::info!(
+            target: "peer",
+            peer_id = %self.redact.display(peer_id),
+            outcome = %self.redact.debug(&outcome),
+            "connect finished"
+        );
+        for responder in pending.responders {
+            let _ = responder.send(outcome.clone());
+        }
+    }
+
+    /// Starts a connect request: answers right away when a direct connection
+    /// exists, otherwise waits for an upgrade or dials the peer.
+    fn start_connect(&mut self, peer_id: PeerId, responder: oneshot::Sender<ConnectOutcome>) {
+        if peer_id == self.local_peer_id {
+            tracing::warn!(target: "peer", "refusing to connect to the local node");
+            let _ = responder.send(ConnectOutcome::SelfDial);
+            return;
+        }
+        let mut direct = None;
+        let mut relayed = None;
+        for (peer, address) in self.connection_addrs.values() {
+            if *peer != peer_id {
+                continue;
+            }
+            if is_relayed(address) {
+                relayed = Some(address.clone());
+            } else {
+                direct = Some(address.clone());
+            }
+        }
+        if let Some(address) = direct {
+            let _ = responder.send(ConnectOutcome::Direct { address });
+            return;
+        }
+
+        if let Some(pending) = self.pending_connects.get_mut(&peer_id) {
+            pending.responders.push(responder);
+            return;
+        }
+
+        let deadline = Instant::now()
+            + if relayed.is_some() {
+                CONNECT_UPGRADE_TIMEOUT
+            } else {
+                CONNECT_TIMEOUT
+            };
+        let waiting_for_upgrade = relayed.is_some();
+        self.pending_connects.insert(
+            peer_id,
+            PendingConnect {
+                responders: vec![responder],
+                relayed,
+                deadline,
+            },
+        );
+        if waiting_for_upgrade {
+            return;
+        }
+
+        let opts = DialOpts::peer_id(peer_id)
+            .condition(PeerCondition::DisconnectedAndNotDialing)
+            .build();
+        if let Err(error) = self.swarm.dial(opts) {
+            tracing::debug!(
+                target: "peer",
+                peer_id = %self.redact.display(&peer_id),
+                error = %self.redact.display(&error),
+                "direct dial for connect failed"
+            );
+            if !self.try_dial_via_relay(&peer_id, &error) {
+                self.resolve_connect(
+                    &peer_id,
+                    ConnectOutcome::Failed {
+                        reason: self.redact.display(&error),
+                    },
+                );
+            }
+        }
+    }
+
+    /// Applies the runtime-tunable settings of `runtime` and reports which
+    /// changed settings need a restart.
+    fn reload_config(&mut self, runtime: RuntimeConfig) -> Result<ReloadReport> {
+        let RuntimeConfig {
+            transport: config,
+            log_filter,
+        } = runtime;
+        config.validate()?;
+        let mut report = ReloadReport {
+            restart_required: self.config.restart_required_changes(&config),
+            ..ReloadReport::default()
+        };
+
+        // Everything that can fail runs before the first setting changes, so
+        // a failed reload leaves the node as it was. Imported updates survive
+        // the reload, from the file when one is configured and from memory
+        // otherwise.
+        let mut blocklist = config.blocklist.clone();
+        match &self.config.blocklist_file {
+            Some(path) => blocklist.extend(Blocklist::load(path)?),
+            None => blocklist.extend(self.imported_blocklist.clone()),
+        }
+        // Settings needing a restart keep their current value.
+        let reloaded = TransportConfig {
+            blocklist: blocklist.clone(),
+            ..config.clone().keeping_restart_required(&self.config)
+        };
+        let power_profile_swarm = if reloaded.power_profile != self.config.power_profile {
+            Some(self.build_power_profile_swarm(&reloaded)?)
+        } else {
+            None
+        };
+        if let Some(directives) = log_filter {
+            crate::config::set_log_filter(&directives)?;
+            report.applied.push("log_filter");
+        }
+        if config.max_substreams_per_connection != self.config.max_substreams_per_connection {
+            self.substream_metrics
+                .set_limit(config.max_substreams_per_connection);
+            report.applied.push("max_substreams_per_connection");
+        }
+        if config.watchdog_timeout != self.config.watchdog_timeout {
+            self.last_swarm_event = Instant::now();
+            report.applied.push("watchdog_timeout");
+        }
+        if config.relay_reservation_ttl != self.config.relay_reservation_ttl {
+            report.applied.push("relay_reservation_ttl");
+        }
+        if config.slow_consumer != self.config.slow_consumer {
+            if self.consumer_watch.slow {
+                self.emit_network_event(NetworkEvent::SlowConsumer(SlowConsumerEvent::Recovered));
+            }
+            self.consumer_watch = ConsumerWatch::new();
+            report.applied.push("slow_consumer");
+        }
+        if config.inbound_message_ttl != self.config.inbound_message_ttl {
+            report.applied.push("inbound_message_ttl");
+        }
+        if config.outbound_message_ttl != self.config.outbound_message_ttl {
+            report.applied.push("outbound_message_ttl");
+        }
+        if config.replay_window != self.config.replay_window {
+            self.replay_cache = config.replay_window.map(ReplayCache::new);
+            report.applied.push("replay_window");
+        }
+        if config.time_sync != self.config.time_sync {
+            // Peers are sampled again on their next identify exchange.
+            self.clock_offsets.clear();
+            self.swarm
+                .behaviour_mut()
+                .time_sync
+                .set_open(config.time_sync);
+            report.applied.push("time_sync");
+        }
+        if config.trace_messages != self.config.trace_messages {
+            report.applied.push("trace_messages");
+        }
+        if config.observed_addr_confirmations != self.config.observed_addr_confirmations {
+            self.observed_addrs
+                .set_threshold(config.observed_addr_confirmations);
+            self.apply_observed_addr_changes();
+            report.applied.push("observed_addr_confirmations");
+        }
+        if config.topic_keys != self.config.topic_keys {
+            self.topic_ciphers = topic_ciphers(&config);
+            report.applied.push("topic_keys");
+        }
+        if config.redact_logs != self.config.redact_logs {
+            self.redact = LogRedactor::new(config.redact_logs);
+            report.applied.push("redact_logs");
+        }
+        if config.external_address_candidates != self.config.external_address_candidates {
+            self.next_candidate_probe = Instant::now();
+            report.applied.push("external_address_candidates");
+        }
+        if config.pinned_peers != self.config.pinned_peers {
+            let keep_alive = &mut self.swarm.behaviour_mut().keep_alive;
+            for peer_id in &self.config.pinned_peers {
+                keep_alive.set_pinned(*peer_id, false);
+            }
+            for peer_id in &config.pinned_peers {
+                keep_alive.set_pinned(*peer_id, true);
+            }
+            report.applied.push("pinned_peers");
+        }
+        if config.identity_rotation != self.config.identity_rotation {
+            self.next_identity_rotation = config
+                .identity_rotation
+                .filter(|_| self.config.ephemeral_identity)
+                .map(|rotation| Instant::now() + rotation);
+            report.applied.push("identity_rotation");
+        }
+        if blocklist != self.config.blocklist {
+            self.set_blocklist(blocklist);
+            report.applied.push("blocklist");
+        }
+
+        if config.duplicate_connections != self.config.duplicate_connections {
+            report.applied.push("duplicate_connections");
+        }
+        if config.delegated_routing != self.config.delegated_routing {
+            report.applied.push("delegated_routing");
+        }
+        if config.version_gate != self.config.version_gate {
+            report.applied.push("version_gate");
+        }
+
+        self.config = reloaded;
+        if let Some((swarm, registry)) = power_profile_swarm {
+            self.switch_power_profile(swarm, registry);
+            report.applied.push("power_profile");
+        }
+
+        tracing::info!(
+            target: "peer",
+            applied = ?report.applied,
+            restart_required = ?report.restart_required,
+            "configuration reloaded"
+        );
+        Ok(report)
+    }
+
+    /// Rotates the ephemeral identity when its lifetime has elapsed.
+    fn poll_identity_rotation(&mut self) {
+        let (Some(deadline), Some(rotation)) =
+            (self.next_identity_rotation, self.config.identity_rotation)
+        else {
+            return;
+        };
+        let now = Instant::now();
+        if now < deadline {
+            return;
+        }
+        self.next_identity_rotation = Some(now + rotation);
+        self.rotate_identity();
+    }
+
+    /// Replaces the identity with a fresh one by rebuilding the swarm.
+    fn rotate_identity(&mut self) {
+        let previous_peer_id = self.local_peer_id;
+        if let Err(err) = self.rebuild_swarm(identity::Keypair::generate_ed25519()) {
+            tracing::warn!(target: "peer", %err, "failed to rotate ephemeral identity");
+            return;
+        }
+
+        tracing::info!(
+            target: "peer",
+            previous = %self.redact.display(&previous_peer_id),
+            current = %self.redact.display(&self.local_peer_id),
+            "rotated ephemeral identity"
+        );
+    }
+
+    /// Rebuilds the swarm in place for `keypair`; the handle stays valid.
+    ///
+    /// Connections of the old swarm are closed and its pending discovery
+    /// queries fail; listeners, relay reservations, the topic subscription and
+    /// bootstrap peers are restored on the new swarm.
+    fn rebuild_swarm(&mut self, keypair: identity::Keypair) -> Result<()> {
+        let (swarm, registry) = self.build_swarm(&self.config, &keypair)?;
+        self.install_swarm(keypair, swarm, registry);
+        Ok(())
+    }
+
+    /// Builds a swarm for `config` and `keypair` joined to the node's topics
+    /// and groups, leaving the running swarm untouched.
+    fn build_swarm(
+        &self,
+        config: &TransportConfig,
+        keypair: &identity::Keypair,
+    ) -> Result<(Swarm<NetworkBehaviour>, Registry)> {
+        let mut registry = self.prometheus.new_registry();
+        self.connection_metrics.register(&mut registry);
+        let mut swarm =
+            config.build_with_keypair(keypair, self.substream_metrics.clone(), &mut registry)?;
+        swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&self.gossipsub_topic)
+            .map_err(|err| anyhow!("failed to subscribe to gossipsub topic: {err}"))?;
+        for topic in self.topics.values() {
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .subscribe(topic)
+                .map_err(|err| anyhow!("failed to subscribe to topic {topic}: {err}"))?;
+        }
+        for key in &self.provided_keys {
+            if let Err(err) = swarm.behaviour_mut().kademlia.start_providing(key.clone()) {
+                tracing::warn!(target: "peer", %err, "failed to provide key again");
+            }
+        }
+        for group in self.groups.values() {
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .subscribe(&group.topic)
+                .map_err(|err| anyhow!("failed to subscribe to group {}: {err}", group.name))?;
+        }
+        swarm
+            .behaviour_mut()
+            .autonat
+            .set_open(self.autonat_server_enabled);
+        swarm
+            .behaviour_mut()
+            .relay_server
+            .set_open(self.relay_server_enabled && !config.power_profile.is_low_power());
+        Ok((swarm, registry))
+    }
+
+    /// Replaces the running swarm with `swarm` built for `keypair` and
+    /// restores the node's listeners and peers on it.
+    fn install_swarm(
+        &mut self,
+        keypair: identity::Keypair,
+        swarm: Swarm<NetworkBehaviour>,
+        registry: Registry,
+    ) {
+        let old_listeners: Vec<Multiaddr> = self.swarm.listeners().cloned().collect();
+        // Dropping the old swarm closes its connections and frees the listen ports.
+        drop(std::mem::replace(&mut self.swarm, swarm));
+        self.prometheus.install(registry);
+        self.connection_addrs.clear();
+        self.direct_connections.clear();
+        self.exchange_addresses.clear();
+        self.clock_offsets.clear();
+        self.exchange_query = None;
+        // Confirmations were made on the old swarm and die with it.
+        self.observed_addrs = ObservedAddrs::new(self.config.observed_addr_confirmations);
+        self.reported_connections.clear();
+        self.connection_metrics.reset();
+        // Scores were kept by the old gossipsub behaviour.
+        self.gossip_rates.clear();
+        self.keypair = keypair;
+        self.local_peer_id = PeerId::from(self.keypair.public());
+        self.local_peer_id_sender.send_replace(self.local_peer_id);
+        // Let group members learn the (possibly rotated) identity right away.
+        self.next_group_announce = Instant::now();
+
+        for address in old_listeners {
+            self.emit_addr_event(AddrEvent::ListenerRemoved { address });
+        }
+        if self.relay_base_address.take().is_some() {
+            self.emit_addr_event(AddrEvent::RelayReachableLost);
+        }
+        for (query_id, request) in std::mem::take(&mut self.discovery_queries) {
+            self.finish_discovery(query_id, request, DiscoveryStatus::InternalError);
+        }
+        for (_, request) in std::mem::take(&mut self.provider_queries) {
+            self.send_providers_finished(request, DiscoveryStatus::InternalError);
+        }
+        for (_, request) in std::mem::take(&mut self.record_queries) {
+            self.send_record_finished(request, DiscoveryStatus::InternalError);
+        }
+        for (_, request) in std::mem::take(&mut self.provide_queries) {
+            self.send_providing_started(request, DiscoveryStatus::InternalError);
+        }
+        for (_, dial) in std::mem::take(&mut self.dial_lookups) {
+            self.conclude_dial(
+                dial,
+                ConnectOutcome::Failed {
+                    reason: "swarm restarted during dht lookup".into(),
+                },
+            );
+        }
+
+        let mut relay_listeners: Vec<RelayListener> = self
+            .relay_listeners
+            .drain()
+            .map(|(_, listener)| listener)
+            .collect();
+        self.relay_reservations.clear();
+        for address in self.listen_addrs.clone() {
+            match self.swarm.listen_on(address.clone()) {
+                Ok(listener_id) => {
+                    if is_relayed(&address) {
+                        self.track_relay_reservation(listener_id, address.clone());
+                    }
+                    // Keep reporting reservation events to the original requester.
+                    if let Some(index) = relay_listeners
+                        .iter()
+                        .position(|listener| listener.address == address)
+                    {
+                        let listener = relay_listeners.swap_remove(index);
+                        self.relay_listeners.insert(listener_id, listener);
+                    }
+                }
+                Err(err) => tracing::warn!(
+                    target: "peer",
+                    address = %self.redact.display(&address),
+                    err = %self.redact.display(&err),
+                    "failed to restore listener after swarm rebuild"
+                ),
+            }
+        }
+        for listener in relay_listeners {
+            let _ = listener.events.try_send(RelayReservationEvent::Closed {
+                relay_peer_id: listener.relay_peer_id,
+                reason: Some("relay listener not restored after swarm rebuild".into()),
+            });
+        }
+        self.add_bootstrap_peers(self.bootstrap_peers.clone());
+        for (peer_id, addresses) in &self.imported_addresses {
+            for address in addresses {
+                self.swarm.add_peer_address(*peer_id, address.clone());
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(peer_id, address.clone());
+            }
+        }
+    }
+
+    /// Records a metrics snapshot when the configured interval has elapsed.
+    fn poll_metrics_snapshot(&mut self) {
+        let Some(recorder) = self.metrics_recorder.clone() else {
+            return;
+        };
+        let now = Instant::now();
+        if now < self.next_metrics_snapshot {
+            return;
+        }
+        self.next_metrics_snapshot = now + self.metrics_interval;
+
+        let snapshot = MetricsSnapshot {
+            timestamp_ms: unix_time_ms(),
+            connected_peers: self.swarm.connected_peers().count(),
+            routing_table_size: self
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .kbuckets()
+                .map(|bucket| bucket.num_entries())
+                .sum(),
+            dht_records: self
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .store_mut()
+                .records()
+                .count(),
+            dht_store: self.dht_metrics.store_snapshot(),
+            inbound_queue_depth: self.inbound_sender.depth(),
+            inbound_expired: self.inbound_sender.stats().expired,
+            outbound_expired: self.warmup_publishes.stats().expired,
+            discovery_queue_depth: self.discovery_sender.depth(),
+            substreams: self.substream_metrics.snapshot(),
+            relay: self.relay_metrics.snapshot(&self.substream_metrics),
+            connections: self.connection_metrics.snapshot(),
+        };
+
+        if let Err(err) = recorder.record(snapshot) {
+            tracing::warn!(target: "peer", %err, "failed to record metrics snapshot");
+        }
+    }
+
+    /// Resolves waiters whose topic mesh reached the requested size and drops
+    /// those whose caller went away.
+    fn poll_mesh_waiters(&mut self) {
+        if self.mesh_waiters.is_empty() {
+            return;
+        }
+
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let mut pending = Vec::with_capacity(self.mesh_waiters.len());
+        for waiter in self.mesh_waiters.drain(..) {
+            if waiter.responder.is_closed() {
+                continue;
+            }
+
+            let mesh_peers = gossipsub.mesh_peers(&waiter.topic).count();
+            if mesh_peers >= waiter.min_peers {
+                tracing::info!(target: "peer", topic = %waiter.topic, mesh_peers, "topic mesh ready");
+                let _ = waiter.responder.send(());
+            } else {
+                pending.push(waiter);
+            }
+        }
+        self.mesh_waiters = pending;
+    }
+
+    /// Ends the warm-up once the topic has mesh peers or the hold-off elapsed,
+    /// then flushes buffered publishes and signals that the topic is ready.
+    fn poll_publish_warmup(&mut self) {
+        let Some(deadline) = self.warmup_deadline else {
+            return;
+        };
+
+        let mesh_peers = self
+            .swarm
+            .behaviour()
+            .gossipsub
+            .mesh_peers(&self.gossipsub_topic.hash())
+            .count();
+        if mesh_peers == 0 && Instant::now() < deadline {
+            return;
+        }
+
+        tracing::info!(
+            target: "peer",
+            mesh_peers,
+            held = self.warmup_publishes.stats().depth,
+            "publish warm-up finished; topic ready"
+        );
+        self.warmup_deadline = None;
+        while let Some(held) = self.warmup_publishes.try_recv() {
+            self.publish_payload(held.payload, held.retry, held.trace_id, held.receipt);
+        }
+        let expired = self.warmup_publishes.stats().expired;
+        if expired > 0 {
+            tracing::warn!(target: "peer", expired, "publishes expired during warm-up");
+        }
+        self.topic_ready.send_replace(true);
+    }
+
+    /// Publishes a payload, buffering it while the startup warm-up is active.
+    fn publish_payload(
+        &mut self,
+        payload: Vec<u8>,
+        retry: RetryPolicy,
+        trace_id: Option<TraceId>,
+        receipt: Option<oneshot::Sender<Result<PublishReceipt>>>,
+    ) {
+        if let Err(err) = self.refuse_in_observer_mode("publishing") {
+            tracing::warn!(target: "peer", %err, "dropping publish");
+            if let Some(receipt) = receipt {
+                let _ = receipt.send(Err(err));
+            }
+            return;
+        }
+        if self.warmup_deadline.is_some() {
+            if self.warmup_sender.depth() >= MAX_WARMUP_PUBLISHES {
+                tracing::warn!(target: "peer", "warm-up publish buffer full; dropping oldest message");
+            }
+            let ttl = self.config.outbound_message_ttl;
+            let held = HeldPublish {
+                payload,
+                retry,
+                trace_id,
+                receipt,
+            };
+            if let Err(err) = self.warmup_sender.try_send_with_ttl(held, ttl) {
+                tracing::warn!(target: "peer", %err, "failed to hold publish");
+                return;
+            }
+            tracing::debug!(target: "peer", "holding publish until topic is ready");
+            return;
+        }
+
+        self.publish_to_topic(PublishAttempt {
+            topic: self.gossipsub_topic.clone(),
+            payload,
+            retry,
+            attempt: 1,
+            nonce: None,
+            trace_id,
+            receipt,
+        });
+    }
+
+    /// Fails with an explanation when the node runs as an observer, which
+    /// must not do `what`.
+    fn refuse_in_observer_mode(&self, what: &str) -> Result<()> {
+        if self.config.observer {
+            return Err(anyhow!("{what} is disabled on observer nodes"));
+        }
+        Ok(())
+    }
+
+    /// Seals and publishes a payload right away, retrying later while no peer
+    /// can take it and the policy allows.
+    fn publish_to_topic(&mut self, mut publish: PublishAttempt) {
+        if let Err(err) = self.refuse_in_observer_mode("publishing") {
+            tracing::warn!(target: "peer", %err, topic = %publish.topic, "dropping publish");
+            if let Some(receipt) = publish.receipt.take() {
+                let _ = receipt.send(Err(err));
+            }
+            return;
+        }
+        if publish.trace_id.is_some() && self.replay_cache.is_none() {
+            let err =
+                anyhow!("trace ids travel in replay envelopes; set `replay_window` to publish one");
+            tracing::warn!(target: "peer", %err, "dropping publish");
+            if let Some(receipt) = publish.receipt.take() {
+                let _ = receipt.send(Err(err));
+            }
+            return;
+        }
+        if publish.nonce.is_none() && self.replay_cache.is_some() {
+            self.next_nonce = self.next_nonce.wrapping_add(1);
+            publish.nonce = Some(self.next_nonce);
+            if publish.trace_id.is_none() && self.config.trace_messages {
+                publish.trace_id = Some(TraceId::derive(
+                    &self.local_peer_id.to_bytes(),
+                    self.next_nonce,
+                ));
+            }
+        }
+        let _span = message_span(publish.trace_id).entered();
+        let payload = self.seal_envelope(publish.payload.clone(), publish.nonce, publish.trace_id);
+        let payload = match self.topic_ciphers.get(&publish.topic.hash()) {
+            Some(cipher) => match cipher.encrypt(&payload) {
+                Ok(ciphertext) => ciphertext,
+                Err(err) => {
+                    tracing::warn!(target: "peer", %err, "failed to publish message");
+                    self.audit_publish(
+                        &publish,
+                        AuditOutcome::Failed {
+                            reason: err.to_string(),
+                        },
+                    );
+                    if let Some(receipt) = publish.receipt.take() {
+                        let _ = receipt.send(Err(err));
+                    }
+                    return;
+                }
+            },
+            None => payload,
+        };
+        let result = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(publish.topic.clone(), payload);
+        match result {
+            Ok(message_id) => {
+                let receipt = self.publish_receipt(&publish, message_id);
+                self.audit_publish(
+                    &publish,
+                    AuditOutcome::Published {
+                        message_id: receipt.message_id.to_string(),
+                        forwarded_to: receipt.forwarded_to,
+                    },
+                );
+                tracing::info!(
+                    target: "peer",
+                    mesh_peers = receipt.mesh_peers,
+                    forwarded_to = receipt.forwarded_to,
+                    "published message"
+                );
+                if let Some(responder) = publish.receipt.take() {
+                    let _ = responder.send(Ok(receipt));
+                }
+            }
+            Err(
+                err @ (gossipsub::PublishError::NoPeersSubscribedToTopic
+                | gossipsub::PublishError::AllQueuesFull(_)),
+            ) if publish.retry.allows_retry(publish.attempt) => {
+                let backoff = publish.retry.backoff(publish.attempt);
+                tracing::debug!(target: "peer", %err, attempt = publish.attempt, ?backoff, "publish failed; retrying");
+                publish.attempt += 1;
+                self.schedule_retry(backoff, RetryTask::Publish(publish));
+            }
+            Err(err) => {
+                tracing::warn!(target: "peer", %err, "failed to publish message");
+                self.audit_publish(
+                    &publish,
+                    AuditOutcome::Failed {
+                        reason: err.to_string(),
+                    },
+                );
+                if let Some(receipt) = publish.receipt.take() {
+                    let _ = receipt.send(Err(anyhow!(
+                        "publish failed after {} attempt(s): {err}",
+                        publish.attempt
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Records the outcome of `publish` in the audit trail, when enabled.
+    fn audit_publish(&mut self, publish: &PublishAttempt, outcome: AuditOutcome) {
+        let Some(audit_log) = self.audit_log.as_ref() else {
+            return;
+        };
+        let topic = publish.topic.hash();
+        if let Err(err) = audit_log.record_publish(topic.as_str(), &publish.payload, &outcome) {
+            tracing::warn!(target: "peer", %err, "failed to record outbound message audit");
+        }
+    }
+
+    /// Estimates where the message just published for `publish` went, from
+    /// the gossipsub view of its topic.
+    fn publish_receipt(
+        &self,
+        publish: &PublishAttempt,
+        message_id: gossipsub::MessageId,
+    ) -> PublishReceipt {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let topic = publish.topic.hash();
+        let mesh_peers = gossipsub.mesh_peers(&topic).count();
+        let forwarded_to = if self.config.gossipsub.flood_publish.unwrap_or(true) {
+            gossipsub
+                .all_peers()
+                .filter(|(_, topics)| topics.contains(&&topic))
+                .count()
+        } else {
+            mesh_peers
+        };
+        PublishReceipt {
+            message_id,
+            mesh_peers,
+            forwarded_to,
+            attempts: publish.attempt,
+            trace_id: publish.trace_id,
+        }
+    }
+
+    /// Queues a failed operation for another attempt after `backoff`.
+    fn schedule_retry(&mut self, backoff: Duration, task: RetryTask) {
+        self.next_retry_id += 1;
+        self.retries
+            .insert((Instant::now() + backoff, self.next_retry_id), task);
+    }
+
+    /// Runs the retries whose backoff elapsed, in due order.
+    fn poll_retries(&mut self) {
+        let now = Instant::now();
+        while let Some(entry) = self.retries.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            match entry.remove() {
+                RetryTask::Dial(dial) => self.start_dial(dial),
+                RetryTask::Publish(publish) => self.publish_to_topic(publish),
+                RetryTask::Discovery(request) => self.start_discovery(request),
+                RetryTask::Providers(request) => self.start_provider_query(request),
+            }
+        }
+    }
+
+    /// Queues a publish for `at` and returns its id.
+    fn schedule_publish(
+        &mut self,
+        topic: gossipsub::IdentTopic,
+        payload: Vec<u8>,
+        at: Instant,
+        retry: RetryPolicy,
+    ) -> Result<u64> {
+        self.refuse_in_observer_mode("publishing")?;
+        if self.scheduled_publishes.len() >= MAX_SCHEDULED_PUBLISHES {
+            return Err(anyhow!(
+                "too many scheduled publishes (limit {MAX_SCHEDULED_PUBLISHES})"
+            ));
+        }
+        self.next_scheduled_publish_id += 1;
+        let id = self.next_scheduled_publish_id;
+        self.scheduled_publishes.insert(
+            (at, id),
+            ScheduledPublish {
+                topic,
+                payload,
+                retry,
+            },
+        );
+        self.scheduled_publish_times.insert(id, at);
+        Ok(id)
+    }
+
+    /// Publishes the scheduled messages whose time has come, in due order.
+    fn poll_scheduled_publishes(&mut self) {
+        let now = Instant::now();
+        while let Some(entry) = self.scheduled_publishes.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            let ((_, id), publish) = entry.remove_entry();
+            self.scheduled_publish_times.remove(&id);
+            tracing::debug!(target: "peer", id, topic = %publish.topic, "firing scheduled publish");
+            // The default topic honours the startup warm-up like any publish.
+            if publish.topic.hash() == self.gossipsub_topic.hash() {
+                self.publish_payload(publish.payload, publish.retry, None, None);
+            } else {
+                self.publish_to_topic(PublishAttempt {
+                    topic: publish.topic,
+                    payload: publish.payload,
+                    retry: publish.retry,
+                    attempt: 1,
+                    nonce: None,
+                    trace_id: None,
+                    receipt: None,
+                });
+            }
+        }
+    }
+
+    /// Processes a command and returns whether shutdown was requested
+    fn handle_command(&mut self, command: PeerCommand) -> Result<bool> {
+        match command {
+            PeerCommand::StartListening { address, responder } => {
+                if let Err(err) = self.config.validate_listen_address(&address) {
+                    tracing::error!(target: "peer", address = %self.redact.display(&address), err = %self.redact.display(&err), "failed to listen");
+                    let _ = responder.send(Err(anyhow!(self.redact.display(&err))));
+                    return Ok(false);
+                }
+                let result = match self.swarm.listen_on(address.clone()) {
+                    Ok(listener_id) => {
+                        tracing::info!(target: "peer", address = %self.redact.display(&address), "started listening");
+                        self.listen_addrs.push(address);
+                        Ok(listener_id)
+                    }
+                    Err(err) => {
+                        tracing::error!(target: "peer", address = %self.redact.display(&address), err = %self.redact.display(&err), "failed to listen");
+                        Err(anyhow!(
+                            "failed to listen on {}: {}",
+                            self.redact.display(&address),
+                            self.redact.display(&err)
+                        ))
+                    }
+                };
+                let _ = responder.send(result);
+                Ok(false)
+            }
+            PeerCommand::Connect { peer_id, responder } => {
+                self.start_connect(peer_id, responder);
+                Ok(false)
+            }
+            PeerCommand::DialWithTransport {
+                address,
+                transport,
+                retry,
+                responder,
+            } => {
+                if !transport.matches(&address) {
+                    let _ = responder.send(ConnectOutcome::Failed {
+                        reason: format!(
+                            "address {} does not use {transport:?}",
+                            self.redact.display(&address)
+                        ),
+                    });
+                    return Ok(false);
+                }
+                tracing::info!(target: "peer", address = %self.redact.display(&address), ?transport, "dialing remote over required transport");
+                self.start_dial(DialAttempt {
+                    target: DialTarget::Address(address),
+                    responder: Some(responder),
+                    request_id: None,
+                    retry,
+                    attempt: 1,
+                    started: None,
+                });
+                Ok(false)
+            }
+            PeerCommand::DialPeer {
+                peer_id,
+                transport,
+                request_id,
+                retry,
+                responder,
+            } => {
+                tracing::info!(target: "peer", peer_id = %self.redact.display(&peer_id), ?transport, ?request_id, "dialing peer");
+                self.start_dial(DialAttempt {
+                    target: DialTarget::Peer { peer_id, transport },
+                    responder: Some(responder),
+                    request_id,
+                    retry,
+                    attempt: 1,
+                    started: None,
+                });
+                Ok(false)
+            }
+            PeerCommand::ProbePeer {
+                peer_id,
+                count,
+                responder,
+            } => {
+                self.start_peer_probe(peer_id, count, responder);
+                Ok(false)
+            }
+            PeerCommand::ProbeAddress { address, responder } => {
+                tracing::debug!(target: "peer", address = %self.redact.display(&address), "probing address on demand");
+                if let Some(autonat) = self.swarm.behaviour_mut().autonat.as_mut() {
+                    autonat.probe_address(address.clone());
+                }
+                self.address_probes.push(AddressProbe {
+                    address,
+                    responder,
+                    probe_id: None,
+                    deadline: Instant::now() + ADDRESS_PROBE_TIMEOUT,
+                });
+                Ok(false)
+            }
+            PeerCommand::Dial {
+                address,
+                request_id,
+                retry,
+                responder,
+            } if request_id.is_some() || retry.max_attempts > 1 => {
+                tracing::info!(target: "peer", address = %self.redact.display(&address), ?request_id, "dialing remote");
+                self.start_dial(DialAttempt {
+                    target: DialTarget::Address(address),
+                    responder: None,
+                    request_id,
+                    retry,
+                    attempt: 1,
+                    started: Some(responder),
+                });
+                Ok(false)
+            }
+            PeerCommand::Dial {
+                address, responder, ..
+            } => {
+                if self.is_own_address(&address) {
+                    tracing::warn!(target: "peer", address = %self.redact.display(&address), "refusing to dial own address");
+                    let _ = responder.send(Err(anyhow!(
+                        "refusing to dial own address {}",
+                        self.redact.display(&address)
+                    )));
+                    return Ok(false);
+                }
+                let opts = DialOpts::unknown_peer_id().address(address.clone()).build();
+                let connection_id = opts.connection_id();
+                let result = match self.swarm.dial(opts) {
+                    Ok(()) => {
+                        tracing::info!(target: "peer", address = %self.redact.display(&address), "dialing remote");
+                        Ok(connection_id)
+                    }
+                    Err(err) => {
+                        tracing::error!(target: "peer", address = %self.redact.display(&address), err = %self.redact.display(&err), "failed to dial");
+                        Err(anyhow!(
+                            "failed to dial {}: {}",
+                            self.redact.display(&address),
+                            self.redact.display(&err)
+                        ))
+                    }
+                };
+                let _ = responder.send(result);
+                Ok(false)
+            }
+            PeerCommand::ReserveRelay {
+                mut address,
+                responder,
+            } => {
+                // This one should contain relay peerId
+                if let Some(peer_id) = extract_peer_id(&address) {
+                    self.relay_peer_id = Some(peer_id);
+                }
+
+                // ensure /p2p-circuit is present
+                let has_circuit = address.iter().any(|p| matches!(p, Protocol::P2pCircuit));
+                if !has_circuit {
+                    // Force it be a p2p-circuit (relay)
+                    address.push(Protocol::P2pCircuit);
+                }
+
+                // This one is a reservation itself
+                let result = match self.swarm.listen_on(address.clone()) {
+                    Ok(listener_id) => {
+                        tracing::info!(target: "peer", address = %self.redact.display(&address), "listening via relay");
+                        self.track_relay_reservation(listener_id, address.clone());
+                        self.listen_addrs.push(address);
+                        Ok(listener_id)
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            target: "peer",
+                            address = %self.redact.display(&address),
+                            err = %self.redact.display(&err),
+                            "failed to start relay reservation"
+                        );
+                        Err(anyhow!(
+                            "failed to start relay reservation on {}: {}",
+                            self.redact.display(&address),
+                            self.redact.display(&err)
+                        ))
+                    }
+                };
+                let _ = responder.send(result);
+
+                Ok(false)
+            }
+            PeerCommand::FindPeer {
+                peer_id,
+                request_id,
+                results,
+                retry,
+            } => {
+                self.start_discovery(DiscoveryRequest {
+                    request_id,
+                    target_peer_id: peer_id,
+                    kind: DiscoveryKind::FindPeer,
+                    peers_found: 0,
+                    results,
+                    deadline: Instant::now(),
+                    retry,
+                    attempt: 1,
+                    delegated: false,
+                });
+                Ok(false)
+            }
+            PeerCommand::GetClosestPeers {
+                peer_id,
+                request_id,
+                results,
+                retry,
+            } => {
+                self.start_discovery(DiscoveryRequest {
+                    request_id,
+                    target_peer_id: peer_id,
+                    kind: DiscoveryKind::GetClosestPeers,
+                    peers_found: 0,
+                    results,
+                    deadline: Instant::now(),
+                    retry,
+                    attempt: 1,
+                    delegated: false,
+                });
+                Ok(false)
+            }
+            PeerCommand::GetProviders {
+                key,
+                request_id,
+                results,
+                retry,
+                limits,
+            } => {
+                let now = Instant::now();
+                self.start_provider_query(ProviderRequest {
+                    request_id,
+                    key: kad::RecordKey::new(&key),
+                    providers: HashSet::new(),
+                    results,
+                    deadline: now,
+                    retry,
+                    attempt: 1,
+                    max_results: limits.max_results,
+                    limit_deadline: limits.deadline.map(|deadline| now + deadline),
+                    delegated: false,
+                });
+                Ok(false)
+            }
+            PeerCommand::PutRecord {
+                key,
+                value,
+                quorum,
+                request_id,
+                results,
+            } => {
+                self.start_put_record(key, value, quorum, request_id, results);
+                Ok(false)
+            }
+            PeerCommand::StartProviding {
+                key,
+                request_id,
+                results,
+            } => {
+                self.start_providing(key, request_id, results);
+                Ok(false)
+            }
+            PeerCommand::StopProviding { key, responder } => {
+                let key = kad::RecordKey::new(&key);
+                let provided = self.provided_keys.remove(&key);
+                if provided {
+                    self.swarm.behaviour_mut().kademlia.stop_providing(&key);
+                    tracing::info!(target: "peer", "stopped providing key");
+                }
+                let _ = responder.send(provided);
+                Ok(false)
+            }
+            PeerCommand::GetRecord {
+                key,
+                request_id,
+                results,
+            } => {
+                let key = kad::RecordKey::new(&key);
+                let query_id = self.swarm.behaviour_mut().kademlia.get_record(key.clone());
+                tracing::info!(target: "peer", ?query_id, request_id, "started get_record query");
+                self.record_queries.insert(
+                    query_id,
+                    RecordRequest {
+                        request_id,
+                        key,
+                        results,
+                        put: false,
+                        records_found: 0,
+                    },
+                );
+                Ok(false)
+            }
+            PeerCommand::GetClosestPeersLocal { peer_id, responder } => {
+                let key = kad::KBucketKey::from(peer_id);
+                let peers: Vec<PeerId> = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .get_closest_local_peers(&key)
+                    .take(kad::K_VALUE.get())
+                    .map(|key| *key.preimage())
+                    .collect();
+
+                tracing::debug!(
+                    target: "peer",
+                    peer_id = %self.redact.display(&peer_id),
+                    count = peers.len(),
+                    "answered local closest peers lookup"
+                );
+
+                if responder.send(peers).is_err() {
+                    tracing::debug!(target: "peer", "closest peers requester went away");
+                }
+                Ok(false)
+            }
+            PeerCommand::ListenViaRelay {
+                relay_peer,
+                relay_addr,
+                events,
+                responder,
+            } => {
+                let mut address = relay_addr;
+                if !matches!(address.iter().last(), Some(Protocol::P2p(peer)) if peer == relay_peer)
+                {
+                    address.push(Protocol::P2p(relay_peer));
+                }
+                address.push(Protocol::P2pCircuit);
+
+                self.relay_peer_id = Some(relay_peer);
+                match self.swarm.listen_on(address.clone()) {
+                    Ok(listener_id) => {
+                        tracing::info!(
+                            target: "peer",
+                            address = %self.redact.display(&address),
+                            "requested relay reservation"
+                        );
+                        self.listen_addrs.push(address.clone());
+                        self.track_relay_reservation(listener_id, address.clone());
+                        self.relay_listeners.insert(
+                            listener_id,
+                            RelayListener {
+                                address,
+                                relay_peer_id: relay_peer,
+                                events,
+                            },
+                        );
+                        let _ = responder.send(Ok(listener_id));
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            target: "peer",
+                            address = %self.redact.display(&address),
+                            err = %self.redact.display(&err),
+                            "failed to start relay reservation"
+                        );
+                        let reason = self.redact.display(&err);
+                        let _ = events.try_send(RelayReservationEvent::Closed {
+                            relay_peer_id: relay_peer,
+                            reason: Some(reason.clone()),
+                        });
+                        let _ = responder.send(Err(anyhow!(
+                            "failed to start relay reservation on {}: {reason}",
+                            self.redact.display(&address)
+                        )));
+                    }
+                }
+                Ok(false)
+            }
+            PeerCommand::PushIdentify { responder } => {
+                let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+                tracing::debug!(target: "peer", peers = peers.len(), "pushing identify info");
+                let pushed = peers.len();
+                self.swarm.behaviour_mut().identify.push(peers);
+                let _ = responder.send(Ok(pushed));
+                Ok(false)
+            }
+            PeerCommand::Publish {
+                payload,
+                retry,
+                trace_id,
+                receipt,
+            } => {
+                self.publish_payload(payload, retry, trace_id, receipt);
+                Ok(false)
+            }
+            PeerCommand::PublishTo {
+                topic,
+                payload,
+                retry,
+                responder,
+            } => {
+                if topic.hash() == self.gossipsub_topic.hash() {
+                    // Goes through the warm-up like any default topic publish.
+                    self.publish_payload(payload, retry, None, Some(responder));
+                } else if self.groups.contains_key(&topic.hash())
+                    || topic.to_string().starts_with(GROUP_TOPIC_PREFIX)
+                {
+                    let _ = responder.send(Err(anyhow!(
+                        "topic {topic} belongs to a group; publish to the group instead"
+                    )));
+                } else {
+                    self.publish_to_topic(PublishAttempt {
+                        topic,
+                        payload,
+                        retry,
+                        attempt: 1,
+                        nonce: None,
+                        trace_id: None,
+                        receipt: Some(responder),
+                    });
+                }
+                Ok(false)
+            }
+            PeerCommand::Subscribe { topic, responder } => {
+                let _ = responder.send(self.subscribe_topic(topic));
+                Ok(false)
+            }
+            PeerCommand::Unsubscribe { topic, responder } => {
+                let _ = responder.send(self.unsubscribe_topic(&topic));
+                Ok(false)
+            }
+            PeerCommand::SchedulePublish {
+                topic,
+                payload,
+                at,
+                retry,
+                responder,
+            } => {
+                let scheduled = self.schedule_publish(topic, payload, at, retry);
+                if let Ok(id) = &scheduled {
+                    let delay_ms = at.saturating_duration_since(Instant::now()).as_millis();
+                    tracing::debug!(target: "peer", id, delay_ms, "scheduled publish");
+                }
+                let _ = responder.send(scheduled);
+                Ok(false)
+            }
+            PeerCommand::TakeOutboundDeadLetters { responder } => {
+                let dead_letters = self
+                    .warmup_publishes
+                    .take_dead_letters()
+                    .into_iter()
+                    .map(|dead_letter| DeadLetter {
+                        event: dead_letter.event.payload,
+                        queued_for: dead_letter.queued_for,
+                    })
+                    .collect();
+                let _ = responder.send(dead_letters);
+                Ok(false)
+            }
+            PeerCommand::CancelScheduledPublish { id, responder } => {
+                let cancelled = match self.scheduled_publish_times.remove(&id) {
+                    Some(at) => self.scheduled_publishes.remove(&(at, id)).is_some(),
+                    None => false,
+                };
+                tracing::debug!(target: "peer", id, cancelled, "cancel scheduled publish");
+                let _ = responder.send(cancelled);
+                Ok(false)
+            }
+            PeerCommand::SetPeerPinned {
+                peer_id,
+                pinned,
+                responder,
+            } => {
+                self.swarm
+                    .behaviour_mut()
+                    .keep_alive
+                    .set_pinned(peer_id, pinned);
+                // Recorded in the config so a swarm rebuild keeps the pin.
+                self.config
+                    .pinned_peers
+                    .retain(|pinned_peer| *pinned_peer != peer_id);
+                if pinned {
+                    self.config.pinned_peers.push(peer_id);
+                }
+                tracing::debug!(target: "peer", peer_id = %self.redact.display(&peer_id), pinned, "peer pin updated");
+                let _ = responder.send(Ok(()));
+                Ok(false)
+            }
+            PeerCommand::ImportBlocklist { update, responder } => {
+                let _ = responder.send(self.import_blocklist(update));
+                Ok(false)
+            }
+            PeerCommand::NetworkTimeOffset { responder } => {
+                let _ = responder.send(self.clock_offsets.median_offset_ms());
+                Ok(false)
+            }
+            PeerCommand::ExportKeypair { responder } => {
+                let encoded = self
+                    .keypair
+                    .to_protobuf_encoding()
+                    .map_err(|err| anyhow!("failed to encode keypair: {err}"));
+                let _ = responder.send(encoded);
+                Ok(false)
+            }
+            PeerCommand::ExportPeerRecord { peer_id, responder } => {
+                let _ = responder.send(self.export_peer_record(peer_id));
+                Ok(false)
+            }
+            PeerCommand::ImportPeerRecord { record, responder } => {
+                let _ = responder.send(self.import_peer_record(&record));
+                Ok(false)
+            }
+            PeerCommand::ImportPeerRecords { bytes, responder } => {
+                let _ = responder.send(self.import_peer_records(&bytes));
+                Ok(false)
+            }
+            PeerCommand::Blocklist { responder } => {
+                let _ = responder.send(self.config.blocklist.clone());
+                Ok(false)
+            }
+            PeerCommand::SetPowerProfile { profile, responder } => {
+                let _ = responder.send(self.set_power_profile(profile));
+                Ok(false)
+            }
+            PeerCommand::NetworkChanged { change, responder } => {
+                let _ = responder.send(self.handle_network_change(change));
+                Ok(false)
+            }
+            PeerCommand::SetAutonatServer { enabled, responder } => {
+                let result = if enabled {
+                    self.refuse_in_observer_mode("the autonat server role")
+                } else {
+                    Ok(())
+                };
+                if result.is_ok() {
+                    self.autonat_server_enabled = enabled;
+                    self.swarm.behaviour_mut().autonat.set_open(enabled);
+                    tracing::info!(target: "peer", enabled, "autonat server role switched");
+                }
+                let _ = responder.send(result);
+                Ok(false)
+            }
+            PeerCommand::SetRelayServer { enabled, responder } => {
+                // The low-power profile keeps the role closed until it is left.
+                let open = enabled && !self.config.power_profile.is_low_power();
+                let relay_server = &mut self.swarm.behaviour_mut().relay_server;
+                let result = if relay_server.is_configured() {
+                    relay_server.set_open(open);
+                    self.relay_server_enabled = enabled;
+                    tracing::info!(target: "peer", enabled, "relay server role switched");
+                    Ok(())
+                } else {
+                    Err(anyhow!(
+                        "relay server is not configured; start the node with hop_relay"
+                    ))
+                };
+                let _ = responder.send(result);
+                Ok(false)
+            }
+            PeerCommand::ReloadConfig { config, responder } => {
+                let _ = responder.send(self.reload_config(*config));
+                Ok(false)
+            }
+            PeerCommand::ObservedAddresses { responder } => {
+                let _ = responder.send(self.observed_addrs.snapshot());
+                Ok(false)
+            }
+            PeerCommand::Subscriptions { responder } => {
+                let gossipsub = &self.swarm.behaviour().gossipsub;
+                let mut topic_peers: HashMap<&gossipsub::TopicHash, usize> = HashMap::new();
+                for (_, topics) in gossipsub.all_peers() {
+                    for topic in topics {
+                        *topic_peers.entry(topic).or_default() += 1;
+                    }
+                }
+                let subscriptions = gossipsub
+                    .topics()
+                    .map(|topic| TopicSubscription {
+                        topic: topic.to_string(),
+                        mesh_peers: gossipsub.mesh_peers(topic).count(),
+                        topic_peers: topic_peers.get(topic).copied().unwrap_or_default(),
+                    })
+                    .collect();
+                if responder.send(subscriptions).is_err() {
+                    tracing::debug!(target: "peer", "subscriptions requester went away");
+                }
+                Ok(false)
+            }
+            PeerCommand::JoinGroup { group, responder } => {
+                let _ = responder.send(self.join_group(group));
+                Ok(false)
+            }
+            PeerCommand::LeaveGroup { group, responder } => {
+                let _ = responder.send(self.leave_group(&group));
+                Ok(false)
+            }
+            PeerCommand::PublishToGroup {
+                group,
+                payload,
+                retry,
+                responder,
+            } => {
+                let hash = gossipsub::IdentTopic::new(group_topic(&group)).hash();
+                let result = match self.groups.get(&hash) {
+                    Some(joined) => {
+                        let topic = joined.topic.clone();
+                        self.publish_group_frame(topic, GroupFrame::Message(payload), retry);
+                        Ok(())
+                    }
+                    None => Err(anyhow!("not a member of group {group}")),
+                };
+                let _ = responder.send(result);
+                Ok(false)
+            }
+            PeerCommand::SetSubscriptions { topics, responder } => {
+                let _ = responder.send(self.set_subscriptions(topics));
+                Ok(false)
+            }
+            PeerCommand::GroupMembers { group, responder } => {
+                let hash = gossipsub::IdentTopic::new(group_topic(&group)).hash();
+                let members = self
+                    .groups
+                    .get(&hash)
+                    .map(|joined| joined.members.keys().copied().collect())
+                    .ok_or_else(|| anyhow!("not a member of group {group}"));
+                let _ = responder.send(members);
+                Ok(false)
+            }
+            PeerCommand::WaitMeshReady {
+                topic,
+                min_peers,
+                responder,
+            } => {
+                self.mesh_waiters.push(MeshWaiter {
+                    topic,
+                    min_peers,
+                    responder,
+                });
+                self.poll_mesh_waiters();
+                Ok(false)
+            }
+            PeerCommand::Shutdown => {
+                tracing::info!(target: "peer", "shutdown requested");
+                Ok(true)
+            }
+        }
+    }
+
+    /// Logging and reacting to events coming from the swarm (peer orchestrator)
+    fn handle_swarm_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
+        self.last_swarm_event = Instant::now();
+        match event {
+            SwarmEvent::Behaviour(event) => self.handle_behaviour_event(event),
+
+            SwarmEvent::NewListenAddr { address, .. } => {
+                tracing::info!(target: "peer", address = %self.redact.display(&address), "listening on new address");
+                self.listener_failures = 0;
+
+                self.emit_addr_event(AddrEvent::ListenerAdded {
+                    address: address.clone(),
+                });
+
+                self.update_relay_address(address);
+            }
+
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
+            } => {
+                let address = endpoint.get_remote_address().clone();
+                self.connection_metrics.opened(
+                    connection_id,
+                    peer_id,
+                    &address,
+                    transport_address(&endpoint),
+                    endpoint.is_dialer(),
+                );
+                let (expected_security, expected_muxer) = expected_connection_stack(&address);
+                tracing::info!(
+                    target: "peer",
+                    peer_id = %self.redact.display(&peer_id),
+                    expected_security,
+                    expected_muxer,
+                    "connection established"
+                );
+                if self.bootstrap_query.is_some()
+                    && self
+                        .bootstrap_peers
+                        .iter()
+                        .any(|bootstrap| extract_peer_id(bootstrap) == Some(peer_id))
+                {
+                    self.bootstrap_reached = true;
+                }
+
+                let outcome = if is_relayed(&address) {
+                    ConnectOutcome::Relayed {
+                        address: address.clone(),
+                    }
+                } else {
+                    ConnectOutcome::Direct {
+                        address: address.clone(),
+                    }
+                };
+                self.resolve_dial(connection_id, outcome);
+                if is_relayed(&address) {
+                    self.emit_network_event(NetworkEvent::HolePunch(HolePunchEvent::Initiated {
+                        peer_id,
+                        relayed_address: address.clone(),
+                    }));
+                    if let Some(pending) = self.pending_connects.get_mut(&peer_id) {
+                        pending.relayed = Some(address.clone());
+                        pending.deadline = Instant::now() + CONNECT_UPGRADE_TIMEOUT;
+                    }
+                } else {
+                    self.resolve_connect(
+                        &peer_id,
+                        ConnectOutcome::Direct {
+                            address: address.clone(),
+                        },
+                    );
+                }
+                if !is_relayed(&address) {
+                    self.direct_connections
+                        .entry(peer_id)
+                        .or_default()
+                        .push((connection_id, endpoint.is_dialer()));
+                }
+                self.connection_addrs
+                    .insert(connection_id, (peer_id, address));
+                self.dedup_connections(peer_id);
+            }
+
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                endpoint,
+                num_established,
+                cause,
+                ..
+            } => {
+                self.connection_addrs.remove(&connection_id);
+                self.forget_direct_connection(&peer_id, connection_id);
+                self.connection_metrics.closed(
+                    connection_id,
+                    transport_address(&endpoint),
+                    endpoint.is_dialer(),
+                );
+                self.reported_connections.remove(&connection_id);
+                if num_established == 0 {
+                    self.relay_metrics.peer_disconnected(&peer_id);
+                    self.gossip_rates.peer_disconnected(&peer_id);
+                    self.exchange_addresses.remove(&peer_id);
+                    self.clock_offsets.remove(&peer_id);
+                    self.observed_addrs.forget(&peer_id);
+                    self.apply_observed_addr_changes();
+                }
+                if let Some(error) = &cause {
+                    tracing::warn!(target: "peer", peer_id = %self.redact.display(&peer_id), error = %self.redact.display(error), "connection closed with error");
+                } else {
+                    tracing::info!(target: "peer", peer_id = %self.redact.display(&peer_id), "connection closed");
+                }
+                self.emit_network_event(NetworkEvent::ConnectionClosed(ConnectionClosedInfo {
+                    peer_id,
+                    address: endpoint.get_remote_address().clone(),
+                    remaining: num_established,
+                    error: cause.map(|error| error.to_string()),
+                }));
+            }
+
+            SwarmEvent::IncomingConnection { send_back_addr, .. } => {
+                tracing::debug!(target: "peer", send_back_addr = %self.redact.display(&send_back_addr), "incoming connection");
+            }
+
+            SwarmEvent::IncomingConnectionError {
+                send_back_addr,
+                error,
+                ..
+            } => {
+                tracing::warn!(target: "peer", send_back_addr = %self.redact.display(&send_back_addr), error = %self.redact.display(&error), "incoming connection error");
+            }
+
+            SwarmEvent::NewExternalAddrCandidate { address } => {
+                tracing::info!(target: "peer", address = %self.redact.display(&address), "new external address candidate");
+            }
+
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                tracing::info!(target: "peer", address = %self.redact.display(&address), "external address confirmed");
+
+                self.emit_addr_event(AddrEvent::ExternalConfirmed {
+                    address: address.clone(),
+                });
+
+                self.update_relay_address(address);
+            }
+
+            SwarmEvent::ExternalAddrExpired { address } => {
+                tracing::warn!(target: "peer", address = %self.redact.display(&address), "external address expired");
+                self.clear_relay_address(&address);
+
+                self.emit_addr_event(AddrEvent::ExternalExpired {
+                    address: address.clone(),
+                });
+            }
+
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                addresses,
+                reason,
+            } => {
+                tracing::warn!(target: "peer", addresses = %self.redact.debug(&addresses), ?reason, "listener closed");
+
+                if let Some(relay_peer_id) = self.relay_of_listener(listener_id) {
+                    self.notify_relay_listener(
+                        listener_id,
+                        RelayReservationEvent::Closed {
+                            relay_peer_id,
+                            reason: reason.as_ref().err().map(|err| self.redact.display(err)),
+                        },
+                    );
+                    if let Some(listener) = self.relay_listeners.remove(&listener_id) {
+                        self.forget_listen_address(&listener.address);
+                    }
+                    let closed_reservation = self
+                        .relay_reservations
+                        .get(&relay_peer_id)
+                        .is_some_and(|reservation| reservation.listener_id == listener_id);
+                    if closed_reservation {
+                        if let Some(reservation) = self.relay_reservations.remove(&relay_peer_id) {
+                            self.forget_listen_address(&reservation.address);
+                        }
+                    }
+                } else if reason.is_err() && !addresses.iter().any(is_relayed) {
+                    self.listener_failures += 1;
+                }
+
+                // ListenerClosed can contain multiple addresses. Emit removal for each.
+                for address in addresses {
+                    self.emit_addr_event(AddrEvent::ListenerRemoved { address });
+                }
+            }
+
+            SwarmEvent::ListenerError { listener_id, error } => {
+                tracing::error!(target: "peer", error = %self.redact.display(&error), "listener error");
+
+                if let Some(relay_peer_id) = self.relay_of_listener(listener_id) {
+                    let event =
+                        self.relay_failure_event(relay_peer_id, self.redact.display(&error));
+                    self.notify_relay_listener(listener_id, event);
+                } else {
+                    self.listener_failures += 1;
+                }
+            }
+
+            SwarmEvent::OutgoingConnectionError {
+                connection_id,
+                peer_id,
+                error,
+            } => {
+                tracing::warn!(target: "peer", peer_id = %self.redact.debug(&peer_id), error = %self.redact.display(&error), "outgoing connection error");
+
+                // Explicit dials must not silently switch to a relay circuit.
+                let explicit = self.resolve_dial(
+                    connection_id,
+                    ConnectOutcome::Failed {
+                        reason: self.redact.display(&error),
+                    },
+                );
+                if explicit {
+                    return;
+                }
+                if let Some(peer_id) = peer_id {
+                    let relay_dialed = self.try_dial_via_relay(&peer_id, &error);
+                    let connected = self.swarm.is_connected(&peer_id);
+                    if !relay_dialed && !connected {
+                        self.resolve_connect(
+                            &peer_id,
+                            ConnectOutcome::Failed {
+                                reason: self.redact.display(&error),
+                            },
+                        );
+                    }
+                }
+            }
+            
+            _ => {}
+        }
+    }
+
+    /// Handles events from additional network's features
+    fn handle_behaviour_event(&mut self, event: BehaviourEvent) {
+        match event {
+            BehaviourEvent::Kademlia(event) => {
+                self.handle_kademlia_event(event);
+            }
+
+            BehaviourEvent::Probe(event) => self.resolve_peer_probe(event),
+            BehaviourEvent::TimeSync(event) => self.record_clock_sample(event),
+
+            BehaviourEvent::Ping(event) => match event.result {
+                Ok(rtt) => {
+                    tracing::debug!(target: "peer", ?rtt, "ping success");
+                }
+                Err(error) => {
+                    tracing::warn!(target: "peer", error = %self.redact.display(&error), "ping failure");
+                }
+            },
+
+            BehaviourEvent::Identify(event) => {
+                tracing::debug!(target: "peer", event = %self.redact.debug(&event), "identify event");
+
+                match event {
+                    identify::Event::Received {
+                        connection_id,
+                        peer_id,
+                        info,
+                        ..
+                    } => {
+                        if self.reject_incompatible(connection_id, peer_id, &info) {
+                            return;
+                        }
+                        // Observations over a circuit describe the relay's view.
+                        if !is_relayed(&info.observed_addr) {
+                            self.observed_addrs
+                                .observe(peer_id, info.observed_addr.clone());
+                            self.apply_observed_addr_changes();
+                        }
+                        if info.protocols.contains(&kad::PROTOCOL_NAME) {
+                            self.exchange_addresses
+                                .insert(peer_id, info.listen_addrs.clone());
+                        }
+                        if let Some(envelope) = info.signed_peer_record.clone() {
+                            self.remember_peer_record(peer_id, envelope);
+                        }
+                        if self.config.time_sync && info.protocols.contains(&TIME_PROTOCOL) {
+                            if let Some(time_sync) = self.swarm.behaviour_mut().time_sync.as_mut() {
+                                time_sync.sample(peer_id);
+                            }
+                        }
+                        let protocols = info.protocols.iter().map(|p| p.to_string()).collect();
+                        self.report_connection(connection_id, protocols, Some(info.agent_version));
+                    }
+                    identify::Event::Error { connection_id, .. } => {
+                        self.report_connection(connection_id, Vec::new(), None);
+                    }
+                    _ => {}
+                }
+            }
+
+            BehaviourEvent::Gossipsub(event) => match event {
+                gossipsub::Event::Message {
+                    message,
+                    propagation_source,
+                    message_id,
+                } => {
+                    tracing::info!(target: "peer", propagation_source = %self.redact.display(&propagation_source), len = message.data.len(), "received gossipsub message");
+                    self.handle_inbound_message(message, message_id, propagation_source);
+                }
+                gossipsub::Event::Subscribed { topic, .. } => {
+                    // Tell the newcomer who is already in the group.
+                    if let Some(group) = self.groups.get(&topic) {
+                        let (topic, roster) = (group.topic.clone(), group.roster());
+                        self.publish_group_frame(topic, roster, RetryPolicy::none());
+                    }
+                }
+                gossipsub::Event::Unsubscribed { peer_id, topic } => {
+                    self.remove_group_member(&topic, peer_id);
+                }
+                _ => {}
+            },
+
+            BehaviourEvent::Autonat(event) => {
+                tracing::debug!(target:"peer", event = %self.redact.debug(&event), "autonat event");
+
+                match event {
+                    // A guard would update the status whenever it is evaluated.
+                    #[allow(clippy::collapsible_match)]
+                    autonat::Event::StatusChanged { new, .. } => {
+                        if self.autonat_status.send(new).is_err() {
+                            tracing::trace!(
+                                target: "peer",
+                                "autonat status receiver dropped; skipping update"
+                            );
+                        }
+                    }
+                    autonat::Event::OutboundProbe(autonat::OutboundProbeEvent::Request {
+                        probe_id,
+                        ..
+                    }) => {
+                        for probe in &mut self.address_probes {
+                            probe.probe_id.get_or_insert(probe_id);
+                        }
+                    }
+                    autonat::Event::OutboundProbe(autonat::OutboundProbeEvent::Response {
+                        probe_id,
+                        address,
+                        ..
+                    }) => {
+                        self.resolve_address_probes(probe_id, |candidate| {
+                            if *candidate == address {
+                                AddressProbeOutcome::Reachable
+                            } else {
+                                AddressProbeOutcome::Inconclusive {
+                                    reason: format!("server confirmed another address: {address}"),
+                                }
+                            }
+                        });
+                    }
+                    autonat::Event::OutboundProbe(autonat::OutboundProbeEvent::Error {
+                        probe_id,
+                        error,
+                        ..
+                    }) => {
+                        let reason = format!("{error:?}");
+                        let unreachable = matches!(
+                            error,
+                            autonat::OutboundProbeError::Response(
+                                autonat::ResponseError::DialError
+                            )
+                        );
+                        self.resolve_address_probes(probe_id, |_| {
+                            if unreachable {
+                                AddressProbeOutcome::Unreachable {
+                                    reason: reason.clone(),
+                                }
+                            } else {
+                                AddressProbeOutcome::Inconclusive {
+                                    reason: reason.clone(),
+                                }
+                            }
+                        });
+                    }
+                    _ => {}
+                }
+            }
+
+            BehaviourEvent::RelayClient(event) => match event {
+                relay::client::Event::ReservationReqAccepted {
+                    relay_peer_id,
+                    renewal,
+                    limit,
+                } => {
+                    self.relay_peer_id = Some(relay_peer_id);
+                    tracing::info!(
+                        target: "peer",
+                        relay_id = %self.redact.display(&relay_peer_id),
+                        renewal,
+                        ?limit,
+                        "relay reservation accepted",
+                    );
+
+                    if let Some(reservation) = self.relay_reservations.get_mut(&relay_peer_id) {
+                        reservation.expires_at =
+                            Some(Instant::now() + self.config.relay_reservation_ttl);
+                        reservation.renewal_requested = false;
+                    }
+                    for listener in self.relay_listeners.values() {
+                        if listener.relay_peer_id == relay_peer_id {
+                            let _ = listener.events.try_send(RelayReservationEvent::Accepted {
+                                relay_peer_id,
+                                renewal,
+                            });
+                        }
+                    }
+                    self.emit_network_event(NetworkEvent::RelayReservation(
+                        RelayReservationEvent::Accepted {
+                            relay_peer_id,
+                            renewal,
+                        },
+                    ));
+
+                    if !renewal {
+                        self.advertise_relay_circuit(relay_peer_id);
+                    }
+                }
+
+                relay::client::Event::OutboundCircuitEstablished { relay_peer_id, .. } => {
+                    tracing::info!(
+                        target: "peer",
+                        relay_id = %self.redact.display(&relay_peer_id),
+                        "outbound circuit established",
+                    );
+                }
+
+                other => {
+                    tracing::debug!(target: "peer", other = %self.redact.debug(&other), "relay client event");
+                }
+            },
+
+            BehaviourEvent::Dcutr(dcutr::Event {
+                remote_peer_id,
+                result,
+            }) => {
+                let event = match result {
+                    Ok(connection_id) => {
+                        let address = self
+                            .connection_addrs
+                            .get(&connection_id)
+                            .map(|(_, address)| address.clone());
+                        tracing::info!(
+                            target: "peer",
+                            peer_id = %self.redact.display(&remote_peer_id),
+                            address = %self.redact.debug(&address),
+                            "hole punch succeeded"
+                        );
+                        HolePunchEvent::Succeeded {
+                            peer_id: remote_peer_id,
+                            address,
+                        }
+                    }
+                    Err(error) => {
+                        tracing::warn!(
+                            target: "peer",
+                            peer_id = %self.redact.display(&remote_peer_id),
+                            error = %self.redact.display(&error),
+                            "hole punch failed"
+                        );
+                        HolePunchEvent::Failed {
+                            peer_id: remote_peer_id,
+                            reason: error.to_string(),
+                        }
+                    }
+                };
+
+                // A successful upgrade already resolved pending connects when the
+                // direct connection was established; a failed one leaves them relayed.
+                if let HolePunchEvent::Failed { peer_id, .. } = &event {
+                    let relayed = self
+                        .pending_connects
+                        .get(peer_id)
+                        .and_then(|pending| pending.relayed.clone());
+                    if let Some(address) = relayed {
+                        self.resolve_connect(peer_id, ConnectOutcome::Relayed { address });
+                    }
+                }
+                self.emit_network_event(NetworkEvent::HolePunch(event));
+            }
+
+            BehaviourEvent::RelayServer(event) => {
+                self.relay_metrics.record(&event);
+                match &event {
+                    relay::Event::ReservationReqDenied { .. }
+                    | relay::Event::CircuitReqDenied { .. } => {
+                        tracing::info!(target: "peer", event = %self.redact.debug(&event), "relay server request denied");
+                    }
+                    _ => {
+                        tracing::debug!(target: "peer", event = %self.redact.debug(&event), "relay server event");
+                    }
+                }
+            }
+
+            BehaviourEvent::RendezvousClient(event) => {
+                tracing::info!(target: "peer", event = %self.redact.debug(&event), "rendezvous client event");
+            }
+
+            BehaviourEvent::RendezvousServer(event) => match event {
+                rendezvous::server::Event::PeerRegistered { peer, registration } => {
+                    tracing::info!(
+                        target: "peer",
+                        peer_id = %self.redact.display(&peer),
+                        namespace = %registration.namespace,
+                        ttl = registration.ttl,
+                        "rendezvous registration stored"
+                    );
+                }
+                rendezvous::server::Event::PeerNotRegistered {
+                    peer,
+                    namespace,
+                    error,
+                } => {
+                    tracing::warn!(
+                        target: "peer",
+                        peer_id = %self.redact.display(&peer),
+                        %namespace,
+                        ?error,
+                        "rendezvous registration refused"
+                    );
+                }
+                rendezvous::server::Event::RegistrationExpired(registration) => {
+                    tracing::debug!(
+                        target: "peer",
+                        namespace = %registration.namespace,
+                        "rendezvous registration expired"
+                    );
                 }
+                other => {
+                    tracing::debug!(target: "peer", event = %self.redact.debug(&other), "rendezvous server event");
+                }
+            },
+        }
+    }
+
+    /// Verifies an inbound gossip message, reports the verdict to gossipsub and
+    /// enqueues accepted payloads for the consumer.
+    fn handle_inbound_message(
+        &mut self,
+        message: gossipsub::Message,
+        message_id: gossipsub::MessageId,
+        propagation_source: PeerId,
+    ) {
+        let paused = self.consumer_watch.slow
+            && self
+                .config
+                .slow_consumer
+                .is_some_and(|settings| settings.pause_gossip);
+        if paused {
+            tracing::debug!(target: "peer", %message_id, "ignored inbound message while consumer is slow");
+            self.swarm
+                .behaviour_mut()
+                .gossipsub
+                .report_message_validation_result(
+                    &message_id,
+                    &propagation_source,
+                    gossipsub::MessageAcceptance::Ignore,
+                );
+            return;
+        }
+
+        let opened = self
+            .decrypt_topic_payload(&message.topic, message.data)
+            .and_then(|data| self.open_envelope(data, message.source, propagation_source));
+        let (result, payload, trace_id) = match opened {
+            Ok((payload, trace_id)) => {
+                let result = match &self.message_verifier {
+                    Some(verifier) => verifier.verify(&payload, message.source.as_ref()),
+                    None => VerificationResult::Accept,
+                };
+                (result, payload, trace_id)
+            }
+            Err(result) => (result, Vec::new(), None),
+        };
+        // Logs about this message carry the publisher's trace id from here on.
+        let _span = message_span(trace_id).entered();
+
+        // Observers keep accepted messages to themselves instead of forwarding them.
+        let acceptance = match result {
+            VerificationResult::Accept if self.config.observer => {
+                gossipsub::MessageAcceptance::Ignore
             }
+            result => result.into(),
+        };
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .report_message_validation_result(&message_id, &propagation_source, acceptance);
+
+        match result {
+            VerificationResult::Accept if self.groups.contains_key(&message.topic) => {
+                self.record_gossip(propagation_source, GossipVerdict::Delivered);
+                self.handle_group_frame(&message.topic, message.source, &payload);
+            }
+            VerificationResult::Accept => {
+                self.record_gossip(propagation_source, GossipVerdict::Delivered);
+                let ttl = self.config.inbound_message_ttl;
+                let message = RoutedMessage {
+                    topic: message.topic.into_string(),
+                    source: message.source,
+                    trace_id,
+                    payload,
+                };
+                if self.topic_router.route(&message, ttl) > 0 {
+                    return;
+                }
+                self.consumer_watch.offered += 1;
+                if let Err(err) = self.inbound_sender.try_enqueue_with_ttl(message, ttl) {
+                    self.consumer_watch.dropped += 1;
+                    tracing::warn!(target: "peer", %err, "failed to enqueue inbound message");
+                }
+            }
+            VerificationResult::Reject => {
+                tracing::warn!(
+                    target: "peer",
+                    propagation_source = %self.redact.display(&propagation_source),
+                    author = %self.redact.debug(&message.source),
+                    %message_id,
+                    "rejected inbound message"
+                );
+                self.record_gossip(propagation_source, GossipVerdict::Invalid);
+                self.penalize_invalid_message(propagation_source);
+            }
+            VerificationResult::Ignore => {
+                tracing::debug!(target: "peer", %message_id, "ignored inbound message");
+            }
+        }
+    }
+
+    /// Subscribes to the topic of `name` and announces the local node.
+    fn join_group(&mut self, name: String) -> Result<()> {
+        // Members announce themselves to the group.
+        self.refuse_in_observer_mode("joining groups")?;
+        let topic = gossipsub::IdentTopic::new(group_topic(&name));
+        if self.groups.contains_key(&topic.hash()) {
+            return Ok(());
         }
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&topic)
+            .map_err(|err| anyhow!("failed to subscribe to group {name}: {err}"))?;
+        tracing::info!(target: "peer", group = %name, "joined group");
+        self.groups.insert(
+            topic.hash(),
+            Group {
+                name,
+                topic: topic.clone(),
+                members: HashMap::new(),
+                departed: HashMap::new(),
+            },
+        );
+        // The topic mesh is still forming, so the announcement is retried.
+        let retry = RetryPolicy::exponential(GROUP_JOIN_ATTEMPTS, Duration::from_secs(1));
+        self.publish_group_frame(topic, GroupFrame::Join, retry);
         Ok(())
     }
 
-    /// Processes a command and returns whether shutdown was requested
-    fn handle_command(&mut self, command: PeerCommand) -> Result<bool> {
-        match command {
-            PeerCommand::StartListening(address) => {
-                match self.swarm.listen_on(address.clone()) {
-                    Ok(_) => tracing::info!(target: "peer", %address, "started listening"),
-                    Err(err) => tracing::error!(target: "peer", %address, %err, "failed to listen"),
+    /// Announces leaving `name` and unsubscribes from its topic.
+    fn leave_group(&mut self, name: &str) -> Result<()> {
+        let hash = gossipsub::IdentTopic::new(group_topic(name)).hash();
+        let group = self
+            .groups
+            .remove(&hash)
+            .ok_or_else(|| anyhow!("not a member of group {name}"))?;
+        // Published before unsubscribing, while the mesh still carries it.
+        self.publish_group_frame(group.topic.clone(), GroupFrame::Leave, RetryPolicy::none());
+        let _ = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .unsubscribe(&group.topic);
+        tracing::info!(target: "peer", group = %name, "left group");
+        Ok(())
+    }
+
+    /// Subscribes to `name` next to the default topic; answers whether it was
+    /// not subscribed yet.
+    fn subscribe_topic(&mut self, name: String) -> Result<bool> {
+        validate_topic(&name)?;
+        let topic = gossipsub::IdentTopic::new(name);
+        if topic.hash() == self.gossipsub_topic.hash() || self.topics.contains_key(&topic.hash()) {
+            return Ok(false);
+        }
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&topic)
+            .map_err(|err| anyhow!("failed to subscribe to topic {topic}: {err}"))?;
+        tracing::info!(target: "peer", %topic, "subscribed to topic");
+        self.topics.insert(topic.hash(), topic);
+        Ok(true)
+    }
+
+    /// Unsubscribes from a topic subscribed next to the default one; answers
+    /// whether it was subscribed.
+    fn unsubscribe_topic(&mut self, name: &str) -> Result<bool> {
+        if name.starts_with(GROUP_TOPIC_PREFIX) {
+            return Err(anyhow!(
+                "topic {name} is reserved for groups; leave the group instead"
+            ));
+        }
+        let hash = gossipsub::IdentTopic::new(name).hash();
+        if hash == self.gossipsub_topic.hash() {
+            return Err(anyhow!("cannot unsubscribe from the default topic {name}"));
+        }
+        let Some(topic) = self.topics.remove(&hash) else {
+            return Ok(false);
+        };
+        let _ = self.swarm.behaviour_mut().gossipsub.unsubscribe(&topic);
+        tracing::info!(target: "peer", %topic, "unsubscribed from topic");
+        Ok(true)
+    }
+
+    /// Subscribes to the topics of `names` not subscribed yet and unsubscribes
+    /// from the subscribed ones missing from it. Every name is checked before
+    /// anything changes, and a failed subscription or unsubscription undoes
+    /// the changes made before it.
+    fn set_subscriptions(&mut self, names: Vec<String>) -> Result<SubscriptionChange> {
+        let default_hash = self.gossipsub_topic.hash();
+        let mut requested = HashSet::new();
+        let mut to_subscribe = Vec::new();
+        for name in names {
+            validate_topic(&name)?;
+            let topic = gossipsub::IdentTopic::new(name);
+            let hash = topic.hash();
+            if hash == default_hash || !requested.insert(hash.clone()) {
+                continue;
+            }
+            if !self.topics.contains_key(&hash) {
+                to_subscribe.push(topic);
+            }
+        }
+        let mut to_unsubscribe: Vec<gossipsub::IdentTopic> = self
+            .topics
+            .iter()
+            .filter(|(hash, _)| !requested.contains(*hash))
+            .map(|(_, topic)| topic.clone())
+            .collect();
+        to_unsubscribe.sort_by(|a, b| a.hash().as_str().cmp(b.hash().as_str()));
+
+        let mut subscribed = Vec::new();
+        let mut unsubscribed = Vec::new();
+        let mut failure = None;
+        for topic in to_subscribe {
+            match self.swarm.behaviour_mut().gossipsub.subscribe(&topic) {
+                Ok(_) => subscribed.push(topic),
+                Err(err) => {
+                    failure = Some(anyhow!("failed to subscribe to topic {topic}: {err}"));
+                    break;
                 }
-                Ok(false)
             }
-            PeerCommand::Dial(address) => {
-                match self.swarm.dial(address.clone()) {
-                    Ok(_) => tracing::info!(target: "peer", %address, "dialing remote"),
-                    Err(err) => tracing::error!(target: "peer", %address, %err, "failed to dial"),
+        }
+        if failure.is_none() {
+            for topic in to_unsubscribe {
+                if !self.swarm.behaviour_mut().gossipsub.unsubscribe(&topic) {
+                    failure = Some(anyhow!(
+                        "failed to unsubscribe from topic {topic}: not subscribed"
+                    ));
+                    break;
                 }
-                Ok(false)
+                unsubscribed.push(topic);
             }
-            PeerCommand::ReserveRelay(mut address) => {
-                // This one should contain relay peerId
-                if let Some(peer_id) = extract_peer_id(&address) {
-                    self.relay_peer_id = Some(peer_id);
+        }
+        if let Some(err) = failure {
+            let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+            for topic in &subscribed {
+                let _ = gossipsub.unsubscribe(topic);
+            }
+            for topic in &unsubscribed {
+                if let Err(err) = gossipsub.subscribe(topic) {
+                    tracing::warn!(target: "peer", %topic, %err, "failed to restore topic subscription");
+                    self.topics.remove(&topic.hash());
                 }
+            }
+            return Err(err);
+        }
 
-                // ensure /p2p-circuit is present
-                let has_circuit = address.iter().any(|p| matches!(p, Protocol::P2pCircuit));
-                if !has_circuit {
-                    // Force it be a p2p-circuit (relay)
-                    address.push(Protocol::P2pCircuit);
+        let mut change = SubscriptionChange::default();
+        for topic in subscribed {
+            change.subscribed.push(topic.to_string());
+            self.topics.insert(topic.hash(), topic);
+        }
+        for topic in unsubscribed {
+            self.topics.remove(&topic.hash());
+            change.unsubscribed.push(topic.to_string());
+        }
+        tracing::info!(
+            target: "peer",
+            subscribed = change.subscribed.len(),
+            unsubscribed = change.unsubscribed.len(),
+            "updated topic subscriptions"
+        );
+        self.emit_network_event(NetworkEvent::SubscriptionsChanged(change.clone()));
+        Ok(change)
+    }
+
+    fn publish_group_frame(
+        &mut self,
+        topic: gossipsub::IdentTopic,
+        frame: GroupFrame,
+        retry: RetryPolicy,
+    ) {
+        self.publish_to_topic(PublishAttempt {
+            topic,
+            payload: frame.encode(),
+            retry,
+            attempt: 1,
+            nonce: None,
+            trace_id: None,
+            receipt: None,
+        });
+    }
+
+    /// Applies a frame authored by `source` to the group of `topic` and emits
+    /// the resulting group events.
+    fn handle_group_frame(
+        &mut self,
+        topic: &gossipsub::TopicHash,
+        source: Option<PeerId>,
+        data: &[u8],
+    ) {
+        let Some(from) = source else {
+            tracing::debug!(target: "peer", "ignoring group frame without author");
+            return;
+        };
+        let frame = match GroupFrame::decode(data) {
+            Ok(frame) => frame,
+            Err(err) => {
+                tracing::debug!(target: "peer", %err, "ignoring malformed group frame");
+                return;
+            }
+        };
+        let local_peer_id = self.local_peer_id;
+        let Some(group) = self.groups.get_mut(topic) else {
+            return;
+        };
+        if from == local_peer_id {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut events = Vec::new();
+        if frame == GroupFrame::Leave {
+            group.departed.insert(from, now);
+            if group.members.remove(&from).is_some() {
+                events.push(GroupEvent::MemberLeft {
+                    group: group.name.clone(),
+                    peer_id: from,
+                });
+            }
+        } else {
+            group.departed.remove(&from);
+            if group.members.insert(from, now).is_none() {
+                events.push(GroupEvent::MemberJoined {
+                    group: group.name.clone(),
+                    peer_id: from,
+                });
+            }
+        }
+
+        match frame {
+            GroupFrame::Message(payload) => events.push(GroupEvent::Message {
+                group: group.name.clone(),
+                from,
+                payload,
+            }),
+            GroupFrame::Roster(members) => {
+                for peer_id in members {
+                    let known = peer_id == local_peer_id
+                        || group.members.contains_key(&peer_id)
+                        || group.departed.contains_key(&peer_id);
+                    if known || group.members.len() >= MAX_GROUP_ROSTER {
+                        continue;
+                    }
+                    group.members.insert(peer_id, now);
+                    events.push(GroupEvent::MemberJoined {
+                        group: group.name.clone(),
+                        peer_id,
+                    });
                 }
+            }
+            GroupFrame::Join | GroupFrame::Leave => {}
+        }
 
-                // This one is a reservation itself
-                match self.swarm.listen_on(address.clone()) {
-                    Ok(_) => tracing::info!(target: "peer", %address, "listening via relay"),
-                    Err(err) => tracing::error!(
-                        target: "peer",
-                        %address,
-                        %err,
-                        "failed to start relay reservation"
-                    ),
+        for event in events {
+            self.emit_network_event(NetworkEvent::Group(event));
+        }
+    }
+
+    /// Drops `peer_id` from the group of `topic`, e.g. after it unsubscribed.
+    fn remove_group_member(&mut self, topic: &gossipsub::TopicHash, peer_id: PeerId) {
+        let Some(group) = self.groups.get_mut(topic) else {
+            return;
+        };
+        group.departed.insert(peer_id, Instant::now());
+        if group.members.remove(&peer_id).is_some() {
+            let event = GroupEvent::MemberLeft {
+                group: group.name.clone(),
+                peer_id,
+            };
+            self.emit_network_event(NetworkEvent::Group(event));
+        }
+    }
+
+    /// Drops group members not heard from within [`GROUP_MEMBER_TIMEOUT`] and
+    /// announces the rosters of joined groups every [`GROUP_ANNOUNCE_INTERVAL`].
+    fn poll_groups(&mut self) {
+        if self.groups.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut events = Vec::new();
+        for group in self.groups.values_mut() {
+            group
+                .departed
+                .retain(|_, left_at| now.duration_since(*left_at) < GROUP_MEMBER_TIMEOUT);
+            let silent: Vec<PeerId> = group
+                .members
+                .iter()
+                .filter(|(_, last_seen)| now.duration_since(**last_seen) >= GROUP_MEMBER_TIMEOUT)
+                .map(|(peer_id, _)| *peer_id)
+                .collect();
+            for peer_id in silent {
+                group.members.remove(&peer_id);
+                group.departed.insert(peer_id, now);
+                events.push(GroupEvent::MemberLeft {
+                    group: group.name.clone(),
+                    peer_id,
+                });
+            }
+        }
+        for event in events {
+            self.emit_network_event(NetworkEvent::Group(event));
+        }
+
+        if now < self.next_group_announce {
+            return;
+        }
+        self.next_group_announce = now + GROUP_ANNOUNCE_INTERVAL;
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let announcements: Vec<_> = self
+            .groups
+            .iter()
+            // Nobody to tell while the topic has no mesh peers.
+            .filter(|(hash, _)| gossipsub.mesh_peers(hash).next().is_some())
+            .map(|(_, group)| (group.topic.clone(), group.roster()))
+            .collect();
+        for (topic, roster) in announcements {
+            self.publish_group_frame(topic, roster, RetryPolicy::none());
+        }
+    }
+
+    /// Decrypts an inbound payload when its topic has a shared secret. Payloads
+    /// that fail to decrypt are ignored rather than rejected, since the relaying
+    /// peer cannot check them without the key either.
+    fn decrypt_topic_payload(
+        &self,
+        topic: &gossipsub::TopicHash,
+        data: Vec<u8>,
+    ) -> std::result::Result<Vec<u8>, VerificationResult> {
+        let Some(cipher) = self.topic_ciphers.get(topic) else {
+            return Ok(data);
+        };
+        cipher.decrypt(&data).map_err(|err| {
+            tracing::debug!(target: "peer", %err, "dropping undecryptable message");
+            VerificationResult::Ignore
+        })
+    }
+
+    /// Records the clock offset a peer's time answer revealed.
+    fn record_clock_sample(&mut self, event: TimeSyncEvent) {
+        let peer_id = event.peer_id;
+        match event.result {
+            Ok(sample) => {
+                if !self.clock_offsets.record(peer_id, sample) {
+                    tracing::debug!(target: "peer", peer_id = %self.redact.display(&peer_id), rtt = ?sample.rtt, "discarding imprecise clock sample");
+                    return;
                 }
+                tracing::debug!(
+                    target: "peer",
+                    peer_id = %self.redact.display(&peer_id),
+                    offset_ms = sample.offset_ms,
+                    rtt = ?sample.rtt,
+                    median_offset_ms = ?self.clock_offsets.median_offset_ms(),
+                    "recorded peer clock offset"
+                );
+            }
+            Err(err) => {
+                tracing::debug!(target: "peer", peer_id = %self.redact.display(&peer_id), %err, "clock sample failed");
+            }
+        }
+    }
 
-                Ok(false)
+    /// Wraps an outbound payload in a replay-protection envelope with `nonce`
+    /// and `trace_id`, when replay protection assigned a nonce.
+    fn seal_envelope(
+        &self,
+        payload: Vec<u8>,
+        nonce: Option<u64>,
+        trace_id: Option<TraceId>,
+    ) -> Vec<u8> {
+        let Some(nonce) = nonce.filter(|_| self.replay_cache.is_some()) else {
+            return payload;
+        };
+        let mut envelope = Envelope::new(nonce, payload);
+        envelope.trace_id = trace_id;
+        if self.config.time_sync {
+            envelope.timestamp_ms = self.clock_offsets.network_time_ms();
+        }
+        envelope.encode()
+    }
+
+    /// Unwraps and replay-checks an inbound envelope when replay protection is
+    /// enabled. Malformed envelopes are rejected, stale or replayed ones ignored.
+    /// With time sync on, envelopes are stamped and checked with the network
+    /// time, so a skewed local clock does not get messages refused. Answers
+    /// the payload with the trace id its publisher put in the envelope.
+    fn open_envelope(
+        &mut self,
+        data: Vec<u8>,
+        source: Option<PeerId>,
+        propagation_source: PeerId,
+    ) -> std::result::Result<(Vec<u8>, Option<TraceId>), VerificationResult> {
+        let now_ms = if self.config.time_sync {
+            self.clock_offsets.network_time_ms()
+        } else {
+            unix_time_ms()
+        };
+        let Some(cache) = self.replay_cache.as_mut() else {
+            return Ok((data, None));
+        };
+
+        let envelope = match Envelope::decode(&data) {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                tracing::warn!(target: "peer", %err, "malformed replay envelope");
+                return Err(VerificationResult::Reject);
             }
-            PeerCommand::FindPeer {
-                peer_id,
-                request_id,
-            } => {
-                let query_id = self
-                    .swarm
-                    .behaviour_mut()
-                    .kademlia
-                    .get_closest_peers(peer_id.clone());
+        };
 
-                self.discovery_queries.insert(
-                    query_id,
-                    DiscoveryRequest {
-                        request_id,
-                        target_peer_id: peer_id.clone(),
-                        kind: DiscoveryKind::FindPeer,
-                    },
+        let checked = cache.check_at(source, &envelope, now_ms);
+        let trace_id = envelope.trace_id;
+        match checked {
+            Ok(()) => Ok((envelope.payload, trace_id)),
+            Err(reason) => {
+                tracing::debug!(
+                    target: "peer",
+                    nonce = envelope.nonce,
+                    trace_id = ?trace_id,
+                    timestamp_ms = envelope.timestamp_ms,
+                    ?reason,
+                    "dropping replayed or stale message"
                 );
+                if reason == ReplayError::Replayed {
+                    self.record_gossip(propagation_source, GossipVerdict::Duplicate);
+                }
+                Err(VerificationResult::Ignore)
+            }
+        }
+    }
+
+    /// Counts a message relayed by `peer_id` and lowers the gossip score of
+    /// the peer once its duplicates or invalid messages exceed the configured
+    /// [`GossipThrottleSettings`](crate::peer::GossipThrottleSettings).
+    fn record_gossip(&mut self, peer_id: PeerId, verdict: GossipVerdict) {
+        let settings = self.config.gossip_throttle;
+        let Some((duplicates, invalid)) =
+            self.gossip_rates
+                .record(peer_id, verdict, settings.as_ref(), Instant::now())
+        else {
+            return;
+        };
+        let Some(settings) = settings else {
+            return;
+        };
+
+        tracing::warn!(
+            target: "peer",
+            peer_id = %self.redact.display(&peer_id),
+            duplicates,
+            invalid,
+            "throttling peer relaying excessive gossip"
+        );
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .set_application_score(&peer_id, settings.score_penalty);
+        let (offences, threshold) = if duplicates > settings.max_duplicates {
+            (duplicates, settings.max_duplicates.saturating_add(1))
+        } else {
+            (invalid, settings.max_invalid.saturating_add(1))
+        };
+        self.emit_network_event(NetworkEvent::PeerPenalized(PeerPenalizedInfo {
+            peer_id,
+            reason: PenaltyReason::ExcessiveGossip,
+            banned: false,
+            offences,
+            threshold,
+            detail: format!(
+                "{duplicates} duplicate and {invalid} invalid messages within {:?}",
+                settings.window
+            ),
+        }));
+    }
+
+    /// Counts an invalid message relayed by `peer_id` and blacklists the peer
+    /// once it reaches [`INVALID_MESSAGE_THRESHOLD`].
+    fn penalize_invalid_message(&mut self, peer_id: PeerId) {
+        let count = self.invalid_messages.entry(peer_id).or_default();
+        *count += 1;
+        if *count < INVALID_MESSAGE_THRESHOLD {
+            return;
+        }
+        let offences = *count;
+
+        tracing::warn!(
+            target: "peer",
+            peer_id = %self.redact.display(&peer_id),
+            invalid_messages = offences,
+            "blacklisting peer relaying invalid messages"
+        );
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .blacklist_peer(&peer_id);
+        if self.swarm.disconnect_peer_id(peer_id).is_err() {
+            tracing::debug!(target: "peer", peer_id = %self.redact.display(&peer_id), "peer already disconnected");
+        }
+        self.emit_network_event(NetworkEvent::PeerPenalized(PeerPenalizedInfo {
+            peer_id,
+            reason: PenaltyReason::InvalidMessages,
+            banned: true,
+            offences,
+            threshold: INVALID_MESSAGE_THRESHOLD,
+            detail: format!("{offences} messages failed verification"),
+        }));
+    }
+
+    fn handle_kademlia_event(&mut self, event: kad::Event) {
+        match event {
+            kad::Event::OutboundQueryProgressed {
+                id,
+                result,
+                stats,
+                step,
+            } => {
+                if step.last {
+                    let (kind, succeeded) = query_outcome(&result);
+                    self.dht_metrics
+                        .record(kind, succeeded, &stats, step.count.get());
+                }
+                self.handle_query_result(id, result, step.last);
+            }
+            kad::Event::InboundRequest { request } => self.handle_inbound_kad_request(request),
+            other => {
+                tracing::debug!(target: "peer", other = %self.redact.debug(&other), "kademlia event")
+            }
+        }
+    }
+
+    /// Stores the records and provider announcements of remote peers that
+    /// pass the record validator.
+    fn handle_inbound_kad_request(&mut self, request: kad::InboundRequest) {
+        match request {
+            kad::InboundRequest::PutRecord {
+                source,
+                record: Some(record),
+                ..
+            } => {
+                if let Some(validator) = &self.record_validator {
+                    if let Err(reason) = validator.validate(&record, &source) {
+                        tracing::warn!(
+                            target: "peer",
+                            source = %self.redact.display(&source),
+                            %reason,
+                            "refused inbound dht record"
+                        );
+                        return;
+                    }
+                }
+                self.store_inbound_record(record);
+            }
+            kad::InboundRequest::AddProvider {
+                record: Some(record),
+            } => {
+                if let Some(validator) = &self.record_validator {
+                    if let Err(reason) = validator.validate_provider(&record.key, &record.provider)
+                    {
+                        tracing::warn!(
+                            target: "peer",
+                            provider = %self.redact.display(&record.provider),
+                            %reason,
+                            "refused inbound provider record"
+                        );
+                        return;
+                    }
+                }
+                let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+                if let Err(err) = kademlia.store_mut().add_provider(record) {
+                    self.dht_metrics
+                        .record_store(|stats| stats.providers_rejected += 1);
+                    tracing::warn!(target: "peer", %err, "failed to store inbound provider record");
+                }
+            }
+            other => {
+                tracing::trace!(target: "peer", other = %self.redact.debug(&other), "inbound kademlia request")
+            }
+        }
+    }
 
-                tracing::info!(
-                    target: "peer",
-                    %peer_id,
-                    ?query_id,
-                    request_id,
-                    "started find_peer query"
-                );
+    /// Stores an inbound record. When the store is full, the record of a remote
+    /// publisher expiring soonest (records without expiry last) is evicted to
+    /// make room; records this node published are never evicted.
+    fn store_inbound_record(&mut self, record: kad::Record) {
+        let local_peer_id = *self.swarm.local_peer_id();
+        let store = self.swarm.behaviour_mut().kademlia.store_mut();
+        let err = match store.put(record.clone()) {
+            Ok(()) => return,
+            Err(kad::store::Error::MaxRecords) => {
+                let victim = store
+                    .records()
+                    .filter(|stored| stored.publisher != Some(local_peer_id))
+                    .min_by_key(|stored| (stored.expires.is_none(), stored.expires))
+                    .map(|stored| stored.key.clone());
+                match victim {
+                    Some(key) => {
+                        store.remove(&key);
+                        self.dht_metrics
+                            .record_store(|stats| stats.records_evicted += 1);
+                        tracing::debug!(target: "peer", "evicted dht record to make room");
+                        match store.put(record) {
+                            Ok(()) => return,
+                            Err(err) => err,
+                        }
+                    }
+                    None => kad::store::Error::MaxRecords,
+                }
+            }
+            Err(err) => err,
+        };
+        self.dht_metrics
+            .record_store(|stats| stats.records_rejected += 1);
+        tracing::warn!(target: "peer", %err, "failed to store inbound dht record");
+    }
 
-                Ok(false)
+    fn handle_query_result(&mut self, id: kad::QueryId, result: QueryResult, is_last: bool) {
+        match result {
+            QueryResult::GetClosestPeers(res) => {
+                self.handle_get_closest_peers_result(id, res, is_last)
             }
-            PeerCommand::GetClosestPeers {
-                peer_id,
-                request_id,
-            } => {
-                let query_id = self
-                    .swarm
-                    .behaviour_mut()
-                    .kademlia
-                    .get_closest_peers(peer_id.clone());
+            QueryResult::GetProviders(res) => self.handle_get_providers_result(id, res, is_last),
+            QueryResult::GetRecord(res) => self.handle_get_record_result(id, res, is_last),
+            QueryResult::PutRecord(res) => self.handle_put_record_result(id, res, is_last),
+            QueryResult::StartProviding(res) => {
+                self.handle_start_providing_result(id, res, is_last)
+            }
+            QueryResult::Bootstrap(res) => self.handle_bootstrap_result(id, res, is_last),
+            other => {
+                tracing::debug!(target: "peer", ?id, other = %self.redact.debug(&other), "unhandled kademlia query result");
+                if is_last {
+                    self.discovery_queries.remove(&id);
+                }
+            }
+        }
+    }
 
-                self.discovery_queries.insert(
-                    query_id,
-                    DiscoveryRequest {
-                        request_id,
-                        target_peer_id: peer_id.clone(),
-                        kind: DiscoveryKind::GetClosestPeers,
-                    },
-                );
+    fn handle_get_closest_peers_result(
+        &mut self,
+        query_id: kad::QueryId,
+        result: kad::GetClosestPeersResult,
+        is_last: bool,
+    ) {
+        if self.dial_lookups.contains_key(&query_id) {
+            self.resolve_dial_lookup(query_id, &result, is_last);
+            return;
+        }
+        let Some(request) = self.discovery_queries.get(&query_id).cloned() else {
+            tracing::debug!(target: "peer", ?query_id, "ignoring untracked kademlia query");
+            return;
+        };
 
-                tracing::info!(
+        match &result {
+            Ok(ok) => match request.kind {
+                DiscoveryKind::FindPeer => {
+                    self.handle_find_peer_response(query_id, &request, ok, is_last);
+                }
+                DiscoveryKind::GetClosestPeers => {
+                    self.handle_closest_peers_response(query_id, &request, ok, is_last);
+                }
+            },
+            Err(kad::GetClosestPeersError::Timeout { peers, .. }) => {
+                tracing::warn!(
                     target: "peer",
-                    %peer_id,
                     ?query_id,
-                    request_id,
-                    "started get_closest_peers query"
+                    request_id = request.request_id,
+                    target = %self.redact.display(&request.target_peer_id),
+                    "kademlia query timed out"
                 );
 
-                Ok(false)
-            }
-            PeerCommand::Publish(payload) => {
-                match self
-                    .swarm
-                    .behaviour_mut()
-                    .gossipsub
-                    .publish(self.gossipsub_topic.clone(), payload)
-                {
-                    Ok(_) => tracing::info!(target: "peer", "published message"),
-                    Err(err) => tracing::warn!(target: "peer", %err, "failed to publish message"),
+                if !peers.is_empty() {
+                    self.process_discovered_peers(query_id, &request, peers);
+                }
+
+                if is_last {
+                    let peers_found = self
+                        .discovery_queries
+                        .get(&query_id)
+                        .map_or(0, |request| request.peers_found);
+                    let status = if peers_found > 0 {
+                        DiscoveryStatus::PartialSuccess { peers_found }
+                    } else {
+                        DiscoveryStatus::Timeout
+                    };
+                    self.finish_discovery(query_id, request, status);
                 }
-                Ok(false)
-            }
-            PeerCommand::Shutdown => {
-                tracing::info!(target: "peer", "shutdown requested");
-                Ok(true)
             }
         }
     }
 
-    /// Logging and reacting to events coming from the swarm (peer orchestrator)
-    fn handle_swarm_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
-        match event {
-            SwarmEvent::Behaviour(event) => self.handle_behaviour_event(event),
+    /// Settings of a delegated lookup for a request that was not delegated
+    /// yet; `None` when delegated routing is off or its budget is exhausted.
+    fn delegation(
+        &self,
+        request_id: u64,
+        delegated: bool,
+    ) -> Option<delegated::DelegatedRoutingSettings> {
+        let settings = self
+            .config
+            .delegated_routing
+            .clone()
+            .filter(|_| !delegated)?;
+        if self.delegated_in_flight >= self.config.executor.budgets.delegated_lookups {
+            tracing::debug!(target: "peer", request_id, "delegated lookup budget exhausted; skipping");
+            return None;
+        }
+        Some(settings)
+    }
 
-            SwarmEvent::NewListenAddr { address, .. } => {
-                tracing::info!(target: "peer", %address, "listening on new address");
+    /// Whether Kademlia runs in client mode, answering no queries itself.
+    fn dht_client_only(&self) -> bool {
+        self.swarm.behaviour().kademlia.mode() == kad::Mode::Client
+    }
 
-                self.emit_addr_event(AddrEvent::ListenerAdded {
-                    address: address.clone(),
-                });
+    /// Asks the delegated routing server about the target of a find_peer the
+    /// DHT could not resolve. Hands the request back when delegated routing
+    /// is off or was tried already.
+    fn delegate_find_peer(
+        &mut self,
+        query_id: kad::QueryId,
+        mut request: DiscoveryRequest,
+        status: DiscoveryStatus,
+        dht_next: bool,
+    ) -> Option<DiscoveryRequest> {
+        let Some(settings) = self.delegation(request.request_id, request.delegated) else {
+            return Some(request);
+        };
+        request.delegated = true;
+        self.delegated_in_flight += 1;
+        tracing::info!(
+            target: "peer",
+            request_id = request.request_id,
+            target = %self.redact.display(&request.target_peer_id),
+            "asking delegated routing server"
+        );
 
-                self.update_relay_address(address);
-            }
+        let answers = self.delegated_sender.clone();
+        tokio::spawn(async move {
+            let addresses = delegated::find_peer(&settings, request.target_peer_id).await;
+            let _ = answers.send(DelegatedAnswer::Peer {
+                query_id,
+                request,
+                status,
+                dht_next,
+                addresses,
+            });
+        });
+        None
+    }
+
+    /// Asks the delegated routing server for providers of a key the DHT
+    /// found none for. Hands the request back when delegated routing is off
+    /// or was tried already.
+    fn delegate_get_providers(
+        &mut self,
+        query_id: kad::QueryId,
+        mut request: ProviderRequest,
+        status: DiscoveryStatus,
+        dht_next: bool,
+    ) -> Option<ProviderRequest> {
+        let Some(settings) = self.delegation(request.request_id, request.delegated) else {
+            return Some(request);
+        };
+        request.delegated = true;
+        self.delegated_in_flight += 1;
+        tracing::info!(
+            target: "peer",
+            request_id = request.request_id,
+            "asking delegated routing server for providers"
+        );
 
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
-                tracing::info!(target: "peer", %peer_id, "connection established");
+        let answers = self.delegated_sender.clone();
+        tokio::spawn(async move {
+            let providers = delegated::find_providers(&settings, request.key.as_ref()).await;
+            let _ = answers.send(DelegatedAnswer::Providers {
+                query_id,
+                request,
+                status,
+                dht_next,
+                providers,
+            });
+        });
+        None
+    }
+
+    fn handle_delegated_answer(&mut self, answer: DelegatedAnswer) {
+        self.delegated_in_flight = self.delegated_in_flight.saturating_sub(1);
+        match answer {
+            DelegatedAnswer::Peer {
+                query_id,
+                request,
+                status,
+                dht_next,
+                addresses,
+            } => self.handle_delegated_peer(query_id, request, status, dht_next, addresses),
+            DelegatedAnswer::Providers {
+                query_id,
+                request,
+                status,
+                dht_next,
+                providers,
+            } => self.handle_delegated_providers(query_id, request, status, dht_next, providers),
+        }
+    }
+
+    /// Reports the addresses the delegated routing server knows for the
+    /// target like DHT results, then finishes the query, or queries the DHT
+    /// when the server knows none and `dht_next` is set.
+    fn handle_delegated_peer(
+        &mut self,
+        query_id: kad::QueryId,
+        mut request: DiscoveryRequest,
+        mut status: DiscoveryStatus,
+        dht_next: bool,
+        addresses: Result<Vec<Multiaddr>>,
+    ) {
+        match addresses {
+            Ok(addresses) if !addresses.is_empty() => {
+                let peer = kad::PeerInfo {
+                    peer_id: request.target_peer_id,
+                    addrs: addresses,
+                };
+                // Tracked again for the duration of the report, so the
+                // addresses count towards `peers_found`.
+                self.discovery_queries.insert(query_id, request.clone());
+                self.process_discovered_peers(query_id, &request, &[peer]);
+                if let Some(tracked) = self.discovery_queries.remove(&query_id) {
+                    request.peers_found = tracked.peers_found;
+                }
+                status = DiscoveryStatus::Success;
+            }
+            Ok(_) => {
+                tracing::info!(target: "peer", request_id = request.request_id, "delegated routing server does not know the peer");
             }
+            Err(err) => {
+                tracing::warn!(target: "peer", request_id = request.request_id, %err, "delegated routing lookup failed");
+            }
+        }
+        if dht_next && !matches!(status, DiscoveryStatus::Success) {
+            self.start_discovery(request);
+            return;
+        }
+        self.emit_discovery_finished(request, status);
+    }
 
-            SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
-                if let Some(error) = cause {
-                    tracing::warn!(target: "peer", %peer_id, %error, "connection closed with error");
+    /// Reports the providers the delegated routing server knows like DHT
+    /// results, then finishes the lookup, or queries the DHT when the server
+    /// knows none and `dht_next` is set.
+    fn handle_delegated_providers(
+        &mut self,
+        query_id: kad::QueryId,
+        request: ProviderRequest,
+        status: DiscoveryStatus,
+        dht_next: bool,
+        providers: Result<Vec<(PeerId, Vec<Multiaddr>)>>,
+    ) {
+        let request_id = request.request_id;
+        match providers {
+            Ok(providers) if !providers.is_empty() => {
+                // Tracked again for the duration of the report, which
+                // finishes the lookup once it meets its result limit.
+                self.provider_queries.insert(query_id, request);
+                self.report_providers(query_id, providers);
+                let Some(request) = self.provider_queries.remove(&query_id) else {
+                    return;
+                };
+                if !dht_next || !request.providers.is_empty() {
+                    let status = request.status(false);
+                    self.send_providers_finished(request, status);
                 } else {
-                    tracing::info!(target: "peer", %peer_id, "connection closed");
+                    self.start_provider_query(request);
                 }
+                return;
             }
-
-            SwarmEvent::IncomingConnection { send_back_addr, .. } => {
-                tracing::debug!(target: "peer", %send_back_addr, "incoming connection");
+            Ok(_) => {
+                tracing::info!(target: "peer", request_id, "delegated routing server knows no provider");
             }
-
-            SwarmEvent::IncomingConnectionError {
-                send_back_addr,
-                error,
-                ..
-            } => {
-                tracing::warn!(target: "peer", %send_back_addr, %error, "incoming connection error");
+            Err(err) => {
+                tracing::warn!(target: "peer", request_id, %err, "delegated provider lookup failed");
             }
+        }
+        if dht_next {
+            self.start_provider_query(request);
+            return;
+        }
+        self.send_providers_finished(request, status);
+    }
 
-            SwarmEvent::NewExternalAddrCandidate { address } => {
-                tracing::info!(target: "peer", %address, "new external address candidate");
+    /// Issues the Kademlia query of `request` and tracks it until it finishes.
+    /// With delegated routing configured, a find_peer goes to the delegated
+    /// routing server right away while the routing table is empty, and on a
+    /// client-only node, which queries the DHT only when the server does not
+    /// know the peer.
+    fn start_discovery(&mut self, mut request: DiscoveryRequest) {
+        let client_only = self.dht_client_only();
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        let routing_table_empty = kademlia.kbuckets().all(|bucket| bucket.num_entries() == 0);
+        let query_id = kademlia.get_closest_peers(request.target_peer_id);
+        if (routing_table_empty || client_only)
+            && matches!(request.kind, DiscoveryKind::FindPeer)
+            && !request.delegated
+        {
+            // Without peers to ask the query finishes on its own, otherwise
+            // it is dropped for the server; its id keys the delegated answer.
+            let dht_next = !routing_table_empty;
+            match self.delegate_find_peer(query_id, request, DiscoveryStatus::NotFound, dht_next) {
+                None => {
+                    let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+                    if let Some(mut query) = kademlia.query_mut(&query_id) {
+                        query.finish();
+                    }
+                    return;
+                }
+                Some(returned) => request = returned,
             }
+        }
 
-            SwarmEvent::ExternalAddrConfirmed { address } => {
-                tracing::info!(target: "peer", %address, "external address confirmed");
+        let query = match request.kind {
+            DiscoveryKind::FindPeer => "find_peer",
+            DiscoveryKind::GetClosestPeers => "get_closest_peers",
+        };
+        tracing::info!(
+            target: "peer",
+            peer_id = %self.redact.display(&request.target_peer_id),
+            ?query_id,
+            request_id = request.request_id,
+            attempt = request.attempt,
+            "started {query} query"
+        );
 
-                self.emit_addr_event(AddrEvent::ExternalConfirmed {
-                    address: address.clone(),
-                });
+        request.deadline = Instant::now() + DISCOVERY_QUERY_DEADLINE;
+        self.discovery_queries.insert(query_id, request);
+    }
 
-                self.update_relay_address(address);
+    /// Issues the provider lookup of `request` and tracks it until it finishes.
+    /// With delegated routing configured, a client-only node asks the
+    /// delegated routing server first and queries the DHT only when the
+    /// server knows no provider.
+    fn start_provider_query(&mut self, mut request: ProviderRequest) {
+        let client_only = self.dht_client_only();
+        let query_id = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .get_providers(request.key.clone());
+        if client_only && !request.delegated {
+            match self.delegate_get_providers(query_id, request, DiscoveryStatus::NotFound, true) {
+                None => {
+                    let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+                    if let Some(mut query) = kademlia.query_mut(&query_id) {
+                        query.finish();
+                    }
+                    return;
+                }
+                Some(returned) => request = returned,
             }
+        }
 
-            SwarmEvent::ExternalAddrExpired { address } => {
-                tracing::warn!(target: "peer", %address, "external address expired");
-                self.clear_relay_address(&address);
+        tracing::info!(
+            target: "peer",
+            ?query_id,
+            request_id = request.request_id,
+            attempt = request.attempt,
+            "started get_providers query"
+        );
 
-                self.emit_addr_event(AddrEvent::ExternalExpired {
-                    address: address.clone(),
-                });
-            }
+        request.deadline = Instant::now() + DISCOVERY_QUERY_DEADLINE;
+        if let Some(limit_deadline) = request.limit_deadline {
+            request.deadline = request.deadline.min(limit_deadline);
+        }
+        self.provider_queries.insert(query_id, request);
+    }
 
-            SwarmEvent::ListenerClosed {
-                addresses, reason, ..
-            } => {
-                tracing::warn!(target: "peer", ?addresses, ?reason, "listener closed");
+    fn handle_get_providers_result(
+        &mut self,
+        query_id: kad::QueryId,
+        result: kad::GetProvidersResult,
+        is_last: bool,
+    ) {
+        let Some(request) = self.provider_queries.get(&query_id) else {
+            tracing::debug!(target: "peer", ?query_id, "ignoring untracked provider query");
+            return;
+        };
+        let request_id = request.request_id;
 
-                // ListenerClosed can contain multiple addresses. Emit removal for each.
-                for address in addresses {
-                    self.emit_addr_event(AddrEvent::ListenerRemoved { address });
-                }
+        let timed_out = match result {
+            Ok(kad::GetProvidersOk::FoundProviders { providers, .. }) => {
+                let providers = providers.into_iter().map(|provider| (provider, Vec::new()));
+                self.report_providers(query_id, providers);
+                false
             }
-
-            SwarmEvent::ListenerError { error, .. } => {
-                tracing::error!(target: "peer", %error, "listener error");
+            Ok(kad::GetProvidersOk::FinishedWithNoAdditionalRecord { .. }) => false,
+            Err(kad::GetProvidersError::Timeout { .. }) => {
+                tracing::warn!(target: "peer", ?query_id, request_id, "provider query timed out");
+                true
             }
+        };
 
-            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
-                tracing::warn!(target: "peer", ?peer_id, %error, "outgoing connection error");
-
-                if let Some(peer_id) = peer_id {
-                    self.try_dial_via_relay(&peer_id, &error);
-                }
-            }
-            
-            _ => {}
+        if is_last {
+            self.finish_provider_query(query_id, timed_out);
         }
     }
 
-    /// Handles events from additional network's features
-    fn handle_behaviour_event(&mut self, event: BehaviourEvent) {
-        match event {
-            BehaviourEvent::Kademlia(event) => {
-                self.handle_kademlia_event(event);
-            }
-
-            BehaviourEvent::Ping(event) => match event.result {
-                Ok(rtt) => {
-                    tracing::debug!(target: "peer", ?rtt, "ping success");
-                }
-                Err(error) => {
-                    tracing::warn!(target: "peer", %error, "ping failure");
-                }
-            },
+    /// Reports providers not yet seen by the query right away, so callers can
+    /// use the first one before the lookup completes, along with the known
+    /// addresses and those given with the provider. Finishes the lookup once
+    /// it reported the providers it was limited to.
+    fn report_providers(
+        &mut self,
+        query_id: kad::QueryId,
+        providers: impl IntoIterator<Item = (PeerId, Vec<Multiaddr>)>,
+    ) {
+        let local_peer_id = self.local_peer_id;
+        let Some(request) = self.provider_queries.get_mut(&query_id) else {
+            return;
+        };
+        let remaining = request.max_results.map_or(usize::MAX, |max_results| {
+            max_results.get().saturating_sub(request.providers.len())
+        });
+        let fresh: Vec<(PeerId, Vec<Multiaddr>)> = providers
+            .into_iter()
+            .filter(|(provider, _)| {
+                *provider != local_peer_id && request.providers.insert(*provider)
+            })
+            .take(remaining)
+            .collect();
+        let request_id = request.request_id;
+        let key = request.key.to_vec();
+        let results = request.results.clone();
+        let satisfied = request.is_satisfied();
 
-            BehaviourEvent::Identify(event) => {
-                tracing::debug!(target: "peer", ?event, "identify event");
+        for (peer_id, given) in fresh {
+            let mut addresses = self.known_addresses(&peer_id);
+            addresses.extend(given);
+            addresses.sort();
+            addresses.dedup();
+            tracing::debug!(
+                target: "peer",
+                peer_id = %self.redact.display(&peer_id),
+                request_id,
+                addresses = addresses.len(),
+                "found provider"
+            );
+            let event = DiscoveryEvent::Provider {
+                request_id,
+                key: key.clone(),
+                peer_id,
+                addresses,
+            };
+            let sink = results.as_ref().unwrap_or(&self.discovery_sender);
+            if let Err(err) = sink.try_enqueue(event) {
+                tracing::warn!(target: "peer", %err, "failed to enqueue provider");
             }
+        }
 
-            BehaviourEvent::Gossipsub(event) => {
-                if let gossipsub::Event::Message {
-                    message, propagation_source, ..
-                } = event {
-                    tracing::info!(target: "peer", %propagation_source, len = message.data.len(), "received gossipsub message");
-                    if let Err(err) = self.inbound_sender.try_enqueue(message.data.clone()) {
-                        tracing::warn!(target: "peer", %err, "failed to enqueue inbound message");
-                    }
-                }
+        if satisfied {
+            if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&query_id) {
+                query.finish();
             }
+            tracing::debug!(target: "peer", ?query_id, request_id, "provider query reached its result limit");
+            self.finish_provider_query(query_id, false);
+        }
+    }
 
-            BehaviourEvent::Autonat(event) => {
-                tracing::debug!(target:"peer", ?event, "autonat event");
-                
-                if let autonat::Event::StatusChanged { new, .. } = event {
-                    if self.autonat_status.send(new.clone()).is_err() {
-                        tracing::trace!(
-                            target: "peer",
-                            "autonat status receiver dropped; skipping update"
-                        );
-                    }
-                }
+    fn finish_provider_query(&mut self, query_id: kad::QueryId, timed_out: bool) {
+        let Some(mut request) = self.provider_queries.remove(&query_id) else {
+            return;
+        };
+        let status = request.status(timed_out);
+        let backoff = request.retry.backoff(request.attempt);
+        let within_limit = request
+            .limit_deadline
+            .is_none_or(|limit_deadline| Instant::now() + backoff < limit_deadline);
+        if is_retryable(&status) && request.retry.allows_retry(request.attempt) && within_limit {
+            tracing::debug!(target: "peer", request_id = request.request_id, ?status, ?backoff, "provider query unsuccessful; retrying");
+            request.attempt += 1;
+            self.schedule_retry(backoff, RetryTask::Providers(request));
+            return;
+        }
+        if is_retryable(&status) && within_limit {
+            match self.delegate_get_providers(query_id, request, status.clone(), false) {
+                None => return,
+                Some(returned) => request = returned,
             }
+        }
+        self.send_providers_finished(request, status);
+    }
 
-            BehaviourEvent::RelayClient(event) => match event {
-                relay::client::Event::ReservationReqAccepted {
-                    relay_peer_id,
-                    renewal,
-                    limit,
-                } => {
-                    self.relay_peer_id = Some(relay_peer_id);
-                    tracing::info!(
-                        target: "peer",
-                        relay_id = %relay_peer_id,
-                        renewal,
-                        ?limit,
-                        "relay reservation accepted",
-                    );
-                }
+    fn send_providers_finished(&self, request: ProviderRequest, status: DiscoveryStatus) {
+        let event = DiscoveryEvent::ProvidersFinished {
+            request_id: request.request_id,
+            key: request.key.to_vec(),
+            status,
+            providers_found: request.providers.len(),
+        };
 
-                relay::client::Event::OutboundCircuitEstablished { relay_peer_id, .. } => {
-                    tracing::info!(
-                        target: "peer",
-                        relay_id = %relay_peer_id,
-                        "outbound circuit established",
-                    );
-                }
+        let sink = request.results.as_ref().unwrap_or(&self.discovery_sender);
+        if let Err(err) = sink.try_enqueue(event) {
+            tracing::warn!(target: "peer", %err, "failed to enqueue provider query completion");
+        }
+    }
+
+    /// Stores a record of the local node locally and starts replicating it
+    /// to the peers closest to its key. Records refused by the installed
+    /// [`RecordValidator`] are reported as failed right away.
+    fn start_put_record(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        quorum: kad::Quorum,
+        request_id: u64,
+        results: Option<DiscoveryEventSender>,
+    ) {
+        let key = kad::RecordKey::new(&key);
+        let mut record = kad::Record::new(key.clone(), value);
+        record.publisher = Some(self.local_peer_id);
+        let request = RecordRequest {
+            request_id,
+            key,
+            results,
+            put: true,
+            records_found: 0,
+        };
+
+        let validated = match &self.record_validator {
+            Some(validator) => validator
+                .validate(&record, &self.local_peer_id)
+                .map_err(|reason| anyhow!("record refused by the validator: {reason}")),
+            None => Ok(()),
+        };
+        let started = self
+            .refuse_in_observer_mode("storing dht records")
+            .and(validated)
+            .and_then(|()| {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .put_record(record, quorum)
+                    .map_err(|err| anyhow!("failed to store record locally: {err}"))
+            });
+        match started {
+            Ok(query_id) => {
+                tracing::info!(target: "peer", ?query_id, request_id, ?quorum, "started put_record query");
+                self.record_queries.insert(query_id, request);
+            }
+            Err(err) => {
+                tracing::warn!(target: "peer", %err, request_id, "failed to put record");
+                self.send_record_finished(request, DiscoveryStatus::InternalError);
+            }
+        }
+    }
 
-                other => {
-                    tracing::debug!(target: "peer", ?other, "relay client event");
-                }
-            },
+    /// Stores a provider record of the local node for `key` and starts
+    /// announcing it to the peers closest to the key.
+    fn start_providing(
+        &mut self,
+        key: Vec<u8>,
+        request_id: u64,
+        results: Option<DiscoveryEventSender>,
+    ) {
+        let key = kad::RecordKey::new(&key);
+        let request = ProvideRequest {
+            request_id,
+            key: key.clone(),
+            results,
+        };
 
-            BehaviourEvent::RelayServer(event) => {
-                tracing::debug!(target: "peer", ?event, "relay server event");
+        let started = self
+            .refuse_in_observer_mode("providing dht keys")
+            .and_then(|()| {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .start_providing(key.clone())
+                    .map_err(|err| anyhow!("failed to store provider record locally: {err}"))
+            });
+        match started {
+            Ok(query_id) => {
+                tracing::info!(target: "peer", ?query_id, request_id, "started start_providing query");
+                self.provided_keys.insert(key);
+                self.provide_queries.insert(query_id, request);
             }
-
-            BehaviourEvent::RendezvousClient(event) => {
-                tracing::info!(target: "peer", ?event, "rendezvous client event");
+            Err(err) => {
+                tracing::warn!(target: "peer", %err, request_id, "failed to start providing");
+                self.send_providing_started(request, DiscoveryStatus::InternalError);
             }
+        }
+    }
 
-            BehaviourEvent::RendezvousServer(event) => {
-                tracing::info!(target: "peer", ?event, "rendezvous server event");
-            }
+    fn handle_start_providing_result(
+        &mut self,
+        query_id: kad::QueryId,
+        result: kad::AddProviderResult,
+        is_last: bool,
+    ) {
+        if !is_last {
+            return;
         }
+        // Republications of provided keys are not tracked.
+        let Some(request) = self.provide_queries.remove(&query_id) else {
+            tracing::debug!(target: "peer", ?query_id, ok = result.is_ok(), "provider record republished");
+            return;
+        };
+
+        let status = match result {
+            Ok(_) => DiscoveryStatus::Success,
+            Err(kad::AddProviderError::Timeout { .. }) => DiscoveryStatus::Timeout,
+        };
+        self.send_providing_started(request, status);
     }
 
-    fn handle_kademlia_event(&mut self, event: kad::Event) {
-        match event {
-            kad::Event::OutboundQueryProgressed {
-                id, result, step, ..
-            } => match result {
-                QueryResult::GetClosestPeers(res) => {
-                    self.handle_get_closest_peers_result(id, res, step.last)
-                }
-                other => {
-                    tracing::debug!(target: "peer", ?id, ?other, "unhandled kademlia query result");
-                    if step.last {
-                        self.discovery_queries.remove(&id);
-                    }
-                }
-            },
-            other => tracing::debug!(target: "peer", ?other, "kademlia event"),
+    fn send_providing_started(&self, request: ProvideRequest, status: DiscoveryStatus) {
+        let event = DiscoveryEvent::ProvidingStarted {
+            request_id: request.request_id,
+            key: request.key.to_vec(),
+            status,
+        };
+
+        let sink = request.results.as_ref().unwrap_or(&self.discovery_sender);
+        if let Err(err) = sink.try_enqueue(event) {
+            tracing::warn!(target: "peer", %err, "failed to enqueue start_providing completion");
         }
     }
 
-    fn handle_get_closest_peers_result(
+    fn handle_get_record_result(
         &mut self,
         query_id: kad::QueryId,
-        result: kad::GetClosestPeersResult,
+        result: kad::GetRecordResult,
         is_last: bool,
     ) {
-        let Some(request) = self.discovery_queries.get(&query_id).cloned() else {
-            tracing::debug!(target: "peer", ?query_id, "ignoring untracked kademlia query");
+        let Some(request) = self.record_queries.get_mut(&query_id) else {
+            tracing::debug!(target: "peer", ?query_id, "ignoring untracked record query");
             return;
         };
+        let request_id = request.request_id;
 
-        match &result {
-            Ok(ok) => match request.kind {
-                DiscoveryKind::FindPeer => {
-                    self.handle_find_peer_response(query_id, &request, ok, is_last);
-                }
-                DiscoveryKind::GetClosestPeers => {
-                    self.handle_closest_peers_response(query_id, &request, ok, is_last);
+        let timed_out = match result {
+            Ok(kad::GetRecordOk::FoundRecord(kad::PeerRecord { record, .. })) => {
+                request.records_found += 1;
+                let event = DiscoveryEvent::Record {
+                    request_id,
+                    key: record.key.to_vec(),
+                    value: record.value,
+                    publisher: record.publisher,
+                };
+                let sink = request.results.as_ref().unwrap_or(&self.discovery_sender);
+                if let Err(err) = sink.try_enqueue(event) {
+                    tracing::warn!(target: "peer", %err, "failed to enqueue record");
                 }
-            },
-            Err(kad::GetClosestPeersError::Timeout { peers, .. }) => {
-                tracing::warn!(
-                    target: "peer",
-                    ?query_id,
-                    request_id = request.request_id,
-                    target = %request.target_peer_id,
-                    "kademlia query timed out"
-                );
+                false
+            }
+            Ok(kad::GetRecordOk::FinishedWithNoAdditionalRecord { .. })
+            | Err(
+                kad::GetRecordError::NotFound { .. } | kad::GetRecordError::QuorumFailed { .. },
+            ) => false,
+            Err(kad::GetRecordError::Timeout { .. }) => {
+                tracing::warn!(target: "peer", ?query_id, request_id, "record query timed out");
+                true
+            }
+        };
 
-                if !peers.is_empty() {
-                    self.process_discovered_peers(&request, peers);
-                }
+        if is_last {
+            if let Some(request) = self.record_queries.remove(&query_id) {
+                let status = match (timed_out, request.records_found) {
+                    (false, 0) => DiscoveryStatus::NotFound,
+                    (false, _) => DiscoveryStatus::Success,
+                    (true, 0) => DiscoveryStatus::Timeout,
+                    (true, peers_found) => DiscoveryStatus::PartialSuccess { peers_found },
+                };
+                self.send_record_finished(request, status);
+            }
+        }
+    }
 
-                if is_last {
-                    self.finish_discovery(query_id, request, DiscoveryStatus::Timeout);
-                }
+    fn handle_put_record_result(
+        &mut self,
+        query_id: kad::QueryId,
+        result: kad::PutRecordResult,
+        is_last: bool,
+    ) {
+        if !is_last {
+            return;
+        }
+        let Some(request) = self.record_queries.remove(&query_id) else {
+            tracing::debug!(target: "peer", ?query_id, "ignoring untracked record query");
+            return;
+        };
+
+        let status = match result {
+            Ok(_) => DiscoveryStatus::Success,
+            Err(
+                kad::PutRecordError::QuorumFailed { success, .. }
+                | kad::PutRecordError::Timeout { success, .. },
+            ) if !success.is_empty() => DiscoveryStatus::PartialSuccess {
+                peers_found: success.len(),
+            },
+            Err(kad::PutRecordError::Timeout { .. }) => DiscoveryStatus::Timeout,
+            Err(err) => {
+                tracing::warn!(target: "peer", %err, request_id = request.request_id, "record put failed");
+                DiscoveryStatus::InternalError
+            }
+        };
+        self.send_record_finished(request, status);
+    }
+
+    fn send_record_finished(&self, request: RecordRequest, status: DiscoveryStatus) {
+        let event = if request.put {
+            DiscoveryEvent::RecordStored {
+                request_id: request.request_id,
+                key: request.key.to_vec(),
+                status,
+            }
+        } else {
+            DiscoveryEvent::RecordsFinished {
+                request_id: request.request_id,
+                key: request.key.to_vec(),
+                status,
+                records_found: request.records_found,
             }
+        };
+
+        let sink = request.results.as_ref().unwrap_or(&self.discovery_sender);
+        if let Err(err) = sink.try_enqueue(event) {
+            tracing::warn!(target: "peer", %err, "failed to enqueue record query completion");
         }
     }
 
@@ -625,18 +6909,18 @@ impl PeerManager {
             if peer.addrs.is_empty() {
                 tracing::warn!(
                     target: "peer",
-                    target = %request.target_peer_id,
+                    target = %self.redact.display(&request.target_peer_id),
                     request_id = request.request_id,
                     "find_peer completed without any addresses"
                 );
             } else {
-                self.process_discovered_peers(request, &[peer.clone()]);
+                self.process_discovered_peers(query_id, request, std::slice::from_ref(peer));
                 status = DiscoveryStatus::Success;
             }
         } else {
             tracing::warn!(
                 target: "peer",
-                target = %request.target_peer_id,
+                target = %self.redact.display(&request.target_peer_id),
                 request_id = request.request_id,
                 "find_peer did not return the target peer"
             );
@@ -657,12 +6941,12 @@ impl PeerManager {
         if response.peers.is_empty() {
             tracing::warn!(
                 target: "peer",
-                target = %request.target_peer_id,
+                target = %self.redact.display(&request.target_peer_id),
                 request_id = request.request_id,
                 "get_closest_peers returned no peers"
             );
         } else {
-            self.process_discovered_peers(request, &response.peers);
+            self.process_discovered_peers(query_id, request, &response.peers);
         }
 
         if is_last {
@@ -670,7 +6954,14 @@ impl PeerManager {
         }
     }
 
-    fn process_discovered_peers(&mut self, request: &DiscoveryRequest, peers: &[kad::PeerInfo]) {
+    fn process_discovered_peers(
+        &mut self,
+        query_id: kad::QueryId,
+        request: &DiscoveryRequest,
+        peers: &[kad::PeerInfo],
+    ) {
+        let mut reported = 0usize;
+
         for peer in peers {
             if peer.peer_id == self.local_peer_id {
                 tracing::debug!(target: "peer", "skipping self in discovery results");
@@ -678,25 +6969,23 @@ impl PeerManager {
             }
 
             let now = Instant::now();
-            let backoff = self
-                .discovery_dial_backoff
-                .entry(peer.peer_id.clone())
-                .or_default();
+            let backoff = self.discovery_dial_backoff.entry(peer.peer_id).or_default();
 
             let mut unique_addresses = HashSet::new();
+            let mut peer_reported = false;
 
             for address in peer
                 .addrs
                 .iter()
+                .filter(|addr| unique_addresses.insert((*addr).clone()))
                 .cloned()
-                .filter(|addr| unique_addresses.insert(addr.clone()))
             {
                 if let Some(next_allowed) = backoff.get(&address) {
                     if *next_allowed > now {
                         tracing::debug!(
                             target: "peer",
-                            peer_id = %peer.peer_id,
-                            %address,
+                            peer_id = %self.redact.display(&peer.peer_id),
+                            address = %self.redact.display(&address),
                             remaining_ms = next_allowed.saturating_duration_since(now).as_millis(),
                             "skipping discovery dial due to backoff",
                         );
@@ -706,55 +6995,237 @@ impl PeerManager {
 
                 let event = DiscoveryEvent::Address {
                     request_id: request.request_id,
-                    target_peer_id: request.target_peer_id.clone(),
-                    peer_id: peer.peer_id.clone(),
+                    target_peer_id: request.target_peer_id,
+                    peer_id: peer.peer_id,
                     address: address.clone(),
                 };
 
-                if let Err(err) = self.discovery_sender.try_enqueue(event) {
+                let sink = request.results.as_ref().unwrap_or(&self.discovery_sender);
+                if let Err(err) = sink.try_enqueue(event) {
                     tracing::warn!(target: "peer", %err, "failed to enqueue discovery address");
+                } else {
+                    peer_reported = true;
                 }
 
                 match self.swarm.dial(address.clone()) {
                     Ok(_) => tracing::info!(
                         target: "peer",
-                        peer_id = %peer.peer_id,
-                        %address,
+                        peer_id = %self.redact.display(&peer.peer_id),
+                        address = %self.redact.display(&address),
                         "dialing discovered peer",
                     ),
                     Err(err) => tracing::warn!(
                         target: "peer",
-                        peer_id = %peer.peer_id,
-                        %address,
-                        %err,
+                        peer_id = %self.redact.display(&peer.peer_id),
+                        address = %self.redact.display(&address),
+                        err = %self.redact.display(&err),
                         "failed to dial discovered peer",
                     ),
                 }
 
                 backoff.insert(address, now + DISCOVERY_DIAL_BACKOFF);
             }
+
+            if peer_reported {
+                reported += 1;
+            }
+        }
+
+        if let Some(request) = self.discovery_queries.get_mut(&query_id) {
+            request.peers_found += reported;
         }
     }
 
     fn finish_discovery(
         &mut self,
         query_id: kad::QueryId,
-        request: DiscoveryRequest,
+        mut request: DiscoveryRequest,
         status: DiscoveryStatus,
     ) {
-        self.discovery_queries.remove(&query_id);
+        if let Some(tracked) = self.discovery_queries.remove(&query_id) {
+            request.peers_found = tracked.peers_found;
+        }
+
+        if is_retryable(&status) && request.retry.allows_retry(request.attempt) {
+            let backoff = request.retry.backoff(request.attempt);
+            tracing::debug!(target: "peer", request_id = request.request_id, ?status, ?backoff, "discovery query unsuccessful; retrying");
+            request.attempt += 1;
+            self.schedule_retry(backoff, RetryTask::Discovery(request));
+            return;
+        }
+        if is_retryable(&status) && matches!(request.kind, DiscoveryKind::FindPeer) {
+            let returned = self.delegate_find_peer(query_id, request, status.clone(), false);
+            if let Some(request) = returned {
+                self.emit_discovery_finished(request, status);
+            }
+            return;
+        }
+        self.emit_discovery_finished(request, status);
+    }
+
+    /// Reports the end of a discovery query to its sink.
+    fn emit_discovery_finished(&self, request: DiscoveryRequest, status: DiscoveryStatus) {
+        let peers_found = request.peers_found;
+        let sink = request.results.as_ref().unwrap_or(&self.discovery_sender);
+
+        let found = matches!(
+            status,
+            DiscoveryStatus::Success | DiscoveryStatus::PartialSuccess { .. }
+        );
+        if self.config.signed_peer_records
+            && found
+            && matches!(request.kind, DiscoveryKind::FindPeer)
+        {
+            if let Some(record) = self.peer_records.get(&request.target_peer_id) {
+                let event = DiscoveryEvent::PeerRecord {
+                    request_id: request.request_id,
+                    target_peer_id: request.target_peer_id,
+                    record: encode_peer_record(record),
+                };
+                if let Err(err) = sink.try_enqueue(event) {
+                    tracing::warn!(target: "peer", %err, "failed to enqueue peer record");
+                }
+            }
+        }
 
         let event = DiscoveryEvent::Finished {
             request_id: request.request_id,
             target_peer_id: request.target_peer_id,
             status,
+            peers_found,
         };
 
-        if let Err(err) = self.discovery_sender.try_enqueue(event) {
+        if let Err(err) = sink.try_enqueue(event) {
             tracing::warn!(target: "peer", %err, "failed to enqueue discovery completion");
         }
     }
 
+    /// Signs the record of the local node, or looks up the latest record of
+    /// `peer_id`.
+    fn export_peer_record(&self, peer_id: Option<PeerId>) -> Result<Option<Vec<u8>>> {
+        match peer_id {
+            Some(peer_id) if peer_id != self.local_peer_id => {
+                Ok(self.peer_records.get(&peer_id).map(encode_peer_record))
+            }
+            _ => {
+                let mut addresses: Vec<Multiaddr> =
+                    self.swarm.external_addresses().cloned().collect();
+                if addresses.is_empty() {
+                    addresses = self.swarm.listeners().cloned().collect();
+                }
+                if addresses.is_empty() {
+                    return Err(anyhow!("no address to put in the peer record yet"));
+                }
+                let record = PeerRecord::new(&self.keypair, addresses)
+                    .map_err(|err| anyhow!("failed to sign peer record: {err}"))?;
+                Ok(Some(encode_peer_record(&record)))
+            }
+        }
+    }
+
+    /// Verifies an exported peer record and teaches Kademlia and the swarm
+    /// the addresses it lists.
+    fn import_peer_record(&mut self, bytes: &[u8]) -> Result<PeerId> {
+        let record = decode_peer_record(bytes)?;
+        let peer_id = record.peer_id();
+        if peer_id == self.local_peer_id {
+            return Err(anyhow!("peer record describes the local node"));
+        }
+        if record.addresses().is_empty() {
+            return Err(anyhow!("peer record of {peer_id} lists no address"));
+        }
+        let addresses = record.addresses().to_vec();
+        let seq = record.seq();
+        if !self.peer_records.insert(record) {
+            return Err(anyhow!(
+                "peer record of {peer_id} is older than the known one"
+            ));
+        }
+        self.learn_peer_addresses(peer_id, &addresses);
+        tracing::info!(
+            target: "peer",
+            peer_id = %self.redact.display(&peer_id),
+            addresses = addresses.len(),
+            seq,
+            "imported signed peer record"
+        );
+        Ok(peer_id)
+    }
+
+    /// Imports the signed records and addresses of an address book. Entries
+    /// describing the local node and records older than the known ones are
+    /// skipped.
+    fn import_peer_records(&mut self, bytes: &[u8]) -> Result<Vec<PeerId>> {
+        let book = parse_address_book(bytes)?;
+        let mut imported: Vec<PeerId> = Vec::new();
+
+        for (peer_id, address) in &book.addresses {
+            if *peer_id == self.local_peer_id {
+                continue;
+            }
+            self.learn_peer_addresses(*peer_id, std::slice::from_ref(address));
+            if !imported.contains(peer_id) {
+                imported.push(*peer_id);
+            }
+        }
+        for record in book.records {
+            let peer_id = record.peer_id();
+            if peer_id == self.local_peer_id || record.addresses().is_empty() {
+                continue;
+            }
+            let addresses = record.addresses().to_vec();
+            if !self.peer_records.insert(record) {
+                tracing::debug!(
+                    target: "peer",
+                    peer_id = %self.redact.display(&peer_id),
+                    "skipping stale peer record"
+                );
+                continue;
+            }
+            self.learn_peer_addresses(peer_id, &addresses);
+            if !imported.contains(&peer_id) {
+                imported.push(peer_id);
+            }
+        }
+        tracing::info!(target: "peer", peers = imported.len(), "imported address book");
+        Ok(imported)
+    }
+
+    /// Adds `addresses` of `peer_id` to the swarm and the routing table, so
+    /// the peer can be dialed and queried without a lookup, and keeps them
+    /// for the swarms rebuilt later.
+    fn learn_peer_addresses(&mut self, peer_id: PeerId, addresses: &[Multiaddr]) {
+        let imported = self.imported_addresses.entry(peer_id).or_default();
+        for address in addresses {
+            if !imported.contains(address) {
+                imported.push(address.clone());
+            }
+        }
+        for address in addresses {
+            self.swarm.add_peer_address(peer_id, address.clone());
+            self.swarm
+                .behaviour_mut()
+                .kademlia
+                .add_address(&peer_id, address.clone());
+        }
+    }
+
+    /// Keeps the signed record `peer_id` sent via identify, when it is valid
+    /// and describes the sender.
+    fn remember_peer_record(&mut self, peer_id: PeerId, envelope: SignedEnvelope) {
+        match PeerRecord::from_signed_envelope(envelope) {
+            Ok(record) if record.peer_id() == peer_id => {
+                self.peer_records.insert(record);
+            }
+            Ok(_) => {
+                tracing::warn!(target: "peer", peer_id = %self.redact.display(&peer_id), "ignoring peer record signed by another peer");
+            }
+            Err(err) => {
+                tracing::debug!(target: "peer", peer_id = %self.redact.display(&peer_id), %err, "ignoring invalid peer record");
+            }
+        }
+    }
+
     // Adding bootstraps into node's DHT initial network
     fn add_bootstrap_peers(&mut self, peers: Vec<Multiaddr>) {
         let mut added = 0usize;
@@ -765,8 +7236,8 @@ impl PeerManager {
                 Some(libp2p::multiaddr::Protocol::P2p(peer_id)) => {
                     tracing::info!(
                         target: "peer",
-                        %peer_id,
-                        address = %addr,
+                        peer_id = %self.redact.display(&peer_id),
+                        address = %self.redact.display(&addr),
                         "adding bootstrap peer"
                     );
                     self.swarm
@@ -778,8 +7249,8 @@ impl PeerManager {
                 other => {
                     tracing::warn!(
                         target: "peer",
-                        ?other,
-                        address = %addr,
+                        other = %self.redact.debug(&other),
+                        address = %self.redact.display(&addr),
                         "bootstrap peer missing p2p component"
                     );
                 }
@@ -789,6 +7260,8 @@ impl PeerManager {
         match self.swarm.behaviour_mut().kademlia.bootstrap() {
             Ok(query_id) => {
                 tracing::info!(target: "peer", ?query_id, added, "started kademlia bootstrap");
+                self.bootstrap_query = Some(query_id);
+                self.bootstrap_reached = false;
             }
             Err(err) => {
                 tracing::warn!(target: "peer", %err, added, "failed to start kademlia bootstrap");
@@ -796,52 +7269,131 @@ impl PeerManager {
         }
     }
 
-    fn try_dial_via_relay(&mut self, target_peer_id: &PeerId, error: &DialError) {
+    /// Falls back to peer exchange once the bootstrap from the configured
+    /// bootstrap peers finished without reaching any of them.
+    fn handle_bootstrap_result(
+        &mut self,
+        query_id: kad::QueryId,
+        result: kad::BootstrapResult,
+        is_last: bool,
+    ) {
+        if !is_last {
+            return;
+        }
+        if self.exchange_query == Some(query_id) {
+            self.exchange_query = None;
+            match result {
+                Ok(_) => {
+                    tracing::info!(target: "peer", ?query_id, "peer exchange bootstrap finished")
+                }
+                Err(err) => {
+                    tracing::warn!(target: "peer", ?query_id, %err, "peer exchange bootstrap failed")
+                }
+            }
+            return;
+        }
+        if self.bootstrap_query != Some(query_id) {
+            return;
+        }
+        self.bootstrap_query = None;
+        if self.bootstrap_reached || self.bootstrap_peers.is_empty() {
+            return;
+        }
+        tracing::warn!(
+            target: "peer",
+            ?query_id,
+            error = ?result.err(),
+            "no bootstrap peer reachable; falling back to peer exchange"
+        );
+        self.start_peer_exchange();
+    }
+
+    /// Seeds the routing table with the listen addresses connected peers
+    /// advertised via identify and bootstraps through them, so their
+    /// Kademlia answers point at the other peers they know.
+    fn start_peer_exchange(&mut self) {
+        let candidates: Vec<(PeerId, Vec<Multiaddr>)> = self
+            .exchange_addresses
+            .iter()
+            .filter(|(peer_id, _)| self.swarm.is_connected(peer_id))
+            .map(|(peer_id, addresses)| (*peer_id, addresses.clone()))
+            .collect();
+        if candidates.is_empty() {
+            tracing::warn!(target: "peer", "no connected peer to exchange addresses with");
+            return;
+        }
+
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        for (peer_id, addresses) in &candidates {
+            for address in addresses {
+                kademlia.add_address(peer_id, address.clone());
+            }
+        }
+        match kademlia.bootstrap() {
+            Ok(query_id) => {
+                tracing::info!(target: "peer", ?query_id, peers = candidates.len(), "started peer exchange bootstrap");
+                self.exchange_query = Some(query_id);
+            }
+            Err(err) => {
+                tracing::warn!(target: "peer", %err, "failed to start peer exchange bootstrap");
+            }
+        }
+    }
+
+    /// Retries a failed dial through the reserved relay. Returns `true` when a
+    /// relay circuit dial was started.
+    fn try_dial_via_relay(&mut self, target_peer_id: &PeerId, error: &DialError) -> bool {
         if self.relay_peer_id.as_ref() == Some(target_peer_id) {
             tracing::debug!(
                 target: "peer",
-                %target_peer_id,
+                target_peer_id = %self.redact.display(&target_peer_id),
                 "skipping relay fallback when dialing relay peer itself",
             );
-            return;
+            return false;
         }
 
         let Some(relay_base_address) = self.relay_base_address.clone() else {
             tracing::debug!(
                 target: "peer",
-                %target_peer_id,
+                target_peer_id = %self.redact.display(&target_peer_id),
                 "no relay reservation available for fallback dialing",
             );
-            return;
+            return false;
         };
 
         if dial_error_involves_circuit(error) {
             tracing::debug!(
                 target: "peer",
-                %target_peer_id,
+                target_peer_id = %self.redact.display(&target_peer_id),
                 "dial attempt already used a relay circuit; skipping fallback",
             );
-            return;
+            return false;
         }
 
         let mut relay_circuit_addr = relay_base_address.clone();
         relay_circuit_addr.push(Protocol::P2pCircuit);
-        relay_circuit_addr.push(Protocol::P2p(target_peer_id.clone()));
+        relay_circuit_addr.push(Protocol::P2p(*target_peer_id));
 
         match self.swarm.dial(relay_circuit_addr.clone()) {
-            Ok(_) => tracing::info!(
-                target: "peer",
-                %relay_circuit_addr,
-                %target_peer_id,
-                "retrying dial via relay circuit",
-            ),
-            Err(err) => tracing::error!(
-                target: "peer",
-                %relay_circuit_addr,
-                %target_peer_id,
-                %err,
-                "failed to dial via relay circuit",
-            ),
+            Ok(_) => {
+                tracing::info!(
+                    target: "peer",
+                    relay_circuit_addr = %self.redact.display(&relay_circuit_addr),
+                    target_peer_id = %self.redact.display(&target_peer_id),
+                    "retrying dial via relay circuit",
+                );
+                true
+            }
+            Err(err) => {
+                tracing::error!(
+                    target: "peer",
+                    relay_circuit_addr = %self.redact.display(&relay_circuit_addr),
+                    target_peer_id = %self.redact.display(&target_peer_id),
+                    err = %self.redact.display(&err),
+                    "failed to dial via relay circuit",
+                );
+                false
+            }
         }
     }
 
@@ -851,8 +7403,8 @@ impl PeerManager {
         {
             tracing::info!(
                 target: "peer",
-                %base_address,
-                relay_id = %relay_peer_id,
+                base_address = %self.redact.display(&base_address),
+                relay_id = %self.redact.display(&relay_peer_id),
                 "updated relay base address",
             );
 
@@ -862,7 +7414,7 @@ impl PeerManager {
                 // <relay_base>/p2p-circuit/p2p/<yourPeerId>
                 let mut reachable = base_address.clone();
                 reachable.push(Protocol::P2pCircuit);
-                reachable.push(Protocol::P2p(self.local_peer_id.clone()));
+                reachable.push(Protocol::P2p(self.local_peer_id));
 
                 self.emit_addr_event(AddrEvent::RelayReachableReady { address: reachable });
             }
@@ -875,16 +7427,58 @@ impl PeerManager {
         } else {
             tracing::debug!(
                 target: "peer",
-                %address,
+                address = %self.redact.display(&address),
                 "external address is not a relay reservation for this peer",
             );
         }
     }
 
+    /// Publishes `<relay>/p2p-circuit/p2p/<self>` as a confirmed external
+    /// address once a reservation on `relay_peer_id` is accepted, so identify
+    /// pushes it to connected peers and other nodes can dial through the relay.
+    fn advertise_relay_circuit(&mut self, relay_peer_id: PeerId) {
+        let local_peer_id = self.local_peer_id;
+        let base_address = self
+            .swarm
+            .listeners()
+            .chain(self.listen_addrs.iter())
+            .find_map(|address| {
+                relay_base_from_external(address, &local_peer_id)
+                    .filter(|(_, relay)| *relay == relay_peer_id)
+                    .map(|(base, _)| base)
+            })
+            .or_else(|| {
+                self.relay_base_address.clone().filter(|base| {
+                    matches!(base.iter().last(), Some(Protocol::P2p(relay)) if relay == relay_peer_id)
+                })
+            });
+
+        let Some(base_address) = base_address else {
+            tracing::debug!(
+                target: "peer",
+                relay_id = %self.redact.display(&relay_peer_id),
+                "no known address for accepted relay reservation"
+            );
+            return;
+        };
+
+        let mut reachable = base_address;
+        reachable.push(Protocol::P2pCircuit);
+        reachable.push(Protocol::P2p(local_peer_id));
+
+        tracing::info!(
+            target: "peer",
+            address = %self.redact.display(&reachable),
+            "advertising relay circuit address"
+        );
+        // Reported back as `ExternalAddrConfirmed`, which updates the address state.
+        self.swarm.add_external_address(reachable);
+    }
+
     fn clear_relay_address(&mut self, address: &Multiaddr) {
         if let Some((base_address, _)) = relay_base_from_external(address, &self.local_peer_id) {
             if self.relay_base_address.as_ref() == Some(&base_address) {
-                tracing::info!(target: "peer", %base_address, "clearing relay base address");
+                tracing::info!(target: "peer", base_address = %self.redact.display(&base_address), "clearing relay base address");
                 self.relay_base_address = None;
 
                 // relay reachable snapshot clear
@@ -893,6 +7487,44 @@ impl PeerManager {
         }
     }
 
+    /// Emits [`NetworkEvent::ConnectionEstablished`] for `connection_id` the
+    /// first time identify completes or fails on it.
+    fn report_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        protocols: Vec<String>,
+        agent_version: Option<String>,
+    ) {
+        let Some((peer_id, address)) = self.connection_addrs.get(&connection_id).cloned() else {
+            return;
+        };
+        if !self.reported_connections.insert(connection_id) {
+            return;
+        }
+        let (expected_security, expected_muxer) = expected_connection_stack(&address);
+        tracing::debug!(
+            target: "peer",
+            peer_id = %self.redact.display(&peer_id),
+            expected_security,
+            expected_muxer,
+            ?protocols,
+            "connection protocols negotiated"
+        );
+        self.emit_network_event(NetworkEvent::ConnectionEstablished(ConnectionInfo {
+            peer_id,
+            address,
+            expected_security,
+            expected_muxer,
+            protocols,
+            agent_version,
+        }));
+    }
+
+    /// Broadcasts `event` to subscribers; dropped when nobody is subscribed.
+    fn emit_network_event(&self, event: NetworkEvent) {
+        let _ = self.network_events.send(event);
+    }
+
     fn emit_addr_event(&mut self, ev: AddrEvent) {
         if let Ok(mut st) = self.addr_state.write() {
             st.apply(&ev);
@@ -900,9 +7532,41 @@ impl PeerManager {
             tracing::warn!(target:"peer", "addr_state lock poisoned");
         }
 
-        tracing::debug!(target:"peer", ?ev, "addr event");
+        tracing::debug!(target:"peer", ev = %self.redact.debug(&ev), "addr event");
+    }
+
+}
+
+/// Span tagging the logs about one message with its trace id, or no span
+/// when the message is not traced.
+fn message_span(trace_id: Option<TraceId>) -> tracing::Span {
+    match trace_id {
+        Some(trace_id) => tracing::info_span!(target: "peer", "message", %trace_id),
+        None => tracing::Span::none(),
+    }
+}
+
+/// Checks that `name` can be subscribed next to the default topic: it must
+/// not be empty, and group topics are left to [`PeerManagerHandle::join_group`].
+pub fn validate_topic(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(anyhow!("topic must not be empty"));
+    }
+    if name.starts_with(GROUP_TOPIC_PREFIX) {
+        return Err(anyhow!(
+            "topic {name} is reserved for groups; join the group instead"
+        ));
     }
+    Ok(())
+}
 
+/// Address revealing the transport of a connection: the dialed address, or
+/// the local listen address for inbound connections.
+fn transport_address(endpoint: &ConnectedPoint) -> &Multiaddr {
+    match endpoint {
+        ConnectedPoint::Dialer { address, .. } => address,
+        ConnectedPoint::Listener { local_addr, .. } => local_addr,
+    }
 }
 
 fn extract_peer_id(address: &Multiaddr) -> Option<PeerId> {
@@ -915,6 +7579,28 @@ fn extract_peer_id(address: &Multiaddr) -> Option<PeerId> {
         .last()
 }
 
+/// Deduplicates `addresses` and orders them direct QUIC first, then direct
+/// TCP, then relay circuits, then anything else.
+///
+/// The swarm dials up to its dial concurrency factor of addresses at once, in
+/// this order, followed by those the behaviours add; it is a preference for
+/// which dials start first, not a sequence waiting for each to fail.
+fn order_dial_addresses(mut addresses: Vec<Multiaddr>) -> Vec<Multiaddr> {
+    addresses.sort();
+    addresses.dedup();
+    addresses.sort_by_key(|address| {
+        [
+            DialTransport::Quic,
+            DialTransport::Tcp,
+            DialTransport::Relay,
+        ]
+        .iter()
+        .position(|transport| transport.matches(address))
+        .unwrap_or(3)
+    });
+    addresses
+}
+
 fn dial_error_involves_circuit(error: &DialError) -> bool {
     match error {
         DialError::Transport(address_errors) => address_errors.iter().any(|(addr, _)| {
@@ -935,7 +7621,7 @@ fn relay_base_from_external(
         // .../p2p-circuit/p2p/<local> Format of addr
         (Some(Protocol::P2p(local)), Some(Protocol::P2pCircuit)) if local == *local_peer_id => {
             match addr.iter().last() {
-                Some(Protocol::P2p(relay_peer_id)) => Some((addr, relay_peer_id.clone())),
+                Some(Protocol::P2p(relay_peer_id)) => Some((addr, relay_peer_id)),
                 _ => None,
             }
         }
@@ -948,11 +7634,42 @@ fn relay_base_from_external(
             base.pop(); // poping p2p-circuit
 
             match base.iter().last() {
-                Some(Protocol::P2p(relay_peer_id)) => Some((base, relay_peer_id.clone())),
+                Some(Protocol::P2p(relay_peer_id)) => Some((base, relay_peer_id)),
                 _ => None,
             }
         }
-        
+
         _ => None,
     }
-}
\ No newline at end of file
+}
+
+/// Builds the ciphers for the topics configured with a shared secret.
+fn topic_ciphers(config: &TransportConfig) -> HashMap<gossipsub::TopicHash, TopicCipher> {
+    config
+        .topic_keys
+        .iter()
+        .map(|(topic, key)| {
+            let hash = gossipsub::IdentTopic::new(topic.as_str()).hash();
+            (hash, TopicCipher::new(topic.as_str(), key))
+        })
+        .collect()
+}
+
+/// Whether a discovery query ending with `status` is worth another attempt.
+fn is_retryable(status: &DiscoveryStatus) -> bool {
+    matches!(status, DiscoveryStatus::NotFound | DiscoveryStatus::Timeout)
+}
+
+/// Maps a Kademlia query result to its statistics label and success flag.
+fn query_outcome(result: &QueryResult) -> (&'static str, bool) {
+    match result {
+        QueryResult::Bootstrap(res) => ("bootstrap", res.is_ok()),
+        QueryResult::GetClosestPeers(res) => ("get_closest_peers", res.is_ok()),
+        QueryResult::GetProviders(res) => ("get_providers", res.is_ok()),
+        QueryResult::StartProviding(res) => ("start_providing", res.is_ok()),
+        QueryResult::RepublishProvider(res) => ("republish_provider", res.is_ok()),
+        QueryResult::GetRecord(res) => ("get_record", res.is_ok()),
+        QueryResult::PutRecord(res) => ("put_record", res.is_ok()),
+        QueryResult::RepublishRecord(res) => ("republish_record", res.is_ok()),
+    }
+}
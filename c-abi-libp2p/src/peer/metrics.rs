@@ -0,0 +1,204 @@
+//! Periodic metrics snapshots for devices without a Prometheus scraper.
+//!
+//! Snapshots are either appended to a local file, one logfmt line per
+//! snapshot, or kept in an in-memory ring buffer readable via
+//! [`crate::peer::PeerManagerHandle::metrics_history`]. The file is written
+//! from a background thread and rotated once it exceeds its size limit.
+
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    path::PathBuf,
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::{
+    peer::{file_writer::FileWriter, ConnectionCounts, DhtStoreStats, RelayServerStats},
+    transport::SubstreamStats,
+};
+
+/// Default size in bytes after which the metrics file is rotated.
+pub const DEFAULT_METRICS_FILE_BYTES: u64 = 1024 * 1024;
+/// Default number of rotated metrics files kept.
+pub const DEFAULT_METRICS_ROTATED_FILES: usize = 3;
+
+/// Destination of periodic metrics snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetricsSink {
+    /// Append one line per snapshot to the file at `path`, rotating it once
+    /// it exceeds `max_file_bytes` and keeping `max_rotated_files` old ones.
+    File {
+        path: PathBuf,
+        max_file_bytes: u64,
+        max_rotated_files: usize,
+    },
+    /// Keep the most recent snapshots in memory, up to this many.
+    RingBuffer(usize),
+}
+
+impl MetricsSink {
+    /// Appends to the file at `path` with the default rotation limits.
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Self::File {
+            path: path.into(),
+            max_file_bytes: DEFAULT_METRICS_FILE_BYTES,
+            max_rotated_files: DEFAULT_METRICS_ROTATED_FILES,
+        }
+    }
+}
+
+/// Settings of the periodic metrics snapshot task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricsSnapshotConfig {
+    /// Time between two snapshots.
+    pub interval: Duration,
+    /// Where snapshots are written.
+    pub sink: MetricsSink,
+}
+
+/// Point-in-time view of the node's health counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Wall-clock time of the snapshot in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// Number of peers with at least one open connection.
+    pub connected_peers: usize,
+    /// Number of entries in the Kademlia routing table.
+    pub routing_table_size: usize,
+    /// Records held in the local Kademlia store.
+    pub dht_records: usize,
+    /// Records and provider records the Kademlia store evicted or refused.
+    pub dht_store: DhtStoreStats,
+    /// Inbound messages waiting to be dequeued by the application.
+    pub inbound_queue_depth: usize,
+    /// Inbound messages that outlived their time to live before being dequeued.
+    pub inbound_expired: u64,
+    /// Publishes that outlived their time to live before being sent.
+    pub outbound_expired: u64,
+    /// Discovery events waiting to be drained by the application.
+    pub discovery_queue_depth: usize,
+    /// Substream and byte counters keyed by negotiated protocol.
+    pub substreams: BTreeMap<String, SubstreamStats>,
+    /// Relay server reservations, circuits and rejections.
+    pub relay: RelayServerStats,
+    /// Active connections keyed by transport.
+    pub connections: BTreeMap<String, ConnectionCounts>,
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ts_ms={} connected_peers={} routing_table={} inbound_queue={} discovery_queue={} inbound_expired={} outbound_expired={}",
+            self.timestamp_ms,
+            self.connected_peers,
+            self.routing_table_size,
+            self.inbound_queue_depth,
+            self.discovery_queue_depth,
+            self.inbound_expired,
+            self.outbound_expired,
+        )?;
+        write!(
+            f,
+            " relay.reservations={} relay.circuits={} relay.reservations_denied={} relay.circuits_denied={}",
+            self.relay.active_reservations,
+            self.relay.active_circuits,
+            self.relay.reservations_denied,
+            self.relay.circuits_denied,
+        )?;
+        write!(
+            f,
+            " dht.records={} dht.records_evicted={} dht.records_rejected={} dht.providers_rejected={}",
+            self.dht_records,
+            self.dht_store.records_evicted,
+            self.dht_store.records_rejected,
+            self.dht_store.providers_rejected,
+        )?;
+        for (transport, counts) in &self.connections {
+            write!(
+                f,
+                " {transport}.conn_in={} {transport}.conn_out={}",
+                counts.inbound, counts.outbound,
+            )?;
+        }
+        for (protocol, stats) in &self.substreams {
+            write!(
+                f,
+                " {protocol}.substreams={} {protocol}.bytes_in={} {protocol}.bytes_out={}",
+                stats.active(),
+                stats.bytes_inbound,
+                stats.bytes_outbound,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes snapshots to the configured sink.
+#[derive(Debug)]
+pub(crate) struct MetricsRecorder {
+    sink: MetricsSink,
+    /// Background writer of the file sink.
+    file: Option<FileWriter>,
+    history: Mutex<VecDeque<MetricsSnapshot>>,
+}
+
+impl MetricsRecorder {
+    pub(crate) fn new(sink: MetricsSink) -> Result<Self> {
+        let file = match &sink {
+            MetricsSink::File {
+                path,
+                max_file_bytes,
+                max_rotated_files,
+            } => Some(FileWriter::spawn(
+                "metrics",
+                path.clone(),
+                *max_file_bytes,
+                *max_rotated_files,
+            )?),
+            MetricsSink::RingBuffer(_) => None,
+        };
+        Ok(Self {
+            sink,
+            file,
+            history: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// Stores the snapshot in the sink.
+    pub(crate) fn record(&self, snapshot: MetricsSnapshot) -> Result<()> {
+        match &self.sink {
+            MetricsSink::File { .. } => match &self.file {
+                Some(file) => file.write(format!("{snapshot}\n").into_bytes()),
+                None => Ok(()),
+            },
+            MetricsSink::RingBuffer(capacity) => {
+                let Ok(mut history) = self.history.lock() else {
+                    tracing::warn!(target: "peer", "metrics history lock poisoned");
+                    return Ok(());
+                };
+                if *capacity == 0 {
+                    return Ok(());
+                }
+                while history.len() >= *capacity {
+                    history.pop_front();
+                }
+                history.push_back(snapshot);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the buffered snapshots, oldest first.
+    pub(crate) fn history(&self) -> Vec<MetricsSnapshot> {
+        match self.history.lock() {
+            Ok(history) => history.iter().cloned().collect(),
+            Err(_) => {
+                tracing::warn!(target: "peer", "metrics history lock poisoned");
+                Vec::new()
+            }
+        }
+    }
+}
@@ -1,18 +1,79 @@
 //! Peer-related primitives and utilities.
 
+pub mod addr_events;
+pub mod audit;
+pub mod connections;
+pub mod delegated;
+pub mod dht_stats;
 pub mod discovery;
+pub mod events;
+pub(crate) mod file_writer;
+pub mod gossip_rates;
+pub mod graph;
+pub mod keys;
 pub mod manager;
-pub mod addr_events;
+pub mod metrics;
+pub mod node_events;
+pub mod observed;
+pub mod peer_records;
+pub mod prometheus;
+pub mod records;
+pub(crate) mod redact;
+pub mod relay_events;
+pub mod relay_stats;
+pub mod retry;
+pub mod version;
+
+pub use addr_events::{AddrEvent, AddrState};
 
-pub use addr_events::{
-    AddrEvent, AddrState,
+pub use audit::{AuditLogConfig, DEFAULT_AUDIT_FILE_BYTES, DEFAULT_AUDIT_ROTATED_FILES};
+pub use connections::{
+    ConnectionCounts, ConnectionMetrics, DuplicateConnectionPolicy, PeerConnection,
 };
+pub use delegated::{
+    DelegatedRoutingSettings, DEFAULT_DELEGATED_ROUTING_TIMEOUT, MAX_DELEGATED_RESPONSE_BYTES,
+};
+pub use dht_stats::{DhtMetrics, DhtQueryStats, DhtStoreStats};
 
 pub use discovery::{
-    DiscoveryEvent, DiscoveryEventSender, DiscoveryQueue, DiscoveryStatus,
+    DiscoveryEvent, DiscoveryEventSender, DiscoveryQueue, DiscoveryStatus, QueryLimits,
     DEFAULT_DISCOVERY_QUEUE_CAPACITY,
 };
-pub use manager::{PeerCommand, PeerManager, PeerManagerHandle};
+pub use events::{
+    ConnectionClosedInfo, ConnectionDeduplicatedInfo, ConnectionInfo, DialFinishedEvent,
+    GroupEvent, HolePunchEvent, IncompatiblePeerInfo, NetworkEvent, PeerPenalizedInfo,
+    PenaltyReason, SlowConsumerEvent, SubscriptionChange, DEFAULT_NETWORK_EVENT_CAPACITY,
+};
+pub use gossip_rates::{
+    GossipRates, GossipThrottleSettings, GossipVerdict, PeerGossipStats,
+    DEFAULT_GOSSIP_THROTTLE_WINDOW,
+};
+pub use graph::{
+    PeerGraph, PeerGraphDiff, PeerGraphState, PeerRelations, DEFAULT_PEER_GRAPH_CAPACITY,
+    PEER_GRAPH_INTERVAL,
+};
+pub use keys::{Key, MAX_KEY_ID_LEN, MAX_NAMESPACE_LEN};
+pub use manager::{
+    AddressProbeOutcome, ConnectOutcome, DialTransport, NetworkChange, PeerCommand, PeerManager,
+    PeerManagerHandle, PeerProbeReport, PublishReceipt, ReloadReport, RuntimeConfig,
+    TopicSubscription,
+};
+pub use metrics::{
+    MetricsSink, MetricsSnapshot, MetricsSnapshotConfig, DEFAULT_METRICS_FILE_BYTES,
+    DEFAULT_METRICS_ROTATED_FILES,
+};
+pub use node_events::{try_next_queued, NodeEvent, NodeEventStream};
+pub use observed::{ObservedAddress, ObservedAddrs};
+pub use peer_records::{
+    decode_peer_record, encode_peer_record, parse_address_book, AddressBook, PeerRecordCache,
+    DEFAULT_PEER_RECORD_CAPACITY,
+};
+pub use prometheus::PrometheusMetrics;
+pub use records::{RecordRules, RecordValidator};
+pub use relay_events::RelayReservationEvent;
+pub use relay_stats::{RelayMetrics, RelayPeerStats, RelayServerStats};
+pub use retry::RetryPolicy;
+pub use version::{VersionGate, VersionKind, VersionMismatch, VersionRequirement};
 
 
 /// Represents the local peer identity and metadata.
@@ -0,0 +1,87 @@
+//! Single-stream view over every event source of a node.
+
+use libp2p::autonat;
+use tokio::sync::{broadcast, watch};
+
+use super::{DiscoveryEvent, DiscoveryQueue, NetworkEvent};
+use crate::messaging::{MessageQueue, RoutedMessage};
+
+/// Any event produced by a node, for consumers that prefer polling one stream
+/// over the separate message, discovery and network event APIs.
+#[derive(Debug, Clone)]
+pub enum NodeEvent {
+    /// A message received from the network, tagged with its topic.
+    Message(RoutedMessage),
+    /// Result of a discovery query.
+    Discovery(DiscoveryEvent),
+    /// Connection changes, hole punches, relay reservations and slow-consumer
+    /// state.
+    Network(NetworkEvent),
+    /// The AutoNAT status changed.
+    NatStatus(autonat::NatStatus),
+    /// An event source failed, e.g. network events were missed because the
+    /// stream was not polled fast enough.
+    Error(String),
+}
+
+/// Merges the event sources of a running node into one stream of
+/// [`NodeEvent`]s.
+///
+/// Messages and discovery results are taken from the same queues as their
+/// `try_dequeue` methods, so a consumer should use either this stream or the
+/// per-source APIs, not both.
+#[derive(Debug)]
+pub struct NodeEventStream {
+    network_events: broadcast::Receiver<NetworkEvent>,
+    nat_status: watch::Receiver<autonat::NatStatus>,
+}
+
+impl NodeEventStream {
+    pub(crate) fn new(
+        network_events: broadcast::Receiver<NetworkEvent>,
+        nat_status: watch::Receiver<autonat::NatStatus>,
+    ) -> Self {
+        Self {
+            network_events,
+            nat_status,
+        }
+    }
+
+    /// Returns the next pending event without waiting.
+    ///
+    /// NAT status changes come first, then network events, discovery results
+    /// and inbound messages, so a flood of messages cannot starve the rarer
+    /// events.
+    pub fn try_next(
+        &mut self,
+        discovery: &mut DiscoveryQueue,
+        messages: &mut MessageQueue,
+    ) -> Option<NodeEvent> {
+        if self.nat_status.has_changed().unwrap_or(false) {
+            let status = self.nat_status.borrow_and_update().clone();
+            return Some(NodeEvent::NatStatus(status));
+        }
+
+        match self.network_events.try_recv() {
+            Ok(event) => return Some(NodeEvent::Network(event)),
+            Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                return Some(NodeEvent::Error(format!("missed {missed} network events")));
+            }
+            Err(_) => {}
+        }
+
+        try_next_queued(discovery, messages)
+    }
+}
+
+/// Returns the next queued discovery result or message, for nodes that are not
+/// running and so have no status or network events.
+pub fn try_next_queued(
+    discovery: &mut DiscoveryQueue,
+    messages: &mut MessageQueue,
+) -> Option<NodeEvent> {
+    discovery
+        .try_dequeue()
+        .map(NodeEvent::Discovery)
+        .or_else(|| messages.try_dequeue().map(NodeEvent::Message))
+}
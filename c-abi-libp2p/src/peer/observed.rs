@@ -0,0 +1,116 @@
+//! Aggregation of the addresses remote peers observe us at.
+//!
+//! Identify reports the address each remote peer sees our connection coming
+//! from. A single peer can lie about it, so an address is only treated as
+//! external once enough distinct connected peers reported it.
+
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet};
+
+/// Address observed by remote peers, see
+/// [`crate::peer::PeerManagerHandle::observed_addresses`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObservedAddress {
+    pub address: Multiaddr,
+    /// Distinct connected peers currently reporting the address.
+    pub confirmations: usize,
+    /// Whether the address reached the confirmation threshold and is
+    /// advertised as external.
+    pub confirmed: bool,
+}
+
+/// Latest observed address per connected peer, with the set of addresses
+/// promoted to external once reported by enough peers.
+#[derive(Debug, Default)]
+pub struct ObservedAddrs {
+    /// Distinct reporters needed to confirm an address; `None` only collects.
+    threshold: Option<usize>,
+    by_peer: HashMap<PeerId, Multiaddr>,
+    confirmed: HashSet<Multiaddr>,
+}
+
+impl ObservedAddrs {
+    /// Creates an aggregator confirming addresses reported by `threshold`
+    /// distinct peers, or only collecting reports when `None`.
+    pub fn new(threshold: Option<usize>) -> Self {
+        Self {
+            threshold,
+            ..Self::default()
+        }
+    }
+
+    /// Changes the confirmation threshold; see [`Self::take_changes`].
+    pub fn set_threshold(&mut self, threshold: Option<usize>) {
+        self.threshold = threshold;
+    }
+
+    /// Records that `peer_id` currently observes us at `address`, replacing
+    /// its previous report.
+    pub fn observe(&mut self, peer_id: PeerId, address: Multiaddr) {
+        self.by_peer.insert(peer_id, address);
+    }
+
+    /// Drops the report of `peer_id`, e.g. once it disconnected.
+    pub fn forget(&mut self, peer_id: &PeerId) {
+        self.by_peer.remove(peer_id);
+    }
+
+    /// Reconciles the confirmed set with the current reports and returns the
+    /// addresses that became confirmed (`true`) or lost confirmation (`false`).
+    pub fn take_changes(&mut self) -> Vec<(Multiaddr, bool)> {
+        let counts = self.counts();
+        let should_confirm: HashSet<&Multiaddr> = counts
+            .iter()
+            .filter(|(_, count)| self.threshold.is_some_and(|threshold| **count >= threshold))
+            .map(|(address, _)| *address)
+            .collect();
+
+        let mut changes: Vec<(Multiaddr, bool)> = should_confirm
+            .iter()
+            .filter(|address| !self.confirmed.contains(**address))
+            .map(|address| ((*address).clone(), true))
+            .collect();
+        changes.extend(
+            self.confirmed
+                .iter()
+                .filter(|address| !should_confirm.contains(address))
+                .map(|address| (address.clone(), false)),
+        );
+
+        for (address, confirmed) in &changes {
+            if *confirmed {
+                self.confirmed.insert(address.clone());
+            } else {
+                self.confirmed.remove(address);
+            }
+        }
+        changes
+    }
+
+    /// Reported addresses with their confirmation counts, most reported first.
+    pub fn snapshot(&self) -> Vec<ObservedAddress> {
+        let mut addresses: Vec<ObservedAddress> = self
+            .counts()
+            .into_iter()
+            .map(|(address, confirmations)| ObservedAddress {
+                address: address.clone(),
+                confirmations,
+                confirmed: self.confirmed.contains(address),
+            })
+            .collect();
+        addresses.sort_by(|a, b| {
+            b.confirmations
+                .cmp(&a.confirmations)
+                .then_with(|| a.address.cmp(&b.address))
+        });
+        addresses
+    }
+
+    fn counts(&self) -> HashMap<&Multiaddr, usize> {
+        let mut counts = HashMap::new();
+        for address in self.by_peer.values() {
+            *counts.entry(address).or_default() += 1;
+        }
+        counts
+    }
+}
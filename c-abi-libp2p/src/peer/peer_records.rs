@@ -0,0 +1,143 @@
+//! Signed peer records shared out-of-band, e.g. through a QR code or a
+//! configuration file.
+
+use anyhow::{anyhow, Context, Result};
+use libp2p::{
+    core::{PeerRecord, SignedEnvelope},
+    multiaddr::Protocol,
+    Multiaddr, PeerId,
+};
+use std::collections::{HashMap, VecDeque};
+
+/// Default number of signed peer records kept for other peers.
+pub const DEFAULT_PEER_RECORD_CAPACITY: usize = 256;
+
+/// Encodes `record` as its protobuf signed envelope, the libp2p wire format.
+pub fn encode_peer_record(record: &PeerRecord) -> Vec<u8> {
+    record.to_signed_envelope().into_protobuf_encoding()
+}
+
+/// Decodes a signed envelope and checks it holds a peer record signed by the
+/// peer it describes.
+pub fn decode_peer_record(bytes: &[u8]) -> Result<PeerRecord> {
+    let envelope = SignedEnvelope::from_protobuf_encoding(bytes)
+        .map_err(|err| anyhow!("malformed signed envelope: {err}"))?;
+    PeerRecord::from_signed_envelope(envelope)
+        .map_err(|err| anyhow!("invalid signed peer record: {err}"))
+}
+
+/// Peers and addresses read by [`parse_address_book`].
+#[derive(Debug, Default)]
+pub struct AddressBook {
+    /// Signed records, already verified.
+    pub records: Vec<PeerRecord>,
+    /// Addresses vouched for by nobody but the book's author.
+    pub addresses: Vec<(PeerId, Multiaddr)>,
+}
+
+impl AddressBook {
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty() && self.addresses.is_empty()
+    }
+}
+
+/// Reads a single signed peer record, as exported by `export_peer_record`,
+/// or a text address book with one entry per line: a multiaddr ending in
+/// `/p2p/<peer id>` or a hex-encoded signed peer record. Blank lines and
+/// lines starting with `#` are skipped. Any invalid entry fails the whole
+/// book, so a typo does not go unnoticed.
+pub fn parse_address_book(bytes: &[u8]) -> Result<AddressBook> {
+    let mut book = AddressBook::default();
+    if let Ok(record) = decode_peer_record(bytes) {
+        book.records.push(record);
+        return Ok(book);
+    }
+
+    let text = std::str::from_utf8(bytes)
+        .map_err(|_| anyhow!("neither a signed peer record nor a text address book"))?;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let number = index + 1;
+        if line.starts_with('/') {
+            let mut address: Multiaddr = line
+                .parse()
+                .map_err(|err| anyhow!("line {number}: invalid multiaddr: {err}"))?;
+            let Some(Protocol::P2p(peer_id)) = address.pop() else {
+                return Err(anyhow!(
+                    "line {number}: address lacks a trailing /p2p/<peer id>"
+                ));
+            };
+            book.addresses.push((peer_id, address));
+        } else {
+            let bytes = hex::decode(line)
+                .map_err(|err| anyhow!("line {number}: neither a multiaddr nor hex: {err}"))?;
+            let record = decode_peer_record(&bytes).with_context(|| format!("line {number}"))?;
+            book.records.push(record);
+        }
+    }
+    if book.is_empty() {
+        return Err(anyhow!("address book lists no peer"));
+    }
+    Ok(book)
+}
+
+/// Latest signed record of each peer, evicting the oldest-learnt peer once
+/// the capacity is reached.
+#[derive(Debug)]
+pub struct PeerRecordCache {
+    records: HashMap<PeerId, PeerRecord>,
+    order: VecDeque<PeerId>,
+    capacity: usize,
+}
+
+impl PeerRecordCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Keeps `record` unless a record of the same peer with a higher sequence
+    /// number is known. Returns whether it was kept.
+    pub fn insert(&mut self, record: PeerRecord) -> bool {
+        let peer_id = record.peer_id();
+        if let Some(known) = self.records.get(&peer_id) {
+            if known.seq() > record.seq() {
+                return false;
+            }
+        } else {
+            if self.records.len() >= self.capacity {
+                if let Some(evicted) = self.order.pop_front() {
+                    self.records.remove(&evicted);
+                }
+            }
+            self.order.push_back(peer_id);
+        }
+        self.records.insert(peer_id, record);
+        true
+    }
+
+    /// Returns the latest record of `peer_id`, if any.
+    pub fn get(&self, peer_id: &PeerId) -> Option<&PeerRecord> {
+        self.records.get(peer_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl Default for PeerRecordCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_PEER_RECORD_CAPACITY)
+    }
+}
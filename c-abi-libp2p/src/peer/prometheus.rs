@@ -0,0 +1,52 @@
+//! Prometheus registry collecting the node's metrics for scraping.
+
+use anyhow::{anyhow, Result};
+use prometheus_client::{encoding::text::encode, registry::Registry};
+use std::sync::Mutex;
+
+/// Prefix of every metric exposed by the crate.
+const METRICS_PREFIX: &str = "cabi";
+
+/// Prometheus registry shared between the peer manager and its handles.
+///
+/// Behaviour metrics (e.g. gossipsub's) are registered when the swarm is
+/// built, so rebuilding the swarm installs a fresh registry and resets them.
+#[derive(Debug)]
+pub struct PrometheusMetrics {
+    registry: Mutex<Registry>,
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        Self {
+            registry: Mutex::new(Registry::with_prefix(METRICS_PREFIX)),
+        }
+    }
+}
+
+impl PrometheusMetrics {
+    /// Encodes every registered metric in the OpenMetrics text format.
+    pub fn encode(&self) -> Result<String> {
+        let registry = self
+            .registry
+            .lock()
+            .map_err(|_| anyhow!("prometheus registry lock poisoned"))?;
+        let mut output = String::new();
+        encode(&mut output, &registry)
+            .map_err(|err| anyhow!("failed to encode prometheus metrics: {err}"))?;
+        Ok(output)
+    }
+
+    /// Creates an empty registry for a new swarm to register its metrics into.
+    pub(crate) fn new_registry(&self) -> Registry {
+        Registry::with_prefix(METRICS_PREFIX)
+    }
+
+    /// Replaces the exposed registry with one filled by a new swarm.
+    pub(crate) fn install(&self, registry: Registry) {
+        match self.registry.lock() {
+            Ok(mut current) => *current = registry,
+            Err(_) => tracing::warn!(target: "peer", "prometheus registry lock poisoned"),
+        }
+    }
+}
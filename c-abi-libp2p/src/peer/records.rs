@@ -0,0 +1,124 @@
+//! Validation of DHT records remote peers ask the node to store.
+//!
+//! Inbound `PUT_VALUE` and `ADD_PROVIDER` requests are filtered by the peer
+//! manager: only records passing the installed [`RecordValidator`] reach the
+//! record store, so the node never serves records it refused.
+
+use libp2p::{kad, PeerId};
+
+use super::keys::Key;
+use crate::messaging::SignedPayload;
+
+/// Pluggable validator consulted before an inbound record is stored.
+pub trait RecordValidator: Send + Sync {
+    /// Checks a record `source` asks us to store; `Err` carries the reason
+    /// it is refused.
+    fn validate(&self, record: &kad::Record, source: &PeerId) -> Result<(), String>;
+
+    /// Checks a provider announcement for `key`. Accepts everything unless
+    /// overridden.
+    fn validate_provider(&self, _key: &kad::RecordKey, _provider: &PeerId) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<F> RecordValidator for F
+where
+    F: Fn(&kad::Record, &PeerId) -> Result<(), String> + Send + Sync,
+{
+    fn validate(&self, record: &kad::Record, source: &PeerId) -> Result<(), String> {
+        self(record, source)
+    }
+}
+
+/// Built-in [`RecordValidator`] enforcing key namespaces, a value size limit
+/// and signed values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordRules {
+    /// Accept only keys following the [`Key`] schema.
+    pub namespaced_keys: bool,
+    /// [`Key`] namespaces accepted for records and provider announcements;
+    /// empty accepts every namespace.
+    pub namespaces: Vec<String>,
+    /// Largest accepted record value in bytes.
+    pub max_value_size: Option<usize>,
+    /// Accept only values wrapped in a valid [`SignedPayload`].
+    pub require_signed_values: bool,
+}
+
+impl RecordRules {
+    /// Rules accepting every record.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuses keys not following the [`Key`] schema.
+    pub fn with_namespaced_keys(mut self, require: bool) -> Self {
+        self.namespaced_keys = require;
+        self
+    }
+
+    /// Accepts [`Key`]s in `namespace`, in addition to the namespaces added
+    /// before. Keys not following the schema are refused from then on.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespaces.push(namespace.into());
+        self
+    }
+
+    /// Refuses record values larger than `max_value_size` bytes.
+    pub fn with_max_value_size(mut self, max_value_size: usize) -> Self {
+        self.max_value_size = Some(max_value_size);
+        self
+    }
+
+    /// Refuses record values that are not a validly signed [`SignedPayload`].
+    pub fn with_signed_values(mut self, require: bool) -> Self {
+        self.require_signed_values = require;
+        self
+    }
+
+    fn check_key(&self, key: &kad::RecordKey) -> Result<(), String> {
+        if !self.namespaced_keys && self.namespaces.is_empty() {
+            return Ok(());
+        }
+        let key = Key::parse(key.as_ref()).map_err(|err| err.to_string())?;
+        if self.namespaces.is_empty()
+            || self
+                .namespaces
+                .iter()
+                .any(|namespace| namespace == key.namespace())
+        {
+            Ok(())
+        } else {
+            Err(format!(
+                "key namespace {:?} is not accepted",
+                key.namespace()
+            ))
+        }
+    }
+}
+
+impl RecordValidator for RecordRules {
+    fn validate(&self, record: &kad::Record, _source: &PeerId) -> Result<(), String> {
+        self.check_key(&record.key)?;
+        if let Some(max_value_size) = self.max_value_size {
+            if record.value.len() > max_value_size {
+                return Err(format!(
+                    "value of {} bytes exceeds the {max_value_size} byte limit",
+                    record.value.len()
+                ));
+            }
+        }
+        if self.require_signed_values {
+            match SignedPayload::decode(&record.value) {
+                Ok(signed) if signed.verify() => {}
+                _ => return Err("value is not validly signed".to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_provider(&self, key: &kad::RecordKey, _provider: &PeerId) -> Result<(), String> {
+        self.check_key(key)
+    }
+}
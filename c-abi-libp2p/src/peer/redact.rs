@@ -0,0 +1,87 @@
+//! Redaction of peer IDs and IP addresses in log output.
+//!
+//! When enabled, every peer ID and IP address found in a formatted value is
+//! replaced by a short keyed hash. The key is random per process, so the same
+//! peer keeps the same pseudonym within one run (logs stay correlatable) but
+//! pseudonyms cannot be matched against a list of known addresses.
+
+use libp2p::PeerId;
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::BuildHasher,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+/// Formats values for tracing, redacting them when privacy mode is on.
+#[derive(Debug, Clone)]
+pub(crate) struct LogRedactor {
+    key: Option<RandomState>,
+}
+
+impl LogRedactor {
+    /// Creates a redactor; `enabled == false` formats values unchanged.
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self {
+            key: enabled.then(RandomState::new),
+        }
+    }
+
+    /// Formats `value` with [`fmt::Display`] and redacts the result.
+    pub(crate) fn display<T: fmt::Display + ?Sized>(&self, value: &T) -> String {
+        self.redact(value.to_string())
+    }
+
+    /// Formats `value` with [`fmt::Debug`] and redacts the result.
+    pub(crate) fn debug<T: fmt::Debug + ?Sized>(&self, value: &T) -> String {
+        self.redact(format!("{value:?}"))
+    }
+
+    fn redact(&self, text: String) -> String {
+        let Some(key) = &self.key else {
+            return text;
+        };
+
+        let mut out = String::with_capacity(text.len());
+        let mut token_start = None;
+        for (index, ch) in text.char_indices() {
+            if is_token_char(ch) {
+                token_start.get_or_insert(index);
+                continue;
+            }
+            if let Some(start) = token_start.take() {
+                self.push_token(key, &text[start..index], &mut out);
+            }
+            out.push(ch);
+        }
+        if let Some(start) = token_start {
+            self.push_token(key, &text[start..], &mut out);
+        }
+        out
+    }
+
+    fn push_token(&self, key: &RandomState, token: &str, out: &mut String) {
+        if PeerId::from_str(token).is_ok() {
+            out.push_str(&format!("peer-{:08x}", key.hash_one(token) as u32));
+        } else if IpAddr::from_str(token).is_ok() {
+            out.push_str(&format!("ip-{:08x}", key.hash_one(token) as u32));
+        } else if let Ok(socket) = SocketAddr::from_str(token) {
+            let ip = socket.ip().to_string();
+            out.push_str(&format!(
+                "ip-{:08x}:{}",
+                key.hash_one(&ip) as u32,
+                socket.port()
+            ));
+        } else {
+            out.push_str(token);
+        }
+    }
+}
+
+/// Characters that may appear in a peer ID, IP address or socket address.
+/// `/`, quotes, brackets and whitespace separate tokens, so multiaddr
+/// components and `Debug` output are split apart.
+fn is_token_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '.' || ch == ':'
+}
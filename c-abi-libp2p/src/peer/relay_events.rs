@@ -0,0 +1,35 @@
+//! Events reported to callers that requested a relay reservation.
+
+use libp2p::PeerId;
+use std::time::Duration;
+
+/// Lifecycle of a relay reservation, reported to the caller of
+/// [`crate::peer::PeerManagerHandle::listen_via_relay`] and, for every
+/// reservation, as [`crate::peer::NetworkEvent::RelayReservation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayReservationEvent {
+    /// The relay accepted the reservation. `renewal` is `true` when an existing
+    /// reservation was refreshed.
+    Accepted {
+        relay_peer_id: PeerId,
+        renewal: bool,
+    },
+    /// The relay listener reported an error; the reservation may be retried.
+    Failed {
+        relay_peer_id: PeerId,
+        reason: String,
+    },
+    /// Renewing an accepted reservation failed. The reservation stays usable
+    /// for about `expires_in`; pick another relay before it runs out.
+    RenewalFailed {
+        relay_peer_id: PeerId,
+        reason: String,
+        expires_in: Duration,
+    },
+    /// The reservation ended (expired, refused or closed); the node is no
+    /// longer reachable through this relay. `reason` is set when it ended with an error.
+    Closed {
+        relay_peer_id: PeerId,
+        reason: Option<String>,
+    },
+}
@@ -0,0 +1,149 @@
+//! Statistics of the relay server (hop) role.
+
+use libp2p::{relay, PeerId};
+use std::{collections::BTreeMap, sync::RwLock};
+
+use crate::transport::SubstreamMetrics;
+
+/// Relay activity attributed to one client peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelayPeerStats {
+    /// Whether the peer currently holds a reservation.
+    pub reserved: bool,
+    /// Circuits currently open with the peer as source or destination.
+    pub active_circuits: usize,
+    /// Circuits opened with the peer as source or destination.
+    pub circuits_total: u64,
+    /// Bytes received from the peer over its open connections.
+    pub bytes_inbound: u64,
+    /// Bytes sent to the peer over its open connections.
+    pub bytes_outbound: u64,
+}
+
+/// Snapshot of the relay server activity.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelayServerStats {
+    /// Reservations currently held by clients.
+    pub active_reservations: usize,
+    /// Circuits currently relayed.
+    pub active_circuits: usize,
+    /// Reservation requests accepted, renewals included.
+    pub reservations_accepted: u64,
+    /// Reservation requests denied, typically because of reservation limits.
+    pub reservations_denied: u64,
+    /// Reservations that expired without renewal.
+    pub reservations_timed_out: u64,
+    /// Circuit requests accepted.
+    pub circuits_accepted: u64,
+    /// Circuit requests denied, typically because of circuit limits or a
+    /// missing reservation of the destination.
+    pub circuits_denied: u64,
+    /// Per-client activity, for peers that hold a reservation or a circuit.
+    ///
+    /// Bytes cover all traffic on the connections to the peer, which for relay
+    /// clients is dominated by relayed data.
+    pub peers: BTreeMap<PeerId, RelayPeerStats>,
+}
+
+/// Shared relay server counters updated from relay server events.
+#[derive(Debug, Default)]
+pub struct RelayMetrics {
+    stats: RwLock<RelayServerStats>,
+}
+
+impl RelayMetrics {
+    /// Returns the current relay server statistics, with byte counters taken
+    /// from `substreams`.
+    pub fn snapshot(&self, substreams: &SubstreamMetrics) -> RelayServerStats {
+        let Ok(stats) = self.stats.read() else {
+            tracing::warn!(target: "peer", "relay metrics lock poisoned");
+            return RelayServerStats::default();
+        };
+
+        let mut snapshot = stats.clone();
+        for (peer_id, peer) in snapshot.peers.iter_mut() {
+            if let Some((inbound, outbound)) = substreams.peer_bytes(peer_id) {
+                peer.bytes_inbound = inbound;
+                peer.bytes_outbound = outbound;
+            }
+        }
+        snapshot
+    }
+
+    /// Updates the counters from a relay server event.
+    pub(crate) fn record(&self, event: &relay::Event) {
+        let Ok(mut stats) = self.stats.write() else {
+            tracing::warn!(target: "peer", "relay metrics lock poisoned");
+            return;
+        };
+
+        match event {
+            relay::Event::ReservationReqAccepted { src_peer_id, .. } => {
+                stats.reservations_accepted += 1;
+                stats.peers.entry(*src_peer_id).or_default().reserved = true;
+            }
+            relay::Event::ReservationReqDenied { .. } => {
+                stats.reservations_denied += 1;
+            }
+            relay::Event::ReservationTimedOut { src_peer_id } => {
+                stats.reservations_timed_out += 1;
+                if let Some(peer) = stats.peers.get_mut(src_peer_id) {
+                    peer.reserved = false;
+                }
+            }
+            relay::Event::CircuitReqAccepted {
+                src_peer_id,
+                dst_peer_id,
+            } => {
+                stats.circuits_accepted += 1;
+                for peer_id in [src_peer_id, dst_peer_id] {
+                    let peer = stats.peers.entry(*peer_id).or_default();
+                    peer.active_circuits += 1;
+                    peer.circuits_total += 1;
+                }
+            }
+            relay::Event::CircuitReqDenied { .. } => {
+                stats.circuits_denied += 1;
+            }
+            relay::Event::CircuitClosed {
+                src_peer_id,
+                dst_peer_id,
+                ..
+            } => {
+                for peer_id in [src_peer_id, dst_peer_id] {
+                    if let Some(peer) = stats.peers.get_mut(peer_id) {
+                        peer.active_circuits = peer.active_circuits.saturating_sub(1);
+                    }
+                }
+            }
+            _ => {}
+        }
+        stats.recount();
+    }
+
+    /// Drops the reservation and circuits of a peer whose last connection closed.
+    pub(crate) fn peer_disconnected(&self, peer_id: &PeerId) {
+        let Ok(mut stats) = self.stats.write() else {
+            tracing::warn!(target: "peer", "relay metrics lock poisoned");
+            return;
+        };
+        if stats.peers.remove(peer_id).is_some() {
+            stats.recount();
+        }
+    }
+}
+
+impl RelayServerStats {
+    fn recount(&mut self) {
+        self.peers
+            .retain(|_, peer| peer.reserved || peer.active_circuits > 0);
+        self.active_reservations = self.peers.values().filter(|peer| peer.reserved).count();
+        // Every circuit is counted once for its source and once for its destination.
+        self.active_circuits = self
+            .peers
+            .values()
+            .map(|peer| peer.active_circuits)
+            .sum::<usize>()
+            / 2;
+    }
+}
@@ -0,0 +1,171 @@
+//! Retry policy shared by dials, publishes and DHT queries.
+//!
+//! Retries run inside the peer manager: a failed attempt is rescheduled on
+//! the maintenance tick after the policy's backoff, and the caller only sees
+//! the outcome of the last attempt.
+
+use anyhow::{anyhow, Result};
+use std::{collections::hash_map::RandomState, hash::BuildHasher, time::Duration};
+
+/// How often and how fast a failed operation is attempted again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, the first one included; `1` disables retries.
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubled for every further retry.
+    pub initial_backoff: Duration,
+    /// Upper bound of the wait between two attempts.
+    pub max_backoff: Duration,
+    /// Fraction (0.0 to 1.0) of each wait that is randomised, so peers
+    /// retrying after a shared failure do not retry in lockstep.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl RetryPolicy {
+    /// Single attempt, no retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            jitter: 0.0,
+        }
+    }
+
+    /// Up to `max_attempts` attempts with exponential backoff starting at
+    /// `initial_backoff`, capped at 30 seconds, with 20% jitter.
+    pub fn exponential(max_attempts: u32, initial_backoff: Duration) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff,
+            jitter: 0.2,
+            ..Self::none()
+        }
+    }
+
+    /// Sets the upper bound of the wait between two attempts.
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Sets the randomised fraction of each wait.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Checks that the policy allows at least one attempt and that its jitter
+    /// is a fraction between 0.0 and 1.0.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_attempts == 0 {
+            return Err(anyhow!(
+                "retry `max_attempts` is zero; use 1 to disable retries"
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(anyhow!(
+                "retry `jitter` is {}; use a fraction between 0.0 and 1.0",
+                self.jitter
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` when another attempt may follow attempt number
+    /// `attempt` (the first attempt is `1`).
+    pub fn allows_retry(&self, attempt: u32) -> bool {
+        attempt < self.max_attempts
+    }
+
+    /// Wait before the attempt following attempt number `attempt`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        // NaN survives the clamp and would panic in `mul_f64`.
+        if jitter == 0.0 || jitter.is_nan() {
+            return backoff;
+        }
+        // Scale by a random factor in [1 - jitter, 1].
+        let random = RandomState::new().hash_one(attempt) as f64 / u64::MAX as f64;
+        backoff.mul_f64(1.0 - jitter * random)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::exponential(5, Duration::from_millis(100))
+            .with_max_backoff(Duration::from_millis(500))
+            .with_jitter(0.0)
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let delays: Vec<u64> = (1..=6)
+            .map(|attempt| policy().backoff(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500, 500]);
+    }
+
+    #[test]
+    fn backoff_of_late_attempts_does_not_overflow() {
+        let policy = policy().with_max_backoff(Duration::MAX);
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(
+            policy.backoff(u32::MAX),
+            Duration::from_millis(100) * (1 << 31)
+        );
+    }
+
+    #[test]
+    fn retries_stop_after_max_attempts() {
+        let policy = policy();
+        assert!((1..5).all(|attempt| policy.allows_retry(attempt)));
+        assert!(!policy.allows_retry(5));
+        assert!(!RetryPolicy::none().allows_retry(1));
+    }
+
+    #[test]
+    fn jittered_backoff_stays_within_bounds() {
+        let policy = policy().with_jitter(0.2);
+        for attempt in 1..=6 {
+            let full = policy.with_jitter(0.0).backoff(attempt);
+            for _ in 0..50 {
+                let backoff = policy.backoff(attempt);
+                assert!(backoff <= full, "{backoff:?} above {full:?}");
+                assert!(
+                    backoff >= full.mul_f64(0.8),
+                    "{backoff:?} below 80% of {full:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_jitter_is_clamped_or_rejected() {
+        assert_eq!(
+            policy().with_jitter(f64::NAN).backoff(2),
+            Duration::from_millis(200)
+        );
+        assert!(policy().with_jitter(f64::NAN).validate().is_err());
+        assert!(policy().with_jitter(1.5).validate().is_err());
+        assert!(policy().with_jitter(1.5).backoff(1) <= Duration::from_millis(100));
+        assert!(RetryPolicy::exponential(0, Duration::ZERO)
+            .validate()
+            .is_err());
+        assert!(policy().validate().is_ok());
+    }
+}
@@ -0,0 +1,226 @@
+//! Minimum versions remote peers must run, checked once identify reports what
+//! they announce.
+//!
+//! Versions are compared in the `name/major.minor.patch` form identify uses
+//! for both the protocol version (`/cabi/1.0.0`) and the agent version
+//! (`rust-libp2p/0.56.0`). Missing components count as zero, so `/cabi/1.2`
+//! equals `/cabi/1.2.0`, and pre-release or build suffixes are ignored.
+
+use anyhow::{anyhow, Result};
+use std::{cmp::Ordering, fmt};
+
+/// Minimum version of a named protocol or agent, e.g. `/cabi/1.2.0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRequirement {
+    /// Everything before the last `/`, e.g. `/cabi`.
+    pub name: String,
+    /// Numeric components of the minimum version.
+    pub min: Vec<u64>,
+}
+
+impl VersionRequirement {
+    /// Parses a requirement from the form `name/1.2.3`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (name, version) = split_version(spec.trim())
+            .ok_or_else(|| anyhow!("version requirement `{spec}` is not of the form name/1.2.3"))?;
+        if name.is_empty() {
+            return Err(anyhow!("version requirement `{spec}` has no name"));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            min: version,
+        })
+    }
+
+    /// Returns `true` when `announced` has this requirement's name and a
+    /// version at least the minimum.
+    pub(crate) fn is_met_by(&self, announced: &str) -> bool {
+        split_version(announced).is_some_and(|(name, version)| {
+            name == self.name && compare_versions(&version, &self.min) != Ordering::Less
+        })
+    }
+
+    /// Returns `true` when `announced` names the same agent as this
+    /// requirement, whatever its version.
+    fn names(&self, announced: &str) -> bool {
+        announced
+            .rsplit_once('/')
+            .is_some_and(|(name, _)| name == self.name)
+    }
+}
+
+impl fmt::Display for VersionRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let min: Vec<String> = self.min.iter().map(u64::to_string).collect();
+        write!(f, "{}/{}", self.name, min.join("."))
+    }
+}
+
+/// Versions remote peers must run to stay connected.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionGate {
+    /// Minimum identify protocol version. Peers announcing another protocol
+    /// name or an older version are incompatible.
+    pub protocol: Option<VersionRequirement>,
+    /// Minimum agent versions. A peer whose agent has the name of one of
+    /// them must run at least that version; other agents pass.
+    pub agents: Vec<VersionRequirement>,
+}
+
+impl VersionGate {
+    pub fn with_protocol(mut self, requirement: VersionRequirement) -> Self {
+        self.protocol = Some(requirement);
+        self
+    }
+
+    pub fn with_agent(mut self, requirement: VersionRequirement) -> Self {
+        self.agents.push(requirement);
+        self
+    }
+
+    /// Returns `true` when no version is required.
+    pub fn is_empty(&self) -> bool {
+        self.protocol.is_none() && self.agents.is_empty()
+    }
+
+    /// Checks the versions a peer announced via identify, returning the first
+    /// requirement it fails.
+    pub(crate) fn check(
+        &self,
+        protocol_version: &str,
+        agent_version: &str,
+    ) -> Option<VersionMismatch> {
+        if let Some(protocol) = &self.protocol {
+            if !protocol.is_met_by(protocol_version) {
+                return Some(VersionMismatch {
+                    kind: VersionKind::Protocol,
+                    announced: protocol_version.to_string(),
+                    required: protocol.to_string(),
+                });
+            }
+        }
+        self.agents
+            .iter()
+            .find(|agent| agent.names(agent_version) && !agent.is_met_by(agent_version))
+            .map(|agent| VersionMismatch {
+                kind: VersionKind::Agent,
+                announced: agent_version.to_string(),
+                required: agent.to_string(),
+            })
+    }
+}
+
+/// Which announced version failed its requirement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionKind {
+    Protocol,
+    Agent,
+}
+
+impl VersionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Protocol => "protocol",
+            Self::Agent => "agent",
+        }
+    }
+}
+
+/// Version a peer announced that fails a [`VersionGate`] requirement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionMismatch {
+    pub kind: VersionKind,
+    /// Version the peer announced.
+    pub announced: String,
+    /// Requirement it fails, e.g. `/cabi/1.2.0`.
+    pub required: String,
+}
+
+/// Splits `name/1.2.3-rc1` into its name and numeric components.
+fn split_version(spec: &str) -> Option<(&str, Vec<u64>)> {
+    let (name, version) = spec.rsplit_once('/')?;
+    let version = version.split(['-', '+']).next().unwrap_or_default();
+    let components = version
+        .split('.')
+        .map(|component| component.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    Some((name, components))
+}
+
+fn compare_versions(left: &[u64], right: &[u64]) -> Ordering {
+    let len = left.len().max(right.len());
+    (0..len)
+        .map(|index| {
+            let left = left.get(index).copied().unwrap_or(0);
+            let right = right.get(index).copied().unwrap_or(0);
+            left.cmp(&right)
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requirement(spec: &str) -> VersionRequirement {
+        VersionRequirement::parse(spec).unwrap()
+    }
+
+    fn gate() -> VersionGate {
+        VersionGate::default()
+            .with_protocol(requirement("/cabi/1.2.0"))
+            .with_agent(requirement("rust-libp2p/0.56"))
+    }
+
+    #[test]
+    fn parse_splits_name_and_version() {
+        let requirement = requirement(" /cabi/1.2 ");
+        assert_eq!(requirement.name, "/cabi");
+        assert_eq!(requirement.min, [1, 2]);
+        assert_eq!(requirement.to_string(), "/cabi/1.2");
+    }
+
+    #[test]
+    fn parse_rejects_malformed_requirements() {
+        for spec in ["cabi", "/1.2.0", "/cabi/", "/cabi/1.x", "/cabi/1..2"] {
+            assert!(VersionRequirement::parse(spec).is_err(), "{spec} accepted");
+        }
+    }
+
+    #[test]
+    fn missing_components_and_suffixes_are_ignored() {
+        let requirement = requirement("/cabi/1.2.0");
+        assert!(requirement.is_met_by("/cabi/1.2"));
+        assert!(requirement.is_met_by("/cabi/1.2.0-rc1"));
+        assert!(requirement.is_met_by("/cabi/1.10"));
+        assert!(requirement.is_met_by("/cabi/2"));
+        assert!(!requirement.is_met_by("/cabi/1.1.9+build.7"));
+        assert!(!requirement.is_met_by("/other/9.0.0"));
+        assert!(!requirement.is_met_by("/cabi/latest"));
+    }
+
+    #[test]
+    fn gate_reports_the_failed_protocol_first() {
+        let mismatch = gate().check("/cabi/1.1.0", "rust-libp2p/0.55.0").unwrap();
+        assert_eq!(mismatch.kind, VersionKind::Protocol);
+        assert_eq!(mismatch.announced, "/cabi/1.1.0");
+        assert_eq!(mismatch.required, "/cabi/1.2.0");
+    }
+
+    #[test]
+    fn gate_only_checks_agents_it_names() {
+        assert_eq!(gate().check("/cabi/1.2.0", "go-libp2p/0.1.0"), None);
+        assert_eq!(gate().check("/cabi/1.2.0", "rust-libp2p/0.56.1"), None);
+        assert_eq!(
+            gate().check("/cabi/1.2.0", "rust-libp2p/0.55.0"),
+            Some(VersionMismatch {
+                kind: VersionKind::Agent,
+                announced: "rust-libp2p/0.55.0".to_string(),
+                required: "rust-libp2p/0.56".to_string(),
+            })
+        );
+        assert!(VersionGate::default().is_empty());
+        assert_eq!(VersionGate::default().check("", ""), None);
+    }
+}
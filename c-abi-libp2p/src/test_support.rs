@@ -0,0 +1,228 @@
+//! Helpers for integration tests of crates embedding the library, enabled
+//! with the `test_support` feature.
+//!
+//! Nodes listen on loopback TCP with an OS-assigned port, so any number of
+//! them can run side by side:
+//!
+//! ```ignore
+//! let a = TestNode::spawn().await?;
+//! let b = TestNode::spawn().await?;
+//! connect(&a, &b).await?;
+//! await_gossip(&a, &b, b"hello".to_vec(), DEFAULT_TEST_TIMEOUT).await?;
+//! a.shutdown().await?;
+//! ```
+
+use anyhow::{anyhow, Context, Result};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{
+    sync::broadcast::{self, error::TryRecvError},
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+
+use crate::{
+    config::DEFAULT_GOSSIPSUB_TOPIC,
+    messaging::{MessageQueue, DEFAULT_MESSAGE_QUEUE_CAPACITY},
+    peer::{
+        AddrState, ConnectOutcome, DialTransport, DiscoveryQueue, NetworkEvent, PeerManager,
+        PeerManagerHandle, DEFAULT_DISCOVERY_QUEUE_CAPACITY,
+    },
+    transport::TransportConfig,
+};
+
+/// Timeout the helpers taking no explicit timeout wait for.
+pub const DEFAULT_TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval at which the listen address is checked while a node starts.
+const LISTEN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Running node with its inbound message queue and every network event it
+/// emitted since it was spawned.
+pub struct TestNode {
+    handle: PeerManagerHandle,
+    peer_id: PeerId,
+    listen_address: Multiaddr,
+    messages: MessageQueue,
+    events: broadcast::Receiver<NetworkEvent>,
+    // Kept so the manager's discovery sender stays connected.
+    _discovery: DiscoveryQueue,
+    worker: JoinHandle<Result<()>>,
+}
+
+impl TestNode {
+    /// Spawns a node with the default configuration on a random loopback port.
+    pub async fn spawn() -> Result<Self> {
+        Self::spawn_with_config(TransportConfig::default()).await
+    }
+
+    /// Spawns a node with `config` and waits until it listens on a random
+    /// loopback TCP port.
+    pub async fn spawn_with_config(config: TransportConfig) -> Result<Self> {
+        let messages = MessageQueue::new(DEFAULT_MESSAGE_QUEUE_CAPACITY);
+        let discovery = DiscoveryQueue::new(DEFAULT_DISCOVERY_QUEUE_CAPACITY);
+        let addr_state = Arc::new(RwLock::new(AddrState::default()));
+        let (manager, handle) = PeerManager::new(
+            config,
+            messages.sender(),
+            discovery.sender(),
+            addr_state.clone(),
+            Vec::new(),
+        )?;
+        let peer_id = manager.peer_id();
+        let events = handle.subscribe_events();
+        let worker = tokio::spawn(manager.run());
+
+        handle
+            .start_listening("/ip4/127.0.0.1/tcp/0".parse()?)
+            .await?;
+        let listen_address = timeout(DEFAULT_TEST_TIMEOUT, async {
+            loop {
+                let address = addr_state
+                    .read()
+                    .map_err(|_| anyhow!("address state lock poisoned"))?
+                    .listen_addrs()
+                    .into_iter()
+                    .next();
+                if let Some(address) = address {
+                    return Ok::<_, anyhow::Error>(address);
+                }
+                sleep(LISTEN_POLL_INTERVAL).await;
+            }
+        })
+        .await
+        .context("node did not start listening in time")??;
+
+        Ok(Self {
+            handle,
+            peer_id,
+            listen_address: listen_address.with(Protocol::P2p(peer_id)),
+            messages,
+            events,
+            _discovery: discovery,
+            worker,
+        })
+    }
+
+    pub fn handle(&self) -> &PeerManagerHandle {
+        &self.handle
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Loopback listen address ending with `/p2p/<peer id>`, ready to dial.
+    pub fn listen_address(&self) -> &Multiaddr {
+        &self.listen_address
+    }
+
+    /// Waits up to `wait` for the next message delivered to the node.
+    pub async fn next_message(&self, wait: Duration) -> Result<Vec<u8>> {
+        timeout(wait, self.messages.dequeue())
+            .await
+            .map_err(|_| anyhow!("no message delivered within {wait:?}"))?
+            .map(|message| message.payload)
+            .ok_or_else(|| anyhow!("message queue closed"))
+    }
+
+    /// Takes the network events emitted since the previous call, oldest first.
+    pub fn take_events(&mut self) -> Vec<NetworkEvent> {
+        let mut events = Vec::new();
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => events.push(event),
+                Err(TryRecvError::Lagged(skipped)) => {
+                    tracing::warn!(target: "peer", skipped, "test node missed network events");
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => return events,
+            }
+        }
+    }
+
+    /// Waits up to `wait` for the first event matching `predicate`, dropping
+    /// the events before it.
+    pub async fn wait_for_event<F>(
+        &mut self,
+        wait: Duration,
+        mut predicate: F,
+    ) -> Result<NetworkEvent>
+    where
+        F: FnMut(&NetworkEvent) -> bool,
+    {
+        timeout(wait, async {
+            loop {
+                match self.events.recv().await {
+                    Ok(event) if predicate(&event) => return Ok(event),
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Err(anyhow!("node stopped emitting events"))
+                    }
+                }
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("no matching event within {wait:?}"))?
+    }
+
+    /// Shuts the node down and waits for its manager to exit.
+    pub async fn shutdown(self) -> Result<()> {
+        self.handle.shutdown().await?;
+        self.worker
+            .await
+            .map_err(|err| anyhow!("peer manager task join failed: {err}"))?
+    }
+}
+
+/// Dials `to` from `from` over TCP and waits for the connection.
+pub async fn connect(from: &TestNode, to: &TestNode) -> Result<()> {
+    let dial = from
+        .handle
+        .dial_with_transport(to.listen_address.clone(), DialTransport::Tcp);
+    match timeout(DEFAULT_TEST_TIMEOUT, dial)
+        .await
+        .map_err(|_| anyhow!("connection not established within {DEFAULT_TEST_TIMEOUT:?}"))??
+    {
+        ConnectOutcome::Failed { reason } => Err(anyhow!("failed to connect: {reason}")),
+        ConnectOutcome::SelfDial => Err(anyhow!("refused to connect a node to itself")),
+        _ => Ok(()),
+    }
+}
+
+/// Waits up to `wait` until `node` has `min_peers` mesh peers on the default
+/// topic, so a publish reaches them.
+pub async fn await_mesh(node: &TestNode, min_peers: usize, wait: Duration) -> Result<()> {
+    timeout(
+        wait,
+        node.handle
+            .wait_mesh_ready(DEFAULT_GOSSIPSUB_TOPIC, min_peers),
+    )
+    .await
+    .map_err(|_| anyhow!("mesh not formed within {wait:?}"))?
+}
+
+/// Publishes `payload` from `from` once its mesh formed and waits up to `wait`
+/// for `to` to receive it.
+pub async fn await_gossip(
+    from: &TestNode,
+    to: &TestNode,
+    payload: Vec<u8>,
+    wait: Duration,
+) -> Result<()> {
+    timeout(wait, async {
+        await_mesh(from, 1, wait).await?;
+        from.handle.publish(payload.clone()).await?;
+        loop {
+            match to.messages.dequeue().await {
+                Some(message) if message.payload == payload => return Ok(()),
+                Some(_) => {}
+                None => return Err(anyhow!("message queue closed")),
+            }
+        }
+    })
+    .await
+    .map_err(|_| anyhow!("message not delivered within {wait:?}"))?
+}
@@ -0,0 +1,269 @@
+//! Filters keeping local addresses out of what the node advertises.
+//!
+//! Peers learn the node's addresses from identify, and Kademlia and the
+//! rendezvous client only share what identify and the swarm's external
+//! addresses hold. [`AnnounceFiltered`] wraps those behaviours and hides the
+//! listen and external addresses matching an [`AnnounceFilter`] from them, so
+//! e.g. a docker bridge address is still listened on but never handed out.
+
+use anyhow::{anyhow, Context as _, Result};
+use libp2p::{
+    core::{transport::PortUse, Endpoint},
+    multiaddr::Protocol,
+    swarm::{
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+        THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use std::{
+    net::IpAddr,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use super::circuit::is_relayed;
+
+/// Rule matching local addresses that must not be advertised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnnounceFilter {
+    /// Addresses whose IP lies in `network/prefix_len`, e.g. `172.17.0.0/16`
+    /// for the default docker bridge.
+    Cidr { network: IpAddr, prefix_len: u8 },
+    /// Loopback addresses (`127.0.0.0/8`, `::1`).
+    Loopback,
+    /// Private and unique local addresses (RFC 1918, `fc00::/7`).
+    Private,
+    /// Link-local addresses (`169.254.0.0/16`, `fe80::/10`).
+    LinkLocal,
+    /// Relay circuit addresses.
+    Relayed,
+    /// This exact address.
+    Address(Multiaddr),
+}
+
+impl AnnounceFilter {
+    /// Parses a filter from its textual form: `loopback`, `private`,
+    /// `link-local`, `relayed`, a CIDR range (`10.0.0.0/8`, `fd00::/8`), a
+    /// single IP, or a multiaddr starting with `/`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        match spec {
+            "loopback" => return Ok(Self::Loopback),
+            "private" => return Ok(Self::Private),
+            "link-local" => return Ok(Self::LinkLocal),
+            "relayed" => return Ok(Self::Relayed),
+            _ => {}
+        }
+        if spec.starts_with('/') {
+            let address = spec
+                .parse()
+                .with_context(|| format!("invalid announce filter address `{spec}`"))?;
+            return Ok(Self::Address(address));
+        }
+
+        let (ip, prefix_len) = match spec.split_once('/') {
+            Some((ip, prefix_len)) => (ip, Some(prefix_len)),
+            None => (spec, None),
+        };
+        let network: IpAddr = ip
+            .parse()
+            .with_context(|| format!("invalid announce filter `{spec}`"))?;
+        let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .with_context(|| format!("invalid prefix length in announce filter `{spec}`"))?,
+            None => max_prefix_len,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(anyhow!(
+                "prefix length {prefix_len} in announce filter `{spec}` exceeds {max_prefix_len}"
+            ));
+        }
+        Ok(Self::Cidr {
+            network,
+            prefix_len,
+        })
+    }
+
+    /// Returns `true` when `address` must not be advertised.
+    pub fn matches(&self, address: &Multiaddr) -> bool {
+        match self {
+            Self::Address(filtered) => filtered == address,
+            Self::Relayed => is_relayed(address),
+            Self::Loopback => first_ip(address).is_some_and(|ip| ip.is_loopback()),
+            Self::Private => first_ip(address).is_some_and(|ip| match ip {
+                IpAddr::V4(ip) => ip.is_private(),
+                // Unique local range, fc00::/7.
+                IpAddr::V6(ip) => (ip.segments()[0] & 0xfe00) == 0xfc00,
+            }),
+            Self::LinkLocal => first_ip(address).is_some_and(|ip| match ip {
+                IpAddr::V4(ip) => ip.is_link_local(),
+                // fe80::/10.
+                IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80,
+            }),
+            Self::Cidr {
+                network,
+                prefix_len,
+            } => first_ip(address).is_some_and(|ip| in_network(ip, *network, *prefix_len)),
+        }
+    }
+}
+
+/// IP of the first component of `address`, i.e. the directly dialed host.
+pub(super) fn first_ip(address: &Multiaddr) -> Option<IpAddr> {
+    match address.iter().next()? {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    }
+}
+
+pub(super) fn in_network(ip: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Wraps a behaviour so it never learns about filtered local addresses.
+///
+/// Connections and everything else pass through unchanged. Derefs to the
+/// wrapped behaviour.
+pub struct AnnounceFiltered<B: NetworkBehaviour> {
+    inner: B,
+    filters: Arc<[AnnounceFilter]>,
+}
+
+impl<B: NetworkBehaviour> AnnounceFiltered<B> {
+    pub fn new(inner: B, filters: Arc<[AnnounceFilter]>) -> Self {
+        Self { inner, filters }
+    }
+
+    /// Returns `true` when `address` is hidden from the wrapped behaviour.
+    pub fn hides(&self, address: &Multiaddr) -> bool {
+        self.filters.iter().any(|filter| filter.matches(address))
+    }
+}
+
+impl<B: NetworkBehaviour> Deref for AnnounceFiltered<B> {
+    type Target = B;
+
+    fn deref(&self) -> &B {
+        &self.inner
+    }
+}
+
+impl<B: NetworkBehaviour> DerefMut for AnnounceFiltered<B> {
+    fn deref_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+}
+
+impl<B: NetworkBehaviour> NetworkBehaviour for AnnounceFiltered<B> {
+    type ConnectionHandler = THandler<B>;
+    type ToSwarm = B::ToSwarm;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+        port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_outbound_connection(
+            connection_id,
+            peer,
+            addr,
+            role_override,
+            port_use,
+        )
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        // Addresses of remote peers pass; only the node's own are filtered.
+        let local_address = match &event {
+            FromSwarm::NewListenAddr(new) => Some(new.addr),
+            FromSwarm::ExpiredListenAddr(expired) => Some(expired.addr),
+            FromSwarm::NewExternalAddrCandidate(candidate) => Some(candidate.addr),
+            FromSwarm::ExternalAddrConfirmed(confirmed) => Some(confirmed.addr),
+            FromSwarm::ExternalAddrExpired(expired) => Some(expired.addr),
+            _ => None,
+        };
+        if local_address.is_some_and(|address| self.hides(address)) {
+            return;
+        }
+        self.inner.on_swarm_event(event);
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event);
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        self.inner.poll(cx)
+    }
+}
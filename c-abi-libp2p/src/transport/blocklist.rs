@@ -0,0 +1,662 @@
+//! Peers and address ranges the node refuses to connect to.
+//!
+//! [`Blocker`] denies inbound and outbound connections to blocked peer IDs
+//! and to remote IPs inside blocked CIDR ranges, and closes open connections
+//! once an update blocks them. Entries may carry a reason and an expiry after
+//! which they stop matching.
+//!
+//! Lists are exchanged as JSON, e.g. with a central policy server:
+//!
+//! ```json
+//! {
+//!   "replace": false,
+//!   "entries": [
+//!     { "peer_id": "12D3KooW...", "reason": "spam", "expires_at": 1767225600 },
+//!     { "cidr": "203.0.113.0/24", "reason": "abuse" },
+//!     { "peer_id": "12D3KooX...", "remove": true }
+//!   ]
+//! }
+//! ```
+//!
+//! Every entry names exactly one `peer_id` or `cidr` (a single IP blocks just
+//! that address). `reason` is free text and `expires_at` a Unix time in
+//! seconds; both are optional. When imported as an update, entries replace
+//! those with the same target, `"remove": true` drops the target instead, and
+//! `"replace": true` discards the whole list first. Exported lists use the
+//! same format without `replace` and `remove`.
+
+use anyhow::{anyhow, Context as _, Result};
+use libp2p::{
+    core::{transport::PortUse, Endpoint},
+    swarm::{
+        dummy, CloseConnection, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour,
+        THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    fmt, fs,
+    net::IpAddr,
+    path::Path,
+    task::{Context, Poll},
+};
+
+use super::announce::{first_ip, in_network};
+use crate::messaging::envelope::unix_time_ms;
+
+/// What a [`BlockEntry`] blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockTarget {
+    /// Every connection to this peer.
+    Peer(PeerId),
+    /// Connections whose directly connected IP lies in `network/prefix_len`.
+    Cidr { network: IpAddr, prefix_len: u8 },
+}
+
+impl BlockTarget {
+    /// Parses a CIDR range (`203.0.113.0/24`, `2001:db8::/32`) or a single IP.
+    pub fn parse_cidr(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        let (ip, prefix_len) = match spec.split_once('/') {
+            Some((ip, prefix_len)) => (ip, Some(prefix_len)),
+            None => (spec, None),
+        };
+        let network: IpAddr = ip
+            .parse()
+            .with_context(|| format!("invalid blocked range `{spec}`"))?;
+        let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .with_context(|| format!("invalid prefix length in blocked range `{spec}`"))?,
+            None => max_prefix_len,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(anyhow!(
+                "prefix length {prefix_len} in blocked range `{spec}` exceeds {max_prefix_len}"
+            ));
+        }
+        Ok(Self::Cidr {
+            network,
+            prefix_len,
+        })
+    }
+
+    fn matches(&self, peer_id: Option<&PeerId>, address: Option<&Multiaddr>) -> bool {
+        match self {
+            Self::Peer(blocked) => peer_id == Some(blocked),
+            Self::Cidr {
+                network,
+                prefix_len,
+            } => address
+                .and_then(first_ip)
+                .is_some_and(|ip| in_network(ip, *network, *prefix_len)),
+        }
+    }
+}
+
+impl fmt::Display for BlockTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Peer(peer_id) => write!(f, "{peer_id}"),
+            Self::Cidr {
+                network,
+                prefix_len,
+            } => write!(f, "{network}/{prefix_len}"),
+        }
+    }
+}
+
+/// A blocked peer or range, with why and until when.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockEntry {
+    pub target: BlockTarget,
+    /// Free-text reason, e.g. as given by the policy server.
+    pub reason: Option<String>,
+    /// Unix time in seconds after which the entry stops matching.
+    pub expires_at: Option<u64>,
+}
+
+impl BlockEntry {
+    pub fn new(target: BlockTarget) -> Self {
+        Self {
+            target,
+            reason: None,
+            expires_at: None,
+        }
+    }
+
+    pub fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    pub fn with_expires_at(mut self, expires_at: u64) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Returns `true` when the entry no longer matches at `now` (Unix seconds).
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    fn to_json(&self) -> Value {
+        let mut entry = Map::new();
+        match &self.target {
+            BlockTarget::Peer(peer_id) => {
+                entry.insert("peer_id".into(), json!(peer_id.to_string()))
+            }
+            BlockTarget::Cidr { .. } => entry.insert("cidr".into(), json!(self.target.to_string())),
+        };
+        if let Some(reason) = &self.reason {
+            entry.insert("reason".into(), json!(reason));
+        }
+        if let Some(expires_at) = self.expires_at {
+            entry.insert("expires_at".into(), json!(expires_at));
+        }
+        Value::Object(entry)
+    }
+}
+
+/// Changes to a [`Blocklist`], as pushed by a policy server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlocklistUpdate {
+    /// Discard the current entries before applying this update.
+    pub replace: bool,
+    /// Entries added, replacing those with the same target.
+    pub entries: Vec<BlockEntry>,
+    /// Targets unblocked.
+    pub removals: Vec<BlockTarget>,
+}
+
+impl BlocklistUpdate {
+    /// Parses an update in the format described in the [module docs](self).
+    pub fn from_json(json: &str) -> Result<Self> {
+        let document: Value = serde_json::from_str(json).context("malformed blocklist")?;
+        let replace = match document.get("replace") {
+            None => false,
+            Some(replace) => replace
+                .as_bool()
+                .ok_or_else(|| anyhow!("blocklist `replace` must be a boolean"))?,
+        };
+        let entries = document
+            .get("entries")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("blocklist has no `entries` array"))?;
+
+        let mut update = Self {
+            replace,
+            ..Self::default()
+        };
+        for (index, entry) in entries.iter().enumerate() {
+            let (entry, remove) =
+                parse_entry(entry).with_context(|| format!("invalid blocklist entry {index}"))?;
+            if remove {
+                update.removals.push(entry.target);
+            } else {
+                update.entries.push(entry);
+            }
+        }
+        Ok(update)
+    }
+}
+
+fn parse_entry(entry: &Value) -> Result<(BlockEntry, bool)> {
+    let peer_id = entry.get("peer_id").map(|peer_id| {
+        peer_id
+            .as_str()
+            .ok_or_else(|| anyhow!("`peer_id` must be a string"))
+            .and_then(|peer_id| {
+                peer_id
+                    .parse()
+                    .map(BlockTarget::Peer)
+                    .with_context(|| format!("invalid peer id `{peer_id}`"))
+            })
+    });
+    let cidr = entry.get("cidr").map(|cidr| {
+        cidr.as_str()
+            .ok_or_else(|| anyhow!("`cidr` must be a string"))
+            .and_then(BlockTarget::parse_cidr)
+    });
+    let target = match (peer_id, cidr) {
+        (Some(target), None) | (None, Some(target)) => target?,
+        (Some(_), Some(_)) => return Err(anyhow!("entry has both `peer_id` and `cidr`")),
+        (None, None) => return Err(anyhow!("entry has neither `peer_id` nor `cidr`")),
+    };
+    let reason = match entry.get("reason") {
+        None | Some(Value::Null) => None,
+        Some(reason) => Some(
+            reason
+                .as_str()
+                .ok_or_else(|| anyhow!("`reason` must be a string"))?
+                .to_string(),
+        ),
+    };
+    let expires_at = match entry.get("expires_at") {
+        None | Some(Value::Null) => None,
+        Some(expires_at) => Some(
+            expires_at
+                .as_u64()
+                .ok_or_else(|| anyhow!("`expires_at` must be a Unix time in seconds"))?,
+        ),
+    };
+    let remove = match entry.get("remove") {
+        None => false,
+        Some(remove) => remove
+            .as_bool()
+            .ok_or_else(|| anyhow!("`remove` must be a boolean"))?,
+    };
+    Ok((
+        BlockEntry {
+            target,
+            reason,
+            expires_at,
+        },
+        remove,
+    ))
+}
+
+/// Blocked peers and ranges, at most one entry per target.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Blocklist {
+    entries: Vec<BlockEntry>,
+}
+
+impl Blocklist {
+    /// Parses a list in the format described in the [module docs](self).
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut blocklist = Self::default();
+        blocklist.apply(BlocklistUpdate::from_json(json)?);
+        Ok(blocklist)
+    }
+
+    /// Serializes the list in the format described in the [module docs](self).
+    pub fn to_json(&self) -> String {
+        let entries: Vec<Value> = self.entries.iter().map(BlockEntry::to_json).collect();
+        json!({ "entries": entries }).to_string()
+    }
+
+    /// Reads a list saved with [`Blocklist::save`]; a missing file is an
+    /// empty list.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => Self::from_json(&json)
+                .with_context(|| format!("failed to load blocklist {}", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => {
+                Err(err).with_context(|| format!("failed to read blocklist {}", path.display()))
+            }
+        }
+    }
+
+    /// Writes the list to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, self.to_json())
+            .with_context(|| format!("failed to write blocklist {}", temporary.display()))?;
+        fs::rename(&temporary, path)
+            .with_context(|| format!("failed to replace blocklist {}", path.display()))
+    }
+
+    /// Adds `entry`, replacing any entry with the same target.
+    pub fn insert(&mut self, entry: BlockEntry) {
+        match self
+            .entries
+            .iter_mut()
+            .find(|existing| existing.target == entry.target)
+        {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// Unblocks `target`, returning `true` when it was blocked.
+    pub fn remove(&mut self, target: &BlockTarget) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.target != *target);
+        self.entries.len() != len
+    }
+
+    /// Applies an update pushed by a policy server.
+    pub fn apply(&mut self, update: BlocklistUpdate) {
+        if update.replace {
+            self.entries.clear();
+        }
+        for target in &update.removals {
+            self.remove(target);
+        }
+        for entry in update.entries {
+            self.insert(entry);
+        }
+    }
+
+    /// Adds the entries of `other`, replacing those with the same target.
+    pub fn extend(&mut self, other: Blocklist) {
+        for entry in other.entries {
+            self.insert(entry);
+        }
+    }
+
+    /// Drops entries expired at `now` (Unix seconds), returning how many.
+    pub fn prune_expired(&mut self, now: u64) -> usize {
+        let len = self.entries.len();
+        self.entries.retain(|entry| !entry.is_expired(now));
+        len - self.entries.len()
+    }
+
+    pub fn entries(&self) -> &[BlockEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the unexpired entry blocking a connection to `peer_id` or
+    /// from `address`, if any.
+    pub fn blocking(
+        &self,
+        peer_id: Option<&PeerId>,
+        address: Option<&Multiaddr>,
+        now: u64,
+    ) -> Option<&BlockEntry> {
+        self.entries
+            .iter()
+            .find(|entry| !entry.is_expired(now) && entry.target.matches(peer_id, address))
+    }
+}
+
+/// Error a connection is denied with by [`Blocker`].
+#[derive(Debug, Clone)]
+pub struct Blocked(pub BlockEntry);
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is blocked", self.0.target)?;
+        if let Some(reason) = &self.0.reason {
+            write!(f, ": {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+/// Behaviour denying connections matched by a [`Blocklist`].
+#[derive(Debug, Default)]
+pub struct Blocker {
+    blocklist: Blocklist,
+    connections: HashMap<ConnectionId, (PeerId, Multiaddr)>,
+    pending: VecDeque<ToSwarm<Infallible, Infallible>>,
+}
+
+impl Blocker {
+    pub fn new(blocklist: Blocklist) -> Self {
+        Self {
+            blocklist,
+            ..Self::default()
+        }
+    }
+
+    pub fn blocklist(&self) -> &Blocklist {
+        &self.blocklist
+    }
+
+    /// Replaces the list and closes open connections it now blocks.
+    pub fn set_blocklist(&mut self, blocklist: Blocklist) {
+        self.blocklist = blocklist;
+        let now = unix_time_secs();
+        for (connection_id, (peer_id, address)) in &self.connections {
+            if let Some(entry) = self.blocklist.blocking(Some(peer_id), Some(address), now) {
+                tracing::info!(
+                    target: "transport",
+                    %peer_id,
+                    blocked = %entry.target,
+                    "closing connection to blocked peer"
+                );
+                self.pending.push_back(ToSwarm::CloseConnection {
+                    peer_id: *peer_id,
+                    connection: CloseConnection::One(*connection_id),
+                });
+            }
+        }
+    }
+
+    fn check(
+        &self,
+        peer_id: Option<&PeerId>,
+        address: Option<&Multiaddr>,
+    ) -> Result<(), ConnectionDenied> {
+        match self.blocklist.blocking(peer_id, address, unix_time_secs()) {
+            Some(entry) => Err(ConnectionDenied::new(Blocked(entry.clone()))),
+            None => Ok(()),
+        }
+    }
+
+    fn established(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        address: &Multiaddr,
+    ) -> Result<dummy::ConnectionHandler, ConnectionDenied> {
+        self.check(Some(&peer), Some(address))?;
+        self.connections
+            .insert(connection_id, (peer, address.clone()));
+        Ok(dummy::ConnectionHandler)
+    }
+}
+
+impl NetworkBehaviour for Blocker {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.check(None, Some(remote_addr))
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.established(connection_id, peer, remote_addr)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        _effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.check(maybe_peer.as_ref(), None)?;
+        // Behaviours can only add candidates, not drop them, so a dial that
+        // would try an address in a blocked range is refused as a whole.
+        for address in addresses {
+            self.check(None, Some(address))?;
+        }
+        Ok(Vec::new())
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.established(connection_id, peer, addr)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(closed) = event {
+            self.connections.remove(&closed.connection_id);
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        match self.pending.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Current Unix time in seconds, the unit of [`BlockEntry::expires_at`].
+pub fn unix_time_secs() -> u64 {
+    unix_time_ms() / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(address: &str) -> Multiaddr {
+        address.parse().unwrap()
+    }
+
+    fn blocks(cidr: &str, address: &str) -> bool {
+        BlockTarget::parse_cidr(cidr)
+            .unwrap()
+            .matches(None, Some(&self::address(address)))
+    }
+
+    #[test]
+    fn ipv4_ranges_match_addresses_inside() {
+        assert!(blocks("203.0.113.0/24", "/ip4/203.0.113.7/tcp/4001"));
+        assert!(!blocks("203.0.113.0/24", "/ip4/203.0.114.7/tcp/4001"));
+        assert!(blocks("203.0.113.7", "/ip4/203.0.113.7/udp/4001/quic-v1"));
+        assert!(!blocks("203.0.113.7", "/ip4/203.0.113.8/tcp/4001"));
+        assert!(blocks("0.0.0.0/0", "/ip4/198.51.100.1/tcp/4001"));
+        assert!(!blocks("203.0.113.0/24", "/ip6/::1/tcp/4001"));
+    }
+
+    #[test]
+    fn ipv6_ranges_match_addresses_inside() {
+        assert!(blocks("2001:db8::/32", "/ip6/2001:db8:1::1/tcp/4001"));
+        assert!(!blocks("2001:db8::/32", "/ip6/2001:db9::1/tcp/4001"));
+        assert_eq!(
+            BlockTarget::parse_cidr(" 2001:db8::1 ").unwrap(),
+            BlockTarget::Cidr {
+                network: "2001:db8::1".parse().unwrap(),
+                prefix_len: 128,
+            }
+        );
+    }
+
+    #[test]
+    fn malformed_ranges_are_rejected() {
+        for spec in [
+            "203.0.113.0/33",
+            "2001:db8::/129",
+            "203.0.113.0/",
+            "203.0.113.0/-1",
+            "203.0.113.0/abc",
+            "203.0.113/24",
+            "",
+        ] {
+            assert!(BlockTarget::parse_cidr(spec).is_err(), "{spec} accepted");
+        }
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        for json in [
+            r#"{"entries": [{"peer_id": "not-a-peer-id"}]}"#,
+            r#"{"entries": [{"peer_id": 7}]}"#,
+            r#"{"entries": [{"cidr": "10.0.0.0/8", "peer_id": "x"}]}"#,
+            r#"{"entries": [{"reason": "spam"}]}"#,
+            r#"{"entries": [{"cidr": "10.0.0.0/8", "expires_at": -1}]}"#,
+            r#"{"replace": "yes", "entries": []}"#,
+            r#"{}"#,
+        ] {
+            assert!(Blocklist::from_json(json).is_err(), "{json} accepted");
+        }
+    }
+
+    #[test]
+    fn updates_replace_and_remove_entries() {
+        let peer_id = PeerId::random();
+        let mut blocklist = Blocklist::from_json(&format!(
+            r#"{{"entries": [{{"peer_id": "{peer_id}"}}, {{"cidr": "10.0.0.0/8"}}]}}"#
+        ))
+        .unwrap();
+        blocklist.apply(
+            BlocklistUpdate::from_json(&format!(
+                r#"{{"entries": [{{"peer_id": "{peer_id}", "remove": true}}, {{"cidr": "10.0.0.0/8", "reason": "abuse"}}]}}"#
+            ))
+            .unwrap(),
+        );
+        assert_eq!(
+            blocklist.entries(),
+            [
+                BlockEntry::new(BlockTarget::parse_cidr("10.0.0.0/8").unwrap())
+                    .with_reason("abuse")
+            ]
+        );
+
+        blocklist.apply(BlocklistUpdate::from_json(r#"{"replace": true, "entries": []}"#).unwrap());
+        assert!(blocklist.is_empty());
+    }
+
+    #[test]
+    fn expired_entries_stop_matching() {
+        let peer_id = PeerId::random();
+        let mut blocklist = Blocklist::default();
+        blocklist.insert(BlockEntry::new(BlockTarget::Peer(peer_id)).with_expires_at(100));
+        assert!(blocklist.blocking(Some(&peer_id), None, 99).is_some());
+        assert!(blocklist.blocking(Some(&peer_id), None, 100).is_none());
+        assert_eq!(blocklist.prune_expired(100), 1);
+        assert!(blocklist.is_empty());
+    }
+
+    #[test]
+    fn saved_list_loads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blocklist.json");
+        assert_eq!(Blocklist::load(&path).unwrap(), Blocklist::default());
+
+        let mut blocklist = Blocklist::default();
+        blocklist.insert(
+            BlockEntry::new(BlockTarget::Peer(PeerId::random()))
+                .with_reason("spam")
+                .with_expires_at(1_767_225_600),
+        );
+        blocklist.insert(BlockEntry::new(
+            BlockTarget::parse_cidr("2001:db8::/32").unwrap(),
+        ));
+        blocklist.save(&path).unwrap();
+        assert_eq!(Blocklist::load(&path).unwrap(), blocklist);
+
+        fs::write(&path, "not json").unwrap();
+        assert!(Blocklist::load(&path).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Helpers composing and decomposing `/p2p-circuit` relayed multiaddrs.
+//!
+//! A relayed address has the shape
+//! `<relay transport>/p2p/<relay>/p2p-circuit[/p2p/<target>]`.
+
+use anyhow::{anyhow, Result};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+
+/// Parts of a relayed multiaddr.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayedAddress {
+    /// Address of the relay, ending with `/p2p/<relay_peer_id>`.
+    pub relay_addr: Multiaddr,
+    /// Peer ID of the relay.
+    pub relay_peer_id: PeerId,
+    /// Peer reached through the relay, when the address names one.
+    pub target_peer_id: Option<PeerId>,
+}
+
+/// Builds `<relay_addr>/p2p/<relay_peer_id>/p2p-circuit/p2p/<target_peer_id>`.
+/// `relay_addr` may already end with the relay's `/p2p` component.
+pub fn relayed_address(
+    relay_addr: &Multiaddr,
+    relay_peer_id: PeerId,
+    target_peer_id: PeerId,
+) -> Result<Multiaddr> {
+    if is_relayed(relay_addr) {
+        return Err(anyhow!("relay address already contains /p2p-circuit"));
+    }
+    let mut address = relay_addr.clone();
+    match address.iter().last() {
+        Some(Protocol::P2p(peer_id)) if peer_id == relay_peer_id => {}
+        Some(Protocol::P2p(_)) => {
+            return Err(anyhow!("relay address names a different relay peer"));
+        }
+        _ => address.push(Protocol::P2p(relay_peer_id)),
+    }
+    address.push(Protocol::P2pCircuit);
+    address.push(Protocol::P2p(target_peer_id));
+    Ok(address)
+}
+
+/// Splits a relayed multiaddr into its relay and target parts. Returns `None`
+/// when the address is not relayed or does not name the relay peer.
+pub fn decompose_relayed(address: &Multiaddr) -> Option<RelayedAddress> {
+    let components: Vec<Protocol<'_>> = address.iter().collect();
+    let circuit = components
+        .iter()
+        .position(|p| matches!(p, Protocol::P2pCircuit))?;
+
+    let relay_peer_id = match circuit.checked_sub(1).map(|index| &components[index]) {
+        Some(Protocol::P2p(peer_id)) => *peer_id,
+        _ => return None,
+    };
+    let target_peer_id = match &components[circuit + 1..] {
+        [] => None,
+        [Protocol::P2p(peer_id)] => Some(*peer_id),
+        _ => return None,
+    };
+
+    Some(RelayedAddress {
+        relay_addr: components[..circuit].iter().cloned().collect(),
+        relay_peer_id,
+        target_peer_id,
+    })
+}
+
+/// Returns `true` when the address goes through a relay circuit.
+pub fn is_relayed(address: &Multiaddr) -> bool {
+    address.iter().any(|p| matches!(p, Protocol::P2pCircuit))
+}
+
+/// Checks that `address` ends with `/p2p/<expected>`, so a dial reaches the
+/// intended peer rather than whoever answers at that address.
+pub fn ensure_ends_with_peer(address: &Multiaddr, expected: &PeerId) -> Result<()> {
+    match address.iter().last() {
+        Some(Protocol::P2p(peer_id)) if peer_id == *expected => Ok(()),
+        Some(Protocol::P2p(peer_id)) => Err(anyhow!(
+            "address ends with peer {peer_id}, expected {expected}"
+        )),
+        _ => Err(anyhow!("address does not end with a /p2p component")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(address: &str) -> Multiaddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn relayed_address_appends_relay_and_target() {
+        let relay = PeerId::random();
+        let target = PeerId::random();
+        let expected = address(&format!(
+            "/ip4/198.51.100.1/tcp/4001/p2p/{relay}/p2p-circuit/p2p/{target}"
+        ));
+        for relay_addr in [
+            address("/ip4/198.51.100.1/tcp/4001"),
+            address(&format!("/ip4/198.51.100.1/tcp/4001/p2p/{relay}")),
+        ] {
+            assert_eq!(
+                relayed_address(&relay_addr, relay, target).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn relayed_address_rejects_mismatched_relays() {
+        let relay = PeerId::random();
+        let other = address(&format!(
+            "/ip4/198.51.100.1/tcp/4001/p2p/{}",
+            PeerId::random()
+        ));
+        assert!(relayed_address(&other, relay, PeerId::random()).is_err());
+        let circuit = address(&format!(
+            "/ip4/198.51.100.1/tcp/4001/p2p/{relay}/p2p-circuit"
+        ));
+        assert!(relayed_address(&circuit, relay, PeerId::random()).is_err());
+    }
+
+    #[test]
+    fn decompose_reverses_relayed_address() {
+        let relay = PeerId::random();
+        let target = PeerId::random();
+        let relay_addr = address(&format!("/ip6/2001:db8::1/udp/4001/quic-v1/p2p/{relay}"));
+        let relayed = relayed_address(&relay_addr, relay, target).unwrap();
+        assert!(is_relayed(&relayed));
+        assert_eq!(
+            decompose_relayed(&relayed),
+            Some(RelayedAddress {
+                relay_addr: relay_addr.clone(),
+                relay_peer_id: relay,
+                target_peer_id: Some(target),
+            })
+        );
+
+        let reservation = address(&format!("{relay_addr}/p2p-circuit"));
+        assert_eq!(
+            decompose_relayed(&reservation).unwrap().target_peer_id,
+            None
+        );
+    }
+
+    #[test]
+    fn decompose_rejects_incomplete_circuits() {
+        let relay = PeerId::random();
+        assert!(!is_relayed(&address("/ip4/198.51.100.1/tcp/4001")));
+        assert_eq!(
+            decompose_relayed(&address("/ip4/198.51.100.1/tcp/4001")),
+            None
+        );
+        assert_eq!(
+            decompose_relayed(&address("/ip4/198.51.100.1/tcp/4001/p2p-circuit")),
+            None
+        );
+        assert_eq!(
+            decompose_relayed(&address(&format!(
+                "/ip4/198.51.100.1/tcp/4001/p2p/{relay}/p2p-circuit/tcp/1"
+            ))),
+            None
+        );
+    }
+
+    #[test]
+    fn ensure_ends_with_peer_checks_last_component() {
+        let peer = PeerId::random();
+        let with_peer = address(&format!("/ip4/198.51.100.1/tcp/4001/p2p/{peer}"));
+        assert!(ensure_ends_with_peer(&with_peer, &peer).is_ok());
+        assert!(ensure_ends_with_peer(&with_peer, &PeerId::random()).is_err());
+        assert!(ensure_ends_with_peer(&address("/ip4/198.51.100.1/tcp/4001"), &peer).is_err());
+    }
+}
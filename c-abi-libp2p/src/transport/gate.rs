@@ -0,0 +1,200 @@
+//! Runtime switch for a protocol role served by a behaviour.
+//!
+//! libp2p behaviours fix their roles when built. [`RoleGate`] instead decides
+//! per new connection whether the behaviour takes part in it, so a role can be
+//! turned off without rebuilding the swarm: connections opened while the gate
+//! is closed do not speak the protocol, while already open ones keep serving
+//! it until they close.
+
+use libp2p::{
+    core::{transport::PortUse, Endpoint},
+    swarm::{
+        behaviour::toggle::Toggle, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour,
+        THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use std::{
+    collections::HashSet,
+    task::{Context, Poll},
+};
+
+/// Connections a closed [`RoleGate`] keeps the behaviour away from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateScope {
+    /// Every new connection.
+    AllConnections,
+    /// New inbound connections only; connections the node dials keep the
+    /// behaviour, e.g. so AutoNAT can still probe through dialed servers.
+    InboundOnly,
+}
+
+/// Wraps an optional behaviour whose participation in new connections can be
+/// switched at runtime.
+pub struct RoleGate<B: NetworkBehaviour> {
+    inner: Toggle<B>,
+    scope: GateScope,
+    open: bool,
+    /// Connections established while the gate was closed; hidden from `inner`.
+    gated: HashSet<ConnectionId>,
+}
+
+impl<B: NetworkBehaviour> RoleGate<B> {
+    /// Creates an open gate around `inner`; `None` disables the role for good.
+    pub fn new(inner: Option<B>, scope: GateScope) -> Self {
+        Self {
+            inner: Toggle::from(inner),
+            scope,
+            open: true,
+            gated: HashSet::new(),
+        }
+    }
+
+    /// Returns `true` when the role is configured at all.
+    pub fn is_configured(&self) -> bool {
+        self.inner.is_enabled()
+    }
+
+    /// Returns `true` when new connections get the behaviour.
+    pub fn is_open(&self) -> bool {
+        self.open && self.is_configured()
+    }
+
+    /// Opens or closes the gate for connections established from now on.
+    pub fn set_open(&mut self, open: bool) {
+        self.open = open;
+    }
+
+    /// Returns the wrapped behaviour, if configured.
+    pub fn as_ref(&self) -> Option<&B> {
+        self.inner.as_ref()
+    }
+
+    /// Returns the wrapped behaviour, if configured.
+    pub fn as_mut(&mut self) -> Option<&mut B> {
+        self.inner.as_mut()
+    }
+
+    /// Whether a connection established now, in the given direction, skips the behaviour.
+    fn skips(&self, inbound: bool) -> bool {
+        !self.open && (inbound || self.scope == GateScope::AllConnections)
+    }
+}
+
+impl<B: NetworkBehaviour> NetworkBehaviour for RoleGate<B> {
+    type ConnectionHandler = THandler<Toggle<B>>;
+    type ToSwarm = B::ToSwarm;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        if self.skips(true) {
+            self.gated.insert(connection_id);
+            return Toggle::<B>::from(None).handle_established_inbound_connection(
+                connection_id,
+                peer,
+                local_addr,
+                remote_addr,
+            );
+        }
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+        port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        if self.skips(false) {
+            self.gated.insert(connection_id);
+            return Toggle::<B>::from(None).handle_established_outbound_connection(
+                connection_id,
+                peer,
+                addr,
+                role_override,
+                port_use,
+            );
+        }
+        self.inner.handle_established_outbound_connection(
+            connection_id,
+            peer,
+            addr,
+            role_override,
+            port_use,
+        )
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        // The behaviour never learns about gated connections, so it never
+        // sends events to their disabled handlers.
+        let connection_id = match &event {
+            FromSwarm::ConnectionEstablished(established) => Some(established.connection_id),
+            FromSwarm::AddressChange(change) => Some(change.connection_id),
+            FromSwarm::ConnectionClosed(closed) => {
+                if self.gated.remove(&closed.connection_id) {
+                    return;
+                }
+                None
+            }
+            _ => None,
+        };
+        if connection_id.is_some_and(|id| self.gated.contains(&id)) {
+            return;
+        }
+        self.inner.on_swarm_event(event);
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event);
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        self.inner.poll(cx)
+    }
+}
@@ -0,0 +1,175 @@
+//! Connection keep-alive policy.
+//!
+//! Connections without open streams are closed by the swarm after the idle
+//! connection timeout. [`KeepAlive`] exempts pinned peers: its handler keeps
+//! every connection to a pinned peer alive regardless of activity.
+
+use libp2p::{
+    core::{transport::PortUse, upgrade::DeniedUpgrade, Endpoint},
+    swarm::{
+        handler::{ConnectionEvent, ConnectionHandler, ConnectionHandlerEvent, SubstreamProtocol},
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, NotifyHandler, THandler,
+        THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    convert::Infallible,
+    task::{Context, Poll},
+};
+
+/// Behaviour keeping connections to pinned peers open while idle.
+#[derive(Debug, Default)]
+pub struct KeepAlive {
+    pinned: HashSet<PeerId>,
+    connections: HashMap<PeerId, HashSet<ConnectionId>>,
+    pending: VecDeque<ToSwarm<Infallible, bool>>,
+}
+
+impl KeepAlive {
+    /// Creates the behaviour with `pinned` exempted from the idle timeout.
+    pub fn new(pinned: impl IntoIterator<Item = PeerId>) -> Self {
+        Self {
+            pinned: pinned.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Exempts (or stops exempting) the connections to `peer_id` from the
+    /// idle timeout, open connections included.
+    pub fn set_pinned(&mut self, peer_id: PeerId, pinned: bool) {
+        let changed = if pinned {
+            self.pinned.insert(peer_id)
+        } else {
+            self.pinned.remove(&peer_id)
+        };
+        if !changed {
+            return;
+        }
+        for connection_id in self.connections.get(&peer_id).into_iter().flatten() {
+            self.pending.push_back(ToSwarm::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(*connection_id),
+                event: pinned,
+            });
+        }
+    }
+
+    /// Returns `true` when connections to `peer_id` are kept alive while idle.
+    pub fn is_pinned(&self, peer_id: &PeerId) -> bool {
+        self.pinned.contains(peer_id)
+    }
+
+    /// Returns the pinned peers.
+    pub fn pinned(&self) -> impl Iterator<Item = &PeerId> {
+        self.pinned.iter()
+    }
+
+    fn handler(&mut self, connection_id: ConnectionId, peer: PeerId) -> KeepAliveHandler {
+        self.connections
+            .entry(peer)
+            .or_default()
+            .insert(connection_id);
+        KeepAliveHandler {
+            pinned: self.pinned.contains(&peer),
+        }
+    }
+}
+
+impl NetworkBehaviour for KeepAlive {
+    type ConnectionHandler = KeepAliveHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        _remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(self.handler(connection_id, peer))
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(self.handler(connection_id, peer))
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(closed) = event {
+            if let Some(connections) = self.connections.get_mut(&closed.peer_id) {
+                connections.remove(&closed.connection_id);
+                if connections.is_empty() {
+                    self.connections.remove(&closed.peer_id);
+                }
+            }
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        match self.pending.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Handler speaking no protocol; it only keeps pinned connections alive.
+#[derive(Debug)]
+pub struct KeepAliveHandler {
+    pinned: bool,
+}
+
+impl ConnectionHandler for KeepAliveHandler {
+    /// New pinned state of the peer.
+    type FromBehaviour = bool;
+    type ToBehaviour = Infallible;
+    type InboundProtocol = DeniedUpgrade;
+    type OutboundProtocol = DeniedUpgrade;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = ();
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        SubstreamProtocol::new(DeniedUpgrade, ())
+    }
+
+    fn connection_keep_alive(&self) -> bool {
+        self.pinned
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ConnectionHandlerEvent<Self::OutboundProtocol, (), Self::ToBehaviour>> {
+        Poll::Pending
+    }
+
+    fn on_behaviour_event(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+
+    fn on_connection_event(
+        &mut self,
+        _event: ConnectionEvent<Self::InboundProtocol, Self::OutboundProtocol>,
+    ) {
+    }
+}
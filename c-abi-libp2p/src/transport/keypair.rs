@@ -0,0 +1,181 @@
+//! Where the node's identity keypair comes from.
+//!
+//! A keypair file holds the keypair in the protobuf encoding of
+//! [`identity::Keypair::to_protobuf_encoding`]. It is created with a fresh
+//! Ed25519 keypair on the first start and read back on later ones, so the
+//! `PeerId`, and with it what other peers' routing tables know of the node,
+//! survives restarts.
+
+use anyhow::{anyhow, Context, Result};
+use libp2p::identity;
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Source of the identity keypair, see [`crate::transport::TransportConfig::keypair_source`].
+#[derive(Clone, Default, PartialEq, Eq)]
+pub enum KeypairSource {
+    /// Generate a fresh Ed25519 keypair, or derive it from
+    /// [`crate::transport::TransportConfig::identity_seed`] when set.
+    #[default]
+    Generate,
+    /// Use this protobuf-encoded keypair.
+    Protobuf(Vec<u8>),
+    /// Load the keypair stored in this file, creating the file with a fresh
+    /// Ed25519 keypair when it does not exist.
+    File(PathBuf),
+}
+
+impl KeypairSource {
+    /// Loads the keypair, creating the keypair file if needed. `seed` is
+    /// only used by [`Self::Generate`].
+    pub fn keypair(&self, seed: Option<[u8; 32]>) -> Result<identity::Keypair> {
+        match self {
+            Self::Generate => match seed {
+                Some(seed) => {
+                    let secret = identity::ed25519::SecretKey::try_from_bytes(seed)
+                        .map_err(|err| anyhow!("invalid ed25519 seed provided: {err}"))?;
+                    Ok(identity::ed25519::Keypair::from(secret).into())
+                }
+                None => Ok(identity::Keypair::generate_ed25519()),
+            },
+            Self::Protobuf(bytes) => identity::Keypair::from_protobuf_encoding(bytes)
+                .map_err(|err| anyhow!("invalid protobuf-encoded keypair: {err}")),
+            Self::File(path) => load_or_create_keypair(path),
+        }
+    }
+}
+
+impl fmt::Debug for KeypairSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Generate => f.write_str("Generate"),
+            // The bytes hold the secret key.
+            Self::Protobuf(_) => f.write_str("Protobuf(..)"),
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+        }
+    }
+}
+
+/// Reads the keypair stored at `path`, or generates an Ed25519 keypair and
+/// stores it there when the file does not exist.
+pub fn load_or_create_keypair(path: &Path) -> Result<identity::Keypair> {
+    match fs::read(path) {
+        Ok(bytes) => identity::Keypair::from_protobuf_encoding(&bytes).map_err(|err| {
+            anyhow!(
+                "keypair file {} does not hold a protobuf-encoded keypair: {err}",
+                path.display()
+            )
+        }),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let keypair = identity::Keypair::generate_ed25519();
+            save_keypair(&keypair, path)?;
+            tracing::info!(target: "transport", path = %path.display(), "created identity keypair file");
+            Ok(keypair)
+        }
+        Err(err) => {
+            Err(err).with_context(|| format!("failed to read keypair file {}", path.display()))
+        }
+    }
+}
+
+/// Writes `keypair` to `path`, replacing it atomically. On Unix the file is
+/// only readable by its owner.
+pub fn save_keypair(keypair: &identity::Keypair, path: &Path) -> Result<()> {
+    let bytes = keypair
+        .to_protobuf_encoding()
+        .map_err(|err| anyhow!("failed to encode keypair: {err}"))?;
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory {}", parent.display()))?;
+    }
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    // The mode only applies to a newly created file, so a temporary file
+    // left behind by an interrupted save is not reused.
+    if let Err(err) = fs::remove_file(&temporary) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            return Err(err)
+                .with_context(|| format!("failed to remove stale {}", temporary.display()));
+        }
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&temporary)
+        .and_then(|mut file| {
+            file.write_all(&bytes)?;
+            file.sync_all()
+        })
+        .with_context(|| format!("failed to write keypair file {}", temporary.display()))?;
+    fs::rename(&temporary, path)
+        .with_context(|| format!("failed to replace keypair file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keypair_file_is_created_then_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity").join("node.key");
+
+        let created = load_or_create_keypair(&path).unwrap();
+        let loaded = KeypairSource::File(path.clone()).keypair(None).unwrap();
+        assert_eq!(created.public(), loaded.public());
+        assert!(!dir.path().join("identity").join("node.key.tmp").exists());
+    }
+
+    #[test]
+    fn save_replaces_stale_temporary_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.key");
+        let stale = dir.path().join("node.key.tmp");
+        let sibling = dir.path().join("node.tmp");
+        fs::write(&stale, b"stale").unwrap();
+        fs::write(&sibling, b"unrelated").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&stale, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+
+        let keypair = identity::Keypair::generate_ed25519();
+        save_keypair(&keypair, &path).unwrap();
+        assert_eq!(
+            load_or_create_keypair(&path).unwrap().public(),
+            keypair.public()
+        );
+        assert!(!stale.exists());
+        assert_eq!(fs::read(&sibling).unwrap(), b"unrelated");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn invalid_keypair_file_is_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.key");
+        fs::write(&path, b"not a keypair").unwrap();
+        assert!(load_or_create_keypair(&path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"not a keypair");
+    }
+}
@@ -3,44 +3,93 @@
 use anyhow::{anyhow, Result};
 use futures::future::Either;
 use libp2p::{
+    autonat,
     core::{
         muxing::StreamMuxerBox,
         transport::{Boxed, Transport},
         upgrade,
     },
-    gossipsub,
-    identify, identity,
-    kad::{self, store::MemoryStore},
-    noise, ping, quic,
+    dcutr, gossipsub, identify, identity,
+    kad::{
+        self,
+        store::{MemoryStore, MemoryStoreConfig},
+    },
+    multiaddr::Protocol,
+    noise, ping, quic, relay, rendezvous,
+    swarm::behaviour::toggle::Toggle,
     swarm::{Config as SwarmConfig, Swarm},
-    tcp, PeerId, autonat, 
-    relay, swarm::behaviour::toggle::Toggle,
-    rendezvous
+    tcp, Multiaddr, PeerId,
+};
+use prometheus_client::registry::Registry;
+use std::{
+    collections::HashMap, convert::Infallible, io, num::NonZeroU8, path::PathBuf, sync::Arc,
+    time::Duration,
+};
+use tokio::runtime::{Builder as RuntimeBuilder, Runtime};
+
+use super::{
+    announce::{AnnounceFilter, AnnounceFiltered},
+    blocklist::{Blocker, Blocklist},
+    circuit::is_relayed,
+    gate::{GateScope, RoleGate},
+    keep_alive::KeepAlive,
+    keypair::KeypairSource,
+    probe::{ProbeEvent, Prober},
+    substreams::{limit_substreams, SubstreamMetrics},
+    time_sync::{TimeSync, TimeSyncEvent},
 };
-use std::time::Duration;
+use crate::{
+    config::DEFAULT_GOSSIPSUB_TOPIC,
+    messaging::{SlowConsumerSettings, GROUP_TOPIC_PREFIX, TOPIC_KEY_LEN},
+    peer::{
+        audit::AuditLogConfig,
+        delegated::DelegatedRoutingSettings,
+        gossip_rates::GossipThrottleSettings,
+        metrics::{MetricsSink, MetricsSnapshotConfig},
+        version::VersionGate,
+        DuplicateConnectionPolicy,
+    },
+};
+
+/// Protocol version the node announces via identify.
+pub const IDENTIFY_PROTOCOL_VERSION: &str = "/cabi/1.0.0";
 
 /// Combined libp2p behaviour used across the node.
 #[derive(libp2p::swarm::NetworkBehaviour)]
 #[behaviour(to_swarm = "BehaviourEvent")]
 pub struct NetworkBehaviour {
+    /// Denies connections to blocked peers and ranges; first, so denied
+    /// connections never reach the other behaviours.
+    pub blocker: Blocker,
     /// Kademlia DHT behaviour for peer discovery
-    pub kademlia: kad::Behaviour<MemoryStore>,
+    pub kademlia: AnnounceFiltered<kad::Behaviour<MemoryStore>>,
     /// Ping behaviour to keep connections alive and measure latency
     pub ping: ping::Behaviour,
+    /// On-demand ping bursts measuring a peer's latency right away.
+    pub prober: Prober,
+    /// Answers peers' time requests and samples their clock offsets; only
+    /// takes part in new connections while time sync is on.
+    pub time_sync: RoleGate<TimeSync>,
     /// Identify protocol for exchanging supported protocols and addresses
-    pub identify: identify::Behaviour,
-    /// AutoNAT behaviour to probe for public reachability
-    pub autonat: autonat::Behaviour,
+    pub identify: AnnounceFiltered<identify::Behaviour>,
+    /// AutoNAT behaviour to probe for public reachability; its server role
+    /// can be switched off for new inbound connections.
+    pub autonat: RoleGate<autonat::Behaviour>,
     /// Gossipsub for simple message propagation
     pub gossipsub: gossipsub::Behaviour,
     /// Relay client for connecting through hop relays.
     pub relay_client: relay::client::Behaviour,
-    /// Optional relay server (hop) behaviour for acting as a public relay.
-    pub relay_server: Toggle<relay::Behaviour>,
+    /// Optional relay server (hop) behaviour for acting as a public relay;
+    /// can be switched off for new connections.
+    pub relay_server: RoleGate<relay::Behaviour>,
+    /// DCUtR hole punching to upgrade relayed connections to direct ones.
+    pub dcutr: dcutr::Behaviour,
     /// Optional Rendezvous client for asking for a catalog of peers 
-    pub rendezvous_client: Toggle<rendezvous::client::Behaviour>,
+    pub rendezvous_client: AnnounceFiltered<Toggle<rendezvous::client::Behaviour>>,
     /// Optional Rendezvous server for storing and sharing catalog of peers
     pub rendezvous_server: Toggle<rendezvous::server::Behaviour>,
+    /// Keeps connections to pinned peers open past the idle timeout.
+    pub keep_alive: KeepAlive,
 }
 
 /// Event type produced by the composed [`NetworkBehaviour`].
@@ -48,15 +97,24 @@ pub struct NetworkBehaviour {
 pub enum BehaviourEvent {
     Kademlia(kad::Event),
     Ping(ping::Event),
+    Probe(ProbeEvent),
+    TimeSync(TimeSyncEvent),
     Identify(identify::Event),
     Autonat(autonat::Event),
     Gossipsub(gossipsub::Event),
     RelayClient(relay::client::Event),
     RelayServer(relay::Event),
+    Dcutr(dcutr::Event),
     RendezvousClient(rendezvous::client::Event),
     RendezvousServer(rendezvous::server::Event),
 }
 
+impl From<Infallible> for BehaviourEvent {
+    fn from(event: Infallible) -> Self {
+        match event {}
+    }
+}
+
 impl From<kad::Event> for BehaviourEvent {
     fn from(event: kad::Event) -> Self {
         Self::Kademlia(event)
@@ -69,6 +127,18 @@ impl From<ping::Event> for BehaviourEvent {
     }
 }
 
+impl From<ProbeEvent> for BehaviourEvent {
+    fn from(event: ProbeEvent) -> Self {
+        Self::Probe(event)
+    }
+}
+
+impl From<TimeSyncEvent> for BehaviourEvent {
+    fn from(event: TimeSyncEvent) -> Self {
+        Self::TimeSync(event)
+    }
+}
+
 impl From<identify::Event> for BehaviourEvent {
     fn from(event: identify::Event) -> Self {
         Self::Identify(event)
@@ -99,6 +169,12 @@ impl From<relay::Event> for BehaviourEvent {
     }
 }
 
+impl From<dcutr::Event> for BehaviourEvent {
+    fn from(event: dcutr::Event) -> Self {
+        Self::Dcutr(event)
+    }
+}
+
 impl From<rendezvous::client::Event> for BehaviourEvent {
     fn from(event: rendezvous::client::Event) -> Self {
         Self::RendezvousClient(event)
@@ -111,6 +187,420 @@ impl From<rendezvous::server::Event> for BehaviourEvent {
     }
 }
 
+/// Security protocol and muxer the transport carrying connections to
+/// `address` offers.
+///
+/// TCP and relay circuits are upgraded with Noise and Yamux, QUIC brings its
+/// own TLS 1.3 handshake and stream multiplexing. libp2p does not report what
+/// a connection negotiated, but each transport offers exactly one of each, so
+/// an established connection negotiated these.
+pub fn expected_connection_stack(address: &Multiaddr) -> (&'static str, &'static str) {
+    match transport_label(address) {
+        "quic" => ("/tls/1.0.0", "quic-v1"),
+        _ => ("/noise", "/yamux/1.0.0"),
+    }
+}
+
+/// Transport label (`tcp`, `quic` or `relay`) of connections to `address`,
+/// matching the labels of [`SubstreamMetrics`].
+pub fn transport_label(address: &Multiaddr) -> &'static str {
+    if address.iter().any(|p| matches!(p, Protocol::P2pCircuit)) {
+        "relay"
+    } else if address
+        .iter()
+        .any(|p| matches!(p, Protocol::QuicV1 | Protocol::Quic))
+    {
+        "quic"
+    } else {
+        "tcp"
+    }
+}
+
+/// Whether `address` can be dialed from the public internet: DNS names and
+/// global IP addresses, but no relay circuits.
+fn is_publicly_routable(address: &Multiaddr) -> bool {
+    if is_relayed(address) {
+        return false;
+    }
+    match address.iter().next() {
+        Some(Protocol::Ip4(ip)) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation())
+        }
+        Some(Protocol::Ip6(ip)) => {
+            let segment = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local (fc00::/7) and link-local (fe80::/10) ranges.
+                || (segment & 0xfe00) == 0xfc00
+                || (segment & 0xffc0) == 0xfe80)
+        }
+        Some(Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_) | Protocol::Dnsaddr(_)) => {
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Gossipsub tuning knobs. `None` keeps the libp2p default for that setting.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GossipsubSettings {
+    /// Publish own messages to all known topic peers instead of only the mesh.
+    pub flood_publish: Option<bool>,
+    /// How long a pruned peer must wait before grafting again.
+    pub prune_backoff: Option<Duration>,
+    /// Grafts arriving this soon after a prune are treated as flooding and penalised.
+    pub graft_flood_threshold: Option<Duration>,
+    /// Number of heartbeats between opportunistic grafting rounds.
+    pub opportunistic_graft_ticks: Option<u64>,
+    /// Number of peers grafted per opportunistic grafting round.
+    pub opportunistic_graft_peers: Option<usize>,
+    /// Delay before the first gossipsub heartbeat.
+    pub heartbeat_initial_delay: Option<Duration>,
+    /// Hold-off after startup during which publishes are buffered until the
+    /// topic mesh forms (or the hold-off elapses).
+    pub publish_warmup: Option<Duration>,
+    /// Size in bytes from which a received message is announced to mesh
+    /// peers with a gossipsub v1.2 IDONTWANT, so they skip sending a
+    /// duplicate. Peers speaking older protocol versions are unaffected.
+    pub idontwant_threshold: Option<usize>,
+    /// Also send IDONTWANT for own publishes of at least `idontwant_threshold`.
+    pub idontwant_on_publish: Option<bool>,
+}
+
+/// Ping interval of the low-power profile.
+pub const LOW_POWER_PING_INTERVAL: Duration = Duration::from_secs(60);
+/// Identify interval of the low-power profile.
+pub const LOW_POWER_IDENTIFY_INTERVAL: Duration = Duration::from_secs(300);
+/// Gossipsub heartbeat interval of the low-power profile.
+pub const LOW_POWER_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// Target gossipsub mesh size of the low-power profile.
+pub const LOW_POWER_MESH_N: usize = 4;
+
+/// Trade-off between network participation and battery or data use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PowerProfile {
+    #[default]
+    Normal,
+    /// For mobile and IoT hosts on battery or metered networks: pings every
+    /// [`LOW_POWER_PING_INTERVAL`], identifies every
+    /// [`LOW_POWER_IDENTIFY_INTERVAL`], keeps a gossipsub mesh of
+    /// [`LOW_POWER_MESH_N`] peers with a heartbeat every
+    /// [`LOW_POWER_HEARTBEAT_INTERVAL`], uses the DHT as a client only and
+    /// serves no relay circuits.
+    LowPower,
+}
+
+impl PowerProfile {
+    pub fn is_low_power(&self) -> bool {
+        *self == Self::LowPower
+    }
+}
+
+/// Registration store limits of the rendezvous server role.
+///
+/// Namespaces longer than [`rendezvous::MAX_NAMESPACE`] bytes are always
+/// rejected; registrations asking for a TTL outside `min_ttl..=max_ttl` are
+/// refused with `InvalidTtl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RendezvousServerSettings {
+    /// Shortest registration TTL accepted, in seconds.
+    pub min_ttl: u64,
+    /// Longest registration TTL accepted, in seconds.
+    pub max_ttl: u64,
+}
+
+impl Default for RendezvousServerSettings {
+    fn default() -> Self {
+        Self {
+            min_ttl: rendezvous::MIN_TTL,
+            max_ttl: rendezvous::MAX_TTL,
+        }
+    }
+}
+
+impl RendezvousServerSettings {
+    /// Sets the accepted registration TTL range, in seconds.
+    pub fn with_ttl_bounds(mut self, min_ttl: u64, max_ttl: u64) -> Self {
+        self.min_ttl = min_ttl;
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    fn build_config(&self) -> rendezvous::server::Config {
+        rendezvous::server::Config::default()
+            .with_min_ttl(self.min_ttl)
+            .with_max_ttl(self.max_ttl)
+    }
+}
+
+/// Limits of the local Kademlia record store. `None` keeps the libp2p default
+/// for that limit.
+///
+/// When full, records published by remote peers are evicted soonest-expiring
+/// first to make room; provider records are refused instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DhtStoreSettings {
+    /// Records kept, also bounding the keys with provider records.
+    pub max_records: Option<usize>,
+    /// Keys this node announces itself as a provider for.
+    pub max_provided_keys: Option<usize>,
+    /// Largest record value kept, in bytes.
+    pub max_record_size: Option<usize>,
+    /// Providers kept per key.
+    pub max_providers_per_key: Option<usize>,
+}
+
+impl DhtStoreSettings {
+    /// Sets the number of records kept.
+    pub fn with_max_records(mut self, max_records: usize) -> Self {
+        self.max_records = Some(max_records);
+        self
+    }
+
+    /// Sets the number of keys this node provides.
+    pub fn with_max_provided_keys(mut self, max_provided_keys: usize) -> Self {
+        self.max_provided_keys = Some(max_provided_keys);
+        self
+    }
+
+    /// Sets the largest record value kept, in bytes.
+    pub fn with_max_record_size(mut self, max_record_size: usize) -> Self {
+        self.max_record_size = Some(max_record_size);
+        self
+    }
+
+    /// Sets the number of providers kept per key.
+    pub fn with_max_providers_per_key(mut self, max_providers_per_key: usize) -> Self {
+        self.max_providers_per_key = Some(max_providers_per_key);
+        self
+    }
+
+    fn build_config(&self) -> MemoryStoreConfig {
+        let mut config = MemoryStoreConfig::default();
+        if let Some(max_records) = self.max_records {
+            config.max_records = max_records;
+        }
+        if let Some(max_provided_keys) = self.max_provided_keys {
+            config.max_provided_keys = max_provided_keys;
+        }
+        if let Some(max_record_size) = self.max_record_size {
+            config.max_value_bytes = max_record_size;
+        }
+        if let Some(max_providers_per_key) = self.max_providers_per_key {
+            config.max_providers_per_key = max_providers_per_key;
+        }
+        config
+    }
+}
+
+/// Default number of swarm events the peer manager handles in a row before
+/// yielding its worker thread.
+pub const DEFAULT_SWARM_EVENTS_PER_TURN: usize = 64;
+/// Default number of delegated routing lookups in flight at once.
+pub const DEFAULT_DELEGATED_LOOKUPS: usize = 4;
+
+/// How much work each subsystem may do at once, so a node pinned to few cores
+/// leaves room for the rest of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskBudgets {
+    /// Swarm events handled in a row before the peer manager yields its
+    /// worker thread to other tasks.
+    pub swarm_events_per_turn: usize,
+    /// Delegated routing lookups in flight at once. Lookups past the budget
+    /// are skipped and the query finishes with the DHT's answer.
+    pub delegated_lookups: usize,
+    /// Addresses of one peer dialed concurrently. `None` keeps the libp2p
+    /// default (8).
+    pub dial_concurrency: Option<NonZeroU8>,
+}
+
+impl Default for TaskBudgets {
+    fn default() -> Self {
+        Self {
+            swarm_events_per_turn: DEFAULT_SWARM_EVENTS_PER_TURN,
+            delegated_lookups: DEFAULT_DELEGATED_LOOKUPS,
+            dial_concurrency: None,
+        }
+    }
+}
+
+impl TaskBudgets {
+    pub fn with_swarm_events_per_turn(mut self, events: usize) -> Self {
+        self.swarm_events_per_turn = events;
+        self
+    }
+
+    pub fn with_delegated_lookups(mut self, lookups: usize) -> Self {
+        self.delegated_lookups = lookups;
+        self
+    }
+
+    pub fn with_dial_concurrency(mut self, dials: NonZeroU8) -> Self {
+        self.dial_concurrency = Some(dials);
+        self
+    }
+}
+
+/// Threads of the runtime the node owns, e.g. behind the C ABI, and the
+/// budgets of its subsystems. Embedders driving [`crate::peer::PeerManager::run`]
+/// on their own runtime size its threads themselves; the budgets still apply.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutorSettings {
+    /// Runs everything on a single thread without work stealing, for hosts
+    /// with strict threading rules. `worker_threads` must be unset.
+    pub current_thread: bool,
+    /// Worker threads. `None` starts one per core.
+    pub worker_threads: Option<usize>,
+    /// Threads for blocking work, such as file writes. `None` keeps the
+    /// tokio default (512).
+    pub max_blocking_threads: Option<usize>,
+    pub budgets: TaskBudgets,
+}
+
+impl ExecutorSettings {
+    /// Runs everything on a single thread, see [`Self::current_thread`].
+    pub fn with_current_thread(mut self, enable: bool) -> Self {
+        self.current_thread = enable;
+        self
+    }
+
+    /// Sets the number of worker threads.
+    pub fn with_worker_threads(mut self, threads: usize) -> Self {
+        self.worker_threads = Some(threads);
+        self
+    }
+
+    /// Sets the number of threads for blocking work.
+    pub fn with_max_blocking_threads(mut self, threads: usize) -> Self {
+        self.max_blocking_threads = Some(threads);
+        self
+    }
+
+    pub fn with_budgets(mut self, budgets: TaskBudgets) -> Self {
+        self.budgets = budgets;
+        self
+    }
+
+    /// Builds a runtime with these thread counts. A current-thread runtime
+    /// only makes progress while a thread drives it with `block_on`.
+    pub fn build_runtime(&self) -> io::Result<Runtime> {
+        let mut builder = if self.current_thread {
+            RuntimeBuilder::new_current_thread()
+        } else {
+            RuntimeBuilder::new_multi_thread()
+        };
+        builder.enable_all().thread_name("cabi-libp2p");
+        if let Some(threads) = self.worker_threads.filter(|_| !self.current_thread) {
+            builder.worker_threads(threads);
+        }
+        if let Some(threads) = self.max_blocking_threads {
+            builder.max_blocking_threads(threads);
+        }
+        builder.build()
+    }
+}
+
+impl GossipsubSettings {
+    /// Enables or disables flood publishing (reliability over bandwidth).
+    pub fn with_flood_publish(mut self, enable: bool) -> Self {
+        self.flood_publish = Some(enable);
+        self
+    }
+
+    /// Sets the prune backoff duration.
+    pub fn with_prune_backoff(mut self, backoff: Duration) -> Self {
+        self.prune_backoff = Some(backoff);
+        self
+    }
+
+    /// Sets the graft flood threshold.
+    pub fn with_graft_flood_threshold(mut self, threshold: Duration) -> Self {
+        self.graft_flood_threshold = Some(threshold);
+        self
+    }
+
+    /// Configures opportunistic grafting: every `ticks` heartbeats graft `peers` peers.
+    pub fn with_opportunistic_grafting(mut self, ticks: u64, peers: usize) -> Self {
+        self.opportunistic_graft_ticks = Some(ticks);
+        self.opportunistic_graft_peers = Some(peers);
+        self
+    }
+
+    /// Sets the delay before the first heartbeat.
+    pub fn with_heartbeat_initial_delay(mut self, delay: Duration) -> Self {
+        self.heartbeat_initial_delay = Some(delay);
+        self
+    }
+
+    /// Buffers publishes issued during the first `warmup` after startup until
+    /// the topic has mesh peers, so early messages are not published into an
+    /// empty mesh.
+    pub fn with_publish_warmup(mut self, warmup: Duration) -> Self {
+        self.publish_warmup = Some(warmup);
+        self
+    }
+
+    /// Sets the message size from which IDONTWANT is sent to mesh peers.
+    pub fn with_idontwant_threshold(mut self, bytes: usize) -> Self {
+        self.idontwant_threshold = Some(bytes);
+        self
+    }
+
+    /// Enables or disables IDONTWANT for own publishes above the threshold.
+    pub fn with_idontwant_on_publish(mut self, enable: bool) -> Self {
+        self.idontwant_on_publish = Some(enable);
+        self
+    }
+
+    /// Builds the gossipsub configuration with these settings applied.
+    fn build_config(&self, power_profile: PowerProfile) -> Result<gossipsub::Config> {
+        let mut builder = gossipsub::ConfigBuilder::default();
+        // Messages are only forwarded once the peer manager has verified them.
+        builder.validate_messages();
+        if power_profile.is_low_power() {
+            builder
+                .heartbeat_interval(LOW_POWER_HEARTBEAT_INTERVAL)
+                .mesh_n(LOW_POWER_MESH_N)
+                .mesh_n_low(LOW_POWER_MESH_N / 2)
+                .mesh_n_high(LOW_POWER_MESH_N * 3 / 2)
+                .mesh_outbound_min(1)
+                .gossip_lazy(LOW_POWER_MESH_N);
+        }
+        if let Some(enable) = self.flood_publish {
+            builder.flood_publish(enable);
+        }
+        if let Some(backoff) = self.prune_backoff {
+            builder.prune_backoff(backoff);
+        }
+        if let Some(threshold) = self.graft_flood_threshold {
+            builder.graft_flood_threshold(threshold);
+        }
+        if let Some(ticks) = self.opportunistic_graft_ticks {
+            builder.opportunistic_graft_ticks(ticks);
+        }
+        if let Some(peers) = self.opportunistic_graft_peers {
+            builder.opportunistic_graft_peers(peers);
+        }
+        if let Some(delay) = self.heartbeat_initial_delay {
+            builder.heartbeat_initial_delay(delay);
+        }
+        if let Some(bytes) = self.idontwant_threshold {
+            builder.idontwant_message_size_threshold(bytes);
+        }
+        if let Some(enable) = self.idontwant_on_publish {
+            builder.idontwant_on_publish(enable);
+        }
+        builder
+            .build()
+            .map_err(|err| anyhow!("invalid gossipsub config: {err}"))
+    }
+}
+
 /// Transport configuration builder.
 #[derive(Debug, Clone)]
 pub struct TransportConfig {
@@ -120,8 +610,127 @@ pub struct TransportConfig {
     pub hop_relay: bool,
     /// Controls whether rendezvous behaviours are enabled.
     pub enable_rendezvous: bool,
+    /// When set, the node hosts a rendezvous server with these limits.
+    /// Hop relays run one with default limits when unset.
+    pub rendezvous_server: Option<RendezvousServerSettings>,
     /// Optional seed for deriving an exact Ed25519 identity keypair.
     pub identity_seed: Option<[u8; 32]>,
+    /// Where the identity keypair comes from. Any source but
+    /// [`KeypairSource::Generate`] takes precedence over `identity_seed`.
+    pub keypair_source: KeypairSource,
+    /// When set, a fresh identity is generated on every start and never
+    /// persisted; `identity_seed` and `keypair_source` are ignored.
+    pub ephemeral_identity: bool,
+    /// Optional period after which an ephemeral identity is replaced while running.
+    pub identity_rotation: Option<Duration>,
+    /// Optional network-specific Noise prologue. Both sides must use the same
+    /// bytes, otherwise the handshake fails before any protocol is negotiated.
+    /// QUIC does not run Noise, so a prologue cannot be combined with `use_quic`.
+    pub noise_prologue: Option<Vec<u8>>,
+    /// Optional Yamux per-stream receive window in bytes.
+    pub yamux_receive_window_size: Option<u32>,
+    /// Optional Yamux per-stream buffer limit in bytes.
+    pub yamux_max_buffer_size: Option<usize>,
+    /// Optional cap on concurrent Yamux streams per connection.
+    pub yamux_max_num_streams: Option<usize>,
+    /// Optional cap on concurrent substreams per connection, across all transports.
+    pub max_substreams_per_connection: Option<usize>,
+    /// Gossipsub mesh tuning.
+    pub gossipsub: GossipsubSettings,
+    /// Topic the node subscribes to on start and publishes on by default.
+    pub gossipsub_topic: String,
+    /// Further topics subscribed to on start, next to `gossipsub_topic`.
+    pub gossipsub_topics: Vec<String>,
+    /// When set, payloads are wrapped in a nonce/timestamp envelope and
+    /// replays within this window are rejected.
+    pub replay_window: Option<Duration>,
+    /// When set, the clock offset of every peer is sampled on each identify
+    /// exchange, and replay envelopes are checked against the median
+    /// network time instead of the local clock. Only then does the node
+    /// answer peers' time requests.
+    pub time_sync: bool,
+    /// When set, every message published without a trace id of its own gets
+    /// one in its replay envelope, so the logs about it can be correlated
+    /// across the nodes it reaches. Needs `replay_window`.
+    pub trace_messages: bool,
+    /// When set, metrics snapshots are periodically written to a file or ring buffer.
+    pub metrics_snapshots: Option<MetricsSnapshotConfig>,
+    /// When set, every outbound message is recorded to a rotating audit file.
+    pub audit_log: Option<AuditLogConfig>,
+    /// When set, peer IDs and IP addresses are replaced by hashes in peer manager logs.
+    pub redact_logs: bool,
+    /// Statically declared external addresses (e.g. a known port-forward).
+    /// They are probed with AutoNAT and advertised only once confirmed.
+    pub external_address_candidates: Vec<Multiaddr>,
+    /// When set, the swarm is rebuilt in place after this long without swarm
+    /// events and failing self-checks, or after repeated listener failures.
+    pub watchdog_timeout: Option<Duration>,
+    /// Expected lifetime of relay reservations. A reservation not renewed
+    /// shortly before it elapses is re-requested, then reported as expired.
+    pub relay_reservation_ttl: Duration,
+    /// Interval of the keep-alive pings sent on relayed connections, shorter
+    /// than the regular ping interval so the NAT bindings on the way to the
+    /// relay survive idle periods. `None`, the default, leaves relayed
+    /// connections to the regular pings.
+    pub relay_ping_interval: Option<Duration>,
+    /// When set, gossipsub peer scoring is enabled and peers relaying too many
+    /// duplicate or invalid messages get a negative score, pruning them from
+    /// the mesh until they behave again. Needs `replay_window`: duplicates
+    /// are the envelopes its replay cache refuses.
+    pub gossip_throttle: Option<GossipThrottleSettings>,
+    /// When set, identify exchanges signed peer records and finished
+    /// find_peer queries report the record of the target, when known, so it
+    /// can be shared out-of-band.
+    pub signed_peer_records: bool,
+    /// When set, gossipsub's built-in metrics (mesh churn, IHAVE/IWANT,
+    /// duplicates) are registered in the Prometheus registry.
+    pub gossipsub_metrics: bool,
+    /// When set, a consumer not keeping up with the inbound queue is reported
+    /// (and gossip optionally paused) instead of messages being dropped silently.
+    pub slow_consumer: Option<SlowConsumerSettings>,
+    /// How long a connection without open streams stays up before it is
+    /// closed. `None` keeps the libp2p default.
+    pub idle_connection_timeout: Option<Duration>,
+    /// Peers whose connections are kept open regardless of the idle timeout.
+    pub pinned_peers: Vec<PeerId>,
+    /// When set, inbound messages not dequeued within this long are dropped
+    /// as dead letters instead of being handed to the application.
+    pub inbound_message_ttl: Option<Duration>,
+    /// When set, publishes held by the startup warm-up for longer than this
+    /// are dropped as dead letters instead of being sent.
+    pub outbound_message_ttl: Option<Duration>,
+    /// Shared secrets keyed by topic name. Payloads on these topics are
+    /// encrypted on publish and decrypted on receipt; nodes without the key
+    /// only see ciphertext.
+    pub topic_keys: HashMap<String, [u8; TOPIC_KEY_LEN]>,
+    /// When set, an address observed by this many distinct connected peers
+    /// via identify is advertised as external without waiting for AutoNAT.
+    pub observed_addr_confirmations: Option<usize>,
+    /// Capacity limits of the Kademlia record store.
+    pub dht_store: DhtStoreSettings,
+    /// Local addresses matching any of these filters are listened on but
+    /// never advertised through identify, Kademlia or rendezvous.
+    pub announce_filters: Vec<AnnounceFilter>,
+    /// How redundant direct connections to the same peer are resolved.
+    pub duplicate_connections: DuplicateConnectionPolicy,
+    /// When set, peers and providers the DHT cannot locate are looked up on
+    /// this delegated routing server, which client-only nodes ask first.
+    pub delegated_routing: Option<DelegatedRoutingSettings>,
+    /// Minimum versions peers must announce via identify to stay connected.
+    pub version_gate: VersionGate,
+    /// When set, the node only listens: it receives gossip and queries the
+    /// DHT but never publishes, forwards gossip, serves or stores DHT
+    /// records, relays or answers AutoNAT probes.
+    pub observer: bool,
+    /// Runtime threads and subsystem task budgets.
+    pub executor: ExecutorSettings,
+    /// Battery and data trade-off; switching it rebuilds the swarm.
+    pub power_profile: PowerProfile,
+    /// Peers and IP ranges connections are refused to and from.
+    pub blocklist: Blocklist,
+    /// When set, the blocklist stored in this file is added to `blocklist`
+    /// on start, and the file is rewritten after every imported update.
+    pub blocklist_file: Option<PathBuf>,
 }
 
 impl Default for TransportConfig {
@@ -130,11 +739,89 @@ impl Default for TransportConfig {
             use_quic: false, // Turn on for quic
             hop_relay: false, // Turn on for node act as relay (at least try)
             enable_rendezvous: false, // FEATURE NOT USED. Turn on for rendezvous client/server
+            rendezvous_server: None, // Pass to host a rendezvous discovery point
             identity_seed: None, // Pass to use identity seed for generating keypair
+            keypair_source: KeypairSource::Generate, // Pass a file to keep the identity across restarts
+            ephemeral_identity: false, // Turn on for a fresh identity every session
+            identity_rotation: None, // Pass to rotate the ephemeral identity on a timer
+            noise_prologue: None, // Pass to isolate the network at the Noise handshake
+            yamux_receive_window_size: None, // Yamux default (256 KiB)
+            yamux_max_buffer_size: None, // Yamux default (1 MiB)
+            yamux_max_num_streams: None, // Yamux default (512 streams)
+            max_substreams_per_connection: None, // Pass to cap substreams per connection
+            gossipsub: GossipsubSettings::default(), // libp2p gossipsub defaults
+            gossipsub_topic: DEFAULT_GOSSIPSUB_TOPIC.to_string(), // Pass to move the node to another topic
+            gossipsub_topics: Vec::new(), // Pass to subscribe to more topics on start
+            replay_window: None, // Pass to enable replay protection envelopes
+            time_sync: false, // Turn on to check envelope timestamps against the network's clock
+            trace_messages: false, // Turn on to correlate a message's logs across nodes
+            metrics_snapshots: None, // Pass to persist metrics snapshots periodically
+            audit_log: None, // Pass to keep an audit trail of outbound messages
+            redact_logs: false, // Turn on for privacy-preserving logs
+            external_address_candidates: Vec::new(), // Pass known port-forwards to probe
+            watchdog_timeout: None, // Pass to rebuild a stalled swarm automatically
+            relay_reservation_ttl: Duration::from_secs(60 * 60), // libp2p relay server default
+            relay_ping_interval: None, // Pass an interval to keep NAT bindings of circuits alive
+            gossip_throttle: None, // Pass to prune peers relaying duplicate or invalid gossip
+            signed_peer_records: false, // Turn on to collect peers' signed records via identify
+            gossipsub_metrics: false, // Turn on to export gossipsub internals to Prometheus
+            slow_consumer: None, // Pass to detect an application not draining inbound messages
+            idle_connection_timeout: None, // libp2p default
+            pinned_peers: Vec::new(), // Pass peers that must stay connected while idle
+            inbound_message_ttl: None, // Pass to drop stale inbound messages
+            outbound_message_ttl: None, // Pass to drop stale held publishes
+            topic_keys: HashMap::new(), // Pass to encrypt payloads of private topics
+            observed_addr_confirmations: None, // Pass to trust addresses reported by enough peers
+            dht_store: DhtStoreSettings::default(), // libp2p MemoryStore defaults
+            announce_filters: Vec::new(), // Add to keep e.g. docker bridge addresses private
+            duplicate_connections: DuplicateConnectionPolicy::KeepAll, // Simultaneous dials keep both connections
+            delegated_routing: None, // Pass an indexer endpoint for hostile networks
+            version_gate: VersionGate::default(), // Every version is accepted
+            observer: false, // Turn on for monitoring nodes that must not influence the network
+            executor: ExecutorSettings::default(), // One worker thread per core
+            power_profile: PowerProfile::Normal, // Switch to LowPower on battery or metered networks
+            blocklist: Blocklist::default(), // Every peer may connect
+            blocklist_file: None, // Pass to keep imported blocklist updates across restarts
         }
     }
 }
 
+/// Invokes `$apply` with the [`TransportConfig`] fields that only take effect
+/// when a swarm is built, so the settings reported and the settings kept on a
+/// reload come from one list.
+macro_rules! restart_required_fields {
+    ($apply:ident) => {
+        $apply!(
+            use_quic,
+            hop_relay,
+            enable_rendezvous,
+            rendezvous_server,
+            identity_seed,
+            keypair_source,
+            ephemeral_identity,
+            noise_prologue,
+            yamux_receive_window_size,
+            yamux_max_buffer_size,
+            yamux_max_num_streams,
+            gossipsub,
+            gossipsub_topic,
+            gossipsub_topics,
+            gossipsub_metrics,
+            dht_store,
+            announce_filters,
+            metrics_snapshots,
+            audit_log,
+            observer,
+            executor,
+            blocklist_file,
+            relay_ping_interval,
+            gossip_throttle,
+            signed_peer_records,
+            idle_connection_timeout,
+        )
+    };
+}
+
 impl TransportConfig {
      /// Creates a new configuration with the provided flags.
     pub fn new(use_quic: bool, hop_relay: bool) -> Self {
@@ -153,6 +840,165 @@ impl TransportConfig {
         self
     }
 
+    /// Takes the identity keypair from `source`, e.g. a file created on the
+    /// first start and read back on later ones.
+    pub fn with_keypair_source(mut self, source: KeypairSource) -> Self {
+        self.keypair_source = source;
+        self
+    }
+
+    /// Switches to a fresh, never persisted identity on every start, replaced
+    /// again every `rotation` while running when given. Use an
+    /// [`crate::messaging::AppIdentity`] to keep a stable application-level identity.
+    pub fn with_ephemeral_identity(mut self, rotation: Option<Duration>) -> Self {
+        self.ephemeral_identity = true;
+        self.identity_rotation = rotation;
+        self
+    }
+
+    /// Sets the Noise prologue used to bind handshakes to a specific network.
+    /// Nodes configured with different prologues fail the handshake instead
+    /// of connecting and only disagreeing later at identify. QUIC secures its
+    /// connections with TLS instead of Noise, so the node refuses to build
+    /// with both a prologue and QUIC enabled.
+    pub fn with_noise_prologue(mut self, prologue: impl Into<Vec<u8>>) -> Self {
+        self.noise_prologue = Some(prologue.into());
+        self
+    }
+
+    /// Sets the Yamux receive window per stream. Larger windows let
+    /// high-throughput transfers keep more data in flight per round trip.
+    ///
+    /// Setting this (or [`Self::with_yamux_max_buffer_size`]) switches Yamux to
+    /// its legacy implementation, the only one exposing per-stream windows.
+    pub fn with_yamux_receive_window_size(mut self, num_bytes: u32) -> Self {
+        self.yamux_receive_window_size = Some(num_bytes);
+        self
+    }
+
+    /// Sets the maximum number of bytes Yamux buffers per stream.
+    pub fn with_yamux_max_buffer_size(mut self, num_bytes: usize) -> Self {
+        self.yamux_max_buffer_size = Some(num_bytes);
+        self
+    }
+
+    /// Sets the maximum number of concurrent Yamux streams per connection.
+    pub fn with_yamux_max_num_streams(mut self, num_streams: usize) -> Self {
+        self.yamux_max_num_streams = Some(num_streams);
+        self
+    }
+
+    /// Caps the number of concurrently open substreams on each connection.
+    /// Inbound substreams over the cap are reset, outbound ones wait for a free slot.
+    pub fn with_max_substreams_per_connection(mut self, limit: usize) -> Self {
+        self.max_substreams_per_connection = Some(limit);
+        self
+    }
+
+    /// Sets the gossipsub tuning (flood publish, backoff, opportunistic grafting).
+    pub fn with_gossipsub_settings(mut self, settings: GossipsubSettings) -> Self {
+        self.gossipsub = settings;
+        self
+    }
+
+    /// Subscribes to and publishes on `topic` by default instead of
+    /// [`DEFAULT_GOSSIPSUB_TOPIC`].
+    pub fn with_gossipsub_topic(mut self, topic: impl Into<String>) -> Self {
+        self.gossipsub_topic = topic.into();
+        self
+    }
+
+    /// Subscribes to `topic` on start, next to the default topic.
+    pub fn with_topic(mut self, topic: impl Into<String>) -> Self {
+        self.gossipsub_topics.push(topic.into());
+        self
+    }
+
+    /// Enables replay protection: outbound payloads get a nonce/timestamp
+    /// envelope and inbound envelopes outside `window` or seen before are
+    /// dropped. All nodes of a network must agree on this setting.
+    pub fn with_replay_protection(mut self, window: Duration) -> Self {
+        self.replay_window = Some(window);
+        self
+    }
+
+    /// Estimates the network time from peers' clocks and checks replay
+    /// envelope timestamps against it, tolerating a skewed local clock.
+    pub fn with_time_sync(mut self) -> Self {
+        self.time_sync = true;
+        self
+    }
+
+    /// Gives every message published without a trace id of its own one in
+    /// its replay envelope, so its publish and delivery logs can be
+    /// correlated across nodes. Needs [`Self::with_replay_protection`].
+    pub fn with_message_tracing(mut self) -> Self {
+        self.trace_messages = true;
+        self
+    }
+
+    /// Writes a metrics snapshot (connections, bandwidth, queue depth, DHT
+    /// size) to `sink` every `interval`.
+    pub fn with_metrics_snapshots(mut self, interval: Duration, sink: MetricsSink) -> Self {
+        self.metrics_snapshots = Some(MetricsSnapshotConfig { interval, sink });
+        self
+    }
+
+    /// Records the hash, topic, time and outcome of every outbound message to
+    /// a rotating audit file.
+    pub fn with_audit_log(mut self, audit_log: AuditLogConfig) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Enables privacy mode for logs: peer IDs and IP addresses emitted by the
+    /// peer manager are replaced by per-process keyed hashes.
+    pub fn with_log_redaction(mut self, enable: bool) -> Self {
+        self.redact_logs = enable;
+        self
+    }
+
+    /// Declares an address the node is expected to be reachable at, e.g.
+    /// behind a port-forward. It is advertised only after AutoNAT confirms it.
+    pub fn with_external_address_candidate(mut self, address: Multiaddr) -> Self {
+        self.external_address_candidates.push(address);
+        self
+    }
+
+    /// Enables the health watchdog. The swarm is rebuilt in place, keeping the
+    /// identity, when it produced no events for `stall_timeout` while its
+    /// self-checks fail, or when listeners keep failing.
+    pub fn with_watchdog(mut self, stall_timeout: Duration) -> Self {
+        self.watchdog_timeout = Some(stall_timeout);
+        self
+    }
+
+    /// Sets the reservation lifetime granted by the relays in use, when they
+    /// differ from the libp2p default of one hour.
+    pub fn with_relay_reservation_ttl(mut self, ttl: Duration) -> Self {
+        self.relay_reservation_ttl = ttl;
+        self
+    }
+
+    /// Sets the keep-alive ping interval of relayed connections, e.g.
+    /// [`super::DEFAULT_RELAY_PING_INTERVAL`]; `None` turns the extra pings off.
+    pub fn with_relay_ping_interval(mut self, interval: Option<Duration>) -> Self {
+        self.relay_ping_interval = interval;
+        self
+    }
+
+    /// Throttles peers relaying duplicate or invalid gossip past `settings`.
+    /// Needs [`Self::with_replay_protection`], which detects the duplicates.
+    pub fn with_gossip_throttle(mut self, settings: GossipThrottleSettings) -> Self {
+        self.gossip_throttle = Some(settings);
+        self
+    }
+
+    /// Exchanges signed peer records via identify, see [`Self::signed_peer_records`].
+    pub fn with_signed_peer_records(mut self) -> Self {
+        self.signed_peer_records = true;
+        self
+    }
 
     /// Enables or disables rendezvous client/server behaviours.
     pub fn with_rendezvous_enabled(mut self, enable: bool) -> Self {
@@ -160,72 +1006,568 @@ impl TransportConfig {
         self
     }
 
+    /// Hosts a rendezvous server so other nodes can register and discover
+    /// peers under namespaces, with registration TTLs bounded by `settings`.
+    pub fn with_rendezvous_server(mut self, settings: RendezvousServerSettings) -> Self {
+        self.rendezvous_server = Some(settings);
+        self
+    }
+
+    /// Registers gossipsub's built-in metrics in the Prometheus registry
+    /// exposed by [`crate::peer::PeerManagerHandle::prometheus_metrics`].
+    pub fn with_gossipsub_metrics(mut self, enable: bool) -> Self {
+        self.gossipsub_metrics = enable;
+        self
+    }
+
+    /// Sets the battery and data trade-off, see [`PowerProfile`].
+    pub fn with_power_profile(mut self, profile: PowerProfile) -> Self {
+        self.power_profile = profile;
+        self
+    }
+
+    /// Sets the runtime threads and subsystem task budgets.
+    pub fn with_executor(mut self, executor: ExecutorSettings) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Runs the node as a read-only observer, see [`Self::observer`].
+    pub fn with_observer(mut self, enable: bool) -> Self {
+        self.observer = enable;
+        self
+    }
+
+    /// Enables slow-consumer detection on the inbound message queue.
+    pub fn with_slow_consumer_detection(mut self, settings: SlowConsumerSettings) -> Self {
+        self.slow_consumer = Some(settings);
+        self
+    }
+
+    /// Drops inbound messages still queued after `ttl`.
+    pub fn with_inbound_message_ttl(mut self, ttl: Duration) -> Self {
+        self.inbound_message_ttl = Some(ttl);
+        self
+    }
+
+    /// Drops publishes still held by the startup warm-up after `ttl`.
+    pub fn with_outbound_message_ttl(mut self, ttl: Duration) -> Self {
+        self.outbound_message_ttl = Some(ttl);
+        self
+    }
+
+    /// Encrypts payloads on `topic` with the shared secret `key`. Every member
+    /// of the topic must be configured with the same key.
+    pub fn with_topic_key(mut self, topic: impl Into<String>, key: [u8; TOPIC_KEY_LEN]) -> Self {
+        self.topic_keys.insert(topic.into(), key);
+        self
+    }
+
+    /// Advertises an address as external once `confirmations` distinct
+    /// connected peers report observing us at it. A single lying peer cannot
+    /// poison the advertised addresses unless `confirmations` is 1.
+    pub fn with_observed_addr_confirmations(mut self, confirmations: usize) -> Self {
+        self.observed_addr_confirmations = Some(confirmations);
+        self
+    }
+
+    /// Checks the configuration for contradictory or unusable settings before
+    /// a swarm is built. Every problem found is reported, each with a hint on
+    /// how to fix it.
+    ///
+    /// A relay server without external address candidates passes, since
+    /// AutoNAT may still find it publicly reachable at runtime.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if self.use_quic && self.noise_prologue.is_some() {
+            problems.push(
+                "`noise_prologue` only binds Noise handshakes, but QUIC uses TLS and would let other networks connect; disable `use_quic` or unset `noise_prologue`"
+                    .to_string(),
+            );
+        }
+        for address in &self.external_address_candidates {
+            if !self.use_quic && transport_label(address) == "quic" {
+                problems.push(format!(
+                    "external address candidate {address} uses QUIC but QUIC is disabled; enable `use_quic` or remove the candidate"
+                ));
+            }
+        }
+        if self.hop_relay {
+            let candidates = &self.external_address_candidates;
+            if let Some(address) = candidates.iter().find(|address| is_relayed(address)) {
+                problems.push(format!(
+                    "relay server candidate {address} is itself relayed; declare the direct address clients dial instead"
+                ));
+            }
+            if !candidates.is_empty() && !candidates.iter().any(is_publicly_routable) {
+                problems.push(
+                    "relay server has no publicly routable external address candidate; declare its public address or disable `hop_relay`"
+                        .to_string(),
+                );
+            }
+        }
+
+        let executor_limits = [
+            ("executor.worker_threads", self.executor.worker_threads),
+            (
+                "executor.max_blocking_threads",
+                self.executor.max_blocking_threads,
+            ),
+            (
+                "executor.budgets.swarm_events_per_turn",
+                Some(self.executor.budgets.swarm_events_per_turn),
+            ),
+        ];
+        for (name, limit) in executor_limits {
+            if limit == Some(0) {
+                problems.push(format!(
+                    "`{name}` is zero, which would never run that work; use a positive count"
+                ));
+            }
+        }
+        if self.executor.current_thread && self.executor.worker_threads.is_some() {
+            problems.push(
+                "`executor.worker_threads` is set on a current-thread executor, which has no worker threads; unset one of them"
+                    .to_string(),
+            );
+        }
+        if self.delegated_routing.is_some() && self.executor.budgets.delegated_lookups == 0 {
+            problems.push(
+                "`executor.budgets.delegated_lookups` is zero, which would skip every delegated lookup; use a positive count or unset `delegated_routing`"
+                    .to_string(),
+            );
+        }
+
+        if self.observer && self.hop_relay {
+            problems.push(
+                "`observer` nodes never relay; disable `hop_relay` or `observer`".to_string(),
+            );
+        }
+        if self.observer && self.rendezvous_server.is_some() {
+            problems.push(
+                "`observer` nodes never serve rendezvous registrations; unset `rendezvous_server` or disable `observer`"
+                    .to_string(),
+            );
+        }
+
+        if self.identity_rotation.is_some() && !self.ephemeral_identity {
+            problems.push(
+                "identity rotation requires an ephemeral identity; use `with_ephemeral_identity`"
+                    .to_string(),
+            );
+        }
+
+        for (topic, key) in &self.topic_keys {
+            if key.iter().all(|byte| *byte == 0) {
+                problems.push(format!(
+                    "topic key for `{topic}` is all zeros; use a random shared secret"
+                ));
+            }
+        }
+
+        let durations = [
+            ("identity_rotation", self.identity_rotation),
+            ("replay_window", self.replay_window),
+            ("watchdog_timeout", self.watchdog_timeout),
+            ("relay_reservation_ttl", Some(self.relay_reservation_ttl)),
+            ("relay_ping_interval", self.relay_ping_interval),
+            (
+                "gossip_throttle.window",
+                self.gossip_throttle.map(|settings| settings.window),
+            ),
+            (
+                "metrics_snapshots.interval",
+                self.metrics_snapshots
+                    .as_ref()
+                    .map(|snapshots| snapshots.interval),
+            ),
+            (
+                "slow_consumer.stall_threshold",
+                self.slow_consumer.map(|settings| settings.stall_threshold),
+            ),
+            ("inbound_message_ttl", self.inbound_message_ttl),
+            (
+                "delegated_routing.timeout",
+                self.delegated_routing
+                    .as_ref()
+                    .map(|settings| settings.timeout),
+            ),
+            ("outbound_message_ttl", self.outbound_message_ttl),
+        ];
+        for (name, duration) in durations {
+            if duration == Some(Duration::ZERO) {
+                problems.push(format!(
+                    "`{name}` is zero; use a positive duration or unset it"
+                ));
+            }
+        }
+
+        let capacities = [
+            (
+                "yamux_receive_window_size",
+                self.yamux_receive_window_size.map(|size| size as usize),
+            ),
+            ("yamux_max_buffer_size", self.yamux_max_buffer_size),
+            ("yamux_max_num_streams", self.yamux_max_num_streams),
+            (
+                "max_substreams_per_connection",
+                self.max_substreams_per_connection,
+            ),
+        ];
+        for (name, capacity) in capacities {
+            if capacity == Some(0) {
+                problems.push(format!(
+                    "`{name}` is zero, which would block every stream; use a positive limit or unset it"
+                ));
+            }
+        }
+        if let Some(MetricsSnapshotConfig {
+            sink: MetricsSink::File {
+                max_file_bytes: 0, ..
+            },
+            ..
+        }) = &self.metrics_snapshots
+        {
+            problems.push(
+                "`metrics_snapshots.sink.max_file_bytes` is zero, which would rotate on every snapshot; use a positive size"
+                    .to_string(),
+            );
+        }
+
+        if let Some(protocol) = &self.version_gate.protocol {
+            if !protocol.is_met_by(IDENTIFY_PROTOCOL_VERSION) {
+                problems.push(format!(
+                    "`version_gate.protocol` requires {protocol}, which this node's own {IDENTIFY_PROTOCOL_VERSION} fails; every peer running this build would be disconnected"
+                ));
+            }
+        }
+        if let Some(Err(err)) = self
+            .delegated_routing
+            .as_ref()
+            .map(DelegatedRoutingSettings::parse_endpoint)
+        {
+            problems.push(format!("{err}; use an http://host:port endpoint"));
+        }
+        if self
+            .audit_log
+            .as_ref()
+            .is_some_and(|audit_log| audit_log.max_file_bytes == 0)
+        {
+            problems.push(
+                "`audit_log.max_file_bytes` is zero, which would rotate on every message; use a positive size"
+                    .to_string(),
+            );
+        }
+
+        let store_limits = [
+            ("dht_store.max_records", self.dht_store.max_records),
+            (
+                "dht_store.max_provided_keys",
+                self.dht_store.max_provided_keys,
+            ),
+            ("dht_store.max_record_size", self.dht_store.max_record_size),
+            (
+                "dht_store.max_providers_per_key",
+                self.dht_store.max_providers_per_key,
+            ),
+        ];
+        for (name, limit) in store_limits {
+            if limit == Some(0) {
+                problems.push(format!(
+                    "`{name}` is zero, which would refuse every record; use a positive limit or unset it"
+                ));
+            }
+        }
+
+        if self.observed_addr_confirmations == Some(0) {
+            problems.push(
+                "`observed_addr_confirmations` is zero, which would trust any reported address; use at least 1 or unset it"
+                    .to_string(),
+            );
+        }
+        if let Some(settings) = self.slow_consumer {
+            if !(0.0..=1.0).contains(&settings.max_drop_rate) {
+                problems.push(format!(
+                    "`slow_consumer.max_drop_rate` is {}; use a fraction between 0.0 and 1.0",
+                    settings.max_drop_rate
+                ));
+            }
+        }
+        if let Some(settings) = self.gossip_throttle {
+            if !(settings.score_penalty < 0.0 && settings.score_penalty.is_finite()) {
+                problems.push(format!(
+                    "`gossip_throttle.score_penalty` is {}; use a negative score",
+                    settings.score_penalty
+                ));
+            }
+        }
+        if let Some(settings) = self.rendezvous_server {
+            if settings.min_ttl > settings.max_ttl {
+                problems.push(format!(
+                    "rendezvous server `min_ttl` ({}) exceeds `max_ttl` ({}); swap or widen the bounds",
+                    settings.min_ttl, settings.max_ttl
+                ));
+            }
+        }
+        if let Err(err) = self.gossipsub.build_config(self.power_profile) {
+            problems.push(format!("{err}; adjust the gossipsub settings"));
+        }
+
+        if self.gossip_throttle.is_some() && self.replay_window.is_none() {
+            problems.push(
+                "`gossip_throttle` needs `replay_window`, whose replay cache detects duplicates"
+                    .to_string(),
+            );
+        }
+        if self.trace_messages && self.replay_window.is_none() {
+            problems.push(
+                "`trace_messages` needs `replay_window`, the trace id travels in its envelope"
+                    .to_string(),
+            );
+        }
+        for topic in std::iter::once(&self.gossipsub_topic).chain(&self.gossipsub_topics) {
+            if topic.is_empty() {
+                problems.push("gossipsub topics must not be empty".to_string());
+            } else if topic.starts_with(GROUP_TOPIC_PREFIX) {
+                problems.push(format!(
+                    "topic {topic} is reserved for groups; join the group instead of subscribing to its topic"
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("invalid transport config: {}", problems.join("; ")))
+        }
+    }
+
+    /// Names of the fields differing from `other` that only take effect when
+    /// a swarm is built, e.g. by restarting the node.
+    pub fn restart_required_changes(&self, other: &TransportConfig) -> Vec<&'static str> {
+        macro_rules! changes {
+            ($($field:ident,)*) => {
+                [$((stringify!($field), self.$field != other.$field)),*]
+            };
+        }
+        restart_required_fields!(changes)
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Returns `self` with the fields reported by
+    /// [`Self::restart_required_changes`] taken from `current`, the
+    /// configuration a running swarm was built with.
+    pub fn keeping_restart_required(self, current: &TransportConfig) -> TransportConfig {
+        macro_rules! keep {
+            ($($field:ident,)*) => {
+                TransportConfig {
+                    $($field: current.$field.clone(),)*
+                    ..self
+                }
+            };
+        }
+        restart_required_fields!(keep)
+    }
+
+    /// Checks that a node with this configuration can listen on `address`.
+    pub fn validate_listen_address(&self, address: &Multiaddr) -> Result<()> {
+        if !self.use_quic && transport_label(address) == "quic" {
+            return Err(anyhow!(
+                "cannot listen on {address}: QUIC is disabled; enable `use_quic` or listen on a TCP address"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Closes connections that had no open stream for `timeout`, unless the
+    /// remote peer is pinned.
+    pub fn with_idle_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_connection_timeout = Some(timeout);
+        self
+    }
+
+    /// Keeps connections to `peer_id` open while idle, e.g. for bootstrap or
+    /// relay peers the node must stay connected to.
+    pub fn with_pinned_peer(mut self, peer_id: PeerId) -> Self {
+        self.pinned_peers.push(peer_id);
+        self
+    }
+
+    /// Refuses connections to and from the peers and ranges in `blocklist`.
+    pub fn with_blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.blocklist = blocklist;
+        self
+    }
+
+    /// Loads the blocklist from `path` on start and persists imported
+    /// updates to it.
+    pub fn with_blocklist_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.blocklist_file = Some(path.into());
+        self
+    }
+
+    /// Returns `blocklist` with the entries stored in `blocklist_file` added.
+    pub fn stored_blocklist(&self) -> Result<Blocklist> {
+        let mut blocklist = self.blocklist.clone();
+        if let Some(path) = &self.blocklist_file {
+            blocklist.extend(Blocklist::load(path)?);
+        }
+        Ok(blocklist)
+    }
+
+    /// Keeps local addresses matching `filter` from being advertised to peers.
+    pub fn with_announce_filter(mut self, filter: AnnounceFilter) -> Self {
+        self.announce_filters.push(filter);
+        self
+    }
+
+    /// Looks up peers and providers the DHT cannot locate on a delegated
+    /// routing server. Peer lookups skip the DHT while the routing table is
+    /// empty, and client-only nodes ask the server before the DHT.
+    pub fn with_delegated_routing(mut self, settings: DelegatedRoutingSettings) -> Self {
+        self.delegated_routing = Some(settings);
+        self
+    }
+
+    /// Disconnects peers announcing versions older than `gate` requires.
+    pub fn with_version_gate(mut self, gate: VersionGate) -> Self {
+        self.version_gate = gate;
+        self
+    }
+
+    /// Sets how redundant direct connections to the same peer are resolved.
+    pub fn with_duplicate_connections(mut self, policy: DuplicateConnectionPolicy) -> Self {
+        self.duplicate_connections = policy;
+        self
+    }
+
+    /// Sets the capacity limits of the Kademlia record store.
+    pub fn with_dht_store(mut self, dht_store: DhtStoreSettings) -> Self {
+        self.dht_store = dht_store;
+        self
+    }
+
     /// Builds the swarm using the provided configuration.
     pub fn build(&self) -> Result<(identity::Keypair, Swarm<NetworkBehaviour>)> {
-        let keypair = if let Some(seed) = self.identity_seed {
-            let secret = identity::ed25519::SecretKey::try_from_bytes(seed)
-                .map_err(|err| anyhow!("invalid ed25519 seed provided: {err}"))?;
-            let keypair = identity::ed25519::Keypair::from(secret);
-            identity::Keypair::from(keypair)
-        } else {
+        self.build_with_metrics(Arc::default(), &mut Registry::default())
+    }
+
+    /// Builds the swarm, recording substream activity into `substream_metrics`
+    /// and registering behaviour metrics in `registry`.
+    pub fn build_with_metrics(
+        &self,
+        substream_metrics: Arc<SubstreamMetrics>,
+        registry: &mut Registry,
+    ) -> Result<(identity::Keypair, Swarm<NetworkBehaviour>)> {
+        let keypair = if self.ephemeral_identity {
             identity::Keypair::generate_ed25519()
+        } else {
+            self.keypair_source.keypair(self.identity_seed)?
         };
+        let swarm = self.build_with_keypair(&keypair, substream_metrics, registry)?;
+
+        Ok((keypair, swarm))
+    }
+
+    /// Builds the swarm for an existing identity, e.g. to rebuild it in place.
+    pub fn build_with_keypair(
+        &self,
+        keypair: &identity::Keypair,
+        substream_metrics: Arc<SubstreamMetrics>,
+        registry: &mut Registry,
+    ) -> Result<Swarm<NetworkBehaviour>> {
+        self.validate()?;
         let local_peer_id = PeerId::from(keypair.public());
-        let (transport, relay_client) = self.build_transport(&keypair, local_peer_id)?;
-        let behaviour = Self::build_behaviour(
-            &keypair,
-            relay_client,
-            self.hop_relay,
-            self.enable_rendezvous,
-        );
+        let (transport, relay_client) =
+            self.build_transport(keypair, local_peer_id, &substream_metrics)?;
+        let gossipsub_config = self.gossipsub.build_config(self.power_profile)?;
+        let gossipsub_registry = if self.gossipsub_metrics {
+            Some(registry.sub_registry_with_prefix("gossipsub"))
+        } else {
+            None
+        };
+        let behaviour =
+            self.build_behaviour(keypair, gossipsub_config, relay_client, gossipsub_registry)?;
 
-        let swarm = Swarm::new(
-            transport,
-            behaviour,
-            local_peer_id,
-            SwarmConfig::with_tokio_executor(),
-        );
+        let mut swarm_config = SwarmConfig::with_tokio_executor();
+        if let Some(dials) = self.executor.budgets.dial_concurrency {
+            swarm_config = swarm_config.with_dial_concurrency_factor(dials);
+        }
+        if let Some(timeout) = self.idle_connection_timeout {
+            swarm_config = swarm_config.with_idle_connection_timeout(timeout);
+        }
+        let swarm = Swarm::new(transport, behaviour, local_peer_id, swarm_config);
 
-        Ok((keypair, swarm))
+        Ok(swarm)
     }
 
     /// Constructs the composite network behaviour using the supplied keypair
     fn build_behaviour(
+        &self,
         keypair: &identity::Keypair,
+        gossipsub_config: gossipsub::Config,
         relay_client: relay::client::Behaviour,
-        hop_relay: bool,
-        enable_rendezvous: bool,
-    ) -> NetworkBehaviour {
+        metrics_registry: Option<&mut Registry>,
+    ) -> Result<NetworkBehaviour> {
         let peer_id = PeerId::from(keypair.public());
+        let announce_filters: Arc<[AnnounceFilter]> = self.announce_filters.as_slice().into();
         let mut kad_config = kad::Config::default();
         kad_config.set_query_timeout(Duration::from_secs(5));
-        let store = MemoryStore::new(peer_id);
+        // Inbound records are validated and stored by the peer manager.
+        kad_config.set_record_filtering(kad::StoreInserts::FilterBoth);
+        let store = MemoryStore::with_config(peer_id, self.dht_store.build_config());
 
-        let ping_config = ping::Config::new();
-        let identify_config = identify::Config::new("/cabi/1.0.0".into(), keypair.public())
-            .with_interval(Duration::from_secs(30));
+        let low_power = self.power_profile.is_low_power();
+        let mut ping_config = ping::Config::new();
+        if low_power {
+            ping_config = ping_config.with_interval(LOW_POWER_PING_INTERVAL);
+        }
+        let identify_interval = if low_power {
+            LOW_POWER_IDENTIFY_INTERVAL
+        } else {
+            Duration::from_secs(30)
+        };
+        let identify_config = if self.signed_peer_records {
+            identify::Config::new_with_signed_peer_record(IDENTIFY_PROTOCOL_VERSION.into(), keypair)
+        } else {
+            identify::Config::new(IDENTIFY_PROTOCOL_VERSION.into(), keypair.public())
+        }
+        .with_interval(identify_interval)
+        // Push address changes (e.g. a new relay circuit) right away.
+        .with_push_listen_addr_updates(true);
         let autonat_config = autonat::Config::default();
 
-        let gossipsub_config = gossipsub::ConfigBuilder::default()
-            .build()
-            .expect("valid gossipsub config");
-
-        let gossipsub = gossipsub::Behaviour::new(
-            gossipsub::MessageAuthenticity::Signed(keypair.clone()),
-            gossipsub_config,
-        )
-        .expect("gossipsub behaviour");
+        let authenticity = gossipsub::MessageAuthenticity::Signed(keypair.clone());
+        let mut gossipsub = gossipsub::Behaviour::new(authenticity, gossipsub_config)
+            .map_err(|err| anyhow!("failed to create gossipsub behaviour: {err}"))?;
+        if let Some(registry) = metrics_registry {
+            gossipsub = gossipsub.with_metrics(registry, gossipsub::MetricsConfig::default());
+        }
+        if self.gossip_throttle.is_some() {
+            // Scores only carry the penalties the peer manager assigns; peers
+            // sharing an address (e.g. behind one NAT) are not penalized.
+            let params = gossipsub::PeerScoreParams {
+                app_specific_weight: 1.0,
+                ip_colocation_factor_weight: 0.0,
+                ..Default::default()
+            };
+            gossipsub
+                .with_peer_score(params, gossipsub::PeerScoreThresholds::default())
+                .map_err(|err| anyhow!("invalid gossipsub peer score parameters: {err}"))?;
+        }
 
-        let relay_server = if hop_relay {
-            Toggle::from(Some(relay::Behaviour::new(
-                peer_id,
-                relay::Config::default(),
-            )))
-        } else {
-            Toggle::from(None)
-        };
+        let mut relay_server = RoleGate::new(
+            self.hop_relay
+                .then(|| relay::Behaviour::new(peer_id, relay::Config::default())),
+            GateScope::AllConnections,
+        );
+        relay_server.set_open(!low_power);
 
-        let rendezvous_client = if enable_rendezvous {
+        let rendezvous_client = if self.enable_rendezvous {
             Toggle::from(Some(rendezvous::client::Behaviour::new(
                 keypair.clone(),
             )))
@@ -233,25 +1575,46 @@ impl TransportConfig {
             Toggle::from(None)
         };
 
-        let rendezvous_server = if hop_relay {
-            Toggle::from(
-                Some(rendezvous::server::Behaviour::new(rendezvous::server::Config::default()))
-            )
-        } else {
-            Toggle::from(None)
-        };
+        let rendezvous_server = Toggle::from(
+            self.rendezvous_server
+                .or_else(|| self.hop_relay.then(RendezvousServerSettings::default))
+                .map(|settings| rendezvous::server::Behaviour::new(settings.build_config())),
+        );
+
+        let mut time_sync_gate =
+            RoleGate::new(Some(TimeSync::default()), GateScope::AllConnections);
+        time_sync_gate.set_open(self.time_sync);
 
-        NetworkBehaviour {
-            kademlia: kad::Behaviour::with_config(peer_id, store, kad_config),
+        let mut kademlia = kad::Behaviour::with_config(peer_id, store, kad_config);
+        if self.observer || low_power {
+            // Clients query the DHT but never answer queries or store records.
+            kademlia.set_mode(Some(kad::Mode::Client));
+        }
+        let mut autonat = RoleGate::new(
+            Some(autonat::Behaviour::new(peer_id, autonat_config)),
+            GateScope::InboundOnly,
+        );
+        autonat.set_open(!self.observer);
+
+        Ok(NetworkBehaviour {
+            blocker: Blocker::new(self.blocklist.clone()),
+            kademlia: AnnounceFiltered::new(kademlia, announce_filters.clone()),
             ping: ping::Behaviour::new(ping_config),
-            identify: identify::Behaviour::new(identify_config),
-            autonat: autonat::Behaviour::new(peer_id, autonat_config),
+            prober: Prober::new(self.relay_ping_interval),
+            time_sync: time_sync_gate,
+            identify: AnnounceFiltered::new(
+                identify::Behaviour::new(identify_config),
+                announce_filters.clone(),
+            ),
+            autonat,
             gossipsub,
             relay_client,
             relay_server,
-            rendezvous_client,
+            dcutr: dcutr::Behaviour::new(peer_id),
+            rendezvous_client: AnnounceFiltered::new(rendezvous_client, announce_filters),
             rendezvous_server,
-        }
+            keep_alive: KeepAlive::new(self.pinned_peers.iter().copied()),
+        })
     }
 
     /// Builds the transport stack using TCP and optionally QUIC and Relay
@@ -259,17 +1622,28 @@ impl TransportConfig {
         &self,
         keypair: &identity::Keypair,
         local_peer_id: PeerId,
+        substream_metrics: &Arc<SubstreamMetrics>,
     ) -> Result<(
         Boxed<(PeerId, StreamMuxerBox)>,
         relay::client::Behaviour,
      )> {
-        let noise_config = noise::Config::new(keypair)
+        let mut noise_config = noise::Config::new(keypair)
             .map_err(|err| anyhow!("failed to create noise config: {err}"))?;
+        if let Some(prologue) = &self.noise_prologue {
+            noise_config = noise_config.with_prologue(prologue.clone());
+        }
 
-        let tcp_transport = Self::build_tcp_transport(noise_config.clone())?;
+        substream_metrics.set_limit(self.max_substreams_per_connection);
+        let tcp_transport = limit_substreams(
+            Self::build_tcp_transport(noise_config.clone(), self.yamux_config())?,
+            substream_metrics.clone(),
+        );
 
         let base_transport = if self.use_quic {
-            let quic_transport = Self::build_quic_transport(keypair);
+            let quic_transport = limit_substreams(
+                Self::build_quic_transport(keypair),
+                substream_metrics.clone(),
+            );
             quic_transport
                 .or_transport(tcp_transport)
                 .map(|either, _| match either {
@@ -281,7 +1655,8 @@ impl TransportConfig {
         };
 
         let (relay_transport, relay_client) =
-            Self::build_relay_transport(noise_config.clone(), local_peer_id);
+            Self::build_relay_transport(noise_config.clone(), self.yamux_config(), local_peer_id);
+        let relay_transport = limit_substreams(relay_transport, substream_metrics.clone());
 
         Ok((
             relay_transport
@@ -294,13 +1669,32 @@ impl TransportConfig {
         ))
     }
 
+    /// Builds the Yamux configuration shared by the TCP and relay transports
+    #[allow(deprecated)] // window/buffer setters are only offered on the legacy Yamux implementation
+    fn yamux_config(&self) -> libp2p::yamux::Config {
+        let mut yamux_config = libp2p::yamux::Config::default();
+        if let Some(num_bytes) = self.yamux_receive_window_size {
+            yamux_config.set_receive_window_size(num_bytes);
+        }
+        if let Some(num_bytes) = self.yamux_max_buffer_size {
+            yamux_config.set_max_buffer_size(num_bytes);
+        }
+        if let Some(num_streams) = self.yamux_max_num_streams {
+            yamux_config.set_max_num_streams(num_streams);
+        }
+        yamux_config
+    }
+
     /// Configures TCP with Noise authentication and Yamux multiplexing
-    fn build_tcp_transport(noise_config: noise::Config) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
+    fn build_tcp_transport(
+        noise_config: noise::Config,
+        yamux_config: libp2p::yamux::Config,
+    ) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
         let tcp_transport = tcp::tokio::Transport::new(tcp::Config::default());
         Ok(tcp_transport
             .upgrade(upgrade::Version::V1Lazy)
             .authenticate(noise_config)
-            .multiplex(libp2p::yamux::Config::default())
+            .multiplex(yamux_config)
             .boxed())
     }
 
@@ -316,6 +1710,7 @@ impl TransportConfig {
     /// Configures Relay transport
     fn build_relay_transport(
         noise_config: noise::Config,
+        yamux_config: libp2p::yamux::Config,
         local_peer_id: PeerId,
     ) -> (
         Boxed<(PeerId, StreamMuxerBox)>,
@@ -326,7 +1721,7 @@ impl TransportConfig {
         let relay_transport = relay_transport
             .upgrade(upgrade::Version::V1Lazy)
             .authenticate(noise_config)
-            .multiplex(libp2p::yamux::Config::default())
+            .multiplex(yamux_config)
             .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
             .boxed();
 
@@ -1,5 +1,43 @@
 //! Transport configuration and builders.
 
+pub mod announce;
+pub mod blocklist;
+pub mod circuit;
+pub mod gate;
+pub mod keep_alive;
+pub mod keypair;
 pub mod libp2p;
+pub mod probe;
+pub mod substreams;
+pub mod time_sync;
 
-pub use libp2p::{BehaviourEvent, NetworkBehaviour, TransportConfig};
+pub use announce::{AnnounceFilter, AnnounceFiltered};
+pub use blocklist::{
+    unix_time_secs, BlockEntry, BlockTarget, Blocked, Blocker, Blocklist, BlocklistUpdate,
+};
+pub use circuit::{
+    decompose_relayed, ensure_ends_with_peer, is_relayed, relayed_address, RelayedAddress,
+};
+pub use gate::{GateScope, RoleGate};
+pub use keep_alive::KeepAlive;
+pub use keypair::{load_or_create_keypair, save_keypair, KeypairSource};
+pub use libp2p::{
+    expected_connection_stack, transport_label, BehaviourEvent, DhtStoreSettings, ExecutorSettings,
+    GossipsubSettings, NetworkBehaviour, PowerProfile, RendezvousServerSettings, TaskBudgets,
+    TransportConfig, DEFAULT_DELEGATED_LOOKUPS, DEFAULT_SWARM_EVENTS_PER_TURN,
+    IDENTIFY_PROTOCOL_VERSION, LOW_POWER_HEARTBEAT_INTERVAL, LOW_POWER_IDENTIFY_INTERVAL,
+    LOW_POWER_MESH_N, LOW_POWER_PING_INTERVAL,
+};
+pub use probe::{
+    ProbeEvent, Prober, DEFAULT_PROBE_PINGS, DEFAULT_RELAY_PING_INTERVAL, MAX_PROBE_DURATION,
+    MAX_PROBE_PINGS, PROBE_PING_TIMEOUT,
+};
+pub use substreams::{
+    PeerStats, ProtocolStats, SubstreamMetrics, SubstreamStats, MAX_TRACKED_PEER_PROTOCOLS,
+    MAX_TRACKED_PROTOCOLS, OTHER_PROTOCOL, UNNEGOTIATED_PROTOCOL,
+};
+pub use time_sync::{
+    ClockOffsets, ClockSample, TimeSync, TimeSyncEvent, MAX_CLOCK_CORRECTION_MS,
+    MAX_TIME_SAMPLE_OFFSET_MS, MAX_TIME_SAMPLE_RTT, MIN_TIME_SYNC_PEERS, TIME_PROTOCOL,
+    TIME_REQUEST_TIMEOUT,
+};
@@ -0,0 +1,445 @@
+//! On-demand latency probes.
+//!
+//! The ping behaviour measures a connection once per ping interval.
+//! [`Prober`] measures it right away instead: it opens a fresh ping substream
+//! on one connection to the peer, a direct one when there is one, which the
+//! remote's regular ping handler answers, and sends a burst of pings over it
+//! back to back, reporting every answer as it arrives.
+//!
+//! Relayed connections also get a single keep-alive ping every relay ping
+//! interval, shorter than the regular ping interval, so the NAT bindings on
+//! the way to the relay do not expire while the circuit is idle.
+
+use futures::{
+    future::{self, BoxFuture},
+    stream::FuturesUnordered,
+    AsyncReadExt, AsyncWriteExt, FutureExt, StreamExt,
+};
+use libp2p::{
+    core::{
+        transport::PortUse,
+        upgrade::{DeniedUpgrade, ReadyUpgrade},
+        Endpoint,
+    },
+    swarm::{
+        handler::{
+            ConnectionEvent, ConnectionHandler, ConnectionHandlerEvent, DialUpgradeError,
+            FullyNegotiatedOutbound, SubstreamProtocol,
+        },
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, NotifyHandler, THandler,
+        THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId, Stream, StreamProtocol,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::time::{Interval, MissedTickBehavior};
+
+use super::circuit::is_relayed;
+
+/// Protocol of the regular ping behaviour, answered by every peer.
+const PING_PROTOCOL: StreamProtocol = StreamProtocol::new("/ipfs/ping/1.0.0");
+/// Payload size mandated by the ping protocol.
+const PING_SIZE: usize = 32;
+
+/// Pings sent by a probe unless the caller asks for another count.
+pub const DEFAULT_PROBE_PINGS: u32 = 5;
+/// Most pings a single probe may send.
+pub const MAX_PROBE_PINGS: u32 = 100;
+/// Time each ping of a probe, and the substream negotiation before them,
+/// has to be answered.
+pub const PROBE_PING_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest the pings of one probe may take together; pings not answered by
+/// then count as lost.
+pub const MAX_PROBE_DURATION: Duration = Duration::from_secs(30);
+/// Suggested interval of the keep-alive pings sent on relayed connections,
+/// well under the UDP binding timeout of common NATs. The pings are off
+/// unless configured, since each one opens a substream and wakes the radio.
+pub const DEFAULT_RELAY_PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Burst of pings requested from a connection handler.
+#[derive(Debug, Clone, Copy)]
+pub struct ProbeRequest {
+    id: u64,
+    count: u32,
+    /// Sent by the handler itself to keep a relayed connection warm.
+    keep_alive: bool,
+    /// When pings still unanswered are given up, see [`MAX_PROBE_DURATION`].
+    deadline: Option<Instant>,
+}
+
+/// Answer of one ping of a burst, or its end, reported by the connection
+/// handler.
+#[derive(Debug)]
+pub struct ProbeOutcome {
+    id: u64,
+    rtt: Option<Duration>,
+    error: Option<String>,
+    finished: bool,
+    keep_alive: bool,
+}
+
+/// Progress of a probe of a peer: one answered ping, the end of the burst, or
+/// both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeEvent {
+    pub peer_id: PeerId,
+    /// Identifier passed to [`Prober::probe`].
+    pub id: u64,
+    /// Round-trip time of the ping just answered, if one was.
+    pub rtt: Option<Duration>,
+    /// Why the burst stopped before every ping was answered, if it did.
+    pub error: Option<String>,
+    /// Whether this is the last event of the probe.
+    pub finished: bool,
+}
+
+/// Behaviour sending bursts of pings on demand.
+#[derive(Debug, Default)]
+pub struct Prober {
+    /// Keep-alive ping interval of relayed connections; `None` sends none.
+    relay_ping_interval: Option<Duration>,
+    /// Open connections of every peer, and whether each one is relayed.
+    connections: HashMap<PeerId, HashMap<ConnectionId, bool>>,
+    /// Connection every unfinished probe runs on, by probe id.
+    running: HashMap<u64, (PeerId, ConnectionId)>,
+    pending: VecDeque<ToSwarm<ProbeEvent, ProbeRequest>>,
+}
+
+impl Prober {
+    /// Creates the behaviour, pinging relayed connections every
+    /// `relay_ping_interval` when set.
+    pub fn new(relay_ping_interval: Option<Duration>) -> Self {
+        Self {
+            relay_ping_interval,
+            ..Self::default()
+        }
+    }
+
+    /// Sends `count` pings to `peer_id` over one of its connections, a direct
+    /// one when there is one; every answer and the end of the burst are
+    /// reported as [`ProbeEvent`]s carrying `id`. Returns `false` when the
+    /// peer is not connected.
+    pub fn probe(&mut self, peer_id: PeerId, id: u64, count: u32) -> bool {
+        let Some(connection_id) = self.connections.get(&peer_id).and_then(|connections| {
+            connections
+                .iter()
+                .min_by_key(|(_, relayed)| **relayed)
+                .map(|(connection_id, _)| *connection_id)
+        }) else {
+            return false;
+        };
+        self.running.insert(id, (peer_id, connection_id));
+        self.pending.push_back(ToSwarm::NotifyHandler {
+            peer_id,
+            handler: NotifyHandler::One(connection_id),
+            event: ProbeRequest {
+                id,
+                count,
+                keep_alive: false,
+                deadline: Some(Instant::now() + MAX_PROBE_DURATION),
+            },
+        });
+        true
+    }
+
+    fn handler(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        relayed: bool,
+    ) -> ProbeHandler {
+        self.connections
+            .entry(peer)
+            .or_default()
+            .insert(connection_id, relayed);
+        let keep_alive = self.relay_ping_interval.filter(|_| relayed).map(|period| {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+        ProbeHandler {
+            keep_alive,
+            ..ProbeHandler::default()
+        }
+    }
+}
+
+impl NetworkBehaviour for Prober {
+    type ConnectionHandler = ProbeHandler;
+    type ToSwarm = ProbeEvent;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        // Inbound circuits carry the circuit on the local, relayed listen address.
+        let relayed = is_relayed(local_addr) || is_relayed(remote_addr);
+        Ok(self.handler(connection_id, peer, relayed))
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(self.handler(connection_id, peer, is_relayed(addr)))
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(closed) = event {
+            if let Some(connections) = self.connections.get_mut(&closed.peer_id) {
+                connections.remove(&closed.connection_id);
+                if connections.is_empty() {
+                    self.connections.remove(&closed.peer_id);
+                }
+            }
+            // The handler is gone with its bursts, so end them here.
+            let pending = &mut self.pending;
+            self.running.retain(|id, (peer_id, connection_id)| {
+                if *connection_id != closed.connection_id {
+                    return true;
+                }
+                pending.push_back(ToSwarm::GenerateEvent(ProbeEvent {
+                    peer_id: *peer_id,
+                    id: *id,
+                    rtt: None,
+                    error: Some("connection closed".into()),
+                    finished: true,
+                }));
+                false
+            });
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        _connection_id: ConnectionId,
+        outcome: THandlerOutEvent<Self>,
+    ) {
+        if outcome.keep_alive {
+            if let Some(error) = outcome.error {
+                tracing::debug!(target: "transport", %peer_id, %error, "relay keep-alive ping failed");
+            }
+            return;
+        }
+        if outcome.finished {
+            self.running.remove(&outcome.id);
+        }
+        self.pending.push_back(ToSwarm::GenerateEvent(ProbeEvent {
+            peer_id,
+            id: outcome.id,
+            rtt: outcome.rtt,
+            error: outcome.error,
+            finished: outcome.finished,
+        }));
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        match self.pending.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Handler opening a ping substream per requested burst, and per keep-alive
+/// ping on relayed connections. Inbound pings are left to the ping behaviour.
+#[derive(Default)]
+pub struct ProbeHandler {
+    /// Keep-alive ping schedule, on relayed connections only.
+    keep_alive: Option<Interval>,
+    /// Whether a keep-alive ping has not finished yet.
+    keep_alive_pending: bool,
+    /// Bursts waiting for their substream to be requested.
+    requested: VecDeque<ProbeRequest>,
+    /// Bursts whose substream is being negotiated, in request order.
+    negotiating: VecDeque<ProbeRequest>,
+    /// The ping in flight of every running burst.
+    running: FuturesUnordered<BoxFuture<'static, PingStep>>,
+}
+
+impl ConnectionHandler for ProbeHandler {
+    type FromBehaviour = ProbeRequest;
+    type ToBehaviour = ProbeOutcome;
+    type InboundProtocol = DeniedUpgrade;
+    type OutboundProtocol = ReadyUpgrade<StreamProtocol>;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = ();
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        SubstreamProtocol::new(DeniedUpgrade, ())
+    }
+
+    fn connection_keep_alive(&self) -> bool {
+        !self.requested.is_empty() || !self.negotiating.is_empty() || !self.running.is_empty()
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ConnectionHandlerEvent<Self::OutboundProtocol, (), Self::ToBehaviour>> {
+        if let Some(interval) = self.keep_alive.as_mut() {
+            if interval.poll_tick(cx).is_ready() && !self.keep_alive_pending {
+                self.keep_alive_pending = true;
+                self.requested.push_back(ProbeRequest {
+                    id: 0,
+                    count: 1,
+                    keep_alive: true,
+                    deadline: None,
+                });
+            }
+        }
+        if let Some(request) = self.requested.pop_front() {
+            self.negotiating.push_back(request);
+            return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(ReadyUpgrade::new(PING_PROTOCOL), ())
+                    .with_timeout(PROBE_PING_TIMEOUT),
+            });
+        }
+        if let Poll::Ready(Some(step)) = self.running.poll_next_unpin(cx) {
+            let PingStep {
+                stream,
+                request,
+                sequence,
+                result,
+            } = step;
+            let (rtt, error) = match result {
+                Ok(rtt) => (Some(rtt), None),
+                Err(error) => (None, Some(error)),
+            };
+            let finished = match stream {
+                Some(stream) => {
+                    self.running
+                        .push(ping_step(stream, request, sequence + 1).boxed());
+                    false
+                }
+                None => true,
+            };
+            if finished && request.keep_alive {
+                self.keep_alive_pending = false;
+            }
+            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(ProbeOutcome {
+                id: request.id,
+                rtt,
+                error,
+                finished,
+                keep_alive: request.keep_alive,
+            }));
+        }
+        Poll::Pending
+    }
+
+    fn on_behaviour_event(&mut self, request: ProbeRequest) {
+        self.requested.push_back(request);
+    }
+
+    fn on_connection_event(
+        &mut self,
+        event: ConnectionEvent<Self::InboundProtocol, Self::OutboundProtocol>,
+    ) {
+        match event {
+            ConnectionEvent::FullyNegotiatedOutbound(FullyNegotiatedOutbound {
+                protocol: stream,
+                ..
+            }) => {
+                if let Some(request) = self.negotiating.pop_front() {
+                    self.running.push(ping_step(stream, request, 0).boxed());
+                }
+            }
+            ConnectionEvent::DialUpgradeError(DialUpgradeError { error, .. }) => {
+                if let Some(request) = self.negotiating.pop_front() {
+                    self.running.push(
+                        future::ready(PingStep {
+                            stream: None,
+                            request,
+                            sequence: 0,
+                            result: Err(format!("ping substream failed: {error}")),
+                        })
+                        .boxed(),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// One ping of a burst, with the stream to send the next one over; `None`
+/// once the burst is over.
+struct PingStep {
+    stream: Option<Stream>,
+    request: ProbeRequest,
+    sequence: u32,
+    result: Result<Duration, String>,
+}
+
+/// Sends ping `sequence` of `request` over `stream`, closing the stream when
+/// it is the last one or goes unanswered.
+async fn ping_step(mut stream: Stream, request: ProbeRequest, sequence: u32) -> PingStep {
+    let left = request
+        .deadline
+        .map_or(PROBE_PING_TIMEOUT, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        })
+        .min(PROBE_PING_TIMEOUT);
+    let result = match tokio::time::timeout(left, ping(&mut stream, request.id, sequence)).await {
+        Ok(Ok(rtt)) => Ok(rtt),
+        Ok(Err(err)) => Err(format!("ping {} failed: {err}", sequence + 1)),
+        Err(_) if left < PROBE_PING_TIMEOUT => Err(format!(
+            "ping {} unanswered when the probe hit its {MAX_PROBE_DURATION:?} limit",
+            sequence + 1
+        )),
+        Err(_) => Err(format!(
+            "ping {} unanswered after {PROBE_PING_TIMEOUT:?}",
+            sequence + 1
+        )),
+    };
+    let stream = if result.is_ok() && sequence + 1 < request.count {
+        Some(stream)
+    } else {
+        let _ = stream.close().await;
+        None
+    };
+    PingStep {
+        stream,
+        request,
+        sequence,
+        result,
+    }
+}
+
+/// Sends one ping and waits for its echo.
+async fn ping(stream: &mut Stream, id: u64, sequence: u32) -> io::Result<Duration> {
+    // Unpredictable enough that a stale echo never matches.
+    let payload: [u8; PING_SIZE] =
+        Sha256::digest(format!("{id}/{sequence}/{:?}", SystemTime::now())).into();
+    let started = Instant::now();
+    stream.write_all(&payload).await?;
+    stream.flush().await?;
+    let mut echo = [0u8; PING_SIZE];
+    stream.read_exact(&mut echo).await?;
+    if echo != payload {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "ping echo does not match",
+        ));
+    }
+    Ok(started.elapsed())
+}
@@ -0,0 +1,665 @@
+//! Stream muxer wrapper that caps concurrent substreams per connection and
+//! counts substream activity per protocol, in total and per peer.
+//!
+//! Application protocols are negotiated on top of an already opened substream,
+//! so the protocol is learned by watching the multistream-select answer of the
+//! listening side of each substream. A substream is counted as opened once its
+//! protocol is agreed on; bytes exchanged before that are attributed to the
+//! protocol afterwards. Substreams closed or refused before a protocol was
+//! agreed on are counted under [`UNNEGOTIATED_PROTOCOL`].
+//!
+//! The protocol name comes from the wire, so a remote picks it. Only the first
+//! [`MAX_TRACKED_PROTOCOLS`] names in total, and [`MAX_TRACKED_PEER_PROTOCOLS`]
+//! per peer, get their own counters; later ones are folded into
+//! [`OTHER_PROTOCOL`].
+
+use futures::{ready, task::AtomicWaker, AsyncRead, AsyncWrite};
+use libp2p::{
+    core::{
+        muxing::{StreamMuxer, StreamMuxerBox, StreamMuxerEvent, SubstreamBox},
+        transport::{Boxed, Transport},
+    },
+    PeerId,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    task::{Context, Poll},
+};
+
+/// Snapshot of the substream counters recorded for a single protocol.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubstreamStats {
+    /// Inbound substreams accepted.
+    pub opened_inbound: u64,
+    /// Outbound substreams opened.
+    pub opened_outbound: u64,
+    /// Substreams closed or dropped.
+    pub closed: u64,
+    /// Inbound substreams refused because the connection was at its limit,
+    /// always reported under [`UNNEGOTIATED_PROTOCOL`].
+    pub rejected: u64,
+    /// Bytes read from substreams.
+    pub bytes_inbound: u64,
+    /// Bytes written to substreams.
+    pub bytes_outbound: u64,
+}
+
+impl SubstreamStats {
+    /// Number of substreams currently open.
+    pub fn active(&self) -> u64 {
+        (self.opened_inbound + self.opened_outbound).saturating_sub(self.closed)
+    }
+}
+
+#[derive(Debug, Default)]
+struct SubstreamCounters {
+    opened_inbound: AtomicU64,
+    opened_outbound: AtomicU64,
+    closed: AtomicU64,
+    rejected: AtomicU64,
+    bytes_inbound: AtomicU64,
+    bytes_outbound: AtomicU64,
+}
+
+/// Usage of one protocol with one peer, see [`PeerStats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProtocolStats {
+    /// Substreams the peer opened to us for the protocol.
+    pub substreams_inbound: u64,
+    /// Substreams we opened to the peer for the protocol.
+    pub substreams_outbound: u64,
+    /// Bytes read, negotiation included.
+    pub bytes_inbound: u64,
+    /// Bytes written, negotiation included.
+    pub bytes_outbound: u64,
+}
+
+/// Traffic exchanged with one peer since its oldest currently open connection
+/// was established.
+///
+/// Request/response protocols such as Kademlia, identify and ping open a
+/// substream per exchange, while gossipsub keeps one long-lived substream, so
+/// substream counts only compare within a protocol; bytes compare across them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    /// Bytes read from the peer over all substreams.
+    pub bytes_inbound: u64,
+    /// Bytes written to the peer over all substreams.
+    pub bytes_outbound: u64,
+    /// Usage keyed by negotiated protocol name. Substreams closed before a
+    /// protocol was agreed on are reported under [`UNNEGOTIATED_PROTOCOL`],
+    /// protocols beyond [`MAX_TRACKED_PEER_PROTOCOLS`] under [`OTHER_PROTOCOL`].
+    pub protocols: BTreeMap<String, ProtocolStats>,
+}
+
+/// Protocol label of substreams closed before a protocol was agreed on.
+pub const UNNEGOTIATED_PROTOCOL: &str = "unnegotiated";
+
+/// Protocol label collecting substreams of protocols beyond
+/// [`MAX_TRACKED_PROTOCOLS`].
+pub const OTHER_PROTOCOL: &str = "other";
+
+/// Distinct protocol names counted separately, [`UNNEGOTIATED_PROTOCOL`] and
+/// [`OTHER_PROTOCOL`] excluded.
+pub const MAX_TRACKED_PROTOCOLS: usize = 64;
+
+/// Distinct protocol names counted separately for one peer, see
+/// [`MAX_TRACKED_PROTOCOLS`].
+pub const MAX_TRACKED_PEER_PROTOCOLS: usize = 16;
+
+/// Multistream-select messages buffered per substream before giving up on
+/// learning its protocol.
+const MAX_NEGOTIATION_BYTES: usize = 1024;
+const MULTISTREAM_HEADER: &[u8] = b"/multistream/1.0.0";
+
+#[derive(Debug, Default)]
+struct ProtocolCounters {
+    substreams_inbound: AtomicU64,
+    substreams_outbound: AtomicU64,
+    bytes_inbound: AtomicU64,
+    bytes_outbound: AtomicU64,
+}
+
+/// Bytes exchanged with one remote peer over its open connections.
+#[derive(Debug, Default)]
+struct PeerCounters {
+    connections: AtomicUsize,
+    bytes_inbound: AtomicU64,
+    bytes_outbound: AtomicU64,
+    protocols: Mutex<BTreeMap<String, Arc<ProtocolCounters>>>,
+}
+
+impl PeerCounters {
+    fn protocol(&self, name: &str) -> Arc<ProtocolCounters> {
+        match self.protocols.lock() {
+            Ok(mut protocols) => {
+                let name = tracked_name(&protocols, name, MAX_TRACKED_PEER_PROTOCOLS);
+                protocols.entry(name.to_string()).or_default().clone()
+            }
+            Err(_) => {
+                tracing::warn!(target: "transport", "substream metrics lock poisoned");
+                Arc::default()
+            }
+        }
+    }
+}
+
+/// Shared registry of substream counters keyed by negotiated protocol.
+#[derive(Debug, Default)]
+pub struct SubstreamMetrics {
+    protocols: RwLock<BTreeMap<String, Arc<SubstreamCounters>>>,
+    peers: RwLock<HashMap<PeerId, Arc<PeerCounters>>>,
+    /// Per-connection substream cap shared by every connection; 0 means none.
+    limit: Arc<AtomicUsize>,
+}
+
+impl SubstreamMetrics {
+    /// Returns the counters of every protocol seen so far.
+    pub fn snapshot(&self) -> BTreeMap<String, SubstreamStats> {
+        let Ok(protocols) = self.protocols.read() else {
+            tracing::warn!(target: "transport", "substream metrics lock poisoned");
+            return BTreeMap::new();
+        };
+
+        protocols
+            .iter()
+            .map(|(name, counters)| {
+                let stats = SubstreamStats {
+                    opened_inbound: counters.opened_inbound.load(Ordering::Relaxed),
+                    opened_outbound: counters.opened_outbound.load(Ordering::Relaxed),
+                    closed: counters.closed.load(Ordering::Relaxed),
+                    rejected: counters.rejected.load(Ordering::Relaxed),
+                    bytes_inbound: counters.bytes_inbound.load(Ordering::Relaxed),
+                    bytes_outbound: counters.bytes_outbound.load(Ordering::Relaxed),
+                };
+                (name.clone(), stats)
+            })
+            .collect()
+    }
+
+    /// Returns `(bytes_inbound, bytes_outbound)` exchanged with `peer_id`
+    /// since its oldest currently open connection was established. A peer is
+    /// forgotten once its last connection closes.
+    pub fn peer_bytes(&self, peer_id: &PeerId) -> Option<(u64, u64)> {
+        let peers = self.peers.read().ok()?;
+        let counters = peers.get(peer_id)?;
+        Some((
+            counters.bytes_inbound.load(Ordering::Relaxed),
+            counters.bytes_outbound.load(Ordering::Relaxed),
+        ))
+    }
+
+    /// Returns the traffic exchanged with `peer_id` split by protocol, `None`
+    /// when no connection to the peer is open.
+    pub fn peer_stats(&self, peer_id: &PeerId) -> Option<PeerStats> {
+        let peers = self.peers.read().ok()?;
+        let counters = peers.get(peer_id)?;
+        let protocols = match counters.protocols.lock() {
+            Ok(protocols) => protocols
+                .iter()
+                .map(|(name, protocol)| {
+                    let stats = ProtocolStats {
+                        substreams_inbound: protocol.substreams_inbound.load(Ordering::Relaxed),
+                        substreams_outbound: protocol.substreams_outbound.load(Ordering::Relaxed),
+                        bytes_inbound: protocol.bytes_inbound.load(Ordering::Relaxed),
+                        bytes_outbound: protocol.bytes_outbound.load(Ordering::Relaxed),
+                    };
+                    (name.clone(), stats)
+                })
+                .collect(),
+            Err(_) => {
+                tracing::warn!(target: "transport", "substream metrics lock poisoned");
+                BTreeMap::new()
+            }
+        };
+        Some(PeerStats {
+            bytes_inbound: counters.bytes_inbound.load(Ordering::Relaxed),
+            bytes_outbound: counters.bytes_outbound.load(Ordering::Relaxed),
+            protocols,
+        })
+    }
+
+    /// Per-connection substream cap currently enforced.
+    pub fn limit(&self) -> Option<usize> {
+        Some(self.limit.load(Ordering::Acquire)).filter(|limit| *limit > 0)
+    }
+
+    /// Changes the per-connection substream cap, open connections included.
+    /// Substreams already open over a lowered cap are left alone.
+    pub(crate) fn set_limit(&self, limit: Option<usize>) {
+        self.limit.store(limit.unwrap_or(0), Ordering::Release);
+    }
+
+    fn register_peer(&self, peer_id: PeerId) -> Arc<PeerCounters> {
+        match self.peers.write() {
+            Ok(mut peers) => {
+                let counters = peers.entry(peer_id).or_default().clone();
+                counters.connections.fetch_add(1, Ordering::AcqRel);
+                counters
+            }
+            Err(_) => {
+                tracing::warn!(target: "transport", "substream metrics lock poisoned");
+                Arc::default()
+            }
+        }
+    }
+
+    /// Drops the counters of `peer_id` unless a connection to it is open.
+    fn release_peer(&self, peer_id: &PeerId, counters: &Arc<PeerCounters>) {
+        let Ok(mut peers) = self.peers.write() else {
+            tracing::warn!(target: "transport", "substream metrics lock poisoned");
+            return;
+        };
+        // Connections are only added under the write lock, so the check holds.
+        if peers.get(peer_id).is_some_and(|current| {
+            Arc::ptr_eq(current, counters) && current.connections.load(Ordering::Acquire) == 0
+        }) {
+            peers.remove(peer_id);
+        }
+    }
+
+    fn protocol(&self, name: &str) -> Arc<SubstreamCounters> {
+        if let Some(counters) = self
+            .protocols
+            .read()
+            .ok()
+            .and_then(|p| p.get(name).cloned())
+        {
+            return counters;
+        }
+        match self.protocols.write() {
+            Ok(mut protocols) => {
+                let name = tracked_name(&protocols, name, MAX_TRACKED_PROTOCOLS);
+                protocols.entry(name.to_string()).or_default().clone()
+            }
+            Err(_) => {
+                tracing::warn!(target: "transport", "substream metrics lock poisoned");
+                Arc::default()
+            }
+        }
+    }
+}
+
+/// Returns `name`, or [`OTHER_PROTOCOL`] when `name` is new and `protocols`
+/// already tracks `max` names besides the built-in labels.
+fn tracked_name<'a, V>(protocols: &BTreeMap<String, V>, name: &'a str, max: usize) -> &'a str {
+    if name == UNNEGOTIATED_PROTOCOL || protocols.contains_key(name) {
+        return name;
+    }
+    let builtin = [UNNEGOTIATED_PROTOCOL, OTHER_PROTOCOL]
+        .iter()
+        .filter(|label| protocols.contains_key(**label))
+        .count();
+    if protocols.len() - builtin >= max {
+        OTHER_PROTOCOL
+    } else {
+        name
+    }
+}
+
+/// Wraps every connection produced by `transport` into a [`LimitedMuxer`].
+pub(crate) fn limit_substreams(
+    transport: Boxed<(PeerId, StreamMuxerBox)>,
+    metrics: Arc<SubstreamMetrics>,
+) -> Boxed<(PeerId, StreamMuxerBox)> {
+    transport
+        .map(move |(peer_id, muxer), _| {
+            let peer = metrics.register_peer(peer_id);
+            let muxer = LimitedMuxer::new(muxer, metrics.clone(), peer_id, peer);
+            (peer_id, StreamMuxerBox::new(muxer))
+        })
+        .boxed()
+}
+
+/// Per-connection muxer enforcing the substream limit.
+///
+/// Inbound substreams over the limit are dropped (reset) immediately, outbound
+/// requests stay pending until one of the open substreams is closed.
+struct LimitedMuxer {
+    inner: StreamMuxerBox,
+    active: Arc<AtomicUsize>,
+    closed_waker: Arc<AtomicWaker>,
+    metrics: Arc<SubstreamMetrics>,
+    peer_id: PeerId,
+    peer: Arc<PeerCounters>,
+}
+
+impl LimitedMuxer {
+    fn new(
+        inner: StreamMuxerBox,
+        metrics: Arc<SubstreamMetrics>,
+        peer_id: PeerId,
+        peer: Arc<PeerCounters>,
+    ) -> Self {
+        Self {
+            inner,
+            active: Arc::new(AtomicUsize::new(0)),
+            closed_waker: Arc::new(AtomicWaker::new()),
+            metrics,
+            peer_id,
+            peer,
+        }
+    }
+
+    fn at_limit(&self) -> bool {
+        let limit = self.metrics.limit.load(Ordering::Acquire);
+        limit > 0 && self.active.load(Ordering::Acquire) >= limit
+    }
+
+    fn track(&self, inner: SubstreamBox, inbound: bool) -> CountedSubstream {
+        self.active.fetch_add(1, Ordering::AcqRel);
+        CountedSubstream {
+            inner,
+            active: self.active.clone(),
+            closed_waker: self.closed_waker.clone(),
+            metrics: self.metrics.clone(),
+            peer: self.peer.clone(),
+            inbound,
+            protocol: ProtocolTracker::default(),
+        }
+    }
+}
+
+impl Drop for LimitedMuxer {
+    fn drop(&mut self) {
+        if self.peer.connections.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.metrics.release_peer(&self.peer_id, &self.peer);
+        }
+    }
+}
+
+impl StreamMuxer for LimitedMuxer {
+    type Substream = CountedSubstream;
+    type Error = io::Error;
+
+    fn poll_inbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        loop {
+            let substream = ready!(Pin::new(&mut self.inner).poll_inbound(cx))?;
+
+            if self.at_limit() {
+                let counters = self.metrics.protocol(UNNEGOTIATED_PROTOCOL);
+                counters.rejected.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!(
+                    target: "transport",
+                    limit = self.metrics.limit.load(Ordering::Relaxed),
+                    "rejecting inbound substream over per-connection limit"
+                );
+                drop(substream);
+                continue;
+            }
+
+            return Poll::Ready(Ok(self.track(substream, true)));
+        }
+    }
+
+    fn poll_outbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        if self.at_limit() {
+            self.closed_waker.register(cx.waker());
+            // Re-check to avoid missing a wake-up from a substream closed concurrently.
+            if self.at_limit() {
+                return Poll::Pending;
+            }
+        }
+
+        let substream = ready!(Pin::new(&mut self.inner).poll_outbound(cx))?;
+        Poll::Ready(Ok(self.track(substream, false)))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
+
+/// Substream that counts transferred bytes and releases its slot in the
+/// connection limit when dropped.
+struct CountedSubstream {
+    inner: SubstreamBox,
+    active: Arc<AtomicUsize>,
+    closed_waker: Arc<AtomicWaker>,
+    metrics: Arc<SubstreamMetrics>,
+    peer: Arc<PeerCounters>,
+    /// Whether the remote opened the substream, making us the listener.
+    inbound: bool,
+    protocol: ProtocolTracker,
+}
+
+impl CountedSubstream {
+    /// Attributes `len` transferred bytes to the substream protocol. `data`
+    /// was sent by the listening side when `listener_data` is set and may
+    /// carry its multistream-select answer.
+    fn record(&mut self, data: &[u8], listener_data: bool, inbound_bytes: bool) {
+        if listener_data {
+            if let Some(name) = self.protocol.observe(data) {
+                self.resolve(&name);
+            }
+        }
+        self.protocol.add(data.len() as u64, inbound_bytes);
+    }
+
+    /// Counts the substream as opened for protocol `name`.
+    fn resolve(&mut self, name: &str) {
+        let peer = self.peer.protocol(name);
+        let totals = self.metrics.protocol(name);
+        if self.inbound {
+            peer.substreams_inbound.fetch_add(1, Ordering::Relaxed);
+            totals.opened_inbound.fetch_add(1, Ordering::Relaxed);
+        } else {
+            peer.substreams_outbound.fetch_add(1, Ordering::Relaxed);
+            totals.opened_outbound.fetch_add(1, Ordering::Relaxed);
+        }
+        self.protocol.resolve(peer, totals);
+    }
+}
+
+/// Protocol of a substream, learned from the listener's multistream-select
+/// answer. Bytes transferred before the answer are held back until then.
+#[derive(Default)]
+struct ProtocolTracker {
+    negotiation: Vec<u8>,
+    /// Gave up on reading the answer.
+    abandoned: bool,
+    /// Counters of the agreed protocol, for this peer and in total.
+    counters: Option<(Arc<ProtocolCounters>, Arc<SubstreamCounters>)>,
+    pending_inbound: u64,
+    pending_outbound: u64,
+}
+
+impl ProtocolTracker {
+    /// Feeds bytes sent by the listener and returns the protocol once the
+    /// listener accepted one.
+    fn observe(&mut self, data: &[u8]) -> Option<String> {
+        if self.counters.is_some() || self.abandoned {
+            return None;
+        }
+        self.negotiation.extend_from_slice(data);
+        let mut rest = self.negotiation.as_slice();
+        while let Some((message, next)) = next_negotiation_message(rest) {
+            rest = next;
+            let message = message.strip_suffix(b"\n").unwrap_or(message);
+            if message != MULTISTREAM_HEADER && message != b"na" {
+                let name = String::from_utf8_lossy(message).into_owned();
+                self.negotiation = Vec::new();
+                return Some(name);
+            }
+        }
+        if self.negotiation.len() > MAX_NEGOTIATION_BYTES {
+            self.abandoned = true;
+            self.negotiation = Vec::new();
+        }
+        None
+    }
+
+    fn resolve(&mut self, peer: Arc<ProtocolCounters>, totals: Arc<SubstreamCounters>) {
+        let inbound = std::mem::take(&mut self.pending_inbound);
+        let outbound = std::mem::take(&mut self.pending_outbound);
+        peer.bytes_inbound.fetch_add(inbound, Ordering::Relaxed);
+        peer.bytes_outbound.fetch_add(outbound, Ordering::Relaxed);
+        totals.bytes_inbound.fetch_add(inbound, Ordering::Relaxed);
+        totals.bytes_outbound.fetch_add(outbound, Ordering::Relaxed);
+        self.counters = Some((peer, totals));
+    }
+
+    fn add(&mut self, len: u64, inbound: bool) {
+        match (&self.counters, inbound) {
+            (Some((peer, totals)), true) => {
+                peer.bytes_inbound.fetch_add(len, Ordering::Relaxed);
+                totals.bytes_inbound.fetch_add(len, Ordering::Relaxed);
+            }
+            (Some((peer, totals)), false) => {
+                peer.bytes_outbound.fetch_add(len, Ordering::Relaxed);
+                totals.bytes_outbound.fetch_add(len, Ordering::Relaxed);
+            }
+            (None, true) => self.pending_inbound += len,
+            (None, false) => self.pending_outbound += len,
+        }
+    }
+}
+
+/// Splits the first length-prefixed multistream-select message off `data`.
+fn next_negotiation_message(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut len = 0usize;
+    for (index, byte) in data.iter().enumerate().take(2) {
+        len |= usize::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            let rest = &data[index + 1..];
+            return (rest.len() >= len).then(|| rest.split_at(len));
+        }
+    }
+    None
+}
+
+impl AsyncRead for CountedSubstream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let read = ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let listener_data = !self.inbound;
+        self.record(&buf[..read], listener_data, true);
+        self.peer
+            .bytes_inbound
+            .fetch_add(read as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl AsyncWrite for CountedSubstream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        let listener_data = self.inbound;
+        self.record(&buf[..written], listener_data, false);
+        self.peer
+            .bytes_outbound
+            .fetch_add(written as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl Drop for CountedSubstream {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::AcqRel);
+        self.closed_waker.wake();
+        if self.protocol.counters.is_none() {
+            self.resolve(UNNEGOTIATED_PROTOCOL);
+        }
+        if let Some((_, totals)) = &self.protocol.counters {
+            totals.closed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protocols_beyond_the_cap_are_folded() {
+        let metrics = SubstreamMetrics::default();
+        metrics.protocol(UNNEGOTIATED_PROTOCOL);
+        for index in 0..MAX_TRACKED_PROTOCOLS + 10 {
+            metrics.protocol(&format!("/spam/{index}"));
+        }
+        metrics.protocol(UNNEGOTIATED_PROTOCOL);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), MAX_TRACKED_PROTOCOLS + 2);
+        assert!(snapshot.contains_key(OTHER_PROTOCOL));
+        assert!(snapshot.contains_key(UNNEGOTIATED_PROTOCOL));
+        assert!(snapshot.contains_key("/spam/0"));
+        assert!(!snapshot.contains_key(&format!("/spam/{MAX_TRACKED_PROTOCOLS}")));
+    }
+
+    #[test]
+    fn peer_protocols_beyond_the_cap_are_folded() {
+        let peer = PeerCounters::default();
+        for index in 0..MAX_TRACKED_PEER_PROTOCOLS + 10 {
+            peer.protocol(&format!("/spam/{index}"));
+        }
+        let protocols = peer.protocols.lock().unwrap();
+        assert_eq!(protocols.len(), MAX_TRACKED_PEER_PROTOCOLS + 1);
+        assert!(protocols.contains_key(OTHER_PROTOCOL));
+    }
+
+    #[test]
+    fn peer_is_forgotten_with_its_last_connection() {
+        let metrics = SubstreamMetrics::default();
+        let peer_id = PeerId::random();
+        let first = metrics.register_peer(peer_id);
+        let second = metrics.register_peer(peer_id);
+        assert!(Arc::ptr_eq(&first, &second));
+
+        first.connections.fetch_sub(1, Ordering::AcqRel);
+        metrics.release_peer(&peer_id, &first);
+        assert!(metrics.peer_stats(&peer_id).is_some());
+
+        second.connections.fetch_sub(1, Ordering::AcqRel);
+        metrics.release_peer(&peer_id, &second);
+        assert!(metrics.peer_stats(&peer_id).is_none());
+    }
+
+    #[test]
+    fn tracked_protocols_keep_their_counters() {
+        let metrics = SubstreamMetrics::default();
+        let first = metrics.protocol("/ipfs/kad/1.0.0");
+        for index in 0..MAX_TRACKED_PROTOCOLS {
+            metrics.protocol(&format!("/spam/{index}"));
+        }
+        assert!(Arc::ptr_eq(&first, &metrics.protocol("/ipfs/kad/1.0.0")));
+        assert!(Arc::ptr_eq(
+            &metrics.protocol("/late"),
+            &metrics.protocol(OTHER_PROTOCOL)
+        ));
+    }
+}
@@ -0,0 +1,449 @@
+//! Peer-assisted clock offset estimation.
+//!
+//! Nodes with time sync on answer `/pheonx/time/1.0.0`: the requester sends
+//! one byte and the responder echoes its wall-clock time in milliseconds
+//! since the Unix epoch (u64 BE). Assuming symmetric latency, the remote clock read
+//! `remote` half a round trip before the answer arrived, so the offset of
+//! the remote clock is `remote + rtt / 2 - local_now`.
+//!
+//! [`ClockOffsets`] keeps the latest offset per peer and reports their
+//! median once enough peers answered, so a single peer with a wrong clock
+//! cannot move the estimate. The correction applied to the local clock is
+//! capped at [`MAX_CLOCK_CORRECTION_MS`], so colluding peers cannot shift
+//! the network time arbitrarily.
+
+use futures::{
+    future::{self, BoxFuture},
+    stream::FuturesUnordered,
+    AsyncReadExt, AsyncWriteExt, FutureExt, StreamExt,
+};
+use libp2p::{
+    core::{transport::PortUse, upgrade::ReadyUpgrade, Endpoint},
+    swarm::{
+        handler::{
+            ConnectionEvent, ConnectionHandler, ConnectionHandlerEvent, DialUpgradeError,
+            FullyNegotiatedInbound, FullyNegotiatedOutbound, SubstreamProtocol,
+        },
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, NotifyHandler, THandler,
+        THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId, Stream, StreamProtocol,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use crate::messaging::envelope::unix_time_ms;
+
+/// Protocol answered with the responder's wall-clock time.
+pub const TIME_PROTOCOL: StreamProtocol = StreamProtocol::new("/pheonx/time/1.0.0");
+/// Time a time request, including its substream negotiation, has to be answered.
+pub const TIME_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Samples with a longer round trip are too imprecise and are discarded.
+pub const MAX_TIME_SAMPLE_RTT: Duration = Duration::from_secs(2);
+/// Peers that must have answered before a network time offset is reported.
+pub const MIN_TIME_SYNC_PEERS: usize = 3;
+/// Largest clock offset, in milliseconds, a sample may report; peers claiming
+/// a clock more than a day off are ignored rather than averaged in.
+pub const MAX_TIME_SAMPLE_OFFSET_MS: i64 = 24 * 60 * 60 * 1000;
+/// Largest correction, in milliseconds, applied to the local clock by
+/// [`ClockOffsets::network_time_ms`]; larger median offsets are clamped.
+pub const MAX_CLOCK_CORRECTION_MS: i64 = 30_000;
+/// Time requests a connection answers concurrently; further ones are dropped.
+const MAX_CONCURRENT_ANSWERS: usize = 4;
+
+/// Offset of a peer's clock measured by one time request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSample {
+    /// Remote clock minus local clock, in milliseconds.
+    pub offset_ms: i64,
+    /// Round-trip time of the request.
+    pub rtt: Duration,
+}
+
+/// Finished time request to a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeSyncEvent {
+    pub peer_id: PeerId,
+    pub result: Result<ClockSample, String>,
+}
+
+/// Latest clock offset of each peer that answered a time request.
+#[derive(Debug, Default)]
+pub struct ClockOffsets {
+    offsets: HashMap<PeerId, ClockSample>,
+}
+
+impl ClockOffsets {
+    /// Records the latest sample of `peer_id`. Samples whose round trip
+    /// exceeds [`MAX_TIME_SAMPLE_RTT`] or whose offset exceeds
+    /// [`MAX_TIME_SAMPLE_OFFSET_MS`] either way are ignored; returns whether
+    /// it was kept.
+    pub fn record(&mut self, peer_id: PeerId, sample: ClockSample) -> bool {
+        if sample.rtt > MAX_TIME_SAMPLE_RTT
+            || sample.offset_ms.unsigned_abs() > MAX_TIME_SAMPLE_OFFSET_MS.unsigned_abs()
+        {
+            return false;
+        }
+        self.offsets.insert(peer_id, sample);
+        true
+    }
+
+    /// Forgets the offset of a disconnected peer.
+    pub fn remove(&mut self, peer_id: &PeerId) {
+        self.offsets.remove(peer_id);
+    }
+
+    /// Forgets every offset, e.g. when the swarm is rebuilt.
+    pub fn clear(&mut self) {
+        self.offsets.clear();
+    }
+
+    /// Offset of `peer_id`'s clock, if it answered.
+    pub fn offset(&self, peer_id: &PeerId) -> Option<ClockSample> {
+        self.offsets.get(peer_id).copied()
+    }
+
+    /// Number of peers with a known offset.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Returns `true` when no peer answered.
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Median offset of the network clock to the local one, in milliseconds,
+    /// once at least [`MIN_TIME_SYNC_PEERS`] peers answered.
+    pub fn median_offset_ms(&self) -> Option<i64> {
+        if self.offsets.len() < MIN_TIME_SYNC_PEERS {
+            return None;
+        }
+        let mut offsets: Vec<i64> = self
+            .offsets
+            .values()
+            .map(|sample| sample.offset_ms)
+            .collect();
+        offsets.sort_unstable();
+        let middle = offsets.len() / 2;
+        Some(if offsets.len().is_multiple_of(2) {
+            // Widened so two extreme offsets cannot overflow.
+            ((i128::from(offsets[middle - 1]) + i128::from(offsets[middle])) / 2) as i64
+        } else {
+            offsets[middle]
+        })
+    }
+
+    /// Local wall-clock time corrected by the median offset, in milliseconds
+    /// since the Unix epoch. The correction is clamped to
+    /// [`MAX_CLOCK_CORRECTION_MS`] either way. Falls back to the local clock
+    /// until enough peers answered.
+    pub fn network_time_ms(&self) -> u64 {
+        let now = unix_time_ms();
+        match self.median_offset_ms() {
+            Some(offset) => now.saturating_add_signed(
+                offset.clamp(-MAX_CLOCK_CORRECTION_MS, MAX_CLOCK_CORRECTION_MS),
+            ),
+            None => now,
+        }
+    }
+}
+
+/// Behaviour answering time requests and sending them on demand.
+#[derive(Debug, Default)]
+pub struct TimeSync {
+    connections: HashMap<PeerId, HashSet<ConnectionId>>,
+    pending: VecDeque<ToSwarm<TimeSyncEvent, TimeRequest>>,
+}
+
+/// Time request handed to a connection handler.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeRequest;
+
+impl TimeSync {
+    /// Asks `peer_id` for its time over one of its connections; the result
+    /// is reported as a [`TimeSyncEvent`]. Returns `false` when the peer is
+    /// not connected.
+    pub fn sample(&mut self, peer_id: PeerId) -> bool {
+        if !self.connections.contains_key(&peer_id) {
+            return false;
+        }
+        self.pending.push_back(ToSwarm::NotifyHandler {
+            peer_id,
+            handler: NotifyHandler::Any,
+            event: TimeRequest,
+        });
+        true
+    }
+
+    fn handler(&mut self, connection_id: ConnectionId, peer: PeerId) -> TimeSyncHandler {
+        self.connections
+            .entry(peer)
+            .or_default()
+            .insert(connection_id);
+        TimeSyncHandler::default()
+    }
+}
+
+impl NetworkBehaviour for TimeSync {
+    type ConnectionHandler = TimeSyncHandler;
+    type ToSwarm = TimeSyncEvent;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        _remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(self.handler(connection_id, peer))
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(self.handler(connection_id, peer))
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(closed) = event {
+            if let Some(connections) = self.connections.get_mut(&closed.peer_id) {
+                connections.remove(&closed.connection_id);
+                if connections.is_empty() {
+                    self.connections.remove(&closed.peer_id);
+                }
+            }
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        _connection_id: ConnectionId,
+        result: THandlerOutEvent<Self>,
+    ) {
+        self.pending
+            .push_back(ToSwarm::GenerateEvent(TimeSyncEvent { peer_id, result }));
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        match self.pending.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Handler answering inbound time requests and sending outbound ones.
+#[derive(Default)]
+pub struct TimeSyncHandler {
+    /// Requests waiting for their substream to be requested.
+    requested: usize,
+    /// Requests whose substream is being negotiated.
+    negotiating: usize,
+    running: FuturesUnordered<BoxFuture<'static, Result<ClockSample, String>>>,
+    answering: FuturesUnordered<BoxFuture<'static, ()>>,
+}
+
+impl ConnectionHandler for TimeSyncHandler {
+    type FromBehaviour = TimeRequest;
+    type ToBehaviour = Result<ClockSample, String>;
+    type InboundProtocol = ReadyUpgrade<StreamProtocol>;
+    type OutboundProtocol = ReadyUpgrade<StreamProtocol>;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = ();
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        SubstreamProtocol::new(ReadyUpgrade::new(TIME_PROTOCOL), ())
+    }
+
+    fn connection_keep_alive(&self) -> bool {
+        self.requested > 0 || self.negotiating > 0 || !self.running.is_empty()
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ConnectionHandlerEvent<Self::OutboundProtocol, (), Self::ToBehaviour>> {
+        while let Poll::Ready(Some(())) = self.answering.poll_next_unpin(cx) {}
+        if self.requested > 0 {
+            self.requested -= 1;
+            self.negotiating += 1;
+            return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(ReadyUpgrade::new(TIME_PROTOCOL), ())
+                    .with_timeout(TIME_REQUEST_TIMEOUT),
+            });
+        }
+        if let Poll::Ready(Some(result)) = self.running.poll_next_unpin(cx) {
+            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(result));
+        }
+        Poll::Pending
+    }
+
+    fn on_behaviour_event(&mut self, _request: TimeRequest) {
+        self.requested += 1;
+    }
+
+    fn on_connection_event(
+        &mut self,
+        event: ConnectionEvent<Self::InboundProtocol, Self::OutboundProtocol>,
+    ) {
+        match event {
+            // Streams over the limit are dropped unanswered.
+            ConnectionEvent::FullyNegotiatedInbound(FullyNegotiatedInbound {
+                protocol: stream,
+                ..
+            }) if self.answering.len() < MAX_CONCURRENT_ANSWERS => {
+                self.answering.push(answer(stream).boxed());
+            }
+            ConnectionEvent::FullyNegotiatedOutbound(FullyNegotiatedOutbound {
+                protocol: stream,
+                ..
+            }) => {
+                self.negotiating = self.negotiating.saturating_sub(1);
+                self.running.push(
+                    async move {
+                        match tokio::time::timeout(TIME_REQUEST_TIMEOUT, request(stream)).await {
+                            Ok(Ok(sample)) => Ok(sample),
+                            Ok(Err(err)) => Err(format!("time request failed: {err}")),
+                            Err(_) => Err(format!(
+                                "time request unanswered after {TIME_REQUEST_TIMEOUT:?}"
+                            )),
+                        }
+                    }
+                    .boxed(),
+                );
+            }
+            ConnectionEvent::DialUpgradeError(DialUpgradeError { error, .. }) => {
+                self.negotiating = self.negotiating.saturating_sub(1);
+                self.running
+                    .push(future::ready(Err(format!("time substream failed: {error}"))).boxed());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Asks the remote for its time and derives the offset of its clock.
+async fn request(mut stream: Stream) -> io::Result<ClockSample> {
+    let started = Instant::now();
+    stream.write_all(&[0]).await?;
+    stream.flush().await?;
+    let mut remote = [0u8; 8];
+    stream.read_exact(&mut remote).await?;
+    let rtt = started.elapsed();
+    let now = unix_time_ms();
+    let _ = stream.close().await;
+
+    let remote = i128::from(u64::from_be_bytes(remote));
+    let offset = remote + (rtt.as_millis() / 2) as i128 - i128::from(now);
+    let offset_ms = i64::try_from(offset)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "remote time out of range"))?;
+    Ok(ClockSample { offset_ms, rtt })
+}
+
+/// Answers one time request with the local time.
+async fn answer(mut stream: Stream) {
+    let result = tokio::time::timeout(TIME_REQUEST_TIMEOUT, async {
+        let mut marker = [0u8; 1];
+        stream.read_exact(&mut marker).await?;
+        stream.write_all(&unix_time_ms().to_be_bytes()).await?;
+        stream.flush().await?;
+        stream.close().await
+    })
+    .await;
+    if let Ok(Err(err)) = result {
+        tracing::debug!(target: "transport", %err, "failed to answer time request");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(offset_ms: i64) -> ClockSample {
+        ClockSample {
+            offset_ms,
+            rtt: Duration::from_millis(50),
+        }
+    }
+
+    fn offsets(values: &[i64]) -> ClockOffsets {
+        let mut offsets = ClockOffsets::default();
+        for value in values {
+            assert!(offsets.record(PeerId::random(), sample(*value)));
+        }
+        offsets
+    }
+
+    #[test]
+    fn median_needs_enough_peers() {
+        assert_eq!(offsets(&[100, 200]).median_offset_ms(), None);
+        assert_eq!(offsets(&[100, 200, 900]).median_offset_ms(), Some(200));
+    }
+
+    #[test]
+    fn median_of_even_count_averages_middle_pair() {
+        assert_eq!(offsets(&[-40, 10, 30, 5_000]).median_offset_ms(), Some(20));
+    }
+
+    #[test]
+    fn median_of_extreme_offsets_does_not_overflow() {
+        let mut offsets = offsets(&[]);
+        for offset in [i64::MAX, i64::MAX, i64::MAX, i64::MAX] {
+            offsets.offsets.insert(PeerId::random(), sample(offset));
+        }
+        assert_eq!(offsets.median_offset_ms(), Some(i64::MAX));
+    }
+
+    #[test]
+    fn record_rejects_slow_and_implausible_samples() {
+        let mut offsets = ClockOffsets::default();
+        let slow = ClockSample {
+            offset_ms: 0,
+            rtt: MAX_TIME_SAMPLE_RTT + Duration::from_millis(1),
+        };
+        assert!(!offsets.record(PeerId::random(), slow));
+        assert!(!offsets.record(PeerId::random(), sample(MAX_TIME_SAMPLE_OFFSET_MS + 1)));
+        assert!(!offsets.record(PeerId::random(), sample(i64::MIN)));
+        assert!(offsets.record(PeerId::random(), sample(-MAX_TIME_SAMPLE_OFFSET_MS)));
+        assert_eq!(offsets.len(), 1);
+    }
+
+    #[test]
+    fn latest_sample_of_a_peer_wins() {
+        let mut offsets = ClockOffsets::default();
+        let peer_id = PeerId::random();
+        offsets.record(peer_id, sample(10));
+        offsets.record(peer_id, sample(20));
+        assert_eq!(offsets.offset(&peer_id), Some(sample(20)));
+        offsets.remove(&peer_id);
+        assert!(offsets.is_empty());
+    }
+
+    #[test]
+    fn network_time_correction_is_clamped() {
+        let offsets = offsets(&[
+            MAX_TIME_SAMPLE_OFFSET_MS,
+            MAX_TIME_SAMPLE_OFFSET_MS,
+            MAX_TIME_SAMPLE_OFFSET_MS,
+        ]);
+        let before = unix_time_ms();
+        let network = offsets.network_time_ms();
+        let after = unix_time_ms();
+        assert!(network >= before + MAX_CLOCK_CORRECTION_MS as u64);
+        assert!(network <= after + MAX_CLOCK_CORRECTION_MS as u64);
+    }
+}